@@ -0,0 +1,122 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion};
+use mangatra::bench::{SAMPLE_PAGE, SAMPLE_TRANSLATION};
+use mangatra::detection::{Detector, DnnOptions, TextBox};
+use mangatra::ocr::Ocr;
+use mangatra::replacer::{Expansion, Replacer};
+use mangatra::utils::cancellation::CancellationToken;
+use mangatra::utils::image_conversion;
+use opencv::core;
+
+// Fixed regions of the fixture page, so the stages after detection can be measured without the model
+const FIXTURE_BOXES: [(i32, i32, i32, i32); 3] = [
+    (40, 30, 150, 110),
+    (260, 60, 120, 160),
+    (420, 250, 140, 120),
+];
+
+fn fixture_page() -> core::Mat {
+    let image = image_conversion::load_image_from_memory(SAMPLE_PAGE).unwrap();
+
+    image_conversion::dynamic_image_to_mat(&image).unwrap()
+}
+
+fn fixture_regions(page: &core::Mat) -> (core::Vector<core::Mat>, Vec<(i32, i32)>) {
+    let boxes: Vec<TextBox> = FIXTURE_BOXES
+        .iter()
+        .map(|(x, y, width, height)| TextBox {
+            x: *x,
+            y: *y,
+            width: *width,
+            height: *height,
+            score: None,
+        })
+        .collect();
+
+    Detector::crop_regions(page, &boxes, &CancellationToken::new()).unwrap()
+}
+
+fn fixture_replacer<'a>(
+    page: &core::Mat,
+    translations: &'a [Option<&'a str>],
+) -> Replacer<'a, &'a str, core::Mat> {
+    let (text_regions, origins) = fixture_regions(page);
+
+    Replacer::new(
+        text_regions,
+        Some(translations),
+        origins,
+        core::Mat::copy(page).unwrap(),
+        10,
+        Expansion::default(),
+        false,
+    )
+    .unwrap()
+}
+
+fn conversion(c: &mut Criterion) {
+    let image = image_conversion::load_image_from_memory(SAMPLE_PAGE).unwrap();
+    let page = fixture_page();
+
+    c.bench_function("conversion/image_to_mat", |b| {
+        b.iter(|| image_conversion::dynamic_image_to_mat(&image).unwrap())
+    });
+    c.bench_function("conversion/mat_to_image", |b| {
+        b.iter(|| image_conversion::mat_to_image_buffer(&page).unwrap())
+    });
+}
+
+fn typesetting(c: &mut Criterion) {
+    let page = fixture_page();
+    let translations = vec![Some(SAMPLE_TRANSLATION); FIXTURE_BOXES.len()];
+    let replacer = fixture_replacer(&page, &translations);
+    let cancellation_token = CancellationToken::new();
+
+    c.bench_function("layout", |b| {
+        b.iter(|| replacer.typeset(&cancellation_token).unwrap())
+    });
+    c.bench_function("compositing", |b| {
+        b.iter_batched(
+            || replacer.typeset(&cancellation_token).unwrap(),
+            |rendered_regions| {
+                replacer
+                    .composite(rendered_regions, &cancellation_token)
+                    .unwrap()
+            },
+            BatchSize::SmallInput,
+        )
+    });
+    c.bench_function("cleaning", |b| {
+        b.iter(|| replacer.clean_page(&cancellation_token).unwrap())
+    });
+}
+
+// Detection and OCR need the model and language data, which aren't in the repository.
+// They are benchmarked when MANGATRA_TEST_MODEL and TESSDATA_PREFIX are set, the same as for the tests.
+fn recognition(c: &mut Criterion) {
+    let (Some(model), Some(tessdata)) = (
+        std::env::var("MANGATRA_TEST_MODEL").ok(),
+        std::env::var("TESSDATA_PREFIX").ok(),
+    ) else {
+        eprintln!(
+            "Skipping detection and OCR: MANGATRA_TEST_MODEL and TESSDATA_PREFIX are not set"
+        );
+        return;
+    };
+
+    let page = fixture_page();
+    let cancellation_token = CancellationToken::new();
+
+    let mut detector = Detector::new(&model, 10, DnnOptions::default()).unwrap();
+    c.bench_function("detection", |b| {
+        b.iter(|| detector.detect(&page, &cancellation_token).unwrap())
+    });
+
+    let (text_regions, _) = fixture_regions(&page);
+    let mut ocr = Ocr::new("eng", &tessdata).unwrap();
+    c.bench_function("ocr", |b| {
+        b.iter(|| ocr.recognize(&text_regions, &cancellation_token).unwrap())
+    });
+}
+
+criterion_group!(benches, conversion, typesetting, recognition);
+criterion_main!(benches);
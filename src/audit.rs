@@ -0,0 +1,205 @@
+use crate::config::Config;
+use crate::handlers::ImageSource;
+use crate::jobs::{Job, Operation, Outcome};
+use crate::utils::hashing;
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// Options a request was processed with
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AuditParameters {
+    pub model: String,
+    pub lang: String,
+    pub target_lang: String,
+    pub padding: u16,
+}
+
+impl AuditParameters {
+    pub fn from_config(config: &Config) -> AuditParameters {
+        AuditParameters {
+            model: config.model_path.clone(),
+            lang: config.lang.clone(),
+            target_lang: config.target_lang.clone(),
+            padding: config.padding,
+        }
+    }
+}
+
+// One line of the audit log
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct AuditRecord {
+    // Seconds since the epoch when the request was taken
+    pub time: u64,
+    pub request_id: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    pub operation: &'static str,
+    // Where the image came from, without the query of URLs, which may hold credentials
+    pub image: String,
+    // Hash of the image's bytes (see "hashing::content_hash"), missing if it couldn't be read
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub image_hash: Option<String>,
+    pub parameters: AuditParameters,
+    pub duration_ms: u64,
+    // "completed", "failed", or "released" for requests stopped part way and left to be retried
+    pub outcome: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regions: Option<usize>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+impl AuditRecord {
+    // Record of a job that was taken at "started" and took "duration". "outcome" is None for a job
+    // that was stopped and put back in the queue.
+    pub fn for_job(
+        job: &Job,
+        image_bytes: Option<&[u8]>,
+        parameters: AuditParameters,
+        started: SystemTime,
+        duration: Duration,
+        outcome: Option<&Outcome>,
+    ) -> AuditRecord {
+        let (outcome, regions, error) = match outcome {
+            Some(Outcome::Extracted { extraction, .. }) => {
+                ("completed", Some(extraction.regions.len()), None)
+            }
+            Some(Outcome::Written { .. } | Outcome::Masked { .. }) => ("completed", None, None),
+            Some(Outcome::Failed { error }) => ("failed", None, Some(error.clone())),
+            None => ("released", None, None),
+        };
+
+        AuditRecord {
+            time: started
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_secs())
+                .unwrap_or_default(),
+            request_id: job.id.clone(),
+            tenant: job.tenant.clone(),
+            operation: match job.operation {
+                Operation::Extract { .. } => "extract",
+                Operation::Clean { .. } => "clean",
+                Operation::Replace { .. } => "replace",
+            },
+            image: match &job.image {
+                ImageSource::Bytes(_) => "bytes".to_string(),
+                ImageSource::Url(url) => url.split(['?', '#']).next().unwrap_or(url).to_string(),
+                ImageSource::Object(uri) => uri.clone(),
+            },
+            image_hash: image_bytes
+                .map(|bytes| format!("{:016x}", hashing::content_hash(&[bytes]))),
+            parameters,
+            duration_ms: duration.as_millis() as u64,
+            outcome,
+            regions,
+            error,
+        }
+    }
+}
+
+// Append-only log of every processed request as JSON lines, for debugging and usage accounting.
+// Each record is written with a single write, so several processes can append to the same file.
+pub struct AuditLog {
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    pub fn open(path: &Path) -> Result<AuditLog> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .with_context(|| format!("Could not open the audit log {}.", path.display()))?;
+
+        Ok(AuditLog {
+            file: Mutex::new(file),
+        })
+    }
+
+    pub fn record(&self, record: &AuditRecord) -> Result<()> {
+        let mut line = serde_json::to_string(record)?;
+        line.push('\n');
+
+        let mut file = self.file.lock().unwrap_or_else(PoisonError::into_inner);
+        file.write_all(line.as_bytes())?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::audit::{AuditLog, AuditParameters, AuditRecord};
+    use crate::handlers::ImageSource;
+    use crate::jobs::{Job, Operation, Outcome, JOB_FORMAT_VERSION};
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_audit_log() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("audit.jsonl");
+
+        let parameters = AuditParameters {
+            model: "model.onnx".to_string(),
+            lang: "jpn".to_string(),
+            target_lang: "en".to_string(),
+            padding: 10,
+        };
+        let job = Job {
+            version: JOB_FORMAT_VERSION,
+            id: "1f".to_string(),
+            operation: Operation::Clean { mask: None },
+            image: ImageSource::Url(
+                "https://images.example.com/001.png?X-Amz-Signature=abc".to_string(),
+            ),
+            output: Some("s3://releases/001.png".to_string()),
+            tenant: None,
+            timings: false,
+        };
+        let started = UNIX_EPOCH + Duration::from_secs(1_700_000_000);
+
+        let failed = AuditRecord::for_job(
+            &job,
+            None,
+            parameters.clone(),
+            started,
+            Duration::from_millis(12),
+            Some(&Outcome::Failed {
+                error: "Could not download the image.".to_string(),
+            }),
+        );
+        let completed = AuditRecord::for_job(
+            &job,
+            Some(b"page"),
+            parameters,
+            started,
+            Duration::from_millis(1500),
+            Some(&Outcome::Written {
+                output: "s3://releases/001.png".to_string(),
+                mask: None,
+            }),
+        );
+        assert_eq!(completed.image, "https://images.example.com/001.png");
+        assert_eq!(completed.outcome, "completed");
+
+        // Records are appended to what earlier processes wrote
+        AuditLog::open(&path).unwrap().record(&failed).unwrap();
+        AuditLog::open(&path).unwrap().record(&completed).unwrap();
+
+        let log = std::fs::read_to_string(&path).unwrap();
+        let lines: Vec<&str> = log.lines().collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(
+            lines[0],
+            r#"{"time":1700000000,"request_id":"1f","operation":"clean","image":"https://images.example.com/001.png","parameters":{"model":"model.onnx","lang":"jpn","target_lang":"en","padding":10},"duration_ms":12,"outcome":"failed","error":"Could not download the image."}"#
+        );
+        assert!(lines[1].contains(r#""image_hash":""#));
+        assert!(lines[1].contains(r#""duration_ms":1500,"outcome":"completed""#));
+    }
+}
@@ -0,0 +1,385 @@
+use crate::detection::{Detector, Device, DnnBackend, DnnOptions, DnnTarget, InferenceEngine};
+use crate::ocr::Ocr;
+use crate::replacer::{Expansion, Replacer};
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::{image_conversion, validation};
+use anyhow::{bail, ensure, Context, Result};
+use image::DynamicImage;
+use std::fmt;
+use std::path::PathBuf;
+use std::time::{Duration, Instant};
+
+// Page benchmarked when no input is given. It's built into the binary, so the bench command runs
+// from any directory.
+pub const SAMPLE_PAGE: &[u8] = include_bytes!("../assets/input.png");
+
+// Typeset into every detected region in place of a real translation. Long enough to be wrapped in most bubbles.
+pub const SAMPLE_TRANSLATION: &str =
+    "I never thought it would come to this, but here we are again.";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Decoding,
+    Detection,
+    Ocr,
+    Layout,
+    Compositing,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Decoding,
+        Stage::Detection,
+        Stage::Ocr,
+        Stage::Layout,
+        Stage::Compositing,
+    ];
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Stage::Decoding => write!(f, "decoding"),
+            Stage::Detection => write!(f, "detection"),
+            Stage::Ocr => write!(f, "ocr"),
+            Stage::Layout => write!(f, "layout"),
+            Stage::Compositing => write!(f, "compositing"),
+        }
+    }
+}
+
+// Time spent in each stage of the pipeline over a number of pages
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageTimings {
+    pub pages: usize,
+    pub regions: usize,
+    durations: [Duration; Stage::ALL.len()],
+}
+
+impl StageTimings {
+    pub fn new() -> StageTimings {
+        StageTimings::default()
+    }
+
+    pub fn add(&mut self, stage: Stage, duration: Duration) {
+        self.durations[stage as usize] += duration;
+    }
+
+    pub fn duration(&self, stage: Stage) -> Duration {
+        self.durations[stage as usize]
+    }
+
+    // Runs one stage of a page and adds the time it took
+    pub fn time<T, F>(&mut self, stage: Stage, run: F) -> Result<T>
+    where
+        F: FnOnce() -> Result<T>,
+    {
+        let start = Instant::now();
+        let result = run();
+        self.add(stage, start.elapsed());
+
+        result
+    }
+
+    // Table of the time per page and the throughput of every stage
+    pub fn report(&self) -> String {
+        let mut report = format!(
+            "{} pages, {} regions\n{:<12} {:>10} {:>10} {:>10} {:>12}\n",
+            self.pages, self.regions, "stage", "total", "per page", "pages/s", "regions/s"
+        );
+
+        let total: Duration = self.durations.iter().sum();
+
+        let rows = Stage::ALL
+            .iter()
+            .map(|stage| (stage.to_string(), self.duration(*stage)))
+            .chain([("total".to_string(), total)]);
+
+        for (name, duration) in rows {
+            let seconds = duration.as_secs_f64();
+
+            let per_page = match self.pages {
+                0 => Duration::ZERO,
+                pages => duration / pages as u32,
+            };
+
+            let throughput = |count: usize| match seconds > 0.0 {
+                true => format!("{:.2}", count as f64 / seconds),
+                false => "-".to_string(),
+            };
+
+            report.push_str(&format!(
+                "{name:<12} {:>10} {:>10} {:>10} {:>12}\n",
+                format!("{:.2?}", duration),
+                format!("{:.2?}", per_page),
+                throughput(self.pages),
+                throughput(self.regions)
+            ));
+        }
+
+        report
+    }
+}
+
+// Page to benchmark, either a file or the sample page
+pub enum BenchPage {
+    File(PathBuf),
+    Sample,
+}
+
+impl BenchPage {
+    fn open(&self) -> Result<DynamicImage> {
+        match self {
+            BenchPage::File(path) => image_conversion::open_image(path),
+            BenchPage::Sample => image_conversion::load_image_from_memory(SAMPLE_PAGE),
+        }
+    }
+}
+
+impl fmt::Display for BenchPage {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            BenchPage::File(path) => write!(f, "{}", path.display()),
+            BenchPage::Sample => write!(f, "the sample page"),
+        }
+    }
+}
+
+// Runs a page through detection, OCR and the typesetting of a sample translation, timing each stage
+pub fn run_page(
+    detector: &mut Detector,
+    ocr: &mut Ocr,
+    page: &BenchPage,
+    timings: &mut StageTimings,
+    cancellation_token: &CancellationToken,
+) -> Result<()> {
+    let image = timings.time(Stage::Decoding, || {
+        image_conversion::dynamic_image_to_mat(&page.open()?)
+    })?;
+
+    let (text_regions, origins) = timings.time(Stage::Detection, || {
+        let boxes = detector.detect(&image, cancellation_token)?;
+
+        Detector::crop_regions(&image, &boxes, cancellation_token)
+    })?;
+
+    timings.time(Stage::Ocr, || {
+        ocr.recognize(&text_regions, cancellation_token)
+    })?;
+
+    let num_regions = text_regions.len();
+    let translations = vec![Some(SAMPLE_TRANSLATION); num_regions];
+
+    let replacer = Replacer::new(
+        text_regions,
+        Some(translations.as_slice()),
+        origins,
+        image,
+        detector.padding(),
+        Expansion::default(),
+        false,
+    )?;
+
+    let rendered_regions = timings.time(Stage::Layout, || replacer.typeset(cancellation_token))?;
+
+    timings.time(Stage::Compositing, || {
+        replacer.composite(rendered_regions, cancellation_token)
+    })?;
+
+    timings.pages += 1;
+    timings.regions += num_regions;
+
+    Ok(())
+}
+
+#[derive(clap::Args, Debug)]
+pub struct BenchArgs {
+    #[arg(
+        short,
+        long,
+        help = "Page or directory of pages to benchmark on. Defaults to a sample page built into mangatra"
+    )]
+    pub input: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        help = "Path to the YOLOv5, YOLOv8 or YOLOv11 detection weights (ONNX format)"
+    )]
+    pub model: PathBuf,
+    #[arg(
+        short,
+        long,
+        default_value = "eng",
+        help = "Specify the language for tesseract"
+    )]
+    pub lang: String,
+    #[arg(
+        short,
+        long,
+        help = "[Optional] Specify path to the tessdata folder for tesseract. If no path is specified, the application will look under the 'TESSDATA_PREFIX' environment variable"
+    )]
+    pub data: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        default_value_t = 10,
+        help = "Specify size of padding for text regions"
+    )]
+    pub padding: u16,
+    #[arg(long, default_value_t = 3, help = "Number of times every page is run")]
+    pub iterations: u32,
+    #[arg(
+        long,
+        help = "Number of threads OpenCV uses. Defaults to OpenCV's own default"
+    )]
+    pub opencv_threads: Option<u16>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InferenceEngine::Opencv,
+        help = "Runtime the detection model is run with. ONNX Runtime (ort) loads models that OpenCV can't and is usually faster on the CPU, but needs mangatra built with the \"ort\" feature. --dnn-backend and --dnn-target only apply to OpenCV"
+    )]
+    pub inference_engine: InferenceEngine,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["dnn_backend", "dnn_target"],
+        help = "Device the detection model runs on, instead of picking --dnn-backend and --dnn-target. Running on CUDA needs OpenCV or ONNX Runtime built with CUDA, and the model runs on the CPU where it isn't available"
+    )]
+    pub device: Option<Device>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnBackend::Default,
+        help = "Backend OpenCV runs the detection model with"
+    )]
+    pub dnn_backend: DnnBackend,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnTarget::Cpu,
+        help = "Device OpenCV runs the detection model on"
+    )]
+    pub dnn_target: DnnTarget,
+    #[arg(
+        long,
+        help = "Run the detection model with TensorRT, which needs --inference-engine ort and mangatra built with the \"ort-tensorrt\" feature. The engines TensorRT builds from the model are kept in this directory, so only the first run spends minutes building them"
+    )]
+    pub tensorrt_cache: Option<PathBuf>,
+}
+
+impl BenchArgs {
+    pub fn run(self) -> Result<()> {
+        validation::validate_model(&self.model)?;
+        let data_path = validation::validate_data(&self.data)?;
+        validation::validate_lang(&data_path, &self.lang)?;
+
+        let pages = match &self.input {
+            Some(input) if input.is_dir() => {
+                let pages = validation::images_in_directory(input)?;
+                ensure!(
+                    !pages.is_empty(),
+                    "There are no pages to benchmark in {}.",
+                    input.display()
+                );
+
+                pages.into_iter().map(BenchPage::File).collect()
+            }
+            Some(input) => {
+                validation::validate_image(input)?;
+                vec![BenchPage::File(input.clone())]
+            }
+            None => vec![BenchPage::Sample],
+        };
+
+        let (model_path, data_path) = match (self.model.to_str(), data_path.to_str()) {
+            (Some(model_path), Some(data_path)) => (model_path, data_path),
+            _ => bail!("The model and tessdata paths must be UTF-8 compatible."),
+        };
+
+        if let Some(threads) = self.opencv_threads {
+            opencv::core::set_num_threads(threads as i32)?;
+        }
+
+        let mut detector = Detector::new(
+            model_path,
+            self.padding,
+            DnnOptions::new(
+                self.inference_engine,
+                self.device,
+                self.dnn_backend,
+                self.dnn_target,
+                self.tensorrt_cache.clone(),
+            ),
+        )?;
+        detector.validate()?;
+        let mut ocr = Ocr::new(&self.lang, data_path)?;
+
+        let cancellation_token = CancellationToken::new();
+
+        // The first run of the model and of tesseract includes one-time setup, which is left out
+        run_page(
+            &mut detector,
+            &mut ocr,
+            &pages[0],
+            &mut StageTimings::new(),
+            &cancellation_token,
+        )
+        .with_context(|| format!("Could not run {}.", pages[0]))?;
+
+        let mut timings = StageTimings::new();
+
+        for _ in 0..self.iterations {
+            for page in pages.iter() {
+                run_page(
+                    &mut detector,
+                    &mut ocr,
+                    page,
+                    &mut timings,
+                    &cancellation_token,
+                )
+                .with_context(|| format!("Could not run {page}."))?;
+            }
+        }
+
+        print!("{}", timings.report());
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::bench::{Stage, StageTimings};
+    use std::time::Duration;
+
+    #[test]
+    fn test_report() {
+        let mut timings = StageTimings::new();
+        timings.pages = 4;
+        timings.regions = 40;
+
+        timings.add(Stage::Detection, Duration::from_secs(2));
+        timings.add(Stage::Ocr, Duration::from_millis(1500));
+        timings.add(Stage::Ocr, Duration::from_millis(500));
+
+        assert_eq!(timings.duration(Stage::Ocr), Duration::from_secs(2));
+
+        let report = timings.report();
+        let lines: Vec<&str> = report.lines().collect();
+
+        assert_eq!(lines[0], "4 pages, 40 regions");
+        assert_eq!(
+            lines[3],
+            "detection         2.00s   500.00ms       2.00        20.00"
+        );
+        assert_eq!(
+            lines[5],
+            "layout           0.00ns     0.00ns          -            -"
+        );
+        assert_eq!(
+            lines[7],
+            "total             4.00s      1.00s       1.00        10.00"
+        );
+    }
+}
@@ -0,0 +1,66 @@
+use anyhow::{Context, Result};
+use clap::Parser;
+use mangatra::extraction::{self, Extraction, PageSize};
+use mangatra::tract_detection::TractDetector;
+use mangatra::utils::cancellation::CancellationToken;
+use std::path::PathBuf;
+
+// Detects the text regions of pages with tract, for hosts where OpenCV and tesseract can't be
+// installed. Each page gets an extraction JSON with the boxes of its regions and no text, in the
+// layout of the text files "mangatra" writes.
+#[derive(Parser)]
+#[command(
+    author,
+    version,
+    about = "Detect the text regions of pages without OpenCV and write their boxes as extraction JSONs"
+)]
+struct Cli {
+    #[arg(
+        short,
+        long,
+        help = "Path to the YOLOv5, YOLOv8 or YOLOv11 detection weights (ONNX format)"
+    )]
+    model: String,
+    #[arg(
+        short,
+        long,
+        default_value_t = 10,
+        help = "Specify size of padding for text regions"
+    )]
+    padding: u16,
+    #[arg(short, long, help = "Directory the extraction JSONs are written to")]
+    output: PathBuf,
+    #[arg(required = true, help = "Pages to detect the text regions of")]
+    pages: Vec<PathBuf>,
+}
+
+fn main() -> Result<()> {
+    let cli = Cli::parse();
+    let detector = TractDetector::new(&cli.model, cli.padding)?;
+    let cancellation_token = CancellationToken::new();
+
+    std::fs::create_dir_all(&cli.output)
+        .with_context(|| format!("Could not create {}.", cli.output.display()))?;
+
+    for path in cli.pages.iter() {
+        let page = image::open(path)
+            .with_context(|| format!("Could not read the page {}.", path.display()))?
+            .to_rgb8();
+        let boxes = detector.detect(&page, &cancellation_token)?;
+
+        let name = extraction::page_name(&path.to_string_lossy());
+        let size = PageSize {
+            width: page.width(),
+            height: page.height(),
+        };
+        let extraction =
+            Extraction::new(&name, vec![String::new(); boxes.len()]).with_geometry(size, &boxes);
+
+        let text_path = cli.output.join(format!("{name}.json"));
+        std::fs::write(&text_path, serde_json::to_string_pretty(&extraction)?)
+            .with_context(|| format!("Could not write {}.", text_path.display()))?;
+        println!("{}: {} regions", path.display(), boxes.len());
+    }
+
+    Ok(())
+}
@@ -0,0 +1,233 @@
+use crate::config::Config;
+use crate::utils::hashing;
+use anyhow::{Context, Result};
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::time::UNIX_EPOCH;
+
+// Stages whose results only depend on the page and the parameters, not on the translations
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum CachedStage {
+    Detection,
+    Ocr,
+}
+
+impl CachedStage {
+    fn directory_name(self) -> &'static str {
+        match self {
+            CachedStage::Detection => "detection",
+            CachedStage::Ocr => "ocr",
+        }
+    }
+}
+
+// Text recognized in the regions of a page, in the order of its boxes
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct CachedText {
+    pub text: Vec<String>,
+    pub confidences: Vec<u8>,
+}
+
+// Detection and OCR results of pages, kept on disk between runs. Entries are keyed by a hash of the
+// page's bytes and of every parameter the stage depends on, so they never have to be cleared by
+// hand: a changed page, model, padding or language simply leads to a different key.
+pub struct StageCache {
+    directory: PathBuf,
+    detection_parameters: String,
+    ocr_parameters: String,
+}
+
+impl StageCache {
+    pub fn new(
+        directory: &Path,
+        detection_parameters: String,
+        ocr_parameters: String,
+    ) -> Result<StageCache> {
+        for stage in [CachedStage::Detection, CachedStage::Ocr] {
+            let stage_directory = directory.join(stage.directory_name());
+
+            std::fs::create_dir_all(&stage_directory).with_context(|| {
+                format!(
+                    "Could not create the cache directory {}.",
+                    stage_directory.display()
+                )
+            })?;
+        }
+
+        // OCR runs on the detected regions, so it depends on everything detection depends on
+        let ocr_parameters = format!("{detection_parameters}\n{ocr_parameters}");
+
+        Ok(StageCache {
+            directory: directory.to_path_buf(),
+            detection_parameters,
+            ocr_parameters,
+        })
+    }
+
+    // Cache for the parameters of a run
+    pub fn for_config(directory: &Path, config: &Config) -> Result<StageCache> {
+        // A new model at the same path has to invalidate the detections of the old one
+        let model = std::fs::metadata(&config.model_path)
+            .with_context(|| format!("Could not read the model at {}.", config.model_path))?;
+        let modified = model
+            .modified()
+            .ok()
+            .and_then(|modified| modified.duration_since(UNIX_EPOCH).ok())
+            .map(|modified| modified.as_nanos())
+            .unwrap_or_default();
+
+        // Engines, backends and targets round differently, so they can detect slightly different boxes
+        let mut detection_parameters = format!(
+            "{}\n{}\n{}\n{modified}\n{}\n{:?}\n{}\n{}\n{:?}",
+            env!("CARGO_PKG_VERSION"),
+            config.model_path,
+            model.len(),
+            config.padding,
+            config.enhance,
+            config.deskew_page,
+            config.max_detections,
+            config.dnn
+        );
+        // Detections of other runs can differ in their last bits, so deterministic runs don't reuse them
+        if config.deterministic {
+            detection_parameters.push_str("\ndeterministic");
+        }
+        let ocr_parameters = format!(
+            "{}\n{}\n{}\n{:?}",
+            config.lang, config.tesseract_data_path, config.deskew, config.vocabulary
+        );
+
+        StageCache::new(directory, detection_parameters, ocr_parameters)
+    }
+
+    // Name of the entry for a page, from the hash of its bytes (see "hashing::content_hash")
+    pub fn key(&self, stage: CachedStage, page_hash: u64) -> String {
+        let parameters = match stage {
+            CachedStage::Detection => &self.detection_parameters,
+            CachedStage::Ocr => &self.ocr_parameters,
+        };
+
+        let key = hashing::content_hash(&[&page_hash.to_le_bytes(), parameters.as_bytes()]);
+
+        format!("{key:016x}")
+    }
+
+    fn entry_path(&self, stage: CachedStage, page_hash: u64) -> PathBuf {
+        self.directory
+            .join(stage.directory_name())
+            .join(format!("{}.json", self.key(stage, page_hash)))
+    }
+
+    // Entries that are missing or can't be read are treated as not cached
+    pub fn get<T: DeserializeOwned>(&self, stage: CachedStage, page_hash: u64) -> Option<T> {
+        let data = std::fs::read_to_string(self.entry_path(stage, page_hash)).ok()?;
+
+        serde_json::from_str(&data).ok()
+    }
+
+    // Entries are written to a file of their own and then moved into place, so an interrupted
+    // run or two workers on identical pages never leave a partial entry behind
+    pub fn put<T: Serialize>(&self, stage: CachedStage, page_hash: u64, value: &T) -> Result<()> {
+        static NEXT_WRITE: AtomicUsize = AtomicUsize::new(0);
+
+        let path = self.entry_path(stage, page_hash);
+        let temporary_path = path.with_extension(format!(
+            "{}.{}.tmp",
+            std::process::id(),
+            NEXT_WRITE.fetch_add(1, Ordering::Relaxed)
+        ));
+
+        std::fs::write(&temporary_path, serde_json::to_string(value)?)?;
+        std::fs::rename(&temporary_path, &path)?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::cache::{CachedStage, CachedText, StageCache};
+    use crate::detection::TextBox;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_stage_cache() {
+        let directory = TempDir::new().unwrap();
+
+        let cache = StageCache::new(
+            directory.path(),
+            "model.onnx\n10".to_string(),
+            "eng".to_string(),
+        )
+        .unwrap();
+
+        let boxes = vec![TextBox {
+            x: 10,
+            y: 20,
+            width: 30,
+            height: 40,
+            score: None,
+        }];
+        let text = CachedText {
+            text: vec!["Hello".to_string()],
+            confidences: vec![91],
+        };
+
+        assert_eq!(cache.get::<Vec<TextBox>>(CachedStage::Detection, 1), None);
+
+        cache.put(CachedStage::Detection, 1, &boxes).unwrap();
+        cache.put(CachedStage::Ocr, 1, &text).unwrap();
+
+        assert_eq!(cache.get(CachedStage::Detection, 1), Some(boxes.clone()));
+        assert_eq!(cache.get(CachedStage::Ocr, 1), Some(text));
+        assert_eq!(cache.get::<CachedText>(CachedStage::Ocr, 2), None);
+
+        // Entries survive between runs with the same parameters
+        let reopened = StageCache::new(
+            directory.path(),
+            "model.onnx\n10".to_string(),
+            "eng".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(reopened.get(CachedStage::Detection, 1), Some(boxes));
+
+        // Detection parameters are part of the keys of both stages, OCR parameters only of OCR's
+        let repadded = StageCache::new(
+            directory.path(),
+            "model.onnx\n20".to_string(),
+            "eng".to_string(),
+        )
+        .unwrap();
+        let japanese = StageCache::new(
+            directory.path(),
+            "model.onnx\n10".to_string(),
+            "jpn".to_string(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            repadded.get::<Vec<TextBox>>(CachedStage::Detection, 1),
+            None
+        );
+        assert_eq!(repadded.get::<CachedText>(CachedStage::Ocr, 1), None);
+        assert!(japanese
+            .get::<Vec<TextBox>>(CachedStage::Detection, 1)
+            .is_some());
+        assert_eq!(japanese.get::<CachedText>(CachedStage::Ocr, 1), None);
+
+        // Entries that can't be read are misses rather than errors
+        std::fs::write(
+            directory
+                .path()
+                .join("ocr")
+                .join(format!("{}.json", cache.key(CachedStage::Ocr, 3))),
+            "{",
+        )
+        .unwrap();
+
+        assert_eq!(cache.get::<CachedText>(CachedStage::Ocr, 3), None);
+    }
+}
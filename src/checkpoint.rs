@@ -0,0 +1,90 @@
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::path::{Path, PathBuf};
+
+const CHECKPOINT_FILE_NAME: &str = ".mangatra-checkpoint.json";
+
+// Pages of a directory run that were done when it was stopped, kept in the output directory so the
+// run can be resumed with "--resume" instead of processing every page again
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Checkpoint {
+    // Names of the pages whose outputs were written
+    pub completed: BTreeSet<String>,
+}
+
+impl Checkpoint {
+    pub fn path(output_directory: &Path) -> PathBuf {
+        output_directory.join(CHECKPOINT_FILE_NAME)
+    }
+
+    // Checkpoint of the run writing to the directory, or None if it wasn't stopped part way
+    pub fn load(output_directory: &Path) -> Result<Option<Checkpoint>> {
+        let path = Checkpoint::path(output_directory);
+        if !path.is_file() {
+            return Ok(None);
+        }
+
+        let data = std::fs::read_to_string(&path)
+            .with_context(|| format!("Could not read the checkpoint {}.", path.display()))?;
+        let checkpoint = serde_json::from_str(&data)
+            .with_context(|| format!("{} is not a valid checkpoint.", path.display()))?;
+
+        Ok(Some(checkpoint))
+    }
+
+    // Writes to a temporary file first, like project files, so a second interruption never leaves a
+    // truncated checkpoint behind
+    pub fn save(&self, output_directory: &Path) -> Result<PathBuf> {
+        let path = Checkpoint::path(output_directory);
+        let temporary_path = path.with_extension("json.tmp");
+
+        std::fs::write(&temporary_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temporary_path, &path)?;
+
+        Ok(path)
+    }
+
+    // Removes the checkpoint of a run that went through every page
+    pub fn remove(output_directory: &Path) -> Result<()> {
+        let path = Checkpoint::path(output_directory);
+
+        if path.is_file() {
+            std::fs::remove_file(&path)
+                .with_context(|| format!("Could not remove the checkpoint {}.", path.display()))?;
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::checkpoint::Checkpoint;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_checkpoint() {
+        let directory = TempDir::new().unwrap();
+        assert_eq!(Checkpoint::load(directory.path()).unwrap(), None);
+
+        let checkpoint = Checkpoint {
+            completed: ["002", "001"].map(String::from).into(),
+        };
+        let path = checkpoint.save(directory.path()).unwrap();
+        assert_eq!(path, directory.path().join(".mangatra-checkpoint.json"));
+        assert_eq!(
+            std::fs::read_to_string(&path).unwrap(),
+            "{\n  \"completed\": [\n    \"001\",\n    \"002\"\n  ]\n}"
+        );
+        assert_eq!(
+            Checkpoint::load(directory.path()).unwrap(),
+            Some(checkpoint)
+        );
+
+        Checkpoint::remove(directory.path()).unwrap();
+        assert_eq!(Checkpoint::load(directory.path()).unwrap(), None);
+        // Runs that were never stopped have nothing to remove
+        Checkpoint::remove(directory.path()).unwrap();
+    }
+}
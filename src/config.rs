@@ -1,6 +1,34 @@
-use crate::utils::validation;
+use crate::bench::BenchArgs;
+use crate::credits::{Credits, CreditsPlacement};
+use crate::detection::{
+    Device, DnnBackend, DnnOptions, DnnTarget, InferenceEngine, DEFAULT_MAX_DETECTIONS,
+};
+use crate::diff;
+use crate::evaluation::{EvalDetectArgs, EvalOcrArgs};
+use crate::export::annotations::AnnotationFormat;
+use crate::export::table::TableFormat;
+use crate::font_store::{FontStore, FontsArgs};
+use crate::fonts;
+#[cfg(feature = "history")]
+use crate::history::HistoryArgs;
+use crate::honorifics::HonorificPolicy;
+use crate::hooks::Hooks;
+use crate::project::{Project, ProjectCommand};
+use crate::replacer::{Expansion, InnerMargin};
+use crate::selection::SelectArgs;
+use crate::storage::Staging;
+use crate::style::StylePreset;
+use crate::synthetic::SynthesizeArgs;
+use crate::utils::enhance::EnhanceStep;
+use crate::utils::fetch::{self, FetchPolicy};
+use crate::utils::{image_conversion, validation};
+use crate::verify::VerifyArgs;
+use crate::vocabulary::{self, Vocabulary};
+#[cfg(feature = "server")]
+use crate::worker::{DefaultsArgs, SubmitArgs, WorkerArgs};
 use anyhow::{bail, ensure, Result};
-use clap::Parser;
+use clap::{Parser, Subcommand};
+use rusttype::Font;
 use std::path::{Path, PathBuf};
 use tracing::instrument;
 
@@ -21,20 +49,188 @@ pub struct Config {
     pub model_path: String,
     pub tesseract_data_path: String,
     pub lang: String,
+    // Words and patterns from the project file that OCR is biased towards
+    pub vocabulary: Vocabulary,
+    // Language of the translations, which the default font is chosen for
+    pub target_lang: String,
+    // Font translations are typeset in
+    pub font: Font<'static>,
+    // Named fonts that replace jobs may pick instead
+    pub font_store: Option<FontStore>,
+    pub honorifics: HonorificPolicy,
+    // Typesetting rules, e.g. for shouts
+    pub style: StylePreset,
     pub padding: u16,
+    // Space between the translations and the sides of their regions, the default if not set
+    pub inner_margin: Option<InnerMargin>,
+    // Set every translation of a page in the same size
+    pub uniform_font_size: bool,
+    // Break lines as late as possible, without keeping single words off the last line
+    pub greedy_wrapping: bool,
+    // Paint text out of bubbles and typeset in their colors, for color pages
+    pub color_aware: bool,
+    pub expansion: Expansion,
+    // Corrections applied to the page before detection and OCR
+    pub enhance: Vec<EnhanceStep>,
+    // Straighten crooked pages before detection
+    pub deskew_page: bool,
+    pub deskew: bool,
+    pub skip_empty_regions: bool,
+    pub min_confidence: u8,
+    // Gap in pixels within which bubbles are linked into groups that share one translation
+    pub link_distance: Option<u16>,
     pub input_mode: InputMode,
     pub single: bool,
+    // Size of the pool that processes pages in parallel, rayon's default if not set
+    pub threads: Option<usize>,
+    // Threads OpenCV uses within a page. Negative values restore OpenCV's default.
+    pub opencv_threads: i32,
+    // Pages of a directory run the detection model runs over in one pass
+    pub batch_size: usize,
+    // Produce byte-identical outputs for identical inputs, at the cost of OpenCV's threads and OpenCL
+    pub deterministic: bool,
+    pub dnn: DnnOptions,
+    // Pages with more pixels than this are refused before they are decoded
+    pub max_pixels: u64,
+    // Most text regions processed on a page. The ones the model is least sure about are dropped.
+    pub max_detections: usize,
+    // Where the handlers may download images given by URL from, and how large they may be
+    pub fetch_policy: FetchPolicy,
+    pub skip_duplicates: bool,
+    // Write no text files for text-free pages, and take pages without one to be text-free when
+    // replacing
+    pub skip_empty_text_files: bool,
+    // Skip the pages listed in the checkpoint of a directory run that was stopped
+    pub resume: bool,
+    pub mokuro: bool,
+    pub xliff: bool,
+    pub export_table: Option<TableFormat>,
+    pub export_annotations: Option<AnnotationFormat>,
+    // Detection score below which boxes are left out of the annotations
+    pub annotation_min_score: u8,
+    // Directory of the bundle of regions to review, and the confidence below which they go into it
+    pub review_path: Option<PathBuf>,
+    pub review_threshold: u8,
+    // Directory every region is cropped into, with its text, for building OCR datasets
+    pub dump_regions_path: Option<PathBuf>,
+    // Project file of the input's directory, updated with the progress of every processed page
+    pub project_path: Option<PathBuf>,
+    // Where to also write the summary of a directory run as JSON
+    pub summary_path: Option<PathBuf>,
+    // Add the time each stage of the pages took to the summary
+    pub timings: bool,
+    // Directory where detection and OCR results are kept between runs
+    pub cache_path: Option<PathBuf>,
+    // Credits added to the translated pages
+    pub credits: Option<Credits>,
+    pub hooks: Hooks,
+    // Local copies of inputs and outputs in buckets, with where to upload the outputs once done
+    pub staging: Staging,
+}
+
+// What the command line asked for: processing pages with the options, or one of the subcommands
+pub enum Invocation {
+    Run(Box<Config>),
+    Command(Command),
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Command {
+    #[command(about = "Create, query and update the project file of a volume")]
+    Project {
+        #[command(subcommand)]
+        command: ProjectCommand,
+    },
+    #[command(about = "Compare two revisions of a page's text file")]
+    Diff {
+        #[arg(help = "Earlier revision (JSON, XLIFF, CSV or TSV)")]
+        old: PathBuf,
+        #[arg(help = "Later revision (JSON, XLIFF, CSV or TSV)")]
+        new: PathBuf,
+    },
+    #[command(
+        about = "Time every stage of the pipeline on a set of pages and report their throughput"
+    )]
+    Bench(BenchArgs),
+    #[command(about = "Compare rendered pages against golden images and report regressions")]
+    Verify(VerifyArgs),
+    #[command(
+        about = "Pick the pages the detection model is least sure about from a corpus, for labeling"
+    )]
+    SelectPages(SelectArgs),
+    #[command(
+        about = "Draw random text into the bubbles of cleaned pages to generate detection training data"
+    )]
+    Synthesize(SynthesizeArgs),
+    #[command(
+        about = "Compare the detection model's boxes against labeled pages and report its precision, recall and mAP"
+    )]
+    EvalDetect(EvalDetectArgs),
+    #[command(
+        about = "Compare OCR output against ground truth transcripts and report its character and word error rates"
+    )]
+    EvalOcr(EvalOcrArgs),
+    #[cfg(feature = "server")]
+    #[command(about = "Process jobs from a shared queue until stopped, without serving requests")]
+    Worker(WorkerArgs),
+    #[cfg(feature = "server")]
+    #[command(about = "Put a job in a shared queue for the workers and print its ID")]
+    Submit(SubmitArgs),
+    #[command(about = "List, add and remove the named fonts replace jobs can pick")]
+    Fonts(FontsArgs),
+    #[cfg(feature = "server")]
+    #[command(
+        about = "Show or change the defaults workers process jobs with, without restarting them"
+    )]
+    Defaults(DefaultsArgs),
+    #[cfg(feature = "history")]
+    #[command(about = "Look up the results and failures of jobs in the history of the workers")]
+    History(HistoryArgs),
+}
+
+impl Command {
+    pub fn run(self) -> Result<()> {
+        match self {
+            Command::Project { command } => command.run(),
+            Command::Diff { old, new } => diff::print_diff(&old, &new),
+            Command::Bench(args) => args.run(),
+            Command::Verify(args) => args.run(),
+            Command::SelectPages(args) => args.run(),
+            Command::Synthesize(args) => args.run(),
+            Command::EvalDetect(args) => args.run(),
+            Command::EvalOcr(args) => args.run(),
+            #[cfg(feature = "server")]
+            Command::Worker(args) => args.run(),
+            #[cfg(feature = "server")]
+            Command::Submit(args) => args.run(),
+            Command::Fonts(args) => args.run(),
+            #[cfg(feature = "server")]
+            Command::Defaults(args) => args.run(),
+            #[cfg(feature = "history")]
+            Command::History(args) => args.run(),
+        }
+    }
 }
 
 #[derive(Parser)]
-#[command(author, version, about, long_about = None)]
+#[command(
+    author,
+    version,
+    about,
+    long_about = None,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
 struct Cli {
+    #[command(subcommand)]
+    pub command: Option<Command>,
     #[arg(
         short,
         long,
+        required = true,
         help = "Input path for a directory of images or single image"
     )]
-    pub input: PathBuf,
+    pub input: Option<PathBuf>,
     #[arg(
         short,
         long,
@@ -44,17 +240,46 @@ struct Cli {
     #[arg(
         short,
         long,
-        help = "[Optional] Specify a path to the translated JSONs"
+        help = "[Optional] Specify a path to the translated JSONs or XLIFF files, or to a single translated CSV/TSV table"
     )]
     pub text: Option<PathBuf>,
     #[arg(
         short,
         long,
-        help = "Path to the YOLOv5 detection weights (ONNX format)"
+        required = true,
+        help = "Path to the YOLOv5, YOLOv8 or YOLOv11 detection weights (ONNX format)"
     )]
-    pub model: PathBuf,
-    #[arg(short, long, help = "Specify the language for tesseract")]
-    pub lang: String,
+    pub model: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        required = true,
+        help = "Specify the language for tesseract"
+    )]
+    pub lang: Option<String>,
+    #[arg(
+        long,
+        default_value = "en",
+        help = "Language the translations are written in, e.g. es, ru or ja. Picks a default font that has the letters it needs"
+    )]
+    pub target_lang: String,
+    #[arg(
+        long,
+        help = "TrueType or OpenType font to typeset translations in, instead of the default for --target-lang"
+    )]
+    pub font: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = HonorificPolicy::Keep,
+        help = "What to do with Japanese honorifics (-san, -kun, onii-chan) in the translations: typeset them as written, write them the same way everywhere (\"Tanaka-san\"), or remove them"
+    )]
+    pub honorifics: HonorificPolicy,
+    #[arg(
+        long,
+        help = "JSON file with typesetting rules, e.g. how much larger, bolder or uppercased the translations of shouts are set"
+    )]
+    pub style: Option<PathBuf>,
     #[arg(
         short,
         long,
@@ -63,13 +288,468 @@ struct Cli {
     pub data: Option<PathBuf>,
     #[arg(short, long, help = "Specify size of padding for text regions")]
     pub padding: Option<u16>,
+    #[arg(
+        long,
+        help = "Space kept free between translations and the left and right edges of their regions, in pixels (12) or as a percentage of the region's width (5%)"
+    )]
+    pub inner_margin: Option<InnerMargin>,
+    #[arg(
+        long,
+        help = "Set every translation of a page in the same size, the largest that fits all of its regions, instead of sizing each for its own region"
+    )]
+    pub uniform_font_size: bool,
+    #[arg(
+        long,
+        help = "Break lines as late as possible, even when that leaves a single short word on the last line of a region. By default, words are moved down to it from the lines above"
+    )]
+    pub greedy_wrapping: bool,
+    #[arg(
+        long,
+        help = "For color pages: paint text out of bubbles instead of filling them with white or black, keeping their gradients and screentone, and set translations in the colors of the text they replace"
+    )]
+    pub color_aware: bool,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "How much the color of a pixel may differ (per channel, 0-255) from a bubble's background for text regions to still grow over it. Raise it for noisy scans and screentone"
+    )]
+    pub expand_tolerance: u8,
+    #[arg(
+        long,
+        help = "Largest number of pixels a text region may grow by in each direction to cover its bubble"
+    )]
+    pub max_expansion: Option<u32>,
+    #[arg(
+        long,
+        value_enum,
+        value_delimiter = ',',
+        help = "Corrections for poor scans, applied to the page before detection and OCR. The output is made from the page as it was scanned"
+    )]
+    pub enhance: Vec<EnhanceStep>,
+    #[arg(
+        long,
+        help = "Straighten crooked scans before detection. Cleaned and translated pages are written straightened, and the boxes in the text files refer to the straightened page"
+    )]
+    pub deskew_page: bool,
+    #[arg(
+        long,
+        help = "Straighten slightly rotated text regions before OCR and set their translations at the same angle"
+    )]
+    pub deskew: bool,
+    #[arg(
+        long,
+        help = "Leave regions where OCR found no text untouched when cleaning and replacing. These are usually detections on art"
+    )]
+    pub skip_empty_regions: bool,
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "OCR confidence (0-100) below which a region also counts as empty for --skip-empty-regions"
+    )]
+    pub min_confidence: u8,
+    #[arg(
+        long,
+        value_name = "PIXELS",
+        help = "Link bubbles that touch or lie at most this many pixels apart, such as ones joined by a connector. A translation written for the first bubble of a group flows across all of them in reading order"
+    )]
+    pub link_distance: Option<u16>,
     #[arg(long, help = "Use single-threading for image processing")]
     pub single: bool,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Number of threads a directory run works on pages with, split between reading, detection, OCR and rendering, and writing. About as many pages, plus six waiting between the stages, are held in memory at once. Defaults to the number of CPU cores"
+    )]
+    pub threads: Option<u16>,
+    #[arg(
+        long,
+        help = "Number of threads OpenCV uses within each page. Defaults to 1 when pages are processed in parallel and to OpenCV's own default with --single"
+    )]
+    pub opencv_threads: Option<u16>,
+    #[arg(
+        long,
+        default_value_t = 1,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Number of pages of a directory run the detection model runs over in one pass. Larger batches keep a GPU busy, but need a model exported with a dynamic batch size and hold more pages in memory"
+    )]
+    pub batch_size: u16,
+    #[arg(
+        long,
+        conflicts_with = "opencv_threads",
+        help = "Make identical inputs always produce byte-identical extraction JSONs, pages, volume-wide exports and run summaries (except for --timings). OpenCV runs on one thread per page without OpenCL, and the model has to run on the CPU with OpenCV's own backend"
+    )]
+    pub deterministic: bool,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InferenceEngine::Opencv,
+        help = "Runtime the detection model is run with. ONNX Runtime (ort) loads models that OpenCV can't and is usually faster on the CPU, but needs mangatra built with the \"ort\" feature. --dnn-backend and --dnn-target only apply to OpenCV"
+    )]
+    pub inference_engine: InferenceEngine,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["dnn_backend", "dnn_target"],
+        help = "Device the detection model runs on, instead of picking --dnn-backend and --dnn-target. Running on CUDA needs OpenCV or ONNX Runtime built with CUDA, and the model runs on the CPU where it isn't available"
+    )]
+    pub device: Option<Device>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnBackend::Default,
+        help = "Backend OpenCV runs the detection model with"
+    )]
+    pub dnn_backend: DnnBackend,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnTarget::Cpu,
+        help = "Device OpenCV runs the detection model on"
+    )]
+    pub dnn_target: DnnTarget,
+    #[arg(
+        long,
+        help = "Run the detection model with TensorRT, which needs --inference-engine ort and mangatra built with the \"ort-tensorrt\" feature. The engines TensorRT builds from the model are kept in this directory, so only the first run spends minutes building them"
+    )]
+    pub tensorrt_cache: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = image_conversion::DEFAULT_MAX_PIXELS,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Largest page, in pixels, that is processed. Larger pages are reported as errors before they are decoded, so one huge scan can't exhaust the memory"
+    )]
+    pub max_pixels: u64,
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_DETECTIONS,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Most text regions processed on a page. Pages with more are reported and only their regions the model is most confident about are kept. Far more regions than this usually means the wrong model or a dense index page"
+    )]
+    pub max_detections: u32,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Hosts that handlers may download images given by URL from, e.g. a bucket's host. A host starting with a dot also allows its subdomains, and s3://bucket or gs://bucket allows reading objects from that bucket. No images are downloaded if none are given"
+    )]
+    pub allowed_image_hosts: Vec<String>,
+    #[arg(
+        long,
+        default_value_t = fetch::DEFAULT_MAX_DOWNLOAD_BYTES,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Largest image, in bytes, that handlers download from a URL"
+    )]
+    pub max_download_bytes: u64,
     #[arg(
         long,
         help = "If set, the program will output cleaned pages in PNG format in the output directory"
     )]
     pub clean: bool,
+    #[arg(
+        long,
+        help = "In extraction mode, reuse the text of an earlier page for near-duplicate pages (e.g. repeated credit pages) instead of processing them again"
+    )]
+    pub skip_duplicates: bool,
+    #[arg(
+        long,
+        help = "Write no text file for pages where nothing was detected, such as cover art. In replacement mode, pages without a text file are then copied through instead of failing the run"
+    )]
+    pub skip_empty_text_files: bool,
+    #[arg(
+        long,
+        help = "Skip the pages a directory run had done when it was stopped with Ctrl-C, as listed in the checkpoint in its output directory"
+    )]
+    pub resume: bool,
+    #[arg(
+        long,
+        help = "In extraction mode, also write the positioned text of the pages as a Mokuro volume (.mokuro) for Mokuro readers"
+    )]
+    pub mokuro: bool,
+    #[arg(
+        long,
+        help = "In extraction mode, also write the text as XLIFF 2.0 for CAT tools. Translated XLIFF files can be used as the text input for replacement"
+    )]
+    pub xliff: bool,
+    #[arg(
+        long,
+        value_enum,
+        help = "In extraction mode, also write the text of all pages as a single spreadsheet in the given format"
+    )]
+    pub export_table: Option<TableFormat>,
+    #[arg(
+        long,
+        value_enum,
+        help = "In extraction mode, also write the detected regions as annotations in the given format to an \"annotations\" directory in the output location. YOLO labels are written next to copies of the page images"
+    )]
+    pub export_annotations: Option<AnnotationFormat>,
+    #[arg(
+        long,
+        default_value_t = 0,
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "Detection score (0-100) below which boxes are left out of --export-annotations, e.g. to only pre-label the detections the model is sure about"
+    )]
+    pub annotation_min_score: u8,
+    #[arg(
+        long,
+        help = "In extraction mode, also write the regions that OCR or detection were unsure about to this directory: a crop of each region and a review.json that lists them, so only those need checking"
+    )]
+    pub review: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = 60,
+        value_parser = clap::value_parser!(u8).range(0..=100),
+        help = "OCR confidence or detection score (0-100) below which a region goes into the bundle of --review"
+    )]
+    pub review_threshold: u8,
+    #[arg(
+        long,
+        help = "In extraction mode, also crop every detected region into this directory, with its recognized text as a .gt.txt file and a dataset.json that lists the regions, for building OCR training data"
+    )]
+    pub dump_regions: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "When processing a directory, also write the summary printed at the end of the run to this file as JSON"
+    )]
+    pub summary: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "When processing a directory, add how long each stage (decode, detect, OCR, layout, encode) took to the summary"
+    )]
+    pub timings: bool,
+    #[arg(
+        long,
+        help = "Directory to keep the detection and OCR results of pages in. When a page and the options it was processed with are unchanged, later runs reuse them, e.g. to typeset edited translations without running the model again"
+    )]
+    pub cache: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "In replacement mode, add the credits of the release to the translated pages: an image, or a text file with the group name on the first line and the staff list below it"
+    )]
+    pub credits: Option<PathBuf>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = CreditsPlacement::Append,
+        help = "Whether the credits get a page of their own after the last page or are stamped into the corner of every page"
+    )]
+    pub credits_placement: CreditsPlacement,
+}
+
+// Options of processes that take pages as jobs instead of from a directory, such as workers. Every
+// job is processed with the same options; the rest are left at their defaults.
+#[derive(clap::Args, Clone, Debug)]
+pub struct ServiceArgs {
+    #[arg(
+        short,
+        long,
+        help = "Path to the YOLOv5, YOLOv8 or YOLOv11 detection weights (ONNX format)"
+    )]
+    pub model: PathBuf,
+    #[arg(short, long, help = "Specify the language for tesseract")]
+    pub lang: String,
+    #[arg(
+        short,
+        long,
+        help = "[Optional] Specify a path to the tessdata folder for tesseract. If no path is specified, the application will look under the 'TESSDATA_PREFIX' environment variable"
+    )]
+    pub data: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value = "en",
+        help = "Language the translations are written in, e.g. es, ru or ja. Picks a default font that has the letters it needs"
+    )]
+    pub target_lang: String,
+    #[arg(
+        long,
+        help = "TrueType or OpenType font to typeset translations in, instead of the default for --target-lang"
+    )]
+    pub font: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Directory of named fonts that replace jobs can pick instead of --font, managed with \"mangatra fonts\""
+    )]
+    pub font_dir: Option<PathBuf>,
+    #[arg(
+        short,
+        long,
+        default_value_t = 10,
+        help = "Specify size of padding for text regions"
+    )]
+    pub padding: u16,
+    #[arg(
+        long,
+        help = "Space kept free between translations and the left and right edges of their regions, in pixels (12) or as a percentage of the region's width (5%)"
+    )]
+    pub inner_margin: Option<InnerMargin>,
+    #[arg(
+        long,
+        help = "Set every translation of a page in the same size, the largest that fits all of its regions, instead of sizing each for its own region"
+    )]
+    pub uniform_font_size: bool,
+    #[arg(
+        long,
+        help = "Break lines as late as possible, even when that leaves a single short word on the last line of a region. By default, words are moved down to it from the lines above"
+    )]
+    pub greedy_wrapping: bool,
+    #[arg(
+        long,
+        help = "For color pages: paint text out of bubbles instead of filling them with white or black, keeping their gradients and screentone, and set translations in the colors of the text they replace"
+    )]
+    pub color_aware: bool,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u16).range(1..),
+        help = "Number of pages of an archive processed at once. Defaults to the number of CPU cores"
+    )]
+    pub threads: Option<u16>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InferenceEngine::Opencv,
+        help = "Runtime the detection model is run with. ONNX Runtime (ort) loads models that OpenCV can't and is usually faster on the CPU, but needs mangatra built with the \"ort\" feature. --dnn-backend and --dnn-target only apply to OpenCV"
+    )]
+    pub inference_engine: InferenceEngine,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["dnn_backend", "dnn_target"],
+        help = "Device the detection model runs on, instead of picking --dnn-backend and --dnn-target. Running on CUDA needs OpenCV or ONNX Runtime built with CUDA, and the model runs on the CPU where it isn't available"
+    )]
+    pub device: Option<Device>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnBackend::Default,
+        help = "Backend OpenCV runs the detection model with"
+    )]
+    pub dnn_backend: DnnBackend,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnTarget::Cpu,
+        help = "Device OpenCV runs the detection model on"
+    )]
+    pub dnn_target: DnnTarget,
+    #[arg(
+        long,
+        help = "Run the detection model with TensorRT, which needs --inference-engine ort and mangatra built with the \"ort-tensorrt\" feature. The engines TensorRT builds from the model are kept in this directory, so only the first run spends minutes building them"
+    )]
+    pub tensorrt_cache: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = image_conversion::DEFAULT_MAX_PIXELS,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Largest page, in pixels, that is processed"
+    )]
+    pub max_pixels: u64,
+    #[arg(
+        long,
+        default_value_t = DEFAULT_MAX_DETECTIONS,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Most text regions processed on a page"
+    )]
+    pub max_detections: u32,
+    #[arg(
+        long,
+        value_delimiter = ',',
+        help = "Hosts that images given by URL may be downloaded from. A host starting with a dot also allows its subdomains, and s3://bucket or gs://bucket allows reading objects from that bucket"
+    )]
+    pub allowed_image_hosts: Vec<String>,
+    #[arg(
+        long,
+        default_value_t = fetch::DEFAULT_MAX_DOWNLOAD_BYTES,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Largest image, in bytes, that is downloaded from a URL"
+    )]
+    pub max_download_bytes: u64,
+}
+
+impl ServiceArgs {
+    pub fn config(self) -> Result<Config> {
+        validation::validate_model(&self.model)?;
+
+        let data_path = validation::validate_data(&self.data)?;
+        validation::validate_lang(&data_path, &self.lang)?;
+
+        // Jobs can be of any kind, so the font is loaded even though extraction doesn't need it
+        let font = match &self.font {
+            Some(font_path) => fonts::load_font(font_path)?,
+            None => fonts::default_font(&self.target_lang),
+        };
+
+        Ok(Config {
+            runtime_mode: RuntimeMode::Extraction,
+            clean: false,
+            text_files_path: String::new(),
+            input_files_path: String::new(),
+            output_path: String::new(),
+            cleaned_page_path: String::new(),
+            model_path: Config::path_into_string(PathType::Model(self.model))?,
+            tesseract_data_path: Config::path_into_string(PathType::Data(data_path))?,
+            lang: self.lang,
+            vocabulary: Vocabulary::default(),
+            target_lang: self.target_lang,
+            font,
+            font_store: match &self.font_dir {
+                Some(font_dir) => Some(FontStore::open(font_dir)?),
+                None => None,
+            },
+            honorifics: HonorificPolicy::Keep,
+            style: StylePreset::default(),
+            padding: self.padding,
+            inner_margin: self.inner_margin,
+            uniform_font_size: self.uniform_font_size,
+            greedy_wrapping: self.greedy_wrapping,
+            color_aware: self.color_aware,
+            expansion: Expansion {
+                tolerance: 0,
+                max_distance: None,
+            },
+            enhance: Vec::new(),
+            deskew_page: false,
+            deskew: false,
+            skip_empty_regions: false,
+            min_confidence: 0,
+            link_distance: None,
+            input_mode: InputMode::Image,
+            single: false,
+            threads: self.threads.map(usize::from),
+            opencv_threads: 1,
+            batch_size: 1,
+            deterministic: false,
+            dnn: DnnOptions::new(
+                self.inference_engine,
+                self.device,
+                self.dnn_backend,
+                self.dnn_target,
+                self.tensorrt_cache.clone(),
+            ),
+            max_pixels: self.max_pixels,
+            max_detections: self.max_detections as usize,
+            fetch_policy: FetchPolicy {
+                allowed_hosts: self.allowed_image_hosts,
+                max_bytes: self.max_download_bytes,
+            },
+            skip_duplicates: false,
+            skip_empty_text_files: false,
+            resume: false,
+            mokuro: false,
+            xliff: false,
+            export_table: None,
+            export_annotations: None,
+            annotation_min_score: 0,
+            review_path: None,
+            review_threshold: 60,
+            dump_regions_path: None,
+            project_path: None,
+            summary_path: None,
+            timings: false,
+            cache_path: None,
+            credits: None,
+            hooks: Hooks::default(),
+            staging: Staging::default(),
+        })
+    }
 }
 
 #[derive(PartialEq, Debug, Clone, Copy)]
@@ -102,30 +782,59 @@ impl std::fmt::Display for PathType {
 
 impl Config {
     #[instrument(name = "config_parse")]
-    pub fn parse() -> Result<Config> {
+    pub fn parse() -> Result<Invocation> {
+        let mut cli = Cli::parse();
+
+        match cli.command.take() {
+            Some(command) => Ok(Invocation::Command(command)),
+            None => Ok(Invocation::Run(Box::new(Self::from_cli(cli)?))),
+        }
+    }
+
+    fn from_cli(mut cli: Cli) -> Result<Config> {
         // Default values for text and padding
         let mut text: Option<PathBuf> = None;
         let mut padding: u16 = 10;
 
-        let cli = Cli::parse();
+        // Clap only lets these be missing when a subcommand is given
+        let (input, model, lang) = match (cli.input, cli.model, cli.lang) {
+            (Some(input), Some(model), Some(lang)) => (input, model, lang),
+            _ => bail!("The input, model and language have to be given to process pages."),
+        };
+
+        // Paths can also be s3:// or gs:// URIs. Those are downloaded, or written locally and
+        // uploaded when the run is done, since the rest of the pipeline works on files.
+        let mut staging = Staging::default();
+        let input = staging.input(input, "input")?;
+        cli.text = cli
+            .text
+            .map(|text| staging.input(text, "text"))
+            .transpose()?;
+        cli.output = cli
+            .output
+            .map(|output| staging.output(output))
+            .transpose()?;
 
         let runtime_mode = match cli.text.is_none() {
             true => RuntimeMode::Extraction,
             false => RuntimeMode::Replacement,
         };
         let clean = cli.text.is_none() && cli.clean;
+        // Exports only apply to extraction, the same as cleaned pages
+        let extraction_mode = cli.text.is_none();
 
         // Determining input type (directory or single image)
-        let input_mode = Self::get_input_mode(&cli.input)?;
+        let input_mode = Self::get_input_mode(&input)?;
 
         // If supplied an output path, check to see if it's the same type as the input
         // Otherwise use a default path based on whether running normally or in extract mode
-        let output = Self::get_output_path(&cli.input, &cli.output, runtime_mode, input_mode)?;
+        let output = Self::get_output_path(&input, &cli.output, runtime_mode, input_mode)?;
 
         // Make sure the model file is in the ONNX format
-        validation::validate_model(&cli.model)?;
+        validation::validate_model(&model)?;
 
         let data_path = validation::validate_data(&cli.data)?;
+        validation::validate_lang(&data_path, &lang)?;
 
         // If in replace mode, make sure the text file is a JSON
         if let RuntimeMode::Replacement = runtime_mode {
@@ -142,10 +851,68 @@ impl Config {
             padding = custom_padding;
         }
 
+        // Only replacement typesets anything, so only it looks for a font
+        let font = match (&cli.font, extraction_mode) {
+            (_, true) => fonts::bundled_font(),
+            (Some(font_path), false) => fonts::load_font(font_path)?,
+            (None, false) => fonts::default_font(&cli.target_lang),
+        };
+
+        // Credits only go on translated pages
+        let credits = match (&cli.credits, extraction_mode) {
+            (Some(credits_path), false) => {
+                Some(Credits::load(credits_path, cli.credits_placement)?)
+            }
+            _ => None,
+        };
+
+        let style = match &cli.style {
+            Some(style_path) => StylePreset::load(style_path)?,
+            None => StylePreset::default(),
+        };
+
+        // Pages of a single image belong to the project of the directory it is in
+        let project_path = match input_mode {
+            InputMode::Directory => Project::locate(&input),
+            InputMode::Image => match input.parent() {
+                Some(parent) if !parent.as_os_str().is_empty() => Project::locate(parent),
+                _ => Project::locate(Path::new(".")),
+            },
+        };
+
+        // Tesseract reads the project's vocabulary from copies of the languages next to the project file
+        let vocabulary = match &project_path {
+            Some(project_path) => Project::load(project_path)?.vocabulary,
+            None => Vocabulary::default(),
+        };
+        let data_path = match (&project_path, vocabulary.is_empty()) {
+            (Some(project_path), false) => vocabulary.prepare_tessdata(
+                &data_path,
+                &lang,
+                &project_path.with_file_name(vocabulary::TESSDATA_DIRECTORY),
+            )?,
+            _ => data_path,
+        };
+
+        let mut dnn = DnnOptions::new(
+            cli.inference_engine,
+            cli.device,
+            cli.dnn_backend,
+            cli.dnn_target,
+            cli.tensorrt_cache.clone(),
+        );
+        if cli.deterministic && dnn.backend == DnnBackend::Default {
+            dnn.backend = DnnBackend::Opencv;
+        }
+        ensure!(
+            !cli.deterministic || dnn.is_reproducible(),
+            "--deterministic runs the model on the CPU with OpenCV's own backend, as the other engines, backends and devices can detect slightly different boxes on every run. Leave out --inference-engine, --dnn-backend and --dnn-target, and --device unless it is cpu."
+        );
+
         let mut clean_page_path = None;
         if clean {
             clean_page_path = Some(Self::get_cleaned_page_path(
-                &cli.input,
+                &input,
                 &cli.output,
                 input_mode,
             )?)
@@ -155,15 +922,68 @@ impl Config {
             runtime_mode,
             clean,
             text_files_path: Self::path_into_string(PathType::Text(text))?,
-            input_files_path: Self::path_into_string(PathType::Input(cli.input))?,
+            input_files_path: Self::path_into_string(PathType::Input(input))?,
             output_path: Self::path_into_string(PathType::Output(output))?,
             cleaned_page_path: Self::path_into_string(PathType::CleanedPage(clean_page_path))?,
-            model_path: Self::path_into_string(PathType::Model(cli.model))?,
+            model_path: Self::path_into_string(PathType::Model(model))?,
             tesseract_data_path: Self::path_into_string(PathType::Data(data_path))?,
-            lang: cli.lang,
+            lang,
+            vocabulary,
+            target_lang: cli.target_lang,
+            font,
+            font_store: None,
+            honorifics: cli.honorifics,
+            style,
             padding,
+            inner_margin: cli.inner_margin,
+            uniform_font_size: cli.uniform_font_size,
+            greedy_wrapping: cli.greedy_wrapping,
+            color_aware: cli.color_aware,
+            expansion: Expansion {
+                tolerance: cli.expand_tolerance,
+                max_distance: cli.max_expansion,
+            },
+            enhance: cli.enhance,
+            deskew_page: cli.deskew_page,
+            deskew: cli.deskew,
+            skip_empty_regions: cli.skip_empty_regions,
+            min_confidence: cli.min_confidence,
+            link_distance: cli.link_distance.filter(|_| extraction_mode),
             input_mode,
             single: cli.single,
+            threads: cli.threads.map(usize::from),
+            opencv_threads: match (cli.opencv_threads, cli.single) {
+                (Some(threads), _) => threads as i32,
+                (None, true) if !cli.deterministic => -1,
+                (None, _) => 1,
+            },
+            batch_size: cli.batch_size as usize,
+            deterministic: cli.deterministic,
+            dnn,
+            max_pixels: cli.max_pixels,
+            max_detections: cli.max_detections as usize,
+            fetch_policy: FetchPolicy {
+                allowed_hosts: cli.allowed_image_hosts,
+                max_bytes: cli.max_download_bytes,
+            },
+            skip_duplicates: cli.skip_duplicates,
+            skip_empty_text_files: cli.skip_empty_text_files,
+            resume: cli.resume,
+            mokuro: extraction_mode && cli.mokuro,
+            xliff: extraction_mode && cli.xliff,
+            export_table: cli.export_table.filter(|_| extraction_mode),
+            export_annotations: cli.export_annotations.filter(|_| extraction_mode),
+            annotation_min_score: cli.annotation_min_score,
+            review_path: cli.review.filter(|_| extraction_mode),
+            review_threshold: cli.review_threshold,
+            dump_regions_path: cli.dump_regions.filter(|_| extraction_mode),
+            project_path,
+            summary_path: cli.summary,
+            timings: cli.timings,
+            cache_path: cli.cache,
+            credits,
+            hooks: Hooks::default(),
+            staging,
         })
     }
 
@@ -0,0 +1,263 @@
+use crate::utils::image_conversion;
+use anyhow::{Context, Result};
+use image::imageops::{self, FilterType};
+use image::{Rgb, RgbImage, RgbaImage};
+use imageproc::drawing;
+use rusttype::{Font, Scale};
+use std::path::{Path, PathBuf};
+
+// Where the credits of a release go
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum CreditsPlacement {
+    // A page of their own after the last page
+    #[default]
+    Append,
+    // Stamped into the bottom right corner of every page, like a watermark
+    Overlay,
+}
+
+#[derive(Clone, Debug)]
+enum CreditsSource {
+    Image(RgbaImage),
+    // Lines of a text template: the group name followed by the staff list
+    Text(Vec<String>),
+}
+
+// Credits of the translation group, added to the translated pages of a chapter
+#[derive(Clone, Debug)]
+pub struct Credits {
+    source: CreditsSource,
+    pub placement: CreditsPlacement,
+}
+
+impl Credits {
+    // Reads the credits from an image, or from a text file (.txt) with one line per credit
+    pub fn load(path: &Path, placement: CreditsPlacement) -> Result<Credits> {
+        let is_text = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .is_some_and(|extension| extension.eq_ignore_ascii_case("txt"));
+
+        let source = match is_text {
+            true => CreditsSource::Text(
+                std::fs::read_to_string(path)
+                    .with_context(|| format!("Could not read the credits {}.", path.display()))?
+                    .lines()
+                    .map(|line| line.trim().to_string())
+                    .collect(),
+            ),
+            false => CreditsSource::Image(
+                image_conversion::open_image(path)
+                    .with_context(|| format!("Could not read the credits {}.", path.display()))?
+                    .to_rgba8(),
+            ),
+        };
+
+        Ok(Credits { source, placement })
+    }
+
+    // A page of the given size with the credits centered on white paper
+    pub fn render_page(&self, width: u32, height: u32, font: &Font) -> RgbImage {
+        let mut page = RgbImage::from_pixel(width, height, Rgb([255, 255, 255]));
+
+        match &self.source {
+            CreditsSource::Image(image) => {
+                // Leave a margin around the image and never enlarge it beyond its own size
+                let image = fit(image, width * 9 / 10, height * 9 / 10, false);
+
+                blend(
+                    &mut page,
+                    &image,
+                    (width - image.width()) / 2,
+                    (height - image.height()) / 2,
+                );
+            }
+            CreditsSource::Text(lines) => {
+                // The group name on the first line is set larger than the staff list
+                let line_scale = |index: usize| match index {
+                    0 => Scale::uniform(height as f32 / 14.0),
+                    _ => Scale::uniform(height as f32 / 28.0),
+                };
+
+                let text_height: i32 = (0..lines.len())
+                    .map(|index| (line_scale(index).y * 1.4) as i32)
+                    .sum();
+                let mut start_y = (height as i32 - text_height) / 2;
+
+                for (index, line) in lines.iter().enumerate() {
+                    let scale = line_scale(index);
+                    let (line_width, _) = drawing::text_size(scale, font, line);
+
+                    drawing::draw_text_mut(
+                        &mut page,
+                        Rgb([0, 0, 0]),
+                        (width as i32 - line_width) / 2,
+                        start_y,
+                        scale,
+                        font,
+                        line,
+                    );
+
+                    start_y += (scale.y * 1.4) as i32;
+                }
+            }
+        }
+
+        page
+    }
+
+    // Stamps the credits into the bottom right corner of a page, at a quarter of its width
+    pub fn overlay(&self, page: &mut RgbImage, font: &Font) {
+        let (width, height) = page.dimensions();
+        let margin = width / 40;
+
+        let stamp = match &self.source {
+            CreditsSource::Image(image) => fit(image, width / 4, height / 4, true),
+            CreditsSource::Text(lines) => {
+                let scale = Scale::uniform((height as f32 / 60.0).max(8.0));
+                let line_height = (scale.y * 1.2) as i32;
+
+                let stamp_width = lines
+                    .iter()
+                    .map(|line| drawing::text_size(scale, font, line).0)
+                    .max()
+                    .unwrap_or(0)
+                    .max(1);
+                let stamp_height = (line_height * lines.len() as i32).max(1);
+
+                // Gray and transparent everywhere but the text, so it reads on light and dark art
+                let mut stamp = RgbaImage::new(stamp_width as u32, stamp_height as u32);
+
+                for (index, line) in lines.iter().enumerate() {
+                    let line_width = drawing::text_size(scale, font, line).0;
+
+                    drawing::draw_text_mut(
+                        &mut stamp,
+                        image::Rgba([96, 96, 96, 200]),
+                        stamp_width - line_width,
+                        index as i32 * line_height,
+                        scale,
+                        font,
+                        line,
+                    );
+                }
+
+                stamp
+            }
+        };
+
+        if stamp.width() + margin > width || stamp.height() + margin > height {
+            return;
+        }
+
+        blend(
+            page,
+            &stamp,
+            width - stamp.width() - margin,
+            height - stamp.height() - margin,
+        );
+    }
+}
+
+// Path of the appended credits page, named so it sorts after the output of the last page
+pub fn credits_page_path(output_directory: &Path, last_stem: &str) -> PathBuf {
+    output_directory.join(format!("{last_stem}_output_credits.png"))
+}
+
+// Scales an image down to fit the given size, keeping its aspect ratio. Smaller images are only
+// scaled up if "enlarge" is set.
+fn fit(image: &RgbaImage, max_width: u32, max_height: u32, enlarge: bool) -> RgbaImage {
+    let (width, height) = image.dimensions();
+
+    if !enlarge && width <= max_width && height <= max_height {
+        return image.clone();
+    }
+
+    let factor = (max_width as f32 / width as f32).min(max_height as f32 / height as f32);
+
+    imageops::resize(
+        image,
+        ((width as f32 * factor) as u32).max(1),
+        ((height as f32 * factor) as u32).max(1),
+        FilterType::Triangle,
+    )
+}
+
+// Draws an image with transparency onto a page at the given position
+fn blend(page: &mut RgbImage, image: &RgbaImage, x: u32, y: u32) {
+    for (image_x, image_y, pixel) in image.enumerate_pixels() {
+        let page_pixel = match page.get_pixel_mut_checked(x + image_x, y + image_y) {
+            Some(page_pixel) => page_pixel,
+            None => continue,
+        };
+
+        let alpha = pixel.0[3] as u32;
+
+        for channel in 0..3 {
+            page_pixel.0[channel] = ((pixel.0[channel] as u32 * alpha
+                + page_pixel.0[channel] as u32 * (255 - alpha))
+                / 255) as u8;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::credits::{credits_page_path, Credits, CreditsPlacement};
+    use crate::fonts::bundled_font;
+    use image::{Rgb, RgbImage, Rgba, RgbaImage};
+    use std::path::Path;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_image_credits() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("credits.png");
+        RgbaImage::from_pixel(40, 20, Rgba([255, 0, 0, 255]))
+            .save(&path)
+            .unwrap();
+
+        let credits = Credits::load(&path, CreditsPlacement::Append).unwrap();
+        let font = bundled_font();
+
+        // Appended credits are centered on their own page
+        let page = credits.render_page(100, 100, &font);
+        assert_eq!(*page.get_pixel(50, 50), Rgb([255, 0, 0]));
+        assert_eq!(*page.get_pixel(5, 5), Rgb([255, 255, 255]));
+
+        // Overlaid credits cover a quarter of the page's width in its bottom right corner
+        let mut page = RgbImage::from_pixel(400, 600, Rgb([0, 0, 255]));
+        credits.overlay(&mut page, &font);
+        assert_eq!(*page.get_pixel(360, 580), Rgb([255, 0, 0]));
+        assert_eq!(*page.get_pixel(200, 580), Rgb([0, 0, 255]));
+        assert_eq!(*page.get_pixel(399, 599), Rgb([0, 0, 255]));
+    }
+
+    #[test]
+    fn test_text_credits() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("credits.TXT");
+        std::fs::write(&path, "Scanlation Group\nTL: Someone\nTS: Someone else\n").unwrap();
+
+        let credits = Credits::load(&path, CreditsPlacement::Overlay).unwrap();
+        let font = bundled_font();
+
+        let page = credits.render_page(300, 400, &font);
+        assert!(page.pixels().any(|pixel| pixel.0 == [0, 0, 0]));
+
+        let mut page = RgbImage::from_pixel(600, 900, Rgb([255, 255, 255]));
+        credits.overlay(&mut page, &font);
+        assert!(page
+            .enumerate_pixels()
+            .any(|(x, y, pixel)| x > 300 && y > 800 && pixel.0 != [255, 255, 255]));
+        assert!(page
+            .enumerate_pixels()
+            .all(|(x, y, pixel)| (x > 300 && y > 800) || pixel.0 == [255, 255, 255]));
+
+        assert!(Credits::load(Path::new("missing.png"), CreditsPlacement::Append).is_err());
+        assert_eq!(
+            credits_page_path(Path::new("out"), "010"),
+            Path::new("out").join("010_output_credits.png")
+        );
+    }
+}
@@ -1,44 +1,205 @@
+use crate::onnx;
+pub use crate::text_box::{clamp_boxes, keep_most_confident, sanitize_boxes, TextBox};
+use crate::utils::cancellation::CancellationToken;
 use crate::utils::image_conversion;
-use anyhow::Result;
-use ndarray::{self as nd, Axis};
+use crate::yolo;
+pub use crate::yolo::{InputSize, OutputLayout, YoloVersion, DEFAULT_MAX_DETECTIONS, MIN_SCORE};
+use anyhow::{bail, ensure, Context, Result};
+use ndarray as nd;
 use opencv::{self as cv, core::Rect2i, core::ToInputArray, dnn, prelude::*};
+#[cfg(feature = "ort")]
+use ort::execution_providers::{
+    CPUExecutionProvider, CUDAExecutionProvider, ExecutionProviderDispatch,
+};
+#[cfg(feature = "ort-tensorrt")]
+use ort::execution_providers::{ExecutionProvider, TensorRTExecutionProvider};
+#[cfg(feature = "ort")]
+use ort::{
+    session::{builder::GraphOptimizationLevel, Session},
+    value::TensorRef,
+};
 use std::cmp::max;
-use tracing::instrument;
+use std::path::{Path, PathBuf};
+use tracing::{instrument, warn};
+
+// Runtime the detection model is run with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum InferenceEngine {
+    // OpenCV's DNN module, on the backend and target of "DnnOptions"
+    #[default]
+    Opencv,
+    // ONNX Runtime, which needs the "ort" feature. It loads exports that OpenCV can't and is
+    // usually faster on the CPU.
+    Ort,
+}
+
+// Implementation OpenCV runs the detection model with
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DnnBackend {
+    #[default]
+    Default,
+    Opencv,
+    Openvino,
+    Cuda,
+}
+
+// Device OpenCV runs the detection model on
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum DnnTarget {
+    #[default]
+    Cpu,
+    Opencl,
+    OpenclFp16,
+    Cuda,
+    CudaFp16,
+}
+
+// Device the detection model runs on, a shorthand for the backend and target that run it there
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum Device {
+    Cpu,
+    Cuda,
+    Opencl,
+}
+
+impl Device {
+    pub fn backend_and_target(self) -> (DnnBackend, DnnTarget) {
+        match self {
+            Device::Cpu => (DnnBackend::Default, DnnTarget::Cpu),
+            Device::Cuda => (DnnBackend::Cuda, DnnTarget::Cuda),
+            // OpenCL is a target of OpenCV's own backend
+            Device::Opencl => (DnnBackend::Opencv, DnnTarget::Opencl),
+        }
+    }
+}
 
-type Origin = (i32, i32);
-type TextRegions = cv::core::Vector<cv::core::Mat>;
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct DnnOptions {
+    pub engine: InferenceEngine,
+    // The backend only applies to OpenCV. ONNX Runtime runs the model on the GPU for the CUDA
+    // targets, on the CPU for the CPU target, and not at all with OpenCL.
+    pub backend: DnnBackend,
+    pub target: DnnTarget,
+    // Directory ONNX Runtime's TensorRT provider keeps the engines it builds from the model in, to
+    // run the model with TensorRT. Only applies to ONNX Runtime.
+    pub tensorrt_cache: Option<PathBuf>,
+}
+
+impl DnnOptions {
+    // Options for the arguments of a command, where a device replaces the backend and target
+    pub fn new(
+        engine: InferenceEngine,
+        device: Option<Device>,
+        backend: DnnBackend,
+        target: DnnTarget,
+        tensorrt_cache: Option<PathBuf>,
+    ) -> DnnOptions {
+        let (backend, target) = match device {
+            Some(device) => device.backend_and_target(),
+            None => (backend, target),
+        };
+
+        DnnOptions {
+            engine,
+            backend,
+            target,
+            tensorrt_cache,
+        }
+    }
+
+    // Whether the model gives the same detections on every run. OpenVINO, CUDA and OpenCL split
+    // their sums over threads or work groups in an order that can change between runs, which moves
+    // the scores and boxes in their last bits. The default backend is OpenVINO in builds of OpenCV
+    // that have it.
+    // ONNX Runtime splits its sums over threads as well.
+    pub fn is_reproducible(&self) -> bool {
+        self.engine == InferenceEngine::Opencv
+            && self.backend == DnnBackend::Opencv
+            && self.target == DnnTarget::Cpu
+    }
+}
+
+impl DnnBackend {
+    fn id(self) -> i32 {
+        match self {
+            DnnBackend::Default => dnn::DNN_BACKEND_DEFAULT,
+            DnnBackend::Opencv => dnn::DNN_BACKEND_OPENCV,
+            DnnBackend::Openvino => dnn::DNN_BACKEND_INFERENCE_ENGINE,
+            DnnBackend::Cuda => dnn::DNN_BACKEND_CUDA,
+        }
+    }
+}
+
+impl DnnTarget {
+    fn id(self) -> i32 {
+        match self {
+            DnnTarget::Cpu => dnn::DNN_TARGET_CPU,
+            DnnTarget::Opencl => dnn::DNN_TARGET_OPENCL,
+            DnnTarget::OpenclFp16 => dnn::DNN_TARGET_OPENCL_FP16,
+            DnnTarget::Cuda => dnn::DNN_TARGET_CUDA,
+            DnnTarget::CudaFp16 => dnn::DNN_TARGET_CUDA_FP16,
+        }
+    }
+}
+
+pub type Origin = (i32, i32);
+pub type TextRegions = cv::core::Vector<cv::core::Mat>;
+
+impl TextBox {
+    // Builds the boxes for regions returned by "run_inference"
+    pub fn from_regions(text_regions: &TextRegions, origins: &[Origin]) -> Vec<TextBox> {
+        text_regions
+            .iter()
+            .zip(origins)
+            .map(|(region, (x, y))| TextBox {
+                x: *x,
+                y: *y,
+                width: region.cols(),
+                height: region.rows(),
+                score: None,
+            })
+            .collect()
+    }
+
+    pub fn to_rect(self) -> Rect2i {
+        Rect2i::new(self.x, self.y, self.width, self.height)
+    }
+}
 
 struct Detections {
     pub boxes: cv::core::Vector<cv::core::Rect2i>,
+    // Confidence of each box from 0 to 1
+    pub scores: Vec<f32>,
+    // Number of boxes before non-maximum suppression merged the overlapping ones
+    pub num_candidates: usize,
 }
 
-pub struct Detector {
+// Runs the detection model over a blob of pages, of shape (pages, 3, height, width) for the input size
+// of the model, and returns its raw output
+trait InferenceBackend: Send {
+    fn forward(&mut self, blob: &cv::core::Mat) -> Result<nd::ArrayD<f32>>;
+}
+
+struct OpencvBackend {
     model: dnn::Net,
-    padding: u16,
 }
 
-impl Detector {
-    pub fn new(model_path: &str, padding: u16) -> Result<Detector> {
-        let model = dnn::read_net_from_onnx(model_path)?;
-        Ok(Detector { model, padding })
-    }
+impl OpencvBackend {
+    // OpenCV falls back to the CPU when the combination of backend and target isn't available
+    fn new(model_path: &str, options: DnnOptions) -> Result<OpencvBackend> {
+        let mut model = dnn::read_net_from_onnx(model_path)
+            .with_context(|| format!("Could not load the detection model at {model_path}."))?;
+        model.set_preferable_backend(options.backend.id())?;
+        model.set_preferable_target(options.target.id())?;
 
-    // Main detection function to extract text regions from image
-    #[instrument(name = "run_inference", skip(self, input_image))]
-    pub fn run_inference(&mut self, input_image: &str) -> Result<(TextRegions, Vec<Origin>)> {
-        let input: cv::core::Mat = Self::format_image(input_image)?;
-        let result: cv::core::Mat = dnn::blob_from_image(
-            &input.input_array()?,
-            1.0 / 255.0,
-            cv::core::Size2i::new(640, 640),
-            cv::core::Scalar::new(1.0, 1.0, 1.0, 1.0),
-            true,
-            false,
-            cv::core::CV_32F,
-        )?;
+        Ok(OpencvBackend { model })
+    }
+}
 
+impl InferenceBackend for OpencvBackend {
+    fn forward(&mut self, blob: &cv::core::Mat) -> Result<nd::ArrayD<f32>> {
         self.model
-            .set_input(&result, "", 1.0, cv::core::Scalar::new(1.0, 1.0, 1.0, 1.0))?;
+            .set_input(blob, "", 1.0, cv::core::Scalar::new(1.0, 1.0, 1.0, 1.0))?;
 
         let mut predictions: cv::core::Vector<cv::core::Mat> = cv::core::Vector::new();
 
@@ -47,86 +208,393 @@ impl Detector {
             &self.model.get_unconnected_out_layers_names()?,
         )?;
 
-        let data = predictions.get(0)?;
+        let output = predictions.get(0)?;
+        ensure!(
+            output.depth() == cv::core::CV_32F,
+            "The detection model's output must be 32-bit floats."
+        );
+        let shape: Vec<usize> = output
+            .mat_size()
+            .to_vec()
+            .into_iter()
+            .map(|size| size as usize)
+            .collect();
+
+        Ok(nd::ArrayViewD::from_shape(shape, output.data_typed::<f32>()?)?.to_owned())
+    }
+}
+
+#[cfg(feature = "ort")]
+struct OrtBackend {
+    session: Session,
+}
+
+#[cfg(feature = "ort")]
+impl OrtBackend {
+    // Like OpenCV, ONNX Runtime falls back to the CPU when CUDA isn't available, which it never is
+    // without the "ort-cuda" feature
+    fn new(model_path: &str, options: DnnOptions) -> Result<OrtBackend> {
+        #[cfg(feature = "ort-tensorrt")]
+        if let Some(cache_directory) = &options.tensorrt_cache {
+            if TensorRTExecutionProvider::default().is_available()? {
+                return Self::tensorrt(model_path, cache_directory, options.target);
+            }
+        }
+        // So one command line works on every machine
+        if options.tensorrt_cache.is_some() {
+            warn!("TensorRT isn't available, or mangatra was built without the \"ort-tensorrt\" feature, so the detection model is run without it.");
+        }
 
-        let output = nd::ArrayView3::from_shape((1, 25200, 10), data.data_typed::<f32>()?)?;
+        let execution_providers = match options.target {
+            DnnTarget::Cpu => vec![CPUExecutionProvider::default().build()],
+            DnnTarget::Cuda | DnnTarget::CudaFp16 => {
+                #[cfg(not(feature = "ort-cuda"))]
+                warn!("mangatra was built without the \"ort-cuda\" feature, so ONNX Runtime runs the detection model on the CPU.");
 
-        let detections = Self::get_detections(input, output.index_axis(Axis(0), 0))?;
+                vec![CUDAExecutionProvider::default().build()]
+            }
+            DnnTarget::Opencl | DnnTarget::OpenclFp16 => {
+                bail!("ONNX Runtime can't run the detection model with OpenCL. Run it on CUDA or the CPU, or with OpenCV.")
+            }
+        };
 
-        let boxes = detections.boxes;
+        Self::with_providers(model_path, execution_providers)
+    }
 
-        let original_image = image::open(input_image)?;
-        let original_image = image_conversion::image_buffer_to_mat(original_image.to_rgb8())?;
-        /*
-            for i in 0..boxes.len() {
-                let classid = class_ids[i];
-                let confidence = confidences[i];
-                let bbox = boxes.get(i)?;
+    // Runs the model with TensorRT, which serializes the engines it builds from the model into
+    // "cache_directory" so that only the first run spends minutes building them. Layers TensorRT
+    // can't run are left to CUDA.
+    #[cfg(feature = "ort-tensorrt")]
+    fn tensorrt(model_path: &str, cache_directory: &Path, target: DnnTarget) -> Result<OrtBackend> {
+        std::fs::create_dir_all(cache_directory).with_context(|| {
+            format!(
+                "Could not create the TensorRT cache {}.",
+                cache_directory.display()
+            )
+        })?;
+
+        let tensorrt = TensorRTExecutionProvider::default()
+            .with_engine_cache(true)
+            .with_engine_cache_path(cache_directory.display())
+            .with_fp16(target == DnnTarget::CudaFp16)
+            .build();
+
+        Self::with_providers(
+            model_path,
+            vec![tensorrt, CUDAExecutionProvider::default().build()],
+        )
+    }
+
+    fn with_providers(
+        model_path: &str,
+        execution_providers: Vec<ExecutionProviderDispatch>,
+    ) -> Result<OrtBackend> {
+        let session = Session::builder()?
+            .with_optimization_level(GraphOptimizationLevel::Level3)?
+            .with_execution_providers(execution_providers)?
+            .commit_from_file(model_path)
+            .with_context(|| format!("Could not load the detection model at {model_path}."))?;
+
+        Ok(OrtBackend { session })
+    }
+}
+
+#[cfg(feature = "ort")]
+impl InferenceBackend for OrtBackend {
+    fn forward(&mut self, blob: &cv::core::Mat) -> Result<nd::ArrayD<f32>> {
+        let shape: Vec<usize> = blob
+            .mat_size()
+            .to_vec()
+            .into_iter()
+            .map(|size| size as usize)
+            .collect();
+        let input = TensorRef::from_array_view((shape, blob.data_typed::<f32>()?))?;
+
+        let outputs = self.session.run(ort::inputs![input])?;
+        let output = outputs
+            .values()
+            .next()
+            .context("The detection model has no outputs.")?;
+        let (shape, output) = output
+            .try_extract_tensor::<f32>()
+            .context("The detection model's output must be 32-bit floats.")?;
+        let shape: Vec<usize> = shape.iter().map(|&size| size as usize).collect();
+
+        Ok(nd::ArrayD::from_shape_vec(shape, output.to_vec())?)
+    }
+}
+pub struct Detector {
+    model: Box<dyn InferenceBackend>,
+    // Read from the model, so models trained at other resolutions than 640 x 640 work as well
+    input_size: InputSize,
+    padding: u16,
+}
 
-                cv::imgproc::rectangle(&mut original_image, bbox, cv::core::Scalar::from((255.0, 255.0, 0.0)), 2, cv::imgproc::LINE_8, 0)?;
+impl Detector {
+    pub fn new(model_path: &str, padding: u16, options: DnnOptions) -> Result<Detector> {
+        let input_size = Self::read_input_size(Path::new(model_path))?;
+        let model: Box<dyn InferenceBackend> = match options.engine {
+            InferenceEngine::Opencv => {
+                ensure!(
+                    options.tensorrt_cache.is_none(),
+                    "--tensorrt-cache needs --inference-engine ort, since TensorRT is run by ONNX Runtime."
+                );
+                Box::new(OpencvBackend::new(model_path, options)?)
             }
+            #[cfg(feature = "ort")]
+            InferenceEngine::Ort => Box::new(OrtBackend::new(model_path, options)?),
+            #[cfg(not(feature = "ort"))]
+            InferenceEngine::Ort => bail!(
+                "--inference-engine ort can't be used since mangatra was built without the \"ort\" feature."
+            ),
+        };
 
-            highgui::imshow("boxes", &original_image)?;
-            highgui::wait_key(2000)?;
-            highgui::destroy_all_windows()?;
-        */
+        Ok(Detector {
+            model,
+            input_size,
+            padding,
+        })
+    }
+
+    fn read_input_size(model_path: &Path) -> Result<InputSize> {
+        InputSize::from_dimensions(&onnx::read_input_shape(model_path)?)
+    }
+
+    pub fn padding(&self) -> u16 {
+        self.padding
+    }
+
+    pub fn input_size(&self) -> InputSize {
+        self.input_size
+    }
+
+    // Runs a blank image through the model and checks that the output matches the layout the
+    // detections are decoded from, so a wrong model fails at startup instead of in the middle of a batch
+    pub fn validate(&mut self) -> Result<()> {
+        self.validate_batch(1)
+    }
+
+    // Same as "validate" with a batch of blank images, for runs that detect several pages at once
+    pub fn validate_batch(&mut self, batch_size: usize) -> Result<()> {
+        let blank_images = (0..batch_size)
+            .map(|_| {
+                cv::core::Mat::new_rows_cols_with_default(
+                    self.input_size.height,
+                    self.input_size.width,
+                    cv::core::CV_8UC3,
+                    cv::core::Scalar::all(0.0),
+                )
+            })
+            .collect::<cv::Result<cv::core::Vector<cv::core::Mat>>>()?;
+
+        let output = self
+            .forward(&blank_images)
+            .with_context(|| match batch_size {
+                1 => "The detection model failed to run on a blank image.".to_string(),
+                _ => format!(
+                    "The detection model failed to run on a batch of {batch_size} blank images."
+                ),
+            })?;
+
+        OutputLayout::from_batch_shape(output.shape(), batch_size)?;
+
+        Ok(())
+    }
+
+    // Main detection function to extract text regions from an image on disk
+    pub fn run_inference(
+        &mut self,
+        input_image: &str,
+        cancellation_token: &CancellationToken,
+    ) -> Result<(TextRegions, Vec<Origin>)> {
+        let original_image = image_conversion::open_image(input_image)?;
+        let original_image = image_conversion::dynamic_image_to_mat(&original_image)?;
+
+        self.run_inference_on_mat(&original_image, cancellation_token)
+    }
+
+    // Same as "run_inference" but for an image that has already been decoded into a Mat
+    pub fn run_inference_on_mat(
+        &mut self,
+        original_image: &cv::core::Mat,
+        cancellation_token: &CancellationToken,
+    ) -> Result<(TextRegions, Vec<Origin>)> {
+        let boxes = self.detect(original_image, cancellation_token)?;
+
+        Self::crop_regions(original_image, &boxes, cancellation_token)
+    }
+
+    // Runs the model over an image and returns the padded boxes of every text region
+    #[instrument(name = "detect", skip(self, original_image, cancellation_token))]
+    pub fn detect(
+        &mut self,
+        original_image: &cv::core::Mat,
+        cancellation_token: &CancellationToken,
+    ) -> Result<Vec<TextBox>> {
+        let (boxes, _) = self.detect_with_candidates(original_image, cancellation_token)?;
+
+        Ok(boxes)
+    }
+
+    // Same as "detect", but also returns the number of candidate boxes the model predicted before
+    // the overlapping ones were suppressed
+    pub fn detect_with_candidates(
+        &mut self,
+        original_image: &cv::core::Mat,
+        cancellation_token: &CancellationToken,
+    ) -> Result<(Vec<TextBox>, usize)> {
+        let mut pages = self.detect_batch_with_candidates(&[original_image], cancellation_token)?;
+
+        Ok(pages.remove(0))
+    }
+
+    // Same as "detect" for several pages, which run through the model in a single forward pass. A GPU
+    // is kept far busier that way than with one page at a time, but the model has to be exported with
+    // a dynamic batch size.
+    #[instrument(name = "detect_batch", skip_all, fields(pages = original_images.len()))]
+    pub fn detect_batch(
+        &mut self,
+        original_images: &[&cv::core::Mat],
+        cancellation_token: &CancellationToken,
+    ) -> Result<Vec<Vec<TextBox>>> {
+        let pages = self.detect_batch_with_candidates(original_images, cancellation_token)?;
+
+        Ok(pages.into_iter().map(|(boxes, _)| boxes).collect())
+    }
+
+    // Same as "detect_batch", but also returns the number of candidate boxes of each page
+    pub fn detect_batch_with_candidates(
+        &mut self,
+        original_images: &[&cv::core::Mat],
+        cancellation_token: &CancellationToken,
+    ) -> Result<Vec<(Vec<TextBox>, usize)>> {
+        cancellation_token.check()?;
+
+        if original_images.is_empty() {
+            return Ok(Vec::new());
+        }
+
+        let inputs = original_images
+            .iter()
+            .map(|original_image| Self::format_image(original_image))
+            .collect::<Result<cv::core::Vector<cv::core::Mat>>>()?;
+
+        let output = self.forward(&inputs)?;
+
+        // The forward pass itself can't be interrupted, so check again once it returns
+        cancellation_token.check()?;
+
+        let layout = OutputLayout::from_batch_shape(output.shape(), original_images.len())?;
+        let output = output.into_dimensionality::<nd::Ix3>()?;
+
+        original_images
+            .iter()
+            .zip(output.outer_iter())
+            .map(|(original_image, page_output)| {
+                let width = original_image.cols();
+                let height = original_image.rows();
+
+                // Predictions are in the coordinates of the page padded to a square
+                let side = max(width, height);
+                let detections =
+                    Self::get_detections((side, side), layout, self.input_size, page_output)?;
+
+                let Detections {
+                    boxes,
+                    scores,
+                    num_candidates,
+                } = detections;
+                /*
+                    for i in 0..boxes.len() {
+                        let classid = class_ids[i];
+                        let confidence = confidences[i];
+                        let bbox = boxes.get(i)?;
+
+                        cv::imgproc::rectangle(&mut original_image, bbox, cv::core::Scalar::from((255.0, 255.0, 0.0)), 2, cv::imgproc::LINE_8, 0)?;
+                    }
+
+                    highgui::imshow("boxes", &original_image)?;
+                    highgui::wait_key(2000)?;
+                    highgui::destroy_all_windows()?;
+                */
+                let padded_boxes = boxes
+                    .into_iter()
+                    .zip(scores)
+                    .map(|(bbox, score)| {
+                        TextBox {
+                            x: bbox.x,
+                            y: bbox.y,
+                            width: bbox.width,
+                            height: bbox.height,
+                            score: Some(yolo::score_percent(score)),
+                        }
+                        .padded(self.padding, width, height)
+                    })
+                    .collect::<Vec<TextBox>>();
+
+                // Padding can push boxes into each other, and a region within another would be
+                // read and cleaned twice
+                Ok((sanitize_boxes(&padded_boxes, width, height), num_candidates))
+            })
+            .collect()
+    }
+
+    // Crops the given boxes out of the image, returning a region and its origin for every box in
+    // the same order. Boxes are cut down to the image, so boxes from older files can't fail the page.
+    pub fn crop_regions(
+        original_image: &cv::core::Mat,
+        boxes: &[TextBox],
+        cancellation_token: &CancellationToken,
+    ) -> Result<(TextRegions, Vec<Origin>)> {
         let mut text_regions: cv::core::Vector<cv::core::Mat> = cv::core::Vector::new();
         let mut origins: Vec<(i32, i32)> = Vec::new();
 
-        let width = original_image.cols();
-        let height = original_image.rows();
-
-        for bbox in boxes {
-            let mut x = bbox.x;
-            let mut y = bbox.y;
-            let mut bbox_width = bbox.width;
-            let mut bbox_height = bbox.height;
-
-            if (bbox.width + (self.padding as i32 * 2)) < width
-                && (bbox.height + (self.padding as i32 * 2)) < height
-                && (bbox.x - self.padding as i32 > 0)
-                && (bbox.y - self.padding as i32 > 0)
-            {
-                x = bbox.x - self.padding as i32;
-                y = bbox.y - self.padding as i32;
-                bbox_width = bbox.width + (self.padding as i32 * 2);
-                bbox_height = bbox.height + (self.padding as i32 * 2);
-            }
-
-            let padded_bbox: Rect2i = Rect2i::new(x, y, bbox_width, bbox_height);
+        for bbox in clamp_boxes(boxes, original_image.cols(), original_image.rows())? {
+            cancellation_token.check()?;
 
-            text_regions.push(cv::core::Mat::roi(&original_image, padded_bbox)?);
-            origins.push((x, y));
+            text_regions.push(cv::core::Mat::roi(original_image, bbox.to_rect())?);
+            origins.push((bbox.x, bbox.y));
         }
 
         Ok((text_regions, origins))
     }
 
-    // Helper function that pre-processes input image for the YoloV5 model
-    fn format_image(input_image: &str) -> Result<cv::core::Mat> {
-        let image = image::open(input_image)?;
+    // Runs the model over square images and returns the raw predictions, the first axis going over
+    // the images
+    fn forward(&mut self, inputs: &cv::core::Vector<cv::core::Mat>) -> Result<nd::ArrayD<f32>> {
+        let blob: cv::core::Mat = dnn::blob_from_images(
+            &inputs.input_array()?,
+            1.0 / 255.0,
+            cv::core::Size2i::new(self.input_size.width, self.input_size.height),
+            cv::core::Scalar::new(1.0, 1.0, 1.0, 1.0),
+            true,
+            false,
+            cv::core::CV_32F,
+        )?;
 
-        let image = image_conversion::image_buffer_to_mat(image.to_rgb8())?;
+        self.model.forward(&blob)
+    }
 
+    // Helper function that pre-processes input image for the model
+    fn format_image(image: &cv::core::Mat) -> Result<cv::core::Mat> {
         let cols: i32 = image.cols();
         let rows: i32 = image.rows();
 
         let max = max(cols, rows);
 
-        let padding: cv::core::Mat;
+        // Padded into a single new Mat. For large spreads every extra page-sized buffer counts.
+        let mut resized = cv::core::Mat::default();
 
-        let mut resized: cv::core::Mat =
-            cv::core::Mat::zeros(max, max, cv::core::CV_32F)?.to_mat()?;
-
-        if max == rows && max != cols {
-            padding = cv::core::Mat::zeros(rows, rows - cols, cv::core::CV_8UC3)?.to_mat()?;
-            cv::core::hconcat2(&image, &padding, &mut resized)?;
-        } else if max == cols && max != rows {
-            padding = cv::core::Mat::zeros(cols - rows, cols, cv::core::CV_8UC3)?.to_mat()?;
-            cv::core::vconcat2(&image, &padding, &mut resized)?;
-        } else {
-            resized = cv::core::Mat::copy(&image)?;
-        }
+        cv::core::copy_make_border(
+            image,
+            &mut resized,
+            0,
+            max - rows,
+            0,
+            max - cols,
+            cv::core::BORDER_CONSTANT,
+            cv::core::Scalar::all(0.0),
+        )?;
         /*
         highgui::imshow("resized", &resized)?;
         highgui::wait_key(2000)?;
@@ -138,76 +606,43 @@ impl Detector {
 
     // Function to get text regions from model output
     fn get_detections(
-        image: cv::core::Mat,
+        size: (i32, i32),
+        layout: OutputLayout,
+        input_size: InputSize,
         output_data: nd::ArrayView2<f32>,
     ) -> Result<Detections> {
-        let mut confidences: Vec<f32> = Vec::new();
-        let mut boxes: cv::core::Vector<Rect2i> = cv::core::Vector::new();
-
-        let img_height = image.rows();
-        let img_width = image.cols();
-
-        let x_factor: f32 = img_width as f32 / 640.0;
-        let y_factor: f32 = img_height as f32 / 640.0;
-
-        for i in 0..25200 {
-            let row = output_data.index_axis(Axis(0), i);
-            let confidence = row[[4]];
+        let candidates = yolo::candidates(layout, output_data, input_size, size);
 
-            if confidence >= 0.4 {
-                let classes_scores = row.to_vec();
-
-                let mut max_indx: cv::core::Point2i = cv::core::Point2i::new(0, 0);
-
-                cv::core::min_max_loc(
-                    &Self::convert_to_cv_f32vec(&classes_scores),
-                    None,
-                    None,
-                    None,
-                    Some(&mut max_indx),
-                    &cv::core::no_array(),
-                )?;
-
-                let class_id = max_indx.to_vec2()[1];
-
-                if classes_scores[class_id as usize] > 0.25 {
-                    confidences.push(confidence);
-
-                    let x: f32 = row[[0]];
-                    let y: f32 = row[[1]];
-                    let w: f32 = row[[2]];
-                    let h: f32 = row[[3]];
-
-                    let left: i32 = ((x - 0.5 * w) * x_factor) as i32;
-                    let top: i32 = ((y - 0.5 * h) * y_factor) as i32;
-                    let width: i32 = (w * x_factor) as i32;
-                    let height: i32 = (h * y_factor) as i32;
-
-                    boxes.push(cv::core::Rect2i::new(left, top, width, height));
-                }
-            }
-        }
+        let confidences: Vec<f32> = candidates.iter().map(|(_, score)| *score).collect();
+        let boxes: cv::core::Vector<Rect2i> = candidates
+            .iter()
+            .map(|(candidate, _)| candidate.to_rect())
+            .collect();
 
         let mut indices: cv::core::Vector<i32> = cv::core::Vector::new();
 
         dnn::nms_boxes(
             &boxes,
             &Self::convert_to_cv_f32vec(&confidences),
-            0.25,
-            0.45,
+            yolo::NMS_SCORE_THRESHOLD,
+            yolo::NMS_THRESHOLD,
             &mut indices,
             1.0,
             0,
         )?;
 
         let mut result_boxes: cv::core::Vector<Rect2i> = cv::core::Vector::new();
+        let mut result_scores: Vec<f32> = Vec::new();
 
         for i in indices {
             result_boxes.push(boxes.get(i as usize)?);
+            result_scores.push(confidences[i as usize]);
         }
 
         let detections = Detections {
             boxes: result_boxes,
+            scores: result_scores,
+            num_candidates: confidences.len(),
         };
 
         Ok(detections)
@@ -223,3 +658,72 @@ impl Detector {
         result
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use crate::detection::{Device, DnnBackend, DnnOptions, DnnTarget, InferenceEngine};
+
+    #[test]
+    fn test_reproducible_dnn() {
+        let options = |backend, target| DnnOptions {
+            engine: InferenceEngine::Opencv,
+            backend,
+            target,
+            tensorrt_cache: None,
+        };
+
+        assert!(options(DnnBackend::Opencv, DnnTarget::Cpu).is_reproducible());
+        // OpenCV may pick OpenVINO as its default backend
+        assert!(!options(DnnBackend::Default, DnnTarget::Cpu).is_reproducible());
+        assert!(!options(DnnBackend::Openvino, DnnTarget::Cpu).is_reproducible());
+        assert!(!options(DnnBackend::Opencv, DnnTarget::Opencl).is_reproducible());
+        assert!(!options(DnnBackend::Cuda, DnnTarget::Cuda).is_reproducible());
+        assert!(!DnnOptions {
+            engine: InferenceEngine::Ort,
+            ..options(DnnBackend::Opencv, DnnTarget::Cpu)
+        }
+        .is_reproducible());
+    }
+
+    #[test]
+    fn test_device() {
+        let engine = InferenceEngine::Opencv;
+        let cuda = DnnOptions::new(
+            engine,
+            Some(Device::Cuda),
+            DnnBackend::Default,
+            DnnTarget::Cpu,
+            None,
+        );
+        assert_eq!(
+            (cuda.backend, cuda.target),
+            (DnnBackend::Cuda, DnnTarget::Cuda)
+        );
+
+        let opencl = DnnOptions::new(
+            engine,
+            Some(Device::Opencl),
+            DnnBackend::Default,
+            DnnTarget::Cpu,
+            None,
+        );
+        assert_eq!(
+            (opencl.backend, opencl.target),
+            (DnnBackend::Opencv, DnnTarget::Opencl)
+        );
+
+        // Without a device, the backend and target are taken as given
+        let openvino = DnnOptions::new(engine, None, DnnBackend::Openvino, DnnTarget::Cpu, None);
+        assert_eq!(openvino.backend, DnnBackend::Openvino);
+        assert_eq!(
+            DnnOptions::new(
+                engine,
+                Some(Device::Cpu),
+                DnnBackend::Default,
+                DnnTarget::Cpu,
+                None
+            ),
+            DnnOptions::default()
+        );
+    }
+}
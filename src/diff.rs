@@ -0,0 +1,302 @@
+use crate::detection::TextBox;
+use crate::extraction::{self, Annotation, Extraction, Region};
+use anyhow::Result;
+use indexmap::IndexMap;
+use std::path::Path;
+
+// How a region differs between two revisions of a page, matched by region ID
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum RegionChange {
+    Added(Region),
+    Removed(Region),
+    Changed { old: Region, new: Region },
+}
+
+// Changed and removed regions in the order of the old revision, followed by the added ones
+pub fn diff(old: &Extraction, new: &Extraction) -> Vec<RegionChange> {
+    let mut new_regions: IndexMap<&str, &Region> = new
+        .regions
+        .iter()
+        .map(|region| (region.id.as_str(), region))
+        .collect();
+
+    let mut changes = Vec::new();
+
+    for old_region in old.regions.iter() {
+        match new_regions.shift_remove(old_region.id.as_str()) {
+            Some(new_region) if differs(old_region, new_region) => {
+                changes.push(RegionChange::Changed {
+                    old: old_region.clone(),
+                    new: new_region.clone(),
+                })
+            }
+            Some(_) => {}
+            None => changes.push(RegionChange::Removed(old_region.clone())),
+        }
+    }
+
+    changes.extend(
+        new_regions
+            .into_values()
+            .map(|region| RegionChange::Added(region.clone())),
+    );
+
+    changes
+}
+
+// Only what can be reviewed counts as a change; e.g. the OCR confidence is left out
+fn differs(old: &Region, new: &Region) -> bool {
+    old.text != new.text
+        || old.translation != new.translation
+        || old.text_box.map(TextBox::geometry) != new.text_box.map(TextBox::geometry)
+        || old.annotation != new.annotation
+}
+
+fn format_annotation(annotation: &Option<Annotation>) -> String {
+    match annotation {
+        Some(annotation) => {
+            let placement = format!("{:?}", annotation.placement).to_lowercase();
+
+            format!("{} [{placement}, {}%]", annotation.text, annotation.scale)
+        }
+        None => String::new(),
+    }
+}
+
+// Marks the characters that were removed with "[-...-]" and the inserted ones with "{+...+}",
+// based on the longest common subsequence of the two strings
+pub fn inline_diff(old: &str, new: &str) -> String {
+    let old: Vec<char> = old.chars().collect();
+    let new: Vec<char> = new.chars().collect();
+
+    // lengths[i][j] is the length of the longest common subsequence of old[i..] and new[j..]
+    let mut lengths = vec![vec![0usize; new.len() + 1]; old.len() + 1];
+
+    for i in (0..old.len()).rev() {
+        for j in (0..new.len()).rev() {
+            lengths[i][j] = match old[i] == new[j] {
+                true => lengths[i + 1][j + 1] + 1,
+                false => lengths[i + 1][j].max(lengths[i][j + 1]),
+            };
+        }
+    }
+
+    let mut output = String::new();
+    let (mut removed, mut inserted) = (String::new(), String::new());
+    let (mut i, mut j) = (0, 0);
+
+    while i < old.len() || j < new.len() {
+        if i < old.len() && j < new.len() && old[i] == new[j] {
+            flush_edits(&mut output, &mut removed, &mut inserted);
+            output.push(old[i]);
+            i += 1;
+            j += 1;
+        } else if j < new.len() && (i == old.len() || lengths[i][j + 1] >= lengths[i + 1][j]) {
+            inserted.push(new[j]);
+            j += 1;
+        } else {
+            removed.push(old[i]);
+            i += 1;
+        }
+    }
+
+    flush_edits(&mut output, &mut removed, &mut inserted);
+
+    output
+}
+
+fn flush_edits(output: &mut String, removed: &mut String, inserted: &mut String) {
+    if !removed.is_empty() {
+        output.push_str(&format!("[-{removed}-]"));
+        removed.clear();
+    }
+
+    if !inserted.is_empty() {
+        output.push_str(&format!("{{+{inserted}+}}"));
+        inserted.clear();
+    }
+}
+
+// Human readable listing of the changes with a summary line at the end
+pub fn report(changes: &[RegionChange]) -> String {
+    let mut report = String::new();
+    let (mut num_added, mut num_removed, mut num_changed) = (0, 0, 0);
+
+    for change in changes {
+        match change {
+            RegionChange::Added(region) => {
+                num_added += 1;
+                report.push_str(&format!("+ {}\n", region.id));
+                report.push_str(&format!("    text: {}\n", region.text));
+
+                if !region.translation.is_empty() {
+                    report.push_str(&format!("    translation: {}\n", region.translation));
+                }
+
+                if region.annotation.is_some() {
+                    report.push_str(&format!(
+                        "    annotation: {}\n",
+                        format_annotation(&region.annotation)
+                    ));
+                }
+            }
+            RegionChange::Removed(region) => {
+                num_removed += 1;
+                report.push_str(&format!("- {}\n", region.id));
+                report.push_str(&format!("    text: {}\n", region.text));
+
+                if !region.translation.is_empty() {
+                    report.push_str(&format!("    translation: {}\n", region.translation));
+                }
+
+                if region.annotation.is_some() {
+                    report.push_str(&format!(
+                        "    annotation: {}\n",
+                        format_annotation(&region.annotation)
+                    ));
+                }
+            }
+            RegionChange::Changed { old, new } => {
+                num_changed += 1;
+                report.push_str(&format!("~ {}\n", new.id));
+
+                if old.text != new.text {
+                    report.push_str(&format!(
+                        "    text: {}\n",
+                        inline_diff(&old.text, &new.text)
+                    ));
+                }
+
+                if old.translation != new.translation {
+                    report.push_str(&format!(
+                        "    translation: {}\n",
+                        inline_diff(&old.translation, &new.translation)
+                    ));
+                }
+
+                if old.annotation != new.annotation {
+                    report.push_str(&format!(
+                        "    annotation: {}\n",
+                        inline_diff(
+                            &format_annotation(&old.annotation),
+                            &format_annotation(&new.annotation)
+                        )
+                    ));
+                }
+
+                if old.text_box.map(TextBox::geometry) != new.text_box.map(TextBox::geometry) {
+                    let format_box = |region: &Region| match region.text_box {
+                        Some(text_box) => format!(
+                            "{},{},{},{}",
+                            text_box.x, text_box.y, text_box.width, text_box.height
+                        ),
+                        None => "none".to_string(),
+                    };
+
+                    report.push_str(&format!(
+                        "    box: {} -> {}\n",
+                        format_box(old),
+                        format_box(new)
+                    ));
+                }
+            }
+        }
+    }
+
+    report.push_str(&format!(
+        "{num_changed} changed, {num_added} added, {num_removed} removed\n"
+    ));
+
+    report
+}
+
+// Prints the differences between two revisions of a page's text file. Any format that can be used
+// for replacement is accepted, so e.g. an extraction JSON can be compared against a translated XLIFF file.
+pub fn print_diff(old_path: &Path, new_path: &Path) -> Result<()> {
+    // Legacy files get their region IDs from the page name, so both sides use the same one
+    let page = match old_path.file_stem() {
+        Some(file_stem) => file_stem.to_string_lossy().to_string(),
+        None => old_path.display().to_string(),
+    };
+
+    let old = extraction::read_text_file(old_path, &page)?;
+    let new = extraction::read_text_file(new_path, &page)?;
+
+    print!("{}", report(&diff(&old, &new)));
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detection::TextBox;
+    use crate::diff::{diff, inline_diff, report, RegionChange};
+    use crate::extraction::{Annotation, AnnotationPlacement, Extraction};
+
+    #[test]
+    fn test_inline_diff() {
+        assert_eq!(
+            inline_diff("こんにちは", "こんにちわ"),
+            "こんにち[-は-]{+わ+}"
+        );
+        assert_eq!(inline_diff("", "Hello"), "{+Hello+}");
+        assert_eq!(
+            inline_diff("Hello there", "Hi there!"),
+            "H[-ello-]{+i+} there{+!+}"
+        );
+        assert_eq!(inline_diff("same", "same"), "same");
+    }
+
+    #[test]
+    fn test_region_changes() {
+        let old = Extraction::new(
+            "001",
+            vec!["a".to_string(), "b".to_string(), "c".to_string()],
+        );
+
+        let mut new = old.clone();
+        new.regions[1].translation = "B".to_string();
+        new.regions[2].text_box = Some(TextBox {
+            x: 1,
+            y: 2,
+            width: 3,
+            height: 4,
+            score: None,
+        });
+        new.regions.remove(0);
+        new.regions
+            .push(Extraction::new("001", vec!["d".to_string(); 4]).regions[3].clone());
+
+        let changes = diff(&old, &new);
+
+        assert_eq!(changes.len(), 4);
+        assert_eq!(changes[0], RegionChange::Removed(old.regions[0].clone()));
+        assert!(matches!(&changes[3], RegionChange::Added(region) if region.id == "001-3"));
+
+        assert_eq!(
+            report(&changes),
+            "- 001-0\n    text: a\n\
+             ~ 001-1\n    translation: {+B+}\n\
+             ~ 001-2\n    box: none -> 1,2,3,4\n\
+             + 001-3\n    text: d\n\
+             2 changed, 1 added, 1 removed\n"
+        );
+
+        assert!(diff(&old, &old).is_empty());
+
+        let mut annotated = old.clone();
+        annotated.regions[0].annotation = Some(Annotation {
+            text: "TL note".to_string(),
+            placement: AnnotationPlacement::Below,
+            scale: 50,
+        });
+
+        assert_eq!(
+            report(&diff(&old, &annotated)),
+            "~ 001-0\n    annotation: {+TL note [below, 50%]+}\n\
+             1 changed, 0 added, 0 removed\n"
+        );
+
+        assert!(diff(&old, &old.clone().with_confidences(&[50, 60, 70])).is_empty());
+    }
+}
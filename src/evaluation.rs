@@ -0,0 +1,753 @@
+use crate::detection::{
+    Detector, Device, DnnBackend, DnnOptions, DnnTarget, InferenceEngine, TextBox,
+};
+use crate::extraction::{self, Extraction};
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::{image_conversion, validation};
+use anyhow::{bail, ensure, Context, Result};
+use image::GenericImageView;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+// Overlaps COCO averages the precision over for its headline mAP: 0.5, 0.55, ..., 0.95
+const COCO_IOU_THRESHOLDS: [f64; 10] = [0.5, 0.55, 0.6, 0.65, 0.7, 0.75, 0.8, 0.85, 0.9, 0.95];
+
+// A detection matched against the ground truth of its page
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Match {
+    pub score: u8,
+    pub true_positive: bool,
+}
+
+// Matches the detections of a page to its ground truth, most confident detection first. A detection
+// is a true positive if it overlaps a box that no more confident detection has claimed by at least
+// "min_iou", taking the box it overlaps most.
+pub fn match_page(predictions: &[TextBox], truth: &[TextBox], min_iou: f64) -> Vec<Match> {
+    let mut order: Vec<usize> = (0..predictions.len()).collect();
+    // Unscored boxes weren't found by the model and count as certain
+    order.sort_by_key(|index| std::cmp::Reverse(predictions[*index].score.unwrap_or(u8::MAX)));
+
+    let mut claimed = vec![false; truth.len()];
+
+    order
+        .into_iter()
+        .map(|index| {
+            let prediction = &predictions[index];
+
+            let best = truth
+                .iter()
+                .enumerate()
+                .filter(|(truth_index, _)| !claimed[*truth_index])
+                .map(|(truth_index, text_box)| (truth_index, prediction.iou(text_box)))
+                .filter(|(_, iou)| *iou >= min_iou)
+                .max_by(|(_, a), (_, b)| a.total_cmp(b));
+
+            if let Some((truth_index, _)) = best {
+                claimed[truth_index] = true;
+            }
+
+            Match {
+                score: prediction.score.unwrap_or(u8::MAX),
+                true_positive: best.is_some(),
+            }
+        })
+        .collect()
+}
+
+// Area under the precision-recall curve of matches over all pages, with the precision at each recall
+// taken as the best precision at that recall or above, as in PASCAL VOC and COCO
+pub fn average_precision(matches: &[Match], num_truth: usize) -> f64 {
+    if num_truth == 0 {
+        return 0.0;
+    }
+
+    let mut matches = matches.to_vec();
+    matches.sort_by_key(|detection| std::cmp::Reverse(detection.score));
+
+    let mut true_positives = 0;
+    let points: Vec<(f64, f64)> = matches
+        .iter()
+        .enumerate()
+        .map(|(index, detection)| {
+            if detection.true_positive {
+                true_positives += 1;
+            }
+
+            (
+                true_positives as f64 / num_truth as f64,
+                true_positives as f64 / (index + 1) as f64,
+            )
+        })
+        .collect();
+
+    let mut area = 0.0;
+    let mut best_precision: f64 = 0.0;
+    let mut previous_recall = points.last().map_or(0.0, |(recall, _)| *recall);
+
+    for (recall, precision) in points.iter().rev() {
+        area += (previous_recall - recall) * best_precision;
+        best_precision = best_precision.max(*precision);
+        previous_recall = *recall;
+    }
+
+    area + previous_recall * best_precision
+}
+
+// Results of the detection model over a set of labeled pages
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct DetectionMetrics {
+    pub pages: usize,
+    pub truth: usize,
+    pub predictions: usize,
+    // Matched at an overlap of at least 0.5
+    pub true_positives: usize,
+    pub ap50: f64,
+    // Mean of the average precision at overlaps from 0.5 to 0.95
+    pub map: f64,
+}
+
+impl DetectionMetrics {
+    // Evaluates the detections of every page, given as pairs of predicted and ground truth boxes
+    pub fn evaluate(pages: &[(Vec<TextBox>, Vec<TextBox>)]) -> DetectionMetrics {
+        let truth = pages.iter().map(|(_, truth)| truth.len()).sum();
+
+        let precisions: Vec<(f64, usize)> = COCO_IOU_THRESHOLDS
+            .iter()
+            .map(|min_iou| {
+                let matches: Vec<Match> = pages
+                    .iter()
+                    .flat_map(|(predictions, truth)| match_page(predictions, truth, *min_iou))
+                    .collect();
+
+                let true_positives = matches.iter().filter(|m| m.true_positive).count();
+
+                (average_precision(&matches, truth), true_positives)
+            })
+            .collect();
+
+        DetectionMetrics {
+            pages: pages.len(),
+            truth,
+            predictions: pages.iter().map(|(predictions, _)| predictions.len()).sum(),
+            true_positives: precisions[0].1,
+            ap50: precisions[0].0,
+            map: precisions.iter().map(|(ap, _)| ap).sum::<f64>() / precisions.len() as f64,
+        }
+    }
+
+    // Of all detections, the share that found text
+    pub fn precision(&self) -> f64 {
+        match self.predictions {
+            0 => 0.0,
+            predictions => self.true_positives as f64 / predictions as f64,
+        }
+    }
+
+    // Of all text, the share that was found
+    pub fn recall(&self) -> f64 {
+        match self.truth {
+            0 => 0.0,
+            truth => self.true_positives as f64 / truth as f64,
+        }
+    }
+
+    pub fn report(&self) -> String {
+        format!(
+            "{} pages, {} ground truth boxes, {} detections\n\
+             precision     {:.4}\n\
+             recall        {:.4}\n\
+             AP@0.5        {:.4}\n\
+             mAP@0.5:0.95  {:.4}\n",
+            self.pages,
+            self.truth,
+            self.predictions,
+            self.precision(),
+            self.recall(),
+            self.ap50,
+            self.map
+        )
+    }
+}
+
+// Number of insertions, deletions and substitutions that turn one sequence into the other
+pub fn edit_distance<T: PartialEq>(from: &[T], to: &[T]) -> usize {
+    // distances[j] is the distance from the part of "from" seen so far to to[..j]
+    let mut distances: Vec<usize> = (0..=to.len()).collect();
+
+    for (i, item) in from.iter().enumerate() {
+        let mut diagonal = distances[0];
+        distances[0] = i + 1;
+
+        for (j, other) in to.iter().enumerate() {
+            let substitution = diagonal + usize::from(item != other);
+            diagonal = distances[j + 1];
+            distances[j + 1] = substitution.min(distances[j] + 1).min(diagonal + 1);
+        }
+    }
+
+    distances[to.len()]
+}
+
+// Errors of OCR against the ground truth, counted in characters and in words
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ErrorCounts {
+    pub char_errors: usize,
+    pub chars: usize,
+    pub word_errors: usize,
+    pub words: usize,
+}
+
+impl ErrorCounts {
+    // Whitespace is left out of the characters, since tesseract puts spaces between Japanese
+    // characters that the transcripts don't have
+    pub fn compare(truth: &str, recognized: &str) -> ErrorCounts {
+        let chars = |text: &str| -> Vec<char> {
+            text.chars()
+                .filter(|character| !character.is_whitespace())
+                .collect()
+        };
+        let words =
+            |text: &str| -> Vec<String> { text.split_whitespace().map(str::to_string).collect() };
+
+        let (truth_chars, truth_words) = (chars(truth), words(truth));
+
+        ErrorCounts {
+            char_errors: edit_distance(&truth_chars, &chars(recognized)),
+            chars: truth_chars.len(),
+            word_errors: edit_distance(&truth_words, &words(recognized)),
+            words: truth_words.len(),
+        }
+    }
+
+    // Compares the regions of a page by ID. Regions missing from the OCR output count as deleted,
+    // and regions only in the OCR output, such as detections on artwork, count as inserted.
+    pub fn compare_pages(truth: &Extraction, recognized: &Extraction) -> ErrorCounts {
+        let mut recognized_text: HashMap<&str, &str> = recognized
+            .regions
+            .iter()
+            .map(|region| (region.id.as_str(), region.text.as_str()))
+            .collect();
+
+        let mut counts = ErrorCounts::default();
+
+        for region in truth.regions.iter() {
+            let text = recognized_text.remove(region.id.as_str()).unwrap_or("");
+            counts += ErrorCounts::compare(&region.text, text);
+        }
+
+        for region in recognized.regions.iter() {
+            if recognized_text.contains_key(region.id.as_str()) {
+                counts += ErrorCounts::compare("", &region.text);
+            }
+        }
+
+        counts
+    }
+
+    // Character error rate, from 0 upwards since OCR can insert more than there is
+    pub fn cer(&self) -> f64 {
+        error_rate(self.char_errors, self.chars)
+    }
+
+    // Word error rate
+    pub fn wer(&self) -> f64 {
+        error_rate(self.word_errors, self.words)
+    }
+}
+
+impl std::ops::AddAssign for ErrorCounts {
+    fn add_assign(&mut self, other: ErrorCounts) {
+        self.char_errors += other.char_errors;
+        self.chars += other.chars;
+        self.word_errors += other.word_errors;
+        self.words += other.words;
+    }
+}
+
+// Errors over the length of the ground truth. Any error in an empty transcript counts fully.
+fn error_rate(errors: usize, length: usize) -> f64 {
+    match length {
+        0 if errors == 0 => 0.0,
+        0 => 1.0,
+        length => errors as f64 / length as f64,
+    }
+}
+
+// Table of the error rates of every page, followed by the rates over all pages
+pub fn ocr_report(pages: &[(String, ErrorCounts)]) -> String {
+    let mut report = format!(
+        "{:<16} {:>8} {:>8} {:>8} {:>8}\n",
+        "page", "chars", "CER", "words", "WER"
+    );
+
+    let mut total = ErrorCounts::default();
+
+    for (page, counts) in pages {
+        total += *counts;
+        report.push_str(&ocr_row(page, counts));
+    }
+
+    report.push_str(&ocr_row("total", &total));
+
+    report
+}
+
+fn ocr_row(page: &str, counts: &ErrorCounts) -> String {
+    format!(
+        "{page:<16} {:>8} {:>7.2}% {:>8} {:>7.2}%\n",
+        counts.chars,
+        counts.cer() * 100.0,
+        counts.words,
+        counts.wer() * 100.0
+    )
+}
+
+// Boxes of a YOLO label file, with its coordinates relative to the page size
+pub fn read_yolo_labels(labels: &str, (width, height): (u32, u32)) -> Result<Vec<TextBox>> {
+    let (width, height) = (width as f64, height as f64);
+
+    labels
+        .lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty())
+        .map(|(index, line)| {
+            let values = line
+                .split_whitespace()
+                .skip(1)
+                .map(str::parse::<f64>)
+                .collect::<Result<Vec<f64>, _>>()
+                .ok()
+                .filter(|values| values.len() == 4);
+
+            let (x, y, box_width, box_height) = match values.as_deref() {
+                Some([x, y, box_width, box_height]) => (*x, *y, *box_width, *box_height),
+                _ => bail!("Line {} is not a YOLO label.", index + 1),
+            };
+
+            Ok(TextBox {
+                x: ((x - box_width / 2.0) * width).round() as i32,
+                y: ((y - box_height / 2.0) * height).round() as i32,
+                width: (box_width * width).round() as i32,
+                height: (box_height * height).round() as i32,
+                score: None,
+            })
+        })
+        .collect()
+}
+
+// Boxes of the rectangles in a LabelMe file
+pub fn read_labelme_labels(labels: &str) -> Result<Vec<TextBox>> {
+    let labels: serde_json::Value = serde_json::from_str(labels)?;
+
+    let shapes = match labels["shapes"].as_array() {
+        Some(shapes) => shapes,
+        None => bail!("The LabelMe file has no shapes."),
+    };
+
+    shapes
+        .iter()
+        .filter(|shape| shape["shape_type"] == "rectangle")
+        .map(|shape| {
+            let points: Option<Vec<f64>> = shape["points"].as_array().and_then(|points| {
+                points
+                    .iter()
+                    .flat_map(|point| [&point[0], &point[1]])
+                    .map(serde_json::Value::as_f64)
+                    .collect()
+            });
+
+            let (left, top, right, bottom) = match points.as_deref() {
+                Some([x1, y1, x2, y2]) => (x1.min(*x2), y1.min(*y2), x1.max(*x2), y1.max(*y2)),
+                _ => bail!("A rectangle of the LabelMe file doesn't have two points."),
+            };
+
+            Ok(TextBox {
+                x: left.round() as i32,
+                y: top.round() as i32,
+                width: (right - left).round() as i32,
+                height: (bottom - top).round() as i32,
+                score: None,
+            })
+        })
+        .collect()
+}
+
+#[derive(clap::Args, Debug)]
+pub struct EvalDetectArgs {
+    #[arg(
+        long,
+        help = "Directory of ground truth labels, a YOLO text file or a LabelMe JSON per page named after it"
+    )]
+    pub gt: PathBuf,
+    #[arg(long, help = "Directory of the labeled pages")]
+    pub images: PathBuf,
+    #[arg(
+        short,
+        long,
+        help = "Path to the YOLOv5, YOLOv8 or YOLOv11 detection weights (ONNX format)"
+    )]
+    pub model: PathBuf,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Padding added to the detected boxes before they are compared. Leave at 0 for labels drawn tightly around the text"
+    )]
+    pub padding: u16,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InferenceEngine::Opencv,
+        help = "Runtime the detection model is run with. ONNX Runtime (ort) loads models that OpenCV can't and is usually faster on the CPU, but needs mangatra built with the \"ort\" feature. --dnn-backend and --dnn-target only apply to OpenCV"
+    )]
+    pub inference_engine: InferenceEngine,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["dnn_backend", "dnn_target"],
+        help = "Device the detection model runs on, instead of picking --dnn-backend and --dnn-target. Running on CUDA needs OpenCV or ONNX Runtime built with CUDA, and the model runs on the CPU where it isn't available"
+    )]
+    pub device: Option<Device>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnBackend::Default,
+        help = "Backend OpenCV runs the detection model with"
+    )]
+    pub dnn_backend: DnnBackend,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnTarget::Cpu,
+        help = "Device OpenCV runs the detection model on"
+    )]
+    pub dnn_target: DnnTarget,
+    #[arg(
+        long,
+        help = "Run the detection model with TensorRT, which needs --inference-engine ort and mangatra built with the \"ort-tensorrt\" feature. The engines TensorRT builds from the model are kept in this directory, so only the first run spends minutes building them"
+    )]
+    pub tensorrt_cache: Option<PathBuf>,
+}
+
+impl EvalDetectArgs {
+    pub fn run(self) -> Result<()> {
+        validation::validate_model(&self.model)?;
+
+        let pages = validation::images_in_directory(&self.images)?;
+        ensure!(
+            !pages.is_empty(),
+            "There are no pages to evaluate in {}.",
+            self.images.display()
+        );
+
+        let model_path = match self.model.to_str() {
+            Some(model_path) => model_path,
+            None => bail!("The model path must be UTF-8 compatible."),
+        };
+
+        let mut detector = Detector::new(
+            model_path,
+            self.padding,
+            DnnOptions::new(
+                self.inference_engine,
+                self.device,
+                self.dnn_backend,
+                self.dnn_target,
+                self.tensorrt_cache.clone(),
+            ),
+        )?;
+        detector.validate()?;
+
+        let cancellation_token = CancellationToken::new();
+        let mut evaluated = Vec::new();
+
+        for page in pages.iter() {
+            let image = image_conversion::open_image(page)?;
+
+            let truth = match self
+                .read_labels(page, image.dimensions())
+                .with_context(|| format!("Could not read the labels of {}.", page.display()))?
+            {
+                Some(truth) => truth,
+                None => {
+                    warn!("Skipping {}: it has no labels.", page.display());
+                    continue;
+                }
+            };
+
+            let predictions = detector.detect(
+                &image_conversion::dynamic_image_to_mat(&image)?,
+                &cancellation_token,
+            )?;
+
+            evaluated.push((predictions, truth));
+        }
+
+        ensure!(
+            !evaluated.is_empty(),
+            "None of the pages in {} have labels in {}.",
+            self.images.display(),
+            self.gt.display()
+        );
+
+        print!("{}", DetectionMetrics::evaluate(&evaluated).report());
+
+        Ok(())
+    }
+
+    // Labels of a page from the YOLO or LabelMe file named after it, if there is one
+    fn read_labels(&self, page: &Path, size: (u32, u32)) -> Result<Option<Vec<TextBox>>> {
+        let stem = match page.file_stem() {
+            Some(stem) => stem.to_string_lossy(),
+            None => return Ok(None),
+        };
+
+        let yolo = self.gt.join(format!("{stem}.txt"));
+        if yolo.is_file() {
+            return read_yolo_labels(&std::fs::read_to_string(yolo)?, size).map(Some);
+        }
+
+        let labelme = self.gt.join(format!("{stem}.json"));
+        if labelme.is_file() {
+            return read_labelme_labels(&std::fs::read_to_string(labelme)?).map(Some);
+        }
+
+        Ok(None)
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct EvalOcrArgs {
+    #[arg(
+        long,
+        help = "Ground truth transcripts: a text file of a page or a directory of them, as JSON, XLIFF, CSV or TSV"
+    )]
+    pub gt: PathBuf,
+    #[arg(
+        long,
+        help = "OCR output to evaluate, as written by extraction: a text file or a directory of them named after the ground truth"
+    )]
+    pub ocr: PathBuf,
+}
+
+impl EvalOcrArgs {
+    pub fn run(self) -> Result<()> {
+        let pairs = match self.gt.is_dir() {
+            true => self.page_pairs()?,
+            false => vec![(self.gt.clone(), self.ocr.clone())],
+        };
+
+        let mut pages = Vec::new();
+
+        for (truth_path, ocr_path) in pairs {
+            // Legacy files get their region IDs from the page name, so both sides use the same one
+            let page = match truth_path.file_stem() {
+                Some(file_stem) => file_stem.to_string_lossy().to_string(),
+                None => truth_path.display().to_string(),
+            };
+
+            let truth = extraction::read_text_file(&truth_path, &page)
+                .with_context(|| format!("Could not read {}.", truth_path.display()))?;
+            let recognized = extraction::read_text_file(&ocr_path, &page)
+                .with_context(|| format!("Could not read {}.", ocr_path.display()))?;
+
+            pages.push((page, ErrorCounts::compare_pages(&truth, &recognized)));
+        }
+
+        print!("{}", ocr_report(&pages));
+
+        Ok(())
+    }
+
+    // Pairs every transcript in the ground truth directory with the OCR output of the same name
+    fn page_pairs(&self) -> Result<Vec<(PathBuf, PathBuf)>> {
+        ensure!(
+            self.ocr.is_dir(),
+            "The OCR output must be a directory when the ground truth is."
+        );
+
+        let text_files = |directory: &Path| -> Result<Vec<PathBuf>> {
+            let mut paths: Vec<PathBuf> = std::fs::read_dir(directory)
+                .with_context(|| format!("Could not read {}.", directory.display()))?
+                .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                .filter(|path| path.is_file() && validation::validate_text(path).is_ok())
+                .collect();
+            paths.sort();
+
+            Ok(paths)
+        };
+
+        let recognized = text_files(&self.ocr)?;
+        let mut pairs = Vec::new();
+
+        for truth in text_files(&self.gt)? {
+            match recognized
+                .iter()
+                .find(|path| path.file_stem() == truth.file_stem())
+            {
+                Some(path) => pairs.push((truth, path.clone())),
+                None => warn!(
+                    "Skipping {}: there is no OCR output for it.",
+                    truth.display()
+                ),
+            }
+        }
+
+        ensure!(
+            !pairs.is_empty(),
+            "None of the transcripts in {} have OCR output in {}.",
+            self.gt.display(),
+            self.ocr.display()
+        );
+
+        Ok(pairs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detection::TextBox;
+    use crate::evaluation::{
+        average_precision, edit_distance, match_page, ocr_report, read_labelme_labels,
+        read_yolo_labels, DetectionMetrics, ErrorCounts, Match,
+    };
+    use crate::extraction::Extraction;
+
+    fn text_box(x: i32, score: Option<u8>) -> TextBox {
+        TextBox {
+            x,
+            y: 0,
+            width: 100,
+            height: 50,
+            score,
+        }
+    }
+
+    #[test]
+    fn test_matching() {
+        assert_eq!(text_box(0, None).iou(&text_box(50, None)), 1.0 / 3.0);
+        assert_eq!(text_box(0, None).iou(&text_box(200, None)), 0.0);
+
+        let truth = [text_box(0, None), text_box(300, None)];
+        // The less confident duplicate of the first box comes first, but loses it to the more confident one
+        let predictions = [
+            text_box(5, Some(60)),
+            text_box(0, Some(90)),
+            text_box(600, Some(70)),
+        ];
+
+        let matches = match_page(&predictions, &truth, 0.5);
+        assert_eq!(
+            matches,
+            vec![
+                Match {
+                    score: 90,
+                    true_positive: true
+                },
+                Match {
+                    score: 70,
+                    true_positive: false
+                },
+                Match {
+                    score: 60,
+                    true_positive: false
+                },
+            ]
+        );
+
+        // Precision 1 up to a recall of 0.5, and nothing above it
+        assert_eq!(average_precision(&matches, 2), 0.5);
+        assert_eq!(average_precision(&matches, 0), 0.0);
+
+        let perfect = DetectionMetrics::evaluate(&[(truth.to_vec(), truth.to_vec())]);
+        assert_eq!(perfect.ap50, 1.0);
+        assert_eq!(perfect.map, 1.0);
+        assert_eq!((perfect.precision(), perfect.recall()), (1.0, 1.0));
+
+        let metrics = DetectionMetrics::evaluate(&[
+            (predictions.to_vec(), truth.to_vec()),
+            (Vec::new(), vec![text_box(0, None)]),
+        ]);
+        assert_eq!(metrics.true_positives, 1);
+        assert_eq!(metrics.precision(), 1.0 / 3.0);
+        assert_eq!(metrics.recall(), 1.0 / 3.0);
+        assert_eq!(
+            metrics.report().lines().next(),
+            Some("2 pages, 3 ground truth boxes, 3 detections")
+        );
+    }
+
+    #[test]
+    fn test_labels() {
+        assert_eq!(
+            read_yolo_labels("0 0.25 0.5 0.5 0.5\n\n", (200, 100)).unwrap(),
+            vec![TextBox {
+                x: 0,
+                y: 25,
+                width: 100,
+                height: 50,
+                score: None
+            }]
+        );
+        assert!(read_yolo_labels("0 0.25 0.5\n", (200, 100)).is_err());
+
+        let labelme = r#"{"shapes": [
+            {"shape_type": "rectangle", "points": [[100, 50], [0, 0]]},
+            {"shape_type": "polygon", "points": [[0, 0], [1, 1], [2, 0]]}
+        ]}"#;
+        assert_eq!(
+            read_labelme_labels(labelme).unwrap(),
+            vec![TextBox {
+                x: 0,
+                y: 0,
+                width: 100,
+                height: 50,
+                score: None
+            }]
+        );
+    }
+
+    #[test]
+    fn test_error_rates() {
+        assert_eq!(
+            edit_distance(
+                &['k', 'i', 't', 't', 'e', 'n'],
+                &['s', 'i', 't', 't', 'i', 'n', 'g']
+            ),
+            3
+        );
+        assert_eq!(edit_distance::<char>(&[], &['a', 'b']), 2);
+
+        // The spaces tesseract puts between Japanese characters don't count
+        let counts = ErrorCounts::compare("なにこれ", "な に こ");
+        assert_eq!((counts.char_errors, counts.chars), (1, 4));
+        assert_eq!(counts.cer(), 0.25);
+
+        let counts = ErrorCounts::compare("what is this", "what iz this thing");
+        assert_eq!((counts.word_errors, counts.words), (2, 3));
+
+        let truth = Extraction::new("001", vec!["なにこれ".to_string(), "はい".to_string()]);
+        let recognized = Extraction::new(
+            "001",
+            vec!["なにこれ".to_string(), String::new(), "ドン".to_string()],
+        );
+
+        // The missing text counts as deleted and the extra region as inserted
+        let counts = ErrorCounts::compare_pages(&truth, &recognized);
+        assert_eq!((counts.char_errors, counts.chars), (4, 6));
+
+        let report = ocr_report(&[
+            ("001".to_string(), counts),
+            ("002".to_string(), ErrorCounts::compare("はい", "はい")),
+        ]);
+        let lines: Vec<&str> = report.lines().collect();
+        assert_eq!(
+            lines[1],
+            "001                     6   66.67%        2  100.00%"
+        );
+        assert_eq!(
+            lines[3],
+            "total                   8   50.00%        3   66.67%"
+        );
+    }
+}
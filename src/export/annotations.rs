@@ -0,0 +1,262 @@
+use crate::export;
+use crate::extraction::{Extraction, PageSize};
+use crate::text_box::TextBox;
+use anyhow::{ensure, Result};
+use serde::Serialize;
+use serde_json::json;
+use std::path::Path;
+
+// Every detection has the same class since the model only finds text
+const CATEGORY_NAME: &str = "text";
+
+// Standard annotation formats for reviewing detections in labeling tools or building training data
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum AnnotationFormat {
+    // A single COCO JSON for the whole batch
+    Coco,
+    // A LabelMe JSON per page
+    Labelme,
+    // A YOLO label file per page plus the class list, next to copies of the page images
+    Yolo,
+}
+
+#[derive(Serialize)]
+struct CocoImage {
+    id: usize,
+    file_name: String,
+    width: u32,
+    height: u32,
+}
+
+#[derive(Serialize)]
+struct CocoAnnotation {
+    id: usize,
+    image_id: usize,
+    category_id: usize,
+    bbox: [i32; 4],
+    area: i32,
+    iscrowd: u8,
+}
+
+// Writes the detected regions of the pages into the given directory, leaving out the boxes the
+// model scored below "min_score". Each page is paired with the file name of its image, which is what
+// the annotations refer to.
+pub fn write_annotations(
+    format: AnnotationFormat,
+    directory: &Path,
+    pages: &[(Extraction, String)],
+    min_score: u8,
+) -> Result<()> {
+    ensure!(
+        !pages.is_empty(),
+        "There are no pages to export annotations for."
+    );
+
+    std::fs::create_dir_all(directory)?;
+
+    match format {
+        AnnotationFormat::Coco => {
+            std::fs::write(
+                directory.join("coco.json"),
+                serde_json::to_string_pretty(&coco(pages, min_score)?)?,
+            )?;
+        }
+        AnnotationFormat::Labelme => {
+            for (extraction, file_name) in pages {
+                std::fs::write(
+                    directory.join(format!("{}.json", extraction.page)),
+                    serde_json::to_string_pretty(&labelme(extraction, file_name, min_score)?)?,
+                )?;
+            }
+        }
+        AnnotationFormat::Yolo => {
+            std::fs::write(directory.join("classes.txt"), format!("{CATEGORY_NAME}\n"))?;
+
+            for (extraction, _) in pages {
+                std::fs::write(
+                    directory.join(format!("{}.txt", extraction.page)),
+                    yolo(extraction, min_score)?,
+                )?;
+            }
+        }
+    }
+
+    Ok(())
+}
+
+fn coco(pages: &[(Extraction, String)], min_score: u8) -> Result<serde_json::Value> {
+    let mut images = Vec::new();
+    let mut annotations = Vec::new();
+
+    // COCO IDs start at 1
+    for (image_id, (extraction, file_name)) in (1..).zip(pages) {
+        let size = export::page_size(extraction, "COCO")?;
+
+        images.push(CocoImage {
+            id: image_id,
+            file_name: file_name.clone(),
+            width: size.width,
+            height: size.height,
+        });
+
+        for text_box in boxes(extraction, min_score) {
+            annotations.push(CocoAnnotation {
+                id: annotations.len() + 1,
+                image_id,
+                category_id: 1,
+                bbox: [text_box.x, text_box.y, text_box.width, text_box.height],
+                area: text_box.width * text_box.height,
+                iscrowd: 0,
+            });
+        }
+    }
+
+    Ok(json!({
+        "images": images,
+        "annotations": annotations,
+        "categories": [{ "id": 1, "name": CATEGORY_NAME }],
+    }))
+}
+
+// The recognized text goes into each shape's description so it can be checked alongside the box
+fn labelme(extraction: &Extraction, file_name: &str, min_score: u8) -> Result<serde_json::Value> {
+    let size = export::page_size(extraction, "LabelMe")?;
+
+    let shapes: Vec<serde_json::Value> = extraction
+        .regions
+        .iter()
+        .filter_map(|region| {
+            let text_box = region
+                .text_box
+                .filter(|text_box| is_confident(text_box, min_score))?;
+
+            Some(json!({
+                "label": CATEGORY_NAME,
+                "points": [
+                    [text_box.x, text_box.y],
+                    [text_box.x + text_box.width, text_box.y + text_box.height],
+                ],
+                "group_id": null,
+                "description": region.text,
+                "shape_type": "rectangle",
+                "flags": {},
+            }))
+        })
+        .collect();
+
+    Ok(json!({
+        "version": "5.2.1",
+        "flags": {},
+        "shapes": shapes,
+        "imagePath": file_name,
+        "imageData": null,
+        "imageHeight": size.height,
+        "imageWidth": size.width,
+    }))
+}
+
+// One "class x_center y_center width height" line per box, normalized by the page size
+fn yolo(extraction: &Extraction, min_score: u8) -> Result<String> {
+    let PageSize { width, height } = export::page_size(extraction, "YOLO")?;
+    let (width, height) = (width as f64, height as f64);
+
+    Ok(boxes(extraction, min_score)
+        .map(|text_box| {
+            format!(
+                "0 {:.6} {:.6} {:.6} {:.6}\n",
+                (text_box.x as f64 + text_box.width as f64 / 2.0) / width,
+                (text_box.y as f64 + text_box.height as f64 / 2.0) / height,
+                text_box.width as f64 / width,
+                text_box.height as f64 / height,
+            )
+        })
+        .collect())
+}
+
+fn boxes(extraction: &Extraction, min_score: u8) -> impl Iterator<Item = TextBox> + '_ {
+    extraction
+        .regions
+        .iter()
+        .filter_map(|region| region.text_box)
+        .filter(move |text_box| is_confident(text_box, min_score))
+}
+
+// Boxes without a score were added by hooks or by hand and are always kept
+fn is_confident(text_box: &TextBox, min_score: u8) -> bool {
+    text_box.score.is_none_or(|score| score >= min_score)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::annotations::{coco, labelme, yolo};
+    use crate::extraction::{Extraction, PageSize};
+    use crate::text_box::TextBox;
+
+    fn page() -> (Extraction, String) {
+        let extraction = Extraction::new("001", vec!["a".to_string(), "b".to_string()])
+            .with_geometry(
+                PageSize {
+                    width: 200,
+                    height: 100,
+                },
+                &[
+                    TextBox {
+                        x: 0,
+                        y: 0,
+                        width: 50,
+                        height: 20,
+                        score: None,
+                    },
+                    TextBox {
+                        x: 100,
+                        y: 50,
+                        width: 100,
+                        height: 50,
+                        score: Some(55),
+                    },
+                ],
+            );
+
+        (extraction, "001.png".to_string())
+    }
+
+    #[test]
+    fn test_annotation_formats() {
+        let (extraction, file_name) = page();
+
+        // Only the unscored box is above the threshold
+        let confident = coco(&[page()], 60).unwrap();
+
+        assert_eq!(confident["annotations"].as_array().unwrap().len(), 1);
+
+        let coco = coco(&[page(), page()], 0).unwrap();
+
+        assert_eq!(coco["images"][1]["id"], 2);
+        assert_eq!(coco["annotations"].as_array().unwrap().len(), 4);
+        assert_eq!(coco["annotations"][3]["id"], 4);
+        assert_eq!(coco["annotations"][3]["image_id"], 2);
+        assert_eq!(
+            coco["annotations"][1]["bbox"],
+            serde_json::json!([100, 50, 100, 50])
+        );
+
+        let labelme = labelme(&extraction, &file_name, 0).unwrap();
+
+        assert_eq!(
+            labelme["shapes"][1]["points"],
+            serde_json::json!([[100, 50], [200, 100]])
+        );
+        assert_eq!(labelme["shapes"][1]["description"], "b");
+        assert_eq!(labelme["imagePath"], "001.png");
+
+        assert_eq!(
+            yolo(&extraction, 0).unwrap(),
+            "0 0.125000 0.100000 0.250000 0.200000\n0 0.750000 0.750000 0.500000 0.500000\n"
+        );
+
+        assert_eq!(
+            yolo(&extraction, 60).unwrap(),
+            "0 0.125000 0.100000 0.250000 0.200000\n"
+        );
+    }
+}
@@ -0,0 +1,132 @@
+use crate::export::save_crop;
+use crate::extraction::Extraction;
+use crate::text_box::TextBox;
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use serde::Serialize;
+use std::path::Path;
+
+// A region cropped into the dataset, listed in its index
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct DatasetItem {
+    pub id: String,
+    // File name of the page's image
+    pub image: String,
+    // File names of the region's crop and of its text within the dataset
+    pub crop: String,
+    pub ground_truth: String,
+    #[serde(rename = "box")]
+    pub text_box: TextBox,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct DatasetIndex<'a> {
+    regions: &'a [DatasetItem],
+}
+
+// Crops every region of a page into the dataset directory, next to its recognized text in a
+// "{id}.gt.txt" file as tesstrain expects, to be corrected before training on it. The page has to be
+// the image the boxes were detected on. Regions without a box (from legacy text files) are left out.
+pub fn add_page(
+    directory: &Path,
+    extraction: &Extraction,
+    file_name: &str,
+    page: &DynamicImage,
+) -> Result<Vec<DatasetItem>> {
+    let mut items = Vec::new();
+
+    for region in &extraction.regions {
+        let text_box = match region.text_box {
+            Some(text_box) => text_box,
+            None => continue,
+        };
+
+        let crop = match save_crop(directory, page, &region.id, text_box)? {
+            Some(crop) => crop,
+            None => continue,
+        };
+
+        let ground_truth = format!("{}.gt.txt", region.id);
+        let text = format!("{}\n", region.text);
+
+        std::fs::write(directory.join(&ground_truth), text)
+            .with_context(|| format!("Could not write the text of {}.", region.id))?;
+
+        items.push(DatasetItem {
+            id: region.id.clone(),
+            image: file_name.to_string(),
+            crop,
+            ground_truth,
+            text_box,
+            text: region.text.clone(),
+            confidence: region.confidence,
+        });
+    }
+
+    Ok(items)
+}
+
+// Writes the index of the dataset, listing every region with the page and box it was cropped from
+pub fn write_index(directory: &Path, items: &[DatasetItem]) -> Result<()> {
+    let index = DatasetIndex { regions: items };
+    let path = directory.join("dataset.json");
+
+    std::fs::write(&path, serde_json::to_string_pretty(&index)?)
+        .with_context(|| format!("Could not write the dataset index to {}.", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::dataset::{add_page, write_index};
+    use crate::extraction::{Extraction, PageSize};
+    use crate::text_box::TextBox;
+    use image::{DynamicImage, RgbImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_dataset() {
+        let text_box = |x: i32| TextBox {
+            x,
+            y: 10,
+            width: 30,
+            height: 20,
+            score: Some(90),
+        };
+        let extraction = Extraction::new("007", vec!["あいう".to_string(), "えお".to_string()])
+            .with_geometry(
+                PageSize {
+                    width: 100,
+                    height: 50,
+                },
+                // The second box is off the page
+                &[text_box(10), text_box(150)],
+            )
+            .with_confidences(&[88, 70]);
+
+        let directory = TempDir::new().unwrap();
+        let page = DynamicImage::ImageRgb8(RgbImage::new(100, 50));
+
+        let items = add_page(directory.path(), &extraction, "007.jpg", &page).unwrap();
+        assert_eq!(items.len(), 1);
+        assert_eq!(items[0].crop, "007-0.png");
+
+        let crop = image::open(directory.path().join(&items[0].crop)).unwrap();
+        assert_eq!((crop.width(), crop.height()), (30, 20));
+        assert_eq!(
+            std::fs::read_to_string(directory.path().join(&items[0].ground_truth)).unwrap(),
+            "あいう\n"
+        );
+
+        write_index(directory.path(), &items).unwrap();
+        let index: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(directory.path().join("dataset.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(index["regions"][0]["ground_truth"], "007-0.gt.txt");
+        assert_eq!(index["regions"][0]["box"]["width"], 30);
+        assert_eq!(index["regions"][0]["confidence"], 88);
+    }
+}
@@ -0,0 +1,50 @@
+use crate::extraction::{Extraction, PageSize};
+use crate::text_box::TextBox;
+use anyhow::{bail, Context, Result};
+use image::DynamicImage;
+use std::path::Path;
+
+pub mod annotations;
+pub mod dataset;
+pub mod mokuro;
+pub mod review;
+pub mod table;
+pub mod xliff;
+
+// Exports that place text on the page need the page size, which legacy text files don't have
+fn page_size(extraction: &Extraction, format: &str) -> Result<PageSize> {
+    match extraction.size {
+        Some(size) => Ok(size),
+        None => bail!(
+            "Text file for {} has no page size. Extract the page again to export it to {format}.",
+            extraction.page
+        ),
+    }
+}
+
+// Saves the crop of a region as "{id}.png" in the directory and returns its file name, or None if the
+// box lies outside of the page
+fn save_crop(
+    directory: &Path,
+    page: &DynamicImage,
+    id: &str,
+    text_box: TextBox,
+) -> Result<Option<String>> {
+    let area = match text_box.clamp(page.width() as i32, page.height() as i32) {
+        Some(area) => area,
+        None => return Ok(None),
+    };
+
+    let crop = format!("{id}.png");
+
+    page.crop_imm(
+        area.x as u32,
+        area.y as u32,
+        area.width as u32,
+        area.height as u32,
+    )
+    .save(directory.join(&crop))
+    .with_context(|| format!("Could not write the crop of {id}."))?;
+
+    Ok(Some(crop))
+}
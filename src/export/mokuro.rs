@@ -0,0 +1,210 @@
+use crate::export;
+use crate::extraction::Extraction;
+use anyhow::{ensure, Result};
+use serde::Serialize;
+use std::path::Path;
+
+// Version of the Mokuro format the files are written in
+const MOKURO_VERSION: &str = "0.2.1";
+
+// A volume in the ".mokuro" format read by Mokuro readers
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MokuroVolume {
+    pub version: String,
+    pub title: String,
+    pub title_uuid: String,
+    pub volume: String,
+    pub volume_uuid: String,
+    pub pages: Vec<MokuroPage>,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MokuroPage {
+    pub version: String,
+    pub img_width: u32,
+    pub img_height: u32,
+    pub blocks: Vec<MokuroBlock>,
+    // Path of the page image relative to the volume's image directory
+    pub img_path: String,
+}
+
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct MokuroBlock {
+    // Corners of the block as [x1, y1, x2, y2]
+    #[serde(rename = "box")]
+    pub bounding_box: [i32; 4],
+    pub vertical: bool,
+    pub font_size: f32,
+    pub lines_coords: Vec<[[f32; 2]; 4]>,
+    pub lines: Vec<String>,
+}
+
+impl MokuroPage {
+    // Builds a page from an extraction. OCR returns the text of a region as a single line,
+    // so every region becomes a block with one line covering the whole box.
+    pub fn new(extraction: &Extraction, img_path: &str) -> Result<MokuroPage> {
+        let size = export::page_size(extraction, "Mokuro")?;
+
+        let blocks = extraction
+            .regions
+            .iter()
+            .filter(|region| !region.text.trim().is_empty())
+            .filter_map(|region| {
+                let text_box = region.text_box?;
+
+                let (left, top) = (text_box.x, text_box.y);
+                let (right, bottom) = (text_box.x + text_box.width, text_box.y + text_box.height);
+
+                Some(MokuroBlock {
+                    bounding_box: [left, top, right, bottom],
+                    // Japanese speech bubbles are set vertically, which makes their boxes tall and narrow
+                    vertical: text_box.height > text_box.width,
+                    font_size: estimate_font_size(
+                        text_box.width,
+                        text_box.height,
+                        region.text.chars().count(),
+                    ),
+                    lines_coords: vec![[
+                        [left as f32, top as f32],
+                        [right as f32, top as f32],
+                        [right as f32, bottom as f32],
+                        [left as f32, bottom as f32],
+                    ]],
+                    lines: vec![region.text.clone()],
+                })
+            })
+            .collect();
+
+        Ok(MokuroPage {
+            version: MOKURO_VERSION.to_string(),
+            img_width: size.width,
+            img_height: size.height,
+            blocks,
+            img_path: img_path.to_string(),
+        })
+    }
+}
+
+impl MokuroVolume {
+    // Readers key their progress on the UUIDs, so they are derived from the title to stay the same across exports
+    pub fn new(title: &str, pages: Vec<MokuroPage>) -> MokuroVolume {
+        MokuroVolume {
+            version: MOKURO_VERSION.to_string(),
+            title: title.to_string(),
+            title_uuid: name_uuid(&format!("title/{title}")),
+            volume: title.to_string(),
+            volume_uuid: name_uuid(&format!("volume/{title}")),
+            pages,
+        }
+    }
+
+    pub fn write<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        std::fs::write(path, serde_json::to_string(self)?)?;
+
+        Ok(())
+    }
+}
+
+// Largest square glyph size that fits the characters into the box, capped at the box's narrow side
+fn estimate_font_size(width: i32, height: i32, num_chars: usize) -> f32 {
+    let fitted_size = ((width * height) as f32 / num_chars.max(1) as f32).sqrt();
+
+    fitted_size.min(width.min(height) as f32)
+}
+
+// Formats a 128-bit FNV-1a hash of the name like a UUID
+fn name_uuid(name: &str) -> String {
+    let mut hash: u128 = 0x6c62272e07bb014262b821756295c58d;
+
+    for byte in name.bytes() {
+        hash ^= byte as u128;
+        hash = hash.wrapping_mul(0x0000000001000000000000000000013b);
+    }
+
+    let hex = format!("{hash:032x}");
+
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..]
+    )
+}
+
+// Writes the extractions of a batch, each with the path of its page image, as a single Mokuro volume
+pub fn write_volume<P: AsRef<Path>>(
+    path: P,
+    title: &str,
+    pages: &[(Extraction, String)],
+) -> Result<()> {
+    ensure!(!pages.is_empty(), "There are no pages to export to Mokuro.");
+
+    let pages = pages
+        .iter()
+        .map(|(extraction, img_path)| MokuroPage::new(extraction, img_path))
+        .collect::<Result<Vec<MokuroPage>>>()?;
+
+    MokuroVolume::new(title, pages).write(path)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::mokuro::{MokuroPage, MokuroVolume};
+    use crate::extraction::{Extraction, PageSize};
+    use crate::text_box::TextBox;
+
+    #[test]
+    fn test_mokuro_page() {
+        let extraction = Extraction::new("001", vec!["こんにちは".to_string(), " ".to_string()])
+            .with_geometry(
+                PageSize {
+                    width: 800,
+                    height: 1200,
+                },
+                &[
+                    TextBox {
+                        x: 10,
+                        y: 20,
+                        width: 30,
+                        height: 150,
+                        score: None,
+                    },
+                    TextBox {
+                        x: 0,
+                        y: 0,
+                        width: 10,
+                        height: 10,
+                        score: None,
+                    },
+                ],
+            );
+
+        let page = MokuroPage::new(&extraction, "001.png").unwrap();
+
+        assert_eq!((page.img_width, page.img_height), (800, 1200));
+        assert_eq!(page.blocks.len(), 1);
+        assert_eq!(page.blocks[0].bounding_box, [10, 20, 40, 170]);
+        assert!(page.blocks[0].vertical);
+        assert_eq!(page.blocks[0].font_size, 30.0);
+        assert_eq!(page.blocks[0].lines, vec!["こんにちは"]);
+
+        let volume = MokuroVolume::new("Volume 1", vec![page]);
+        let json = serde_json::to_value(&volume).unwrap();
+
+        assert_eq!(json["pages"][0]["blocks"][0]["box"][2], 40);
+        assert_eq!(
+            volume.volume_uuid,
+            MokuroVolume::new("Volume 1", vec![]).volume_uuid
+        );
+        assert_ne!(volume.volume_uuid, volume.title_uuid);
+
+        let error = MokuroPage::new(&Extraction::new("002", vec![]), "002.png").unwrap_err();
+
+        assert_eq!(
+            format!("{error}"),
+            "Text file for 002 has no page size. Extract the page again to export it to Mokuro."
+        );
+    }
+}
@@ -0,0 +1,158 @@
+use crate::export::save_crop;
+use crate::extraction::{Extraction, Region};
+use crate::text_box::TextBox;
+use anyhow::{Context, Result};
+use image::DynamicImage;
+use serde::Serialize;
+use std::path::Path;
+
+// A region that OCR or detection was unsure about, listed in the index of the review bundle
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct ReviewItem {
+    pub id: String,
+    // File name of the page's image
+    pub image: String,
+    // File name of the region's crop within the bundle
+    pub crop: String,
+    #[serde(rename = "box")]
+    pub text_box: TextBox,
+    pub text: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<u8>,
+}
+
+#[derive(Serialize)]
+struct ReviewIndex<'a> {
+    threshold: u8,
+    regions: &'a [ReviewItem],
+}
+
+// Whether the OCR confidence or the detection score of a region is below the threshold
+pub fn needs_review(region: &Region, threshold: u8) -> bool {
+    let unsure_ocr = region
+        .confidence
+        .is_some_and(|confidence| confidence < threshold);
+    let unsure_detection = region
+        .text_box
+        .and_then(|text_box| text_box.score)
+        .is_some_and(|score| score < threshold);
+
+    unsure_ocr || unsure_detection
+}
+
+// Crops the regions of a page that need review into the bundle directory and returns their items.
+// The page has to be the image the boxes were detected on. Regions without a box (from legacy text
+// files) can't be cropped and are left out.
+pub fn add_page(
+    directory: &Path,
+    extraction: &Extraction,
+    file_name: &str,
+    page: &DynamicImage,
+    threshold: u8,
+) -> Result<Vec<ReviewItem>> {
+    let mut items = Vec::new();
+
+    for region in &extraction.regions {
+        let text_box = match region.text_box {
+            Some(text_box) if needs_review(region, threshold) => text_box,
+            _ => continue,
+        };
+
+        let crop = match save_crop(directory, page, &region.id, text_box)? {
+            Some(crop) => crop,
+            None => continue,
+        };
+
+        items.push(ReviewItem {
+            id: region.id.clone(),
+            image: file_name.to_string(),
+            crop,
+            text_box,
+            text: region.text.clone(),
+            confidence: region.confidence,
+        });
+    }
+
+    Ok(items)
+}
+
+// Writes the index of the bundle, listing the regions to review and the threshold they were picked with
+pub fn write_index(directory: &Path, threshold: u8, items: &[ReviewItem]) -> Result<()> {
+    let index = ReviewIndex {
+        threshold,
+        regions: items,
+    };
+    let path = directory.join("review.json");
+
+    std::fs::write(&path, serde_json::to_string_pretty(&index)?)
+        .with_context(|| format!("Could not write the review index to {}.", path.display()))
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::review::{add_page, needs_review, write_index};
+    use crate::extraction::{Extraction, PageSize};
+    use crate::text_box::TextBox;
+    use image::{DynamicImage, Rgb, RgbImage};
+    use tempfile::TempDir;
+
+    fn text_box(x: i32, score: Option<u8>) -> TextBox {
+        TextBox {
+            x,
+            y: 10,
+            width: 20,
+            height: 10,
+            score,
+        }
+    }
+
+    #[test]
+    fn test_review_bundle() {
+        let extraction = Extraction::new(
+            "005",
+            vec!["sure".to_string(), "blurry".to_string(), "art".to_string()],
+        )
+        .with_geometry(
+            PageSize {
+                width: 100,
+                height: 50,
+            },
+            &[
+                text_box(0, Some(90)),
+                text_box(30, Some(85)),
+                text_box(60, Some(42)),
+            ],
+        )
+        .with_confidences(&[95, 31, 88]);
+
+        let flagged: Vec<bool> = extraction
+            .regions
+            .iter()
+            .map(|region| needs_review(region, 60))
+            .collect();
+        assert_eq!(flagged, vec![false, true, true]);
+
+        let directory = TempDir::new().unwrap();
+        let page = DynamicImage::ImageRgb8(RgbImage::from_fn(100, 50, |x, _| match x < 50 {
+            true => Rgb([255, 255, 255]),
+            false => Rgb([0, 0, 0]),
+        }));
+
+        let items = add_page(directory.path(), &extraction, "005.png", &page, 60).unwrap();
+        let ids: Vec<&str> = items.iter().map(|item| item.id.as_str()).collect();
+        assert_eq!(ids, vec!["005-1", "005-2"]);
+        assert_eq!(items[0].text, "blurry");
+        assert_eq!(items[0].confidence, Some(31));
+
+        let crop = image::open(directory.path().join(&items[1].crop)).unwrap();
+        assert_eq!((crop.width(), crop.height()), (20, 10));
+
+        write_index(directory.path(), 60, &items).unwrap();
+        let index: serde_json::Value = serde_json::from_str(
+            &std::fs::read_to_string(directory.path().join("review.json")).unwrap(),
+        )
+        .unwrap();
+        assert_eq!(index["regions"][1]["box"]["score"], 42);
+        assert_eq!(index["regions"][0]["image"], "005.png");
+    }
+}
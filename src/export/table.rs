@@ -0,0 +1,246 @@
+use crate::extraction::{Extraction, Region, SCHEMA_VERSION};
+use crate::text_box::TextBox;
+use anyhow::{ensure, Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Spreadsheet formats the extracted text can be written as
+#[derive(clap::ValueEnum, Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TableFormat {
+    Csv,
+    Tsv,
+}
+
+impl TableFormat {
+    pub fn extension(self) -> &'static str {
+        match self {
+            TableFormat::Csv => "csv",
+            TableFormat::Tsv => "tsv",
+        }
+    }
+
+    pub fn from_path(path: &Path) -> Option<TableFormat> {
+        match path.extension()?.to_str()?.to_ascii_lowercase().as_str() {
+            "csv" => Some(TableFormat::Csv),
+            "tsv" => Some(TableFormat::Tsv),
+            _ => None,
+        }
+    }
+
+    fn delimiter(self) -> u8 {
+        match self {
+            TableFormat::Csv => b',',
+            TableFormat::Tsv => b'\t',
+        }
+    }
+}
+
+// One row per region. The box columns are left empty for regions without a position.
+// Only the page, region ID and translation columns have to survive editing in a spreadsheet.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct TableRow {
+    pub page: String,
+    pub region_id: String,
+    #[serde(default)]
+    pub x: Option<i32>,
+    #[serde(default)]
+    pub y: Option<i32>,
+    #[serde(default)]
+    pub width: Option<i32>,
+    #[serde(default)]
+    pub height: Option<i32>,
+    #[serde(default)]
+    pub text: String,
+    #[serde(default)]
+    pub translation: String,
+}
+
+// Writes the regions of every page into a single table with a header row, in page order
+pub fn to_table(format: TableFormat, pages: &[Extraction]) -> Result<Vec<u8>> {
+    let mut writer = csv::WriterBuilder::new()
+        .delimiter(format.delimiter())
+        .from_writer(Vec::new());
+
+    for extraction in pages {
+        for region in extraction.regions.iter() {
+            writer.serialize(TableRow {
+                page: extraction.page.clone(),
+                region_id: region.id.clone(),
+                x: region.text_box.map(|text_box| text_box.x),
+                y: region.text_box.map(|text_box| text_box.y),
+                width: region.text_box.map(|text_box| text_box.width),
+                height: region.text_box.map(|text_box| text_box.height),
+                text: region.text.clone(),
+                translation: region.translation.clone(),
+            })?;
+        }
+    }
+
+    Ok(writer.into_inner()?)
+}
+
+pub fn write_table<P: AsRef<Path>>(
+    format: TableFormat,
+    path: P,
+    pages: &[Extraction],
+) -> Result<()> {
+    ensure!(
+        !pages.is_empty(),
+        "There are no pages to export to a table."
+    );
+
+    std::fs::write(path, to_table(format, pages)?)?;
+
+    Ok(())
+}
+
+// Parses a table back into one extraction per page, in the order the pages first appear
+pub fn from_table(format: TableFormat, data: &[u8]) -> Result<Vec<Extraction>> {
+    let mut reader = csv::ReaderBuilder::new()
+        .delimiter(format.delimiter())
+        .from_reader(data);
+
+    let mut pages: IndexMap<String, Vec<Region>> = IndexMap::new();
+
+    for (index, row) in reader.deserialize::<TableRow>().enumerate() {
+        // The header is line 1
+        let row =
+            row.with_context(|| format!("Could not read line {} of the table.", index + 2))?;
+
+        let text_box = match (row.x, row.y, row.width, row.height) {
+            (Some(x), Some(y), Some(width), Some(height)) => Some(TextBox {
+                x,
+                y,
+                width,
+                height,
+                score: None,
+            }),
+            _ => None,
+        };
+
+        pages.entry(row.page).or_default().push(Region {
+            id: row.region_id,
+            text: row.text,
+            translation: row.translation,
+            text_box,
+            confidence: None,
+            annotation: None,
+            padding: None,
+            linked: Vec::new(),
+        });
+    }
+
+    Ok(pages
+        .into_iter()
+        .map(|(page, regions)| Extraction {
+            schema_version: SCHEMA_VERSION,
+            page,
+            size: None,
+            regions,
+        })
+        .collect())
+}
+
+// Reads a filled-in table for replacement. When the extraction JSON of a page sits next to the table
+// (as it does after exporting), the page keeps the JSON's geometry and only takes the translations from the table.
+pub fn read_translations(format: TableFormat, path: &Path) -> Result<Vec<Extraction>> {
+    let pages = from_table(format, &std::fs::read(path)?)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new("."));
+
+    pages
+        .into_iter()
+        .map(|translated| {
+            let json_path = directory.join(format!("{}.json", translated.page));
+
+            if !json_path.is_file() {
+                return Ok(translated);
+            }
+
+            let data = std::fs::read_to_string(json_path)?;
+            let mut extraction = Extraction::from_json(&data, &translated.page)?;
+            extraction.merge_translations(&translated);
+
+            Ok(extraction)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::table::{from_table, to_table, TableFormat};
+    use crate::extraction::{Extraction, PageSize};
+    use crate::text_box::TextBox;
+
+    #[test]
+    fn test_table_export() {
+        let first = Extraction::new("001", vec!["「はい」, 先生".to_string()]).with_geometry(
+            PageSize {
+                width: 10,
+                height: 10,
+            },
+            &[TextBox {
+                x: 1,
+                y: 2,
+                width: 3,
+                height: 4,
+                score: None,
+            }],
+        );
+        let second = Extraction::new("002", vec!["line\ttab".to_string()]);
+
+        let csv = String::from_utf8(
+            to_table(TableFormat::Csv, &[first.clone(), second.clone()]).unwrap(),
+        )
+        .unwrap();
+
+        assert_eq!(
+            csv,
+            "page,region_id,x,y,width,height,text,translation\n\
+             001,001-0,1,2,3,4,\"「はい」, 先生\",\n\
+             002,002-0,,,,,line\ttab,\n"
+        );
+
+        let tsv = String::from_utf8(to_table(TableFormat::Tsv, &[first, second]).unwrap()).unwrap();
+
+        assert_eq!(
+            tsv.lines().nth(2).unwrap(),
+            "002\t002-0\t\t\t\t\t\"line\ttab\"\t"
+        );
+    }
+
+    #[test]
+    fn test_table_import() {
+        let extraction = Extraction::new("001", vec!["a".to_string(), "b".to_string()])
+            .with_geometry(
+                PageSize {
+                    width: 10,
+                    height: 10,
+                },
+                &[TextBox {
+                    x: 1,
+                    y: 2,
+                    width: 3,
+                    height: 4,
+                    score: None,
+                }],
+            );
+
+        let table = to_table(TableFormat::Tsv, std::slice::from_ref(&extraction)).unwrap();
+        let mut pages = from_table(TableFormat::Tsv, &table).unwrap();
+
+        assert_eq!(pages[0].regions, extraction.regions);
+
+        // Columns can be dropped and reordered in a spreadsheet
+        let translated = "translation,region_id,page\nB,001-1,001\nC,002-0,002\n";
+        let translated = from_table(TableFormat::Csv, translated.as_bytes()).unwrap();
+
+        assert_eq!(translated.len(), 2);
+        assert_eq!(translated[1].page, "002");
+
+        pages[0].merge_translations(&translated[0]);
+
+        assert_eq!(pages[0].aligned_translations(2), vec![Some(""), Some("B")]);
+        assert_eq!(pages[0].regions[0].text_box, extraction.regions[0].text_box);
+    }
+}
@@ -0,0 +1,256 @@
+use crate::extraction::{Extraction, PageSize, Region, SCHEMA_VERSION};
+use crate::text_box::TextBox;
+use anyhow::{bail, Context, Result};
+
+const XLIFF_NAMESPACE: &str = "urn:oasis:names:tc:xliff:document:2.0";
+
+// Writes a page as an XLIFF 2.0 document with one unit per region. Region IDs become the unit IDs
+// and the page size and boxes are kept in notes, so the file can be read back for replacement.
+// XLIFF 2.0 requires the target language whenever units carry targets, which they always do here.
+pub fn to_xliff(extraction: &Extraction, file_name: &str, lang: &str, target_lang: &str) -> String {
+    let mut xliff = String::new();
+
+    xliff.push_str("<?xml version=\"1.0\" encoding=\"UTF-8\"?>\n");
+    xliff.push_str(&format!(
+        "<xliff xmlns=\"{XLIFF_NAMESPACE}\" version=\"2.0\" srcLang=\"{}\" trgLang=\"{}\">\n",
+        escape(&source_language(lang)),
+        escape(target_lang)
+    ));
+    xliff.push_str(&format!(
+        "  <file id=\"{}\" original=\"{}\">\n",
+        escape(&extraction.page),
+        escape(file_name)
+    ));
+
+    if let Some(size) = extraction.size {
+        xliff.push_str(&format!(
+            "    <notes>\n      <note category=\"size\">{},{}</note>\n    </notes>\n",
+            size.width, size.height
+        ));
+    }
+
+    for region in extraction.regions.iter() {
+        xliff.push_str(&format!("    <unit id=\"{}\">\n", escape(&region.id)));
+
+        if let Some(text_box) = region.text_box {
+            xliff.push_str(&format!(
+                "      <notes>\n        <note category=\"box\">{},{},{},{}</note>\n      </notes>\n",
+                text_box.x, text_box.y, text_box.width, text_box.height
+            ));
+        }
+
+        xliff.push_str("      <segment>\n");
+        xliff.push_str(&format!(
+            "        <source>{}</source>\n",
+            escape(&region.text)
+        ));
+        xliff.push_str(&format!(
+            "        <target>{}</target>\n",
+            escape(&region.translation)
+        ));
+        xliff.push_str("      </segment>\n    </unit>\n");
+    }
+
+    xliff.push_str("  </file>\n</xliff>\n");
+
+    xliff
+}
+
+// Reads a translated XLIFF document back into an extraction. Only the first file element is used.
+pub fn from_xliff(data: &str, page: &str) -> Result<Extraction> {
+    let document = roxmltree::Document::parse(data)
+        .with_context(|| format!("Text file for {page} is not a valid XLIFF file."))?;
+
+    let file = match document
+        .root_element()
+        .children()
+        .find(|node| node.has_tag_name((XLIFF_NAMESPACE, "file")))
+    {
+        Some(file) => file,
+        None => bail!("Text file for {page} does not contain an XLIFF 2.0 file element."),
+    };
+
+    let size = note(file, "size").and_then(|size| match parse_numbers(&size)[..] {
+        [width, height] => Some(PageSize {
+            width: width as u32,
+            height: height as u32,
+        }),
+        _ => None,
+    });
+
+    let mut regions = Vec::new();
+
+    for unit in file
+        .children()
+        .filter(|node| node.has_tag_name((XLIFF_NAMESPACE, "unit")))
+    {
+        let id = match unit.attribute("id") {
+            Some(id) => id.to_string(),
+            None => bail!("Every unit in the text file for {page} needs an id."),
+        };
+
+        let text_box = note(unit, "box").and_then(|text_box| match parse_numbers(&text_box)[..] {
+            [x, y, width, height] => Some(TextBox {
+                x,
+                y,
+                width,
+                height,
+                score: None,
+            }),
+            _ => None,
+        });
+
+        // A unit can split its text over several segments
+        let segments = unit
+            .children()
+            .filter(|node| node.has_tag_name((XLIFF_NAMESPACE, "segment")));
+
+        let mut text = String::new();
+        let mut translation = String::new();
+
+        for segment in segments {
+            text.push_str(&child_text(segment, "source"));
+            translation.push_str(&child_text(segment, "target"));
+        }
+
+        regions.push(Region {
+            id,
+            text,
+            translation,
+            text_box,
+            confidence: None,
+            annotation: None,
+            padding: None,
+            linked: Vec::new(),
+        });
+    }
+
+    Ok(Extraction {
+        schema_version: SCHEMA_VERSION,
+        page: file.attribute("id").unwrap_or(page).to_string(),
+        size,
+        regions,
+    })
+}
+
+// XLIFF uses BCP 47 language tags, while tesseract names its languages after ISO 639-2
+fn source_language(lang: &str) -> String {
+    // Only the first of several "+" separated languages can be the source language
+    let lang = lang.split('+').next().unwrap_or(lang);
+
+    match lang.trim_end_matches("_vert") {
+        "jpn" => "ja",
+        "kor" => "ko",
+        "chi_sim" => "zh-Hans",
+        "chi_tra" => "zh-Hant",
+        "eng" => "en",
+        other => other,
+    }
+    .to_string()
+}
+
+fn note(node: roxmltree::Node, category: &str) -> Option<String> {
+    node.children()
+        .find(|child| child.has_tag_name((XLIFF_NAMESPACE, "notes")))?
+        .children()
+        .find(|child| {
+            child.has_tag_name((XLIFF_NAMESPACE, "note"))
+                && child.attribute("category") == Some(category)
+        })
+        .and_then(|note| note.text())
+        .map(str::to_string)
+}
+
+// Text of the first child element with the given name, including text inside inline elements
+fn child_text(node: roxmltree::Node, name: &str) -> String {
+    match node
+        .children()
+        .find(|child| child.has_tag_name((XLIFF_NAMESPACE, name)))
+    {
+        Some(child) => child
+            .descendants()
+            .filter(|descendant| descendant.is_text())
+            .filter_map(|descendant| descendant.text())
+            .collect(),
+        None => String::new(),
+    }
+}
+
+fn parse_numbers(values: &str) -> Vec<i32> {
+    values
+        .split(',')
+        .filter_map(|value| value.trim().parse().ok())
+        .collect()
+}
+
+fn escape(text: &str) -> String {
+    text.replace('&', "&amp;")
+        .replace('<', "&lt;")
+        .replace('>', "&gt;")
+        .replace('"', "&quot;")
+        .replace('\'', "&apos;")
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::export::xliff::{from_xliff, to_xliff};
+    use crate::extraction::{Extraction, PageSize};
+    use crate::text_box::TextBox;
+
+    #[test]
+    fn test_xliff_round_trip() {
+        let mut extraction =
+            Extraction::new("001", vec!["「はい」".to_string(), "A & <B>".to_string()])
+                .with_geometry(
+                    PageSize {
+                        width: 800,
+                        height: 1200,
+                    },
+                    &[TextBox {
+                        x: 1,
+                        y: 2,
+                        width: 3,
+                        height: 4,
+                        score: None,
+                    }],
+                );
+
+        extraction.regions[1].translation = "\"Yes\"".to_string();
+
+        let xliff = to_xliff(&extraction, "001.png", "jpn_vert", "en");
+
+        assert!(xliff.contains("srcLang=\"ja\" trgLang=\"en\""));
+        assert!(xliff.contains("<source>A &amp; &lt;B&gt;</source>"));
+
+        assert_eq!(from_xliff(&xliff, "001").unwrap(), extraction);
+    }
+
+    #[test]
+    fn test_translated_xliff() {
+        let xliff = r#"<xliff xmlns="urn:oasis:names:tc:xliff:document:2.0" version="2.0" srcLang="ja" trgLang="en">
+            <file id="002">
+                <unit id="002-0">
+                    <segment><source>こんにちは</source><target>Hello</target></segment>
+                    <segment><source>世界</source><target> <pc id="1">world</pc></target></segment>
+                </unit>
+            </file>
+        </xliff>"#;
+
+        let extraction = from_xliff(xliff, "002").unwrap();
+
+        assert_eq!(extraction.size, None);
+        assert_eq!(extraction.regions[0].text, "こんにちは世界");
+        assert_eq!(extraction.regions[0].translation, "Hello world");
+        assert_eq!(
+            extraction.aligned_translations(1),
+            vec![Some("Hello world")]
+        );
+
+        let error = from_xliff("<xliff", "002").unwrap_err();
+
+        assert_eq!(
+            format!("{error}"),
+            "Text file for 002 is not a valid XLIFF file."
+        );
+    }
+}
@@ -0,0 +1,677 @@
+use crate::export::table::{self, TableFormat};
+use crate::export::xliff;
+use crate::text_box::TextBox;
+use anyhow::{bail, Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+use std::path::Path;
+
+// Version of the extraction JSON layout. Bump it together with a new entry in "MIGRATIONS"
+// whenever a change to the layout can't be read by the previous version's deserializer.
+pub const SCHEMA_VERSION: u32 = 2;
+
+// Each migration upgrades the JSON of an extraction from the version at its index to the next one
+const MIGRATIONS: [fn(Value, &str) -> Result<Value>; SCHEMA_VERSION as usize] =
+    [migrate_legacy, migrate_unversioned];
+
+// A single detected text region as written to and read back from extraction JSONs
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Region {
+    pub id: String,
+    pub text: String,
+    #[serde(default)]
+    pub translation: String,
+    // Where the region was detected on the page. Missing from legacy files.
+    #[serde(default, rename = "box", skip_serializing_if = "Option::is_none")]
+    pub text_box: Option<TextBox>,
+    // OCR confidence from 0 to 100. Missing from files written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub confidence: Option<u8>,
+    // Small text typeset along with the translation
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub annotation: Option<Annotation>,
+    // Margin kept between the translation and the edges of the region, in place of the page's
+    // padding, e.g. less for tiny bubbles and more for large narration boxes
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<u16>,
+    // IDs of the regions the translation flows on to, in reading order, when this is the first of a
+    // group of linked bubbles that share one sentence (--link-distance)
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub linked: Vec<String>,
+}
+
+// Small text set above, below or beside a region's translation, such as a translator's note or the
+// gloss of an honorific that was kept
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Annotation {
+    pub text: String,
+    #[serde(default)]
+    pub placement: AnnotationPlacement,
+    // Size of the annotation in percent of the size the translation is set in
+    #[serde(default = "default_annotation_scale")]
+    pub scale: u8,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AnnotationPlacement {
+    #[default]
+    Above,
+    Below,
+    Beside,
+}
+
+fn default_annotation_scale() -> u8 {
+    50
+}
+
+impl Region {
+    pub fn is_empty(&self, min_confidence: u8) -> bool {
+        is_empty_region(&self.text, self.confidence, min_confidence)
+    }
+}
+
+// Regions where OCR found no text, or only text it is less confident about than "min_confidence",
+// are most likely detections on art rather than text
+pub fn is_empty_region(text: &str, confidence: Option<u8>, min_confidence: u8) -> bool {
+    text.trim().is_empty() || confidence.is_some_and(|confidence| confidence < min_confidence)
+}
+
+// A change to one region of a page that was processed before, e.g. made in a proofreading UI.
+// Fields that are missing are left as they were.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct RegionCorrection {
+    pub id: String,
+    #[serde(default, rename = "box", skip_serializing_if = "Option::is_none")]
+    pub text_box: Option<TextBox>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub translation: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageSize {
+    pub width: u32,
+    pub height: u32,
+}
+
+// Contents of an extraction JSON. Region IDs are derived from the page name and the
+// detection index, so translations are matched by ID rather than by their position in the file.
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Extraction {
+    pub schema_version: u32,
+    pub page: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub size: Option<PageSize>,
+    pub regions: Vec<Region>,
+}
+
+// Older extraction files stored the regions as a map of source text to translation
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum LegacyExtraction {
+    Map(IndexMap<String, String>),
+    Wrapped {
+        #[serde(with = "indexmap::serde_seq")]
+        text: IndexMap<String, String>,
+    },
+}
+
+// Files without a version stamp are either version 1 (page and regions) or one of the legacy maps
+fn schema_version(value: &Value, page: &str) -> Result<u32> {
+    match value.get("schema_version").and_then(Value::as_u64) {
+        Some(version) => u32::try_from(version).with_context(|| {
+            format!("Text file for {page} uses schema version {version}, which no version of mangatra reads.")
+        }),
+        None if value.get("regions").is_some_and(Value::is_array) => Ok(1),
+        None => Ok(0),
+    }
+}
+
+// Version 0 to 1: the legacy maps of source text to translation get IDs assigned by position
+fn migrate_legacy(value: Value, page: &str) -> Result<Value> {
+    let text_pairs = match serde_json::from_value::<LegacyExtraction>(value) {
+        Ok(LegacyExtraction::Map(text_pairs)) => text_pairs,
+        Ok(LegacyExtraction::Wrapped { text }) => text,
+        Err(_) => bail!("Text file for {page} is not a valid extraction JSON."),
+    };
+
+    let regions = text_pairs
+        .into_iter()
+        .enumerate()
+        .map(|(index, (text, translation))| Region {
+            id: region_id(page, index),
+            text,
+            translation,
+            text_box: None,
+            confidence: None,
+            annotation: None,
+            padding: None,
+            linked: Vec::new(),
+        })
+        .collect::<Vec<Region>>();
+
+    Ok(json!({ "page": page, "regions": regions }))
+}
+
+// Version 1 to 2: only the version stamp was added
+fn migrate_unversioned(value: Value, _page: &str) -> Result<Value> {
+    Ok(value)
+}
+
+// Picks the extraction of each of the named pages, in order, out of a document that holds the
+// translations of several pages keyed by page name and region ID, such as the table of a chapter
+pub fn split_document(document: &[Extraction], pages: &[&str]) -> Result<Vec<Extraction>> {
+    let document: IndexMap<&str, &Extraction> = document
+        .iter()
+        .map(|extraction| (extraction.page.as_str(), extraction))
+        .collect();
+
+    let missing_pages: Vec<&str> = pages
+        .iter()
+        .copied()
+        .filter(|page| !document.contains_key(page))
+        .collect();
+    if !missing_pages.is_empty() {
+        bail!(
+            "The translation document has no regions for {}.",
+            missing_pages.join(", ")
+        );
+    }
+
+    Ok(pages.iter().map(|page| document[page].clone()).collect())
+}
+
+// Name used for a page in region IDs
+pub fn page_name(input: &str) -> String {
+    match Path::new(input).file_stem() {
+        Some(file_stem) => file_stem.to_string_lossy().to_string(),
+        None => input.to_string(),
+    }
+}
+
+pub fn region_id(page: &str, index: usize) -> String {
+    format!("{page}-{index}")
+}
+
+// Reads the text of a page for replacement from an extraction JSON, a translated XLIFF file or a table
+pub fn read_text_file(path: &Path, page: &str) -> Result<Extraction> {
+    if let Some(format) = TableFormat::from_path(path) {
+        let mut pages = table::read_translations(format, path)?;
+
+        // A table exported for a single page can be used for the page even if it was renamed since
+        return match pages.iter().position(|extraction| extraction.page == page) {
+            Some(index) => Ok(pages.swap_remove(index)),
+            None if pages.len() == 1 => Ok(pages.remove(0)),
+            None => bail!("{} has no rows for {page}.", path.display()),
+        };
+    }
+
+    let data = std::fs::read_to_string(path)?;
+
+    match path.extension().and_then(|extension| extension.to_str()) {
+        Some(extension)
+            if extension.eq_ignore_ascii_case("xlf") || extension.eq_ignore_ascii_case("xliff") =>
+        {
+            xliff::from_xliff(&data, page)
+        }
+        _ => Extraction::from_json(&data, page),
+    }
+}
+
+impl Extraction {
+    // Builds an extraction for freshly detected regions with empty translations
+    pub fn new(page: &str, text: Vec<String>) -> Extraction {
+        let regions = text
+            .into_iter()
+            .enumerate()
+            .map(|(index, text)| Region {
+                id: region_id(page, index),
+                text,
+                translation: String::new(),
+                text_box: None,
+                confidence: None,
+                annotation: None,
+                padding: None,
+                linked: Vec::new(),
+            })
+            .collect();
+
+        Extraction {
+            schema_version: SCHEMA_VERSION,
+            page: page.to_string(),
+            size: None,
+            regions,
+        }
+    }
+
+    // Records the page size and the box of each region (in detection order) for exports that need positions
+    pub fn with_geometry(mut self, size: PageSize, boxes: &[TextBox]) -> Extraction {
+        self.size = Some(size);
+
+        for (region, text_box) in self.regions.iter_mut().zip(boxes) {
+            region.text_box = Some(*text_box);
+        }
+
+        self
+    }
+
+    // Records the OCR confidence of each region, in detection order
+    pub fn with_confidences(mut self, confidences: &[u8]) -> Extraction {
+        for (region, confidence) in self.regions.iter_mut().zip(confidences) {
+            region.confidence = Some(*confidence);
+        }
+
+        self
+    }
+
+    // Links the regions of each group, given by detection index in reading order, to the first one,
+    // so a translation written for it flows across the whole group
+    pub fn with_linked_regions(mut self, groups: &[Vec<usize>]) -> Extraction {
+        for group in groups {
+            if let Some((first, rest)) = group.split_first() {
+                let linked = rest
+                    .iter()
+                    .map(|index| region_id(&self.page, *index))
+                    .collect();
+
+                if let Some(region) = self.regions.get_mut(*first) {
+                    region.linked = linked;
+                }
+            }
+        }
+
+        self
+    }
+
+    // Parses an extraction JSON of any schema version, migrating older files up to the current one
+    pub fn from_json(data: &str, page: &str) -> Result<Extraction> {
+        let mut value: Value = serde_json::from_str(data)
+            .with_context(|| format!("Text file for {page} is not a valid extraction JSON."))?;
+
+        let version = schema_version(&value, page)?;
+        if version > SCHEMA_VERSION {
+            bail!("Text file for {page} uses schema version {version}, but this version of mangatra only reads up to version {SCHEMA_VERSION}. Update mangatra to read it.");
+        }
+
+        for (from_version, migrate) in MIGRATIONS.iter().enumerate().skip(version as usize) {
+            value = migrate(value, page)?;
+            value["schema_version"] = json!(from_version + 1);
+        }
+
+        serde_json::from_value(value)
+            .with_context(|| format!("Text file for {page} is not a valid extraction JSON."))
+    }
+
+    // Takes the translations of the regions with matching IDs from another copy of the page,
+    // e.g. one that went through a spreadsheet and lost its geometry on the way
+    pub fn merge_translations(&mut self, translated: &Extraction) {
+        let translations: IndexMap<&str, &str> = translated
+            .regions
+            .iter()
+            .map(|region| (region.id.as_str(), region.translation.as_str()))
+            .collect();
+
+        for region in self.regions.iter_mut() {
+            if let Some(translation) = translations.get(region.id.as_str()) {
+                region.translation = translation.to_string();
+            }
+        }
+    }
+
+    // Applies corrections to the regions with matching IDs. A corrected box was drawn by hand, so it
+    // has no detection score.
+    pub fn apply_corrections(&mut self, corrections: &[RegionCorrection]) -> Result<()> {
+        for correction in corrections {
+            let region = match self
+                .regions
+                .iter_mut()
+                .find(|region| region.id == correction.id)
+            {
+                Some(region) => region,
+                None => bail!("Page {} has no region {}.", self.page, correction.id),
+            };
+
+            if let Some(text_box) = correction.text_box {
+                region.text_box = Some(TextBox {
+                    score: None,
+                    ..text_box
+                });
+            }
+            if let Some(text) = &correction.text {
+                region.text = text.clone();
+            }
+            if let Some(translation) = &correction.translation {
+                region.translation = translation.clone();
+            }
+            if let Some(padding) = correction.padding {
+                region.padding = Some(padding);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Returns the translation for each of the detected regions on the page, in detection order.
+    // Regions that were removed from the file get "None" and are left untouched.
+    pub fn aligned_translations(&self, num_regions: usize) -> Vec<Option<&str>> {
+        self.aligned_regions(num_regions)
+            .into_iter()
+            .map(|region| region.map(|region| region.translation.as_str()))
+            .collect()
+    }
+
+    // Same as "aligned_translations" but returns the whole regions
+    pub fn aligned_regions(&self, num_regions: usize) -> Vec<Option<&Region>> {
+        let regions: IndexMap<&str, &Region> = self
+            .regions
+            .iter()
+            .map(|region| (region.id.as_str(), region))
+            .collect();
+
+        (0..num_regions)
+            .map(|index| regions.get(region_id(&self.page, index).as_str()).copied())
+            .collect()
+    }
+
+    // Groups of linked regions, as detection indices in reading order, whose first region's
+    // translation flows across them. Groups where another region got a translation of its own, or
+    // was removed from the file, are typeset region by region instead.
+    pub fn flowing_groups(&self, num_regions: usize) -> Vec<Vec<usize>> {
+        let regions = self.aligned_regions(num_regions);
+        let indices: IndexMap<String, usize> = (0..num_regions)
+            .map(|index| (region_id(&self.page, index), index))
+            .collect();
+
+        regions
+            .iter()
+            .enumerate()
+            .filter_map(|(first, region)| {
+                let region = (*region)?;
+                if region.linked.is_empty() || region.translation.trim().is_empty() {
+                    return None;
+                }
+
+                let mut group = vec![first];
+                for id in &region.linked {
+                    let index = *indices.get(id)?;
+                    if regions[index]?.translation.trim().is_empty() {
+                        group.push(index);
+                    } else {
+                        return None;
+                    }
+                }
+
+                Some(group)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extraction::{
+        is_empty_region, region_id, split_document, Annotation, AnnotationPlacement, Extraction,
+        PageSize, RegionCorrection, SCHEMA_VERSION,
+    };
+    use crate::text_box::TextBox;
+
+    #[test]
+    fn test_new_assigns_ids() {
+        let extraction = Extraction::new("page", vec!["a".to_string(), "b".to_string()]);
+
+        assert_eq!(extraction.regions[0].id, "page-0");
+        assert_eq!(extraction.regions[1].id, "page-1");
+        assert_eq!(extraction.regions[1].text, "b");
+        assert_eq!(extraction.regions[1].translation, "");
+    }
+
+    #[test]
+    fn test_aligned_translations_by_id() {
+        let data = format!(
+            r#"{{"page": "page", "regions": [
+                {{"id": "{}", "text": "c", "translation": "C"}},
+                {{"id": "{}", "text": "a", "translation": "A"}}
+            ]}}"#,
+            region_id("page", 2),
+            region_id("page", 0)
+        );
+
+        let extraction = Extraction::from_json(&data, "ignored").unwrap();
+
+        assert_eq!(
+            extraction.aligned_translations(3),
+            vec![Some("A"), None, Some("C")]
+        );
+    }
+
+    #[test]
+    fn test_legacy_formats() {
+        let map = Extraction::from_json(r#"{"a": "A", "b": "B"}"#, "page").unwrap();
+        let wrapped =
+            Extraction::from_json(r#"{"text": [["a", "A"], ["b", "B"]]}"#, "page").unwrap();
+
+        assert_eq!(map, wrapped);
+        assert_eq!(map.schema_version, SCHEMA_VERSION);
+        assert_eq!(map.regions[1].id, "page-1");
+        assert_eq!(map.aligned_translations(2), vec![Some("A"), Some("B")]);
+
+        let error = Extraction::from_json("[1, 2]", "page").unwrap_err();
+
+        assert_eq!(
+            format!("{error}"),
+            "Text file for page is not a valid extraction JSON."
+        );
+    }
+
+    #[test]
+    fn test_empty_regions() {
+        assert!(is_empty_region(" \n", Some(90), 0));
+        assert!(is_empty_region("text", Some(20), 30));
+        assert!(!is_empty_region("text", Some(20), 0));
+        assert!(!is_empty_region("text", None, 30));
+
+        let extraction =
+            Extraction::new("page", vec!["a".to_string(), "".to_string()]).with_confidences(&[80]);
+        let json = serde_json::to_string(&extraction).unwrap();
+
+        assert!(json.contains(r#""confidence":80"#));
+        assert_eq!(Extraction::from_json(&json, "page").unwrap(), extraction);
+
+        let regions = extraction.aligned_regions(3);
+
+        assert!(!regions[0].unwrap().is_empty(50));
+        assert!(regions[1].unwrap().is_empty(0));
+        assert!(regions[2].is_none());
+    }
+
+    #[test]
+    fn test_annotations() {
+        let data = r#"{"schema_version": 2, "page": "page", "regions": [
+            {"id": "page-0", "text": "a", "translation": "Thanks, Senpai!", "annotation": {"text": "Senpai: an older schoolmate"}},
+            {"id": "page-1", "text": "b", "translation": "B", "annotation": {"text": "TL note", "placement": "beside", "scale": 40}}
+        ]}"#;
+
+        let extraction = Extraction::from_json(data, "page").unwrap();
+
+        assert_eq!(
+            extraction.regions[0].annotation,
+            Some(Annotation {
+                text: "Senpai: an older schoolmate".to_string(),
+                placement: AnnotationPlacement::Above,
+                scale: 50,
+            })
+        );
+        assert_eq!(
+            extraction.regions[1].annotation.as_ref().unwrap().placement,
+            AnnotationPlacement::Beside
+        );
+
+        let json = serde_json::to_string(&extraction).unwrap();
+
+        assert!(json.contains(r#""placement":"beside","scale":40"#));
+        assert_eq!(Extraction::from_json(&json, "page").unwrap(), extraction);
+
+        // Regions without an annotation are written as before
+        let plain = serde_json::to_string(&Extraction::new("page", vec!["a".to_string()])).unwrap();
+
+        assert!(!plain.contains("annotation"));
+    }
+
+    #[test]
+    fn test_linked_regions() {
+        let mut extraction = Extraction::new("page", vec!["a".to_string(); 5])
+            .with_linked_regions(&[vec![3, 1], vec![0, 4]]);
+
+        assert_eq!(extraction.regions[3].linked, vec!["page-1".to_string()]);
+        assert!(extraction.regions[1].linked.is_empty());
+
+        let json = serde_json::to_string(&extraction).unwrap();
+
+        assert!(json.contains(r#""linked":["page-4"]"#));
+        assert_eq!(json.matches("linked").count(), 2);
+        assert_eq!(Extraction::from_json(&json, "page").unwrap(), extraction);
+
+        // Nothing flows until the first region of a group is translated
+        assert!(extraction.flowing_groups(5).is_empty());
+
+        extraction.regions[3].translation = "Don't you dare...".to_string();
+        extraction.regions[0].translation = "Wait".to_string();
+        extraction.regions[4].translation = "for me!".to_string();
+
+        // The second group was translated region by region
+        assert_eq!(extraction.flowing_groups(5), vec![vec![3, 1]]);
+        // Nor does it flow on to a region that was removed
+        extraction.regions.remove(1);
+        assert!(extraction.flowing_groups(5).is_empty());
+    }
+
+    #[test]
+    fn test_corrections() {
+        let text_box = TextBox {
+            x: 10,
+            y: 10,
+            width: 30,
+            height: 20,
+            score: Some(70),
+        };
+        let mut extraction = Extraction::new("page", vec!["a".to_string(), "b".to_string()])
+            .with_geometry(
+                PageSize {
+                    width: 100,
+                    height: 100,
+                },
+                &[text_box, text_box],
+            );
+
+        let corrections: Vec<RegionCorrection> = serde_json::from_str(
+            r#"[
+                {"id": "page-0", "translation": "A", "padding": 4},
+                {"id": "page-1", "box": {"x": 5, "y": 50, "width": 40, "height": 20, "score": 12}, "text": "B?"}
+            ]"#,
+        )
+        .unwrap();
+        extraction.apply_corrections(&corrections).unwrap();
+
+        assert_eq!(extraction.regions[0].translation, "A");
+        assert_eq!(extraction.regions[0].text_box, Some(text_box));
+        assert_eq!(extraction.regions[0].padding, Some(4));
+        assert_eq!(extraction.regions[1].padding, None);
+        assert_eq!(extraction.regions[1].text, "B?");
+        assert_eq!(extraction.regions[1].translation, "");
+        assert_eq!(
+            extraction.regions[1].text_box,
+            Some(TextBox {
+                x: 5,
+                y: 50,
+                width: 40,
+                height: 20,
+                score: None,
+            })
+        );
+
+        let error = extraction
+            .apply_corrections(&[RegionCorrection {
+                id: "page-7".to_string(),
+                text_box: None,
+                text: None,
+                translation: Some("?".to_string()),
+                padding: None,
+            }])
+            .unwrap_err();
+
+        assert_eq!(format!("{error}"), "Page page has no region page-7.");
+    }
+
+    #[test]
+    fn test_split_document() {
+        let document = vec![
+            Extraction::new("002", vec!["b".to_string()]),
+            Extraction::new("001", vec!["a".to_string()]),
+            Extraction::new("003", vec!["c".to_string()]),
+        ];
+
+        let pages = split_document(&document, &["001", "002"]).unwrap();
+
+        assert_eq!(pages, vec![document[1].clone(), document[0].clone()]);
+
+        let error = split_document(&document, &["001", "004", "005"]).unwrap_err();
+
+        assert_eq!(
+            format!("{error}"),
+            "The translation document has no regions for 004, 005."
+        );
+    }
+
+    #[test]
+    fn test_schema_versions() {
+        let extraction = Extraction::new("page", vec!["a".to_string()]);
+        let json = serde_json::to_string(&extraction).unwrap();
+
+        assert!(json.starts_with(&format!(r#"{{"schema_version":{SCHEMA_VERSION},"#)));
+        assert_eq!(Extraction::from_json(&json, "page").unwrap(), extraction);
+
+        // Version 1 files have no stamp
+        let unversioned = r#"{"page": "page", "regions": [{"id": "page-0", "text": "a"}]}"#;
+
+        assert_eq!(
+            Extraction::from_json(unversioned, "page").unwrap(),
+            extraction
+        );
+
+        // Fields added by newer versions are ignored as long as the schema version is supported
+        let extended = r#"{"schema_version": 2, "page": "page", "regions": [{"id": "page-0", "text": "a", "speaker": "narrator"}]}"#;
+
+        assert_eq!(Extraction::from_json(extended, "page").unwrap(), extraction);
+
+        let error = Extraction::from_json(
+            r#"{"schema_version": 99, "page": "page", "regions": []}"#,
+            "page",
+        )
+        .unwrap_err();
+
+        assert!(format!("{error}").starts_with("Text file for page uses schema version 99"));
+
+        // Versions past u32 aren't truncated into a supported one
+        let error = Extraction::from_json(
+            r#"{"schema_version": 4294967298, "page": "page", "regions": []}"#,
+            "page",
+        )
+        .unwrap_err();
+
+        assert!(format!("{error}").starts_with("Text file for page uses schema version 4294967298"));
+
+        let error = Extraction::from_json(
+            r#"{"schema_version": 2, "page": "page", "regions": [{"id": 1}]}"#,
+            "page",
+        )
+        .unwrap_err();
+
+        // The cause is kept for the error chain instead of falling back to the legacy formats
+        assert!(format!("{error:#}").contains("invalid type"));
+    }
+}
@@ -0,0 +1,221 @@
+use crate::fonts;
+use anyhow::{bail, ensure, Context, Result};
+use clap::Subcommand;
+use rusttype::Font;
+use std::path::{Path, PathBuf};
+
+// Formats of the font files kept in a store
+const FONT_EXTENSIONS: [&str; 3] = ["ttf", "otf", "ttc"];
+const MAX_NAME_LENGTH: usize = 64;
+
+// Directory of fonts kept under a name, which replace jobs pick their font by instead of sending the
+// font file along with every job. Each font is a file named "<name>.ttf", ".otf" or ".ttc".
+#[derive(Clone, Debug)]
+pub struct FontStore {
+    directory: PathBuf,
+}
+
+impl FontStore {
+    pub fn open(directory: &Path) -> Result<FontStore> {
+        std::fs::create_dir_all(directory).with_context(|| {
+            format!(
+                "Could not create the font directory {}.",
+                directory.display()
+            )
+        })?;
+
+        Ok(FontStore {
+            directory: directory.to_path_buf(),
+        })
+    }
+
+    // Names of the stored fonts in alphabetical order
+    pub fn list(&self) -> Result<Vec<String>> {
+        let mut names = Vec::new();
+
+        for entry in std::fs::read_dir(&self.directory).with_context(|| {
+            format!(
+                "Could not read the font directory {}.",
+                self.directory.display()
+            )
+        })? {
+            let path = entry?.path();
+
+            if let (Some(name), true) = (
+                path.file_stem().and_then(|stem| stem.to_str()),
+                font_extension(&path).is_some(),
+            ) {
+                names.push(name.to_string());
+            }
+        }
+
+        names.sort();
+
+        Ok(names)
+    }
+
+    // Stores a copy of a font file under "name", replacing the font stored under it before
+    pub fn add(&self, name: &str, path: &Path) -> Result<()> {
+        check_name(name)?;
+
+        let extension = match font_extension(path) {
+            Some(extension) => extension,
+            None => bail!(
+                "{} is not a font file. Fonts are stored from .ttf, .otf or .ttc files.",
+                path.display()
+            ),
+        };
+        // Fonts that can't be loaded would only fail the jobs that use them
+        fonts::load_font(path)?;
+
+        // The earlier font may have been in another format
+        if let Some(earlier) = self.path(name) {
+            std::fs::remove_file(earlier)?;
+        }
+
+        std::fs::copy(path, self.directory.join(format!("{name}.{extension}")))
+            .with_context(|| format!("Could not store the font {name}."))?;
+
+        Ok(())
+    }
+
+    pub fn remove(&self, name: &str) -> Result<()> {
+        check_name(name)?;
+
+        match self.path(name) {
+            Some(path) => std::fs::remove_file(path)
+                .with_context(|| format!("Could not remove the font {name}.")),
+            None => bail!("No font named {name} is stored."),
+        }
+    }
+
+    pub fn load(&self, name: &str) -> Result<Font<'static>> {
+        check_name(name)?;
+
+        match self.path(name) {
+            Some(path) => fonts::load_font(&path),
+            None => bail!(
+                "No font named {name} is stored in {}.",
+                self.directory.display()
+            ),
+        }
+    }
+
+    fn path(&self, name: &str) -> Option<PathBuf> {
+        FONT_EXTENSIONS
+            .iter()
+            .map(|extension| self.directory.join(format!("{name}.{extension}")))
+            .find(|path| path.is_file())
+    }
+}
+
+// Names become file names, so they can't hold anything that leads out of the store's directory
+fn check_name(name: &str) -> Result<()> {
+    ensure!(
+        !name.is_empty()
+            && name.len() <= MAX_NAME_LENGTH
+            && name
+                .chars()
+                .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_'),
+        "\"{name}\" is not a valid font name. Names are up to {MAX_NAME_LENGTH} letters, digits, dashes and underscores."
+    );
+
+    Ok(())
+}
+
+fn font_extension(path: &Path) -> Option<&'static str> {
+    let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+
+    FONT_EXTENSIONS
+        .into_iter()
+        .find(|font_extension| *font_extension == extension)
+}
+
+#[derive(clap::Args, Debug)]
+pub struct FontsArgs {
+    #[arg(
+        long,
+        help = "Directory the fonts are kept in, the --font-dir of the workers"
+    )]
+    pub dir: PathBuf,
+    #[command(subcommand)]
+    pub command: FontsCommand,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum FontsCommand {
+    #[command(about = "List the names of the stored fonts")]
+    List,
+    #[command(about = "Store a font under a name, replacing the font stored under it before")]
+    Add {
+        #[arg(help = "Name replace jobs pick the font by")]
+        name: String,
+        #[arg(help = "TrueType or OpenType font file")]
+        file: PathBuf,
+    },
+    #[command(about = "Remove a stored font")]
+    Remove {
+        #[arg(help = "Name of the font")]
+        name: String,
+    },
+}
+
+impl FontsArgs {
+    pub fn run(self) -> Result<()> {
+        let store = FontStore::open(&self.dir)?;
+
+        match self.command {
+            FontsCommand::List => {
+                for name in store.list()? {
+                    println!("{name}");
+                }
+            }
+            FontsCommand::Add { name, file } => {
+                store.add(&name, &file)?;
+                println!("Stored {} as {name}", file.display());
+            }
+            FontsCommand::Remove { name } => {
+                store.remove(&name)?;
+                println!("Removed {name}");
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::font_store::FontStore;
+    use crate::fonts::BUNDLED_FONT;
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_font_store() {
+        let directory = TempDir::new().unwrap();
+        let store = FontStore::open(&directory.path().join("fonts")).unwrap();
+        assert!(store.list().unwrap().is_empty());
+
+        let font_path = directory.path().join("Comic.TTF");
+        std::fs::write(&font_path, BUNDLED_FONT).unwrap();
+        store.add("comic", &font_path).unwrap();
+        store.add("dialogue_2", &font_path).unwrap();
+        assert_eq!(store.list().unwrap(), vec!["comic", "dialogue_2"]);
+        assert!(store.load("comic").is_ok());
+
+        // Names can't leave the directory, and only fonts are stored
+        assert!(store.add("../comic", &font_path).is_err());
+        assert!(store.load("").is_err());
+        let not_a_font = directory.path().join("notes.ttf");
+        std::fs::write(&not_a_font, "not a font").unwrap();
+        assert!(store.add("notes", &not_a_font).is_err());
+        assert!(store
+            .add("notes", &directory.path().join("notes.txt"))
+            .is_err());
+
+        store.remove("comic").unwrap();
+        assert_eq!(store.list().unwrap(), vec!["dialogue_2"]);
+        assert!(store.load("comic").is_err());
+        assert!(store.remove("comic").is_err());
+    }
+}
@@ -0,0 +1,204 @@
+use anyhow::{Context, Result};
+use rusttype::Font;
+use std::path::Path;
+use tracing::warn;
+
+// Comic font compiled into the binary. It only has glyphs for ASCII, so it is only the default for English.
+pub const BUNDLED_FONT: &[u8] = include_bytes!("../assets/wildwordsroman.ttf");
+
+// Writing systems that need fonts of their own
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Script {
+    Latin,
+    Cyrillic,
+    Cjk,
+    Thai,
+}
+
+impl Script {
+    // Script a language is written in, from its ISO 639-1 or 639-2 code. Region and variant suffixes
+    // are ignored, so "pt-BR", "chi_sim" and "jpn_vert" work as well.
+    pub fn of_language(lang: &str) -> Option<Script> {
+        let script = match primary_language(lang).as_str() {
+            "en" | "eng" | "es" | "spa" | "fr" | "fra" | "fre" | "de" | "deu" | "ger" | "it"
+            | "ita" | "pt" | "por" | "nl" | "nld" | "dut" | "pl" | "pol" | "cs" | "ces" | "cze"
+            | "sk" | "slk" | "slo" | "sl" | "slv" | "hr" | "hrv" | "ro" | "ron" | "rum" | "hu"
+            | "hun" | "tr" | "tur" | "vi" | "vie" | "id" | "ind" | "ms" | "msa" | "may" | "tl"
+            | "tgl" | "fil" | "sv" | "swe" | "da" | "dan" | "no" | "nb" | "nn" | "nor" | "fi"
+            | "fin" | "et" | "est" | "lv" | "lav" | "lt" | "lit" | "ca" | "cat" | "eu" | "eus"
+            | "baq" | "gl" | "glg" | "ga" | "gle" | "is" | "isl" | "ice" | "sq" | "sqi" | "alb"
+            | "af" | "afr" | "sw" | "swa" | "la" | "lat" => Script::Latin,
+            "ru" | "rus" | "uk" | "ukr" | "be" | "bel" | "bg" | "bul" | "sr" | "srp" | "mk"
+            | "mkd" | "mac" | "kk" | "kaz" | "ky" | "kir" | "mn" | "mon" | "tg" | "tgk" => {
+                Script::Cyrillic
+            }
+            "ja" | "jpn" | "zh" | "zho" | "chi" | "ko" | "kor" => Script::Cjk,
+            "th" | "tha" => Script::Thai,
+            _ => return None,
+        };
+
+        Some(script)
+    }
+
+    // Fonts that cover the script, in order of preference, at the paths Linux distributions,
+    // macOS and Windows install them to
+    pub fn system_fonts(self) -> &'static [&'static str] {
+        match self {
+            Script::Latin | Script::Cyrillic => &[
+                "/usr/share/fonts/truetype/noto/NotoSans-Regular.ttf",
+                "/usr/share/fonts/noto/NotoSans-Regular.ttf",
+                "/usr/share/fonts/truetype/dejavu/DejaVuSans.ttf",
+                "/usr/share/fonts/TTF/DejaVuSans.ttf",
+                "/usr/share/fonts/dejavu-sans-fonts/DejaVuSans.ttf",
+                "/usr/share/fonts/truetype/liberation/LiberationSans-Regular.ttf",
+                "/System/Library/Fonts/Supplemental/Arial.ttf",
+                "/Library/Fonts/Arial.ttf",
+                "C:\\Windows\\Fonts\\arial.ttf",
+            ],
+            Script::Cjk => &[
+                "/usr/share/fonts/opentype/noto/NotoSansCJK-Regular.ttc",
+                "/usr/share/fonts/noto-cjk/NotoSansCJK-Regular.ttc",
+                "/usr/share/fonts/google-noto-cjk/NotoSansCJK-Regular.ttc",
+                "/usr/share/fonts/truetype/wqy/wqy-microhei.ttc",
+                "/System/Library/Fonts/Hiragino Sans GB.ttc",
+                "/System/Library/Fonts/PingFang.ttc",
+                "C:\\Windows\\Fonts\\msgothic.ttc",
+                "C:\\Windows\\Fonts\\msyh.ttc",
+            ],
+            Script::Thai => &[
+                "/usr/share/fonts/truetype/noto/NotoSansThai-Regular.ttf",
+                "/usr/share/fonts/noto/NotoSansThai-Regular.ttf",
+                "/usr/share/fonts/truetype/tlwg/Garuda.ttf",
+                "/usr/share/fonts/tlwg/Garuda.otf",
+                "/System/Library/Fonts/Supplemental/Ayuthaya.ttf",
+                "C:\\Windows\\Fonts\\tahoma.ttf",
+            ],
+        }
+    }
+}
+
+pub fn bundled_font() -> Font<'static> {
+    Font::try_from_bytes(BUNDLED_FONT).expect("The bundled font is a valid TrueType font.")
+}
+
+// Loads a TrueType or OpenType font file. Of a font collection (.ttc), the first font is used.
+pub fn load_font(path: &Path) -> Result<Font<'static>> {
+    let data = std::fs::read(path)
+        .with_context(|| format!("Could not read the font {}.", path.display()))?;
+
+    Font::try_from_vec_and_index(data, 0).with_context(|| {
+        format!(
+            "{} is not a valid TrueType or OpenType font.",
+            path.display()
+        )
+    })
+}
+
+// Font to typeset translations into the given language with when none is configured. English uses
+// the bundled comic font; other languages use the first installed system font that covers their script.
+pub fn default_font(target_lang: &str) -> Font<'static> {
+    let script = match Script::of_language(target_lang) {
+        Some(script) => script,
+        None => {
+            warn!("No default font is known for the language \"{target_lang}\"; using the bundled font, which only covers English. Set a font with --font.");
+            return bundled_font();
+        }
+    };
+
+    // Other languages written in Latin script need accented letters, which the bundled font doesn't have
+    if is_english(target_lang) {
+        return bundled_font();
+    }
+
+    for path in script.system_fonts() {
+        let path = Path::new(path);
+
+        if path.is_file() {
+            match load_font(path) {
+                Ok(font) => return font,
+                Err(e) => warn!("{e}"),
+            }
+        }
+    }
+
+    warn!("No installed font for {script:?} text was found; using the bundled font, which only covers English. Set a font with --font.");
+
+    bundled_font()
+}
+
+pub fn is_english(lang: &str) -> bool {
+    matches!(primary_language(lang).as_str(), "en" | "eng")
+}
+
+pub fn is_japanese(lang: &str) -> bool {
+    matches!(primary_language(lang).as_str(), "ja" | "jpn")
+}
+
+pub fn is_korean(lang: &str) -> bool {
+    matches!(primary_language(lang).as_str(), "ko" | "kor")
+}
+
+fn primary_language(lang: &str) -> String {
+    lang.split(['-', '_'])
+        .next()
+        .unwrap_or_default()
+        .to_ascii_lowercase()
+}
+
+// Characters of the text the font has no glyph for, each listed once. Whitespace is never missing.
+pub fn missing_glyphs(font: &Font, text: &str) -> Vec<char> {
+    let mut missing: Vec<char> = Vec::new();
+
+    for character in text.chars() {
+        if !character.is_whitespace()
+            && font.glyph(character).id().0 == 0
+            && !missing.contains(&character)
+        {
+            missing.push(character);
+        }
+    }
+
+    missing
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fonts::{bundled_font, default_font, missing_glyphs, Script};
+
+    #[test]
+    fn test_language_scripts() {
+        assert_eq!(Script::of_language("en"), Some(Script::Latin));
+        assert_eq!(Script::of_language("pt-BR"), Some(Script::Latin));
+        assert_eq!(Script::of_language("SPA"), Some(Script::Latin));
+        assert_eq!(Script::of_language("ru"), Some(Script::Cyrillic));
+        assert_eq!(Script::of_language("uk_UA"), Some(Script::Cyrillic));
+        assert_eq!(Script::of_language("chi_sim"), Some(Script::Cjk));
+        assert_eq!(Script::of_language("jpn_vert"), Some(Script::Cjk));
+        assert_eq!(Script::of_language("th"), Some(Script::Thai));
+        assert_eq!(Script::of_language("xx"), None);
+        assert_eq!(Script::of_language(""), None);
+    }
+
+    #[test]
+    fn test_missing_glyphs() {
+        let font = bundled_font();
+
+        assert!(missing_glyphs(&font, "WAIT... WHAT?!\n").is_empty());
+        assert_eq!(missing_glyphs(&font, "¡Mañana, mañana!"), vec!['¡', 'ñ']);
+        assert_eq!(missing_glyphs(&font, "Да"), vec!['Д', 'а']);
+    }
+
+    #[test]
+    fn test_default_font() {
+        let bundled_glyphs = bundled_font().glyph_count();
+
+        assert_eq!(default_font("en").glyph_count(), bundled_glyphs);
+        assert_eq!(default_font("xx").glyph_count(), bundled_glyphs);
+
+        // Russian gets a font with Cyrillic glyphs if one is installed, and the bundled font otherwise
+        let russian = default_font("ru");
+        assert!(
+            missing_glyphs(&russian, "Да").is_empty() || russian.glyph_count() == bundled_glyphs
+        );
+    }
+}
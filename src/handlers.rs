@@ -0,0 +1,1516 @@
+use crate::config::Config;
+use crate::credits::CreditsPlacement;
+use crate::detection::{self, Detector, Origin, TextBox, TextRegions};
+use crate::extraction::{self, Annotation, Extraction, PageSize, RegionCorrection};
+use crate::fonts;
+use crate::layout;
+use crate::ocr::Ocr;
+use crate::replacer::{Replacer, TextOverflow};
+use crate::storage::{self, ObjectUri};
+use crate::text_box::{self, ReadingDirection};
+#[cfg(feature = "async")]
+use crate::utils::cancellation::CancelOnDrop;
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::linebreak::LineBreaking;
+use crate::utils::metadata::{self, ImageMetadata};
+use crate::utils::{deskew, enhance, image_conversion, mask, polarity, validation};
+#[cfg(feature = "async")]
+use anyhow::anyhow;
+use anyhow::{bail, ensure, Context, Result};
+use image::{DynamicImage, GenericImageView, GrayImage, RgbImage};
+use imageproc::rect::Rect;
+use opencv::{core, prelude::*};
+use rayon::prelude::*;
+use rusttype::Font;
+use serde::{Deserialize, Serialize};
+use std::collections::HashMap;
+use std::io::{Read, Seek, Write};
+use std::path::Path;
+use std::sync::Arc;
+use tracing::warn;
+use zip::write::FileOptions;
+use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+// Where the image of a request comes from: sent along with it, or downloaded from a URL, such as a
+// presigned object storage URL, so clients don't have to pass large pages through themselves
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageSource {
+    Bytes(Vec<u8>),
+    Url(String),
+    // An s3:// or gs:// URI, read with the server's own credentials
+    Object(String),
+}
+
+impl ImageSource {
+    // The encoded image, downloaded within the config's allowed hosts and size limit if it's a URL
+    pub fn read(self, config: &Config) -> Result<Vec<u8>> {
+        match self {
+            ImageSource::Bytes(image_bytes) => Ok(image_bytes),
+            ImageSource::Url(url) => config
+                .fetch_policy
+                .fetch(&url)
+                .with_context(|| format!("Could not download the image at {url}.")),
+            ImageSource::Object(uri) => {
+                let uri = match ObjectUri::parse(&uri)? {
+                    Some(uri) => uri,
+                    None => bail!("{uri} is not an s3:// or gs:// URI."),
+                };
+                // Requests could otherwise read any bucket the server's credentials can
+                ensure!(
+                    config
+                        .fetch_policy
+                        .allowed_hosts
+                        .contains(&uri.bucket_uri()),
+                    "Images can't be read from {}. Allow the bucket with --allowed-image-hosts.",
+                    uri.bucket_uri()
+                );
+
+                storage::get_object(&uri, config.fetch_policy.max_bytes)
+            }
+        }
+    }
+}
+
+// Text found in each detected region along with where the region sits on the page
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ExtractedText {
+    pub text: Vec<String>,
+    pub boxes: Vec<TextBox>,
+    // OCR confidence of each region from 0 to 100
+    pub confidences: Vec<u8>,
+}
+
+// Runs detection and OCR over an encoded image, returning the text found in each region
+pub fn extract_text(
+    config: &Config,
+    image_bytes: &[u8],
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<String>> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+
+    extract_text_from_image(config, &image, cancellation_token)
+}
+
+// Whitens the text regions of an encoded image and returns the cleaned page encoded as a PNG
+pub fn clean_image(
+    config: &Config,
+    image_bytes: &[u8],
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<u8>> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+
+    encode_png(
+        &clean_dynamic_image(config, &image, cancellation_token)?,
+        image_bytes,
+    )
+}
+
+// Same as "clean_image", but also returns the mask of the areas that were erased, white on black,
+// for clients that blend the cleaning into the page themselves
+pub fn clean_image_with_mask(
+    config: &Config,
+    image_bytes: &[u8],
+    cancellation_token: &CancellationToken,
+) -> Result<(Vec<u8>, GrayImage)> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+    let page = detect_page(config, &image, cancellation_token)?;
+    let cleaned_page = clean_detected_page(config, page, None, cancellation_token)?;
+    let (width, height) = cleaned_page.image.dimensions();
+    let mask = mask::from_areas(width, height, &cleaned_page.areas);
+
+    Ok((encode_png(&cleaned_page.image, image_bytes)?, mask))
+}
+
+// Replaces the text regions of an encoded image with the given translations and returns a PNG.
+// Translations are matched to the detected regions by region ID.
+pub fn replace_text(
+    config: &Config,
+    image_bytes: &[u8],
+    extraction: &Extraction,
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<u8>> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+
+    encode_png(
+        &replace_text_in_image(config, &image, extraction, cancellation_token)?,
+        image_bytes,
+    )
+}
+
+// Same as "replace_text" but typesets the translations in "font" instead of the configured font
+pub fn replace_text_in_font(
+    config: &Config,
+    image_bytes: &[u8],
+    extraction: &Extraction,
+    font: &Font<'static>,
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<u8>> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+    let page = detect_page(config, &image, cancellation_token)?;
+    let rendered_page =
+        typeset_detected_page_in_font(config, page, extraction, font, cancellation_token)?;
+
+    encode_png(&rendered_page.image, image_bytes)
+}
+
+// An encoded page sent to "replace_pages", named like its page in the translation document
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct NamedPage {
+    pub page: String,
+    pub image_bytes: Vec<u8>,
+}
+
+// Replaces the text of several pages, e.g. a chapter, with their translations from one document that
+// holds them all, keyed by page name and region ID, such as a table read with "table::from_table".
+// Returns the pages encoded as PNGs, in the order they were given.
+pub fn replace_pages(
+    config: &Config,
+    pages: &[NamedPage],
+    document: &[Extraction],
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<Vec<u8>>> {
+    let page_names: Vec<&str> = pages.iter().map(|page| page.page.as_str()).collect();
+    let extractions = extraction::split_document(document, &page_names)?;
+
+    pages
+        .par_iter()
+        .zip(extractions.par_iter())
+        .map(|(page, extraction)| {
+            replace_text(config, &page.image_bytes, extraction, cancellation_token)
+                .with_context(|| format!("Could not replace the text of {}.", page.page))
+        })
+        .collect()
+}
+
+// Largest entry of an archive that is read, so that an archive of a few kilobytes that unpacks into
+// gigabytes (a zip bomb) can't take up the handler's memory
+const MAX_ARCHIVE_ENTRY_BYTES: u64 = 64 * 1024 * 1024;
+
+// What "process_archive" writes for every page of an archive
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ArchiveMode {
+    // The cleaned page, as "<page>_cleaned.png"
+    Clean,
+    // The extraction JSON, as "<page>.json"
+    Extract,
+    // The page typeset with the translations of "<page>.json" from the same archive, as "<page>_output.png"
+    Replace,
+}
+
+// Processes the pages of a zip or CBZ archive, e.g. a whole chapter uploaded by a web client, and
+// writes the results into a zip archive, named after the pages the way the command line names its
+// output. Pages are named after their file name without directories. They are decoded and processed
+// "config.threads" at a time (as many as rayon has threads if not set), and the results of each batch
+// are written before the next one is read, so a long chapter isn't held in memory all at once.
+// Entries larger than "MAX_ARCHIVE_ENTRY_BYTES" fail the archive.
+pub fn process_archive<R: Read + Seek, W: Write + Seek>(
+    config: &Config,
+    archive: R,
+    output: W,
+    mode: ArchiveMode,
+    cancellation_token: &CancellationToken,
+) -> Result<W> {
+    let mut archive = ZipArchive::new(archive).context("The archive is not a valid zip file.")?;
+
+    let mut pages: Vec<(String, usize)> = Vec::new();
+    let mut translations: HashMap<String, usize> = HashMap::new();
+
+    for index in 0..archive.len() {
+        let file = archive.by_index(index)?;
+        if file.is_dir() {
+            continue;
+        }
+
+        let file_name = match Path::new(file.name()).file_name() {
+            Some(file_name) => file_name.to_string_lossy().to_string(),
+            None => continue,
+        };
+        let page = extraction::page_name(&file_name);
+
+        // Image extensions are checked in lowercase, as archives are often made on Windows
+        if validation::validate_image(Path::new(&file_name.to_lowercase())).is_ok() {
+            ensure!(
+                !pages.iter().any(|(other, _)| *other == page),
+                "The archive has several pages named {page}."
+            );
+            pages.push((page, index));
+        } else if mode == ArchiveMode::Replace && file_name.to_lowercase().ends_with(".json") {
+            translations.insert(page, index);
+        }
+    }
+
+    ensure!(!pages.is_empty(), "The archive has no pages.");
+    pages.sort();
+
+    let batch_size = config
+        .threads
+        .unwrap_or_else(rayon::current_num_threads)
+        .max(1);
+    let mut output = ZipWriter::new(output);
+
+    for batch in pages.chunks(batch_size) {
+        cancellation_token.check()?;
+
+        let mut inputs = Vec::new();
+
+        for (page, index) in batch {
+            let image_bytes = read_entry(&mut archive, *index, MAX_ARCHIVE_ENTRY_BYTES)?;
+
+            let extraction = match mode {
+                ArchiveMode::Replace => {
+                    let index = match translations.get(page) {
+                        Some(index) => *index,
+                        None => bail!("The archive has no translations for {page}."),
+                    };
+                    let data = read_entry(&mut archive, index, MAX_ARCHIVE_ENTRY_BYTES)?;
+                    let data = String::from_utf8(data)
+                        .with_context(|| format!("The translations for {page} are not UTF-8."))?;
+
+                    Some(Extraction::from_json(&data, page)?)
+                }
+                _ => None,
+            };
+
+            inputs.push((page, image_bytes, extraction));
+        }
+
+        let results: Vec<(String, Vec<u8>)> = inputs
+            .par_iter()
+            .map(|(page, image_bytes, extraction)| {
+                process_archived_page(
+                    config,
+                    page,
+                    image_bytes,
+                    extraction.as_ref(),
+                    mode,
+                    cancellation_token,
+                )
+                .with_context(|| format!("Could not process {page}."))
+            })
+            .collect::<Result<_>>()?;
+
+        for (file_name, data) in results {
+            // PNGs are compressed already
+            let compression = match file_name.ends_with(".png") {
+                true => CompressionMethod::Stored,
+                false => CompressionMethod::Deflated,
+            };
+
+            output.start_file(
+                file_name,
+                FileOptions::default().compression_method(compression),
+            )?;
+            output.write_all(&data)?;
+        }
+    }
+
+    Ok(output.finish()?)
+}
+
+// Reads an entry of an archive, refusing ones larger than "max_bytes"
+fn read_entry<R: Read + Seek>(
+    archive: &mut ZipArchive<R>,
+    index: usize,
+    max_bytes: u64,
+) -> Result<Vec<u8>> {
+    let file = archive.by_index(index)?;
+    let name = file.name().to_string();
+    ensure!(
+        file.size() <= max_bytes,
+        "{name} in the archive is {} bytes, which is more than the limit of {max_bytes} bytes.",
+        file.size()
+    );
+
+    // The size in the header can be made up, so one byte more than allowed is read to tell whether
+    // the entry goes past the limit
+    let mut data = Vec::new();
+    file.take(max_bytes + 1)
+        .read_to_end(&mut data)
+        .with_context(|| format!("Could not read {name} from the archive."))?;
+    ensure!(
+        data.len() as u64 <= max_bytes,
+        "{name} in the archive is more than the limit of {max_bytes} bytes."
+    );
+
+    Ok(data)
+}
+
+// Runs a page of an archive through "process_archive"'s mode, returning the name and data of its result
+fn process_archived_page(
+    config: &Config,
+    page: &str,
+    image_bytes: &[u8],
+    extraction: Option<&Extraction>,
+    mode: ArchiveMode,
+    cancellation_token: &CancellationToken,
+) -> Result<(String, Vec<u8>)> {
+    match (mode, extraction) {
+        (ArchiveMode::Clean, _) => Ok((
+            format!("{page}_cleaned.png"),
+            clean_image(config, image_bytes, cancellation_token)?,
+        )),
+        (ArchiveMode::Extract, _) => {
+            let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+            let extracted_text = extract_regions_from_image(config, &image, cancellation_token)?;
+            let size = PageSize {
+                width: image.width(),
+                height: image.height(),
+            };
+
+            let extraction = page_extraction(config, page, extracted_text, size);
+
+            Ok((
+                format!("{page}.json"),
+                serde_json::to_string_pretty(&extraction)?.into_bytes(),
+            ))
+        }
+        (ArchiveMode::Replace, Some(extraction)) => Ok((
+            format!("{page}_output.png"),
+            replace_text(config, image_bytes, extraction, cancellation_token)?,
+        )),
+        (ArchiveMode::Replace, None) => bail!("There are no translations for {page}."),
+    }
+}
+
+// Re-renders a page that was processed before after applying corrections to its regions, e.g. from
+// a proofreading UI. Detection isn't run again: the regions are typeset in the boxes of the
+// extraction. Returns the page encoded as a PNG along with the corrected extraction.
+pub fn correct_page(
+    config: &Config,
+    image_bytes: &[u8],
+    extraction: &Extraction,
+    corrections: &[RegionCorrection],
+    cancellation_token: &CancellationToken,
+) -> Result<(Vec<u8>, Extraction)> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+
+    let (corrected_page, extraction) =
+        correct_image(config, &image, extraction, corrections, cancellation_token)?;
+
+    Ok((encode_png(&corrected_page, image_bytes)?, extraction))
+}
+
+// Re-renders only the corrected regions of a page that was already replaced, e.g. after a proofreader
+// fixed the translation of one bubble, instead of recompositing the whole page. The regions are
+// typeset over the cleaned page the replaced page was made from and pasted onto the replaced page.
+// Returns the page encoded as a PNG along with the corrected extraction.
+pub fn rerender_regions(
+    config: &Config,
+    replaced_bytes: &[u8],
+    cleaned_bytes: &[u8],
+    extraction: &Extraction,
+    corrections: &[RegionCorrection],
+    cancellation_token: &CancellationToken,
+) -> Result<(Vec<u8>, Extraction)> {
+    let replaced_page = image_conversion::load_image_within(replaced_bytes, config.max_pixels)?;
+    let cleaned_page = image_conversion::load_image_within(cleaned_bytes, config.max_pixels)?;
+
+    let (rerendered_page, extraction) = rerender_image_regions(
+        config,
+        &replaced_page,
+        &cleaned_page,
+        extraction,
+        corrections,
+        cancellation_token,
+    )?;
+
+    Ok((encode_png(&rerendered_page, replaced_bytes)?, extraction))
+}
+
+// Runs detection once and returns both the cleaned page (encoded as a PNG) and the extracted text
+pub fn clean_and_extract(
+    config: &Config,
+    image_bytes: &[u8],
+    cancellation_token: &CancellationToken,
+) -> Result<(Vec<u8>, ExtractedText)> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+
+    let (cleaned_page, extracted_text) =
+        clean_and_extract_image(config, &image, cancellation_token)?;
+
+    Ok((encode_png(&cleaned_page, image_bytes)?, extracted_text))
+}
+
+// Skips detection and runs OCR on exactly the given boxes, e.g. after they were corrected by hand
+pub fn extract_text_in_regions(
+    config: &Config,
+    image_bytes: &[u8],
+    boxes: &[TextBox],
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<String>> {
+    let image = image_conversion::load_image_within(image_bytes, config.max_pixels)?;
+
+    extract_text_in_image_regions(config, &image, boxes, cancellation_token)
+}
+
+// Same as "extract_text" but for an image that is already decoded
+pub fn extract_text_from_image(
+    config: &Config,
+    image: &DynamicImage,
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<String>> {
+    Ok(extract_regions_from_image(config, image, cancellation_token)?.text)
+}
+
+// Same as "extract_text_from_image" but also returns where each region is on the page
+pub fn extract_regions_from_image(
+    config: &Config,
+    image: &DynamicImage,
+    cancellation_token: &CancellationToken,
+) -> Result<ExtractedText> {
+    let page = detect_page(config, image, cancellation_token)?;
+
+    recognize_page(config, &page, cancellation_token)
+}
+
+// Same as "extract_regions_from_image", but also returns the crop of every region encoded as a PNG,
+// for clients that show the regions without cropping the page again themselves
+pub fn extract_regions_and_crops(
+    config: &Config,
+    image: &DynamicImage,
+    cancellation_token: &CancellationToken,
+) -> Result<(ExtractedText, Vec<Vec<u8>>)> {
+    let page = detect_page(config, image, cancellation_token)?;
+    let extracted_text = recognize_page(config, &page, cancellation_token)?;
+
+    Ok((extracted_text, page.encoded_crops()?))
+}
+
+// Same as "clean_image" but takes and returns decoded images
+pub fn clean_dynamic_image(
+    config: &Config,
+    image: &DynamicImage,
+    cancellation_token: &CancellationToken,
+) -> Result<DynamicImage> {
+    let page = detect_page(config, image, cancellation_token)?;
+
+    Ok(clean_detected_page(config, page, None, cancellation_token)?.image)
+}
+
+// Same as "extract_text_in_regions" but for an image that is already decoded
+pub fn extract_text_in_image_regions(
+    config: &Config,
+    image: &DynamicImage,
+    boxes: &[TextBox],
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<String>> {
+    let original_image = image_conversion::dynamic_image_to_mat(image)?;
+
+    let mut text_regions: core::Vector<core::Mat> = core::Vector::new();
+
+    for text_box in boxes {
+        ensure!(
+            text_box.x >= 0
+                && text_box.y >= 0
+                && text_box.width > 0
+                && text_box.height > 0
+                && text_box.x + text_box.width <= original_image.cols()
+                && text_box.y + text_box.height <= original_image.rows(),
+            "Box {text_box:?} must be non-empty and lie within the image."
+        );
+
+        text_regions.push(core::Mat::roi(&original_image, text_box.to_rect())?);
+    }
+
+    recognize_text(config, &text_regions, cancellation_token)
+}
+
+// Same as "clean_and_extract" but takes and returns decoded images
+pub fn clean_and_extract_image(
+    config: &Config,
+    image: &DynamicImage,
+    cancellation_token: &CancellationToken,
+) -> Result<(DynamicImage, ExtractedText)> {
+    let page = detect_page(config, image, cancellation_token)?;
+    let extracted_text = recognize_page(config, &page, cancellation_token)?;
+
+    let cleaned_page =
+        clean_detected_page(config, page, Some(&extracted_text), cancellation_token)?;
+
+    Ok((cleaned_page.image, extracted_text))
+}
+
+// Same as "replace_text" but takes and returns decoded images
+pub fn replace_text_in_image(
+    config: &Config,
+    image: &DynamicImage,
+    extraction: &Extraction,
+    cancellation_token: &CancellationToken,
+) -> Result<DynamicImage> {
+    let page = detect_page(config, image, cancellation_token)?;
+
+    Ok(typeset_detected_page(config, page, extraction, cancellation_token)?.image)
+}
+
+// Same as "correct_page" but takes and returns decoded images
+pub fn correct_image(
+    config: &Config,
+    image: &DynamicImage,
+    extraction: &Extraction,
+    corrections: &[RegionCorrection],
+    cancellation_token: &CancellationToken,
+) -> Result<(DynamicImage, Extraction)> {
+    let mut extraction = extraction.clone();
+    extraction.apply_corrections(corrections)?;
+
+    let boxes = extraction_boxes(&extraction)?;
+    let page = crop_page(config, image, &boxes, cancellation_token)?;
+
+    let rendered_page = typeset_detected_page(config, page, &extraction, cancellation_token)?;
+
+    Ok((rendered_page.image, extraction))
+}
+
+// Same as "rerender_regions" but takes and returns decoded images
+pub fn rerender_image_regions(
+    config: &Config,
+    replaced_page: &DynamicImage,
+    cleaned_page: &DynamicImage,
+    extraction: &Extraction,
+    corrections: &[RegionCorrection],
+    cancellation_token: &CancellationToken,
+) -> Result<(DynamicImage, Extraction)> {
+    ensure!(
+        replaced_page.dimensions() == cleaned_page.dimensions(),
+        "The replaced and the cleaned page must be the same size."
+    );
+
+    // The area a moved box was typeset in before would have to be cleaned again as well
+    if let Some(correction) = corrections
+        .iter()
+        .find(|correction| correction.text_box.is_some())
+    {
+        bail!(
+            "Region {} has a corrected box, which needs the whole page to be re-rendered.",
+            correction.id
+        );
+    }
+
+    let mut extraction = extraction.clone();
+    extraction.apply_corrections(corrections)?;
+
+    // The pages were already straightened and enhanced, if at all, before they were typeset
+    let boxes = extraction_boxes(&extraction)?;
+    let page = DetectedPage::crop(
+        image_conversion::dynamic_image_to_mat(cleaned_page)?,
+        None,
+        &boxes,
+        cancellation_token,
+    )?;
+
+    // Regions that weren't corrected have no translation here and are left as they are
+    let mut corrected_regions = extraction.clone();
+    corrected_regions.regions.retain(|region| {
+        corrections
+            .iter()
+            .any(|correction| correction.id == region.id)
+    });
+
+    let rendered_page = typeset_regions(
+        config,
+        page,
+        &corrected_regions,
+        Some(image_conversion::dynamic_image_to_mat(replaced_page)?),
+        &config.font,
+        cancellation_token,
+    )?;
+
+    Ok((rendered_page.image, extraction))
+}
+
+// Boxes of the regions of an extraction in detection order, for cropping the page without detection
+fn extraction_boxes(extraction: &Extraction) -> Result<Vec<TextBox>> {
+    extraction
+        .aligned_regions(extraction.regions.len())
+        .into_iter()
+        .enumerate()
+        .map(|(index, region)| {
+            region.and_then(|region| region.text_box).with_context(|| {
+                format!(
+                    "Region {} has no box to render it in. Extract the page again to record its boxes.",
+                    extraction::region_id(&extraction.page, index)
+                )
+            })
+        })
+        .collect()
+}
+
+// A page after detection. The handlers above are made up of the stages below, which can also be
+// run one at a time, e.g. on different threads of a pipeline.
+pub struct DetectedPage {
+    image: core::Mat,
+    text_regions: TextRegions,
+    origins: Vec<Origin>,
+    // Boxes of the regions, with the scores of the detection model if it found them
+    boxes: Vec<TextBox>,
+    // The same regions cropped out of the enhanced page (--enhance), which OCR reads instead
+    enhanced_regions: Option<TextRegions>,
+}
+
+impl DetectedPage {
+    // Crops the boxes out of the page, and out of its enhanced version if there is one. Every box
+    // gets a region, so regions line up with the boxes, and the translations, of the caller.
+    fn crop(
+        image: core::Mat,
+        enhanced_image: Option<core::Mat>,
+        boxes: &[TextBox],
+        cancellation_token: &CancellationToken,
+    ) -> Result<DetectedPage> {
+        let boxes = detection::clamp_boxes(boxes, image.cols(), image.rows())?;
+        let (text_regions, origins) = Detector::crop_regions(&image, &boxes, cancellation_token)?;
+
+        let enhanced_regions = match enhanced_image {
+            Some(enhanced_image) => {
+                Some(Detector::crop_regions(&enhanced_image, &boxes, cancellation_token)?.0)
+            }
+            None => None,
+        };
+
+        Ok(DetectedPage {
+            image,
+            text_regions,
+            origins,
+            boxes,
+            enhanced_regions,
+        })
+    }
+
+    fn ocr_regions(&self) -> &TextRegions {
+        self.enhanced_regions.as_ref().unwrap_or(&self.text_regions)
+    }
+
+    pub fn num_regions(&self) -> usize {
+        self.text_regions.len()
+    }
+
+    // Pages where nothing was detected, such as cover art and illustrations, are passed through
+    // without OCR, cleaning or typesetting
+    pub fn is_text_free(&self) -> bool {
+        self.text_regions.is_empty()
+    }
+
+    pub fn boxes(&self) -> Vec<TextBox> {
+        self.boxes.clone()
+    }
+
+    // Crops of the regions out of the page as it was detected on, encoded as PNGs
+    pub fn encoded_crops(&self) -> Result<Vec<Vec<u8>>> {
+        self.text_regions
+            .iter()
+            .map(|region| {
+                let crop = image_conversion::mat_to_image_buffer(&region)?;
+
+                metadata::encode_png(&DynamicImage::ImageRgb8(crop), &ImageMetadata::default())
+            })
+            .collect()
+    }
+}
+
+pub fn detect_page(
+    config: &Config,
+    image: &DynamicImage,
+    cancellation_token: &CancellationToken,
+) -> Result<DetectedPage> {
+    detect_pages(config, &[image], cancellation_token).remove(0)
+}
+
+// Error of a batch that failed as a whole, given to each of its pages with the chain of the original
+#[derive(Clone, Debug)]
+struct BatchError(Arc<anyhow::Error>);
+
+impl std::fmt::Display for BatchError {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl std::error::Error for BatchError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        self.0.source()
+    }
+}
+
+// Same as "detect_page" for several pages, which run through the model in a single batch. A page
+// that fails before or after the model fails on its own, and only the model failing fails them all.
+pub fn detect_pages(
+    config: &Config,
+    images: &[&DynamicImage],
+    cancellation_token: &CancellationToken,
+) -> Vec<Result<DetectedPage>> {
+    let pages: Vec<Result<PreparedPage>> = images
+        .iter()
+        .map(|&image| prepare_page(config, image))
+        .collect();
+
+    let detection_images: Vec<&core::Mat> = pages
+        .iter()
+        .flatten()
+        .map(PreparedPage::detection_image)
+        .collect();
+    let mut boxes = match detection_images.is_empty() {
+        true => Ok(Vec::new()),
+        false => detect_boxes(config, &detection_images, cancellation_token),
+    }
+    .map(Vec::into_iter)
+    .map_err(|e| BatchError(Arc::new(e)));
+
+    pages
+        .into_iter()
+        .map(|page| {
+            let PreparedPage {
+                image,
+                enhanced_image,
+                ..
+            } = page?;
+            let boxes = match &mut boxes {
+                Ok(boxes) => boxes
+                    .next()
+                    .context("The model returned fewer pages than it was given.")??,
+                Err(e) => return Err(e.clone().into()),
+            };
+
+            // Post-detection hooks can add boxes that overlap the detected ones
+            let boxes = detection::sanitize_boxes(&boxes, image.cols(), image.rows());
+
+            DetectedPage::crop(image, enhanced_image, &boxes, cancellation_token)
+        })
+        .collect()
+}
+
+// Same as "detect_page" but with boxes found earlier, e.g. in a previous run, instead of running the model.
+// The boxes are cropped as given, one region each.
+pub fn crop_page(
+    config: &Config,
+    image: &DynamicImage,
+    boxes: &[TextBox],
+    cancellation_token: &CancellationToken,
+) -> Result<DetectedPage> {
+    let straightened_image = straighten_page(config, image);
+    let image = straightened_image.as_ref().unwrap_or(image);
+
+    let enhanced_image = enhance_page(config, image)?;
+    let image = image_conversion::dynamic_image_to_mat(image)?;
+
+    DetectedPage::crop(image, enhanced_image, boxes, cancellation_token)
+}
+
+pub fn recognize_page(
+    config: &Config,
+    page: &DetectedPage,
+    cancellation_token: &CancellationToken,
+) -> Result<ExtractedText> {
+    let (text, confidences) = recognize_regions(config, page.ocr_regions(), cancellation_token)?;
+
+    Ok(ExtractedText {
+        text,
+        boxes: page.boxes(),
+        confidences,
+    })
+}
+
+// Extraction of a page that was just detected and read. Under --link-distance, the regions of
+// bubbles that lie close together are linked into groups.
+pub fn page_extraction(
+    config: &Config,
+    page: &str,
+    extracted_text: ExtractedText,
+    size: PageSize,
+) -> Extraction {
+    let extraction = Extraction::new(page, extracted_text.text)
+        .with_geometry(size, &extracted_text.boxes)
+        .with_confidences(&extracted_text.confidences);
+
+    match config.link_distance {
+        Some(link_distance) => extraction.with_linked_regions(&text_box::linked_groups(
+            &extracted_text.boxes,
+            link_distance as i32,
+            ReadingDirection::of_language(&config.lang),
+        )),
+        None => extraction,
+    }
+}
+
+// A cleaned or typeset page, along with how well each of its regions was cleaned
+pub struct RenderedPage {
+    pub image: DynamicImage,
+    // Score from 0 to 100 of every region by index, missing for regions that were left alone
+    pub clean_quality: Vec<Option<u8>>,
+    // Translations that don't fit in their regions
+    pub overflows: Vec<TextOverflow>,
+    // Areas of the page that were erased or typeset over
+    pub areas: Vec<Rect>,
+    // Nothing was detected on the page, which was passed through as it was
+    pub text_free: bool,
+}
+
+impl RenderedPage {
+    // A text-free page as it was detected, with nothing erased or typeset on it
+    fn unchanged(image: RgbImage) -> RenderedPage {
+        RenderedPage {
+            image: DynamicImage::ImageRgb8(image),
+            clean_quality: Vec::new(),
+            overflows: Vec::new(),
+            areas: Vec::new(),
+            text_free: true,
+        }
+    }
+}
+
+// Whitens the text regions of a page. Under "--skip-empty-regions", the text recognized in the
+// page tells which regions to leave alone; OCR is run for it if it isn't given.
+pub fn clean_detected_page(
+    config: &Config,
+    page: DetectedPage,
+    extracted_text: Option<&ExtractedText>,
+    cancellation_token: &CancellationToken,
+) -> Result<RenderedPage> {
+    if page.is_text_free() {
+        return Ok(RenderedPage::unchanged(
+            image_conversion::mat_to_image_buffer(&page.image)?,
+        ));
+    }
+
+    let skipped_regions = match (config.skip_empty_regions, extracted_text) {
+        (false, _) => Vec::new(),
+        (true, Some(extracted_text)) => {
+            empty_regions(config, &extracted_text.text, &extracted_text.confidences)
+        }
+        (true, None) => {
+            let (text, confidences) =
+                recognize_regions(config, page.ocr_regions(), cancellation_token)?;
+            empty_regions(config, &text, &confidences)
+        }
+    };
+
+    let mut replacer: Replacer<'_, &str, core::Mat> = Replacer::new(
+        page.text_regions,
+        None,
+        page.origins,
+        page.image,
+        config.padding,
+        config.expansion,
+        config.deskew,
+    )?
+    .with_skipped_regions(skipped_regions);
+
+    if config.color_aware {
+        replacer = replacer.with_color_aware();
+    }
+
+    let blank_regions = replacer.clean(cancellation_token)?;
+    let clean_quality = replacer.clean_quality(&blank_regions);
+    let areas = replacer.areas(&blank_regions);
+    let cleaned_page = replacer.composite(blank_regions, cancellation_token)?;
+
+    Ok(RenderedPage {
+        image: DynamicImage::ImageRgb8(image_conversion::mat_to_image_buffer(&cleaned_page)?),
+        clean_quality,
+        overflows: Vec::new(),
+        areas,
+        text_free: false,
+    })
+}
+
+// Replaces the text regions of a page with their translations, matched by region ID
+pub fn typeset_detected_page(
+    config: &Config,
+    page: DetectedPage,
+    extraction: &Extraction,
+    cancellation_token: &CancellationToken,
+) -> Result<RenderedPage> {
+    typeset_regions(
+        config,
+        page,
+        extraction,
+        None,
+        &config.font,
+        cancellation_token,
+    )
+}
+
+// Same as "typeset_detected_page" but typesets the translations in "font" instead of the configured font
+pub fn typeset_detected_page_in_font(
+    config: &Config,
+    page: DetectedPage,
+    extraction: &Extraction,
+    font: &Font<'static>,
+    cancellation_token: &CancellationToken,
+) -> Result<RenderedPage> {
+    typeset_regions(config, page, extraction, None, font, cancellation_token)
+}
+
+// Same as "typeset_detected_page" but pastes the typeset regions onto "base_image" if it is given,
+// which already carries the credits, and typesets them in "font"
+fn typeset_regions(
+    config: &Config,
+    page: DetectedPage,
+    extraction: &Extraction,
+    base_image: Option<core::Mat>,
+    font: &Font<'static>,
+    cancellation_token: &CancellationToken,
+) -> Result<RenderedPage> {
+    let rerender = base_image.is_some();
+
+    if page.is_text_free() {
+        let mut image =
+            image_conversion::mat_to_image_buffer(base_image.as_ref().unwrap_or(&page.image))?;
+        overlay_credits(config, &mut image, rerender);
+
+        return Ok(RenderedPage::unchanged(image));
+    }
+
+    let num_regions = page.num_regions();
+    let line_breaking = LineBreaking::of_language(&config.target_lang);
+    let mut translations: Vec<Option<String>> = extraction
+        .aligned_translations(num_regions)
+        .into_iter()
+        .map(|translation| {
+            translation.map(|translation| config.honorifics.apply(translation, &config.target_lang))
+        })
+        .collect();
+
+    // The translation of the first of a group of linked bubbles is shared out over the group, each
+    // bubble getting a part the size of its box
+    for group in extraction.flowing_groups(num_regions) {
+        let areas: Vec<u64> = group
+            .iter()
+            .map(|index| page.boxes.get(*index).map_or(0, TextBox::area))
+            .collect();
+        let translation = translations[group[0]].take().unwrap_or_default();
+        let parts = layout::split_across(&translation, line_breaking, &areas);
+
+        for (index, part) in group.iter().zip(parts) {
+            translations[*index] = Some(part);
+        }
+    }
+
+    let annotations: Vec<Option<Annotation>> = extraction
+        .aligned_regions(num_regions)
+        .into_iter()
+        .map(|region| region.and_then(|region| region.annotation.clone()))
+        .collect();
+    let region_padding: Vec<Option<u16>> = extraction
+        .aligned_regions(num_regions)
+        .into_iter()
+        .map(|region| region.and_then(|region| region.padding))
+        .collect();
+
+    // Empty regions are only typeset if a translation was written for them anyway
+    let skipped_regions = match config.skip_empty_regions {
+        true => extraction
+            .aligned_regions(num_regions)
+            .into_iter()
+            .zip(&translations)
+            .map(|(region, translation)| {
+                region.is_some_and(|region| {
+                    translation.as_deref().unwrap_or_default().trim().is_empty()
+                        && region.is_empty(config.min_confidence)
+                })
+            })
+            .collect(),
+        false => Vec::new(),
+    };
+
+    // Letters the font doesn't have are drawn as empty boxes, which is easy to miss on a full volume
+    let missing_glyphs = fonts::missing_glyphs(
+        font,
+        &translations
+            .iter()
+            .flatten()
+            .map(String::as_str)
+            .chain(
+                annotations
+                    .iter()
+                    .flatten()
+                    .map(|annotation| annotation.text.as_str()),
+            )
+            .collect::<String>(),
+    );
+    if !missing_glyphs.is_empty() {
+        warn!(
+            "The font has no glyphs for {:?} in the translations of {}. Set a font that does with --font or --target-lang.",
+            missing_glyphs.into_iter().collect::<String>(),
+            extraction.page
+        );
+    }
+
+    let mut replacer = Replacer::new(
+        page.text_regions,
+        Some(translations.as_slice()),
+        page.origins,
+        page.image,
+        config.padding,
+        config.expansion,
+        config.deskew,
+    )?
+    .with_skipped_regions(skipped_regions)
+    .with_font(font.clone())
+    .with_annotations(annotations)
+    .with_region_padding(region_padding)
+    .with_line_breaking(line_breaking)
+    .with_case(config.style.case)
+    .with_max_lines(config.style.max_lines)
+    .with_shout_style(config.style.shout);
+
+    if let Some(inner_margin) = config.inner_margin {
+        replacer = replacer.with_inner_margin(inner_margin);
+    }
+
+    if config.uniform_font_size {
+        replacer = replacer.with_uniform_font_size();
+    }
+
+    if config.greedy_wrapping {
+        replacer = replacer.with_greedy_wrapping();
+    }
+
+    if config.color_aware {
+        replacer = replacer.with_color_aware();
+    }
+
+    if let Some(base_image) = base_image {
+        replacer = replacer.with_base_image(base_image);
+    }
+
+    let translated_regions = replacer.typeset(cancellation_token)?;
+    let clean_quality = replacer.clean_quality(&translated_regions);
+    let overflows = replacer.overflows(&translated_regions);
+    let areas = replacer.areas(&translated_regions);
+    let final_image = replacer.composite(translated_regions, cancellation_token)?;
+    let mut final_image = image_conversion::mat_to_image_buffer(&final_image)?;
+    overlay_credits(config, &mut final_image, rerender);
+
+    Ok(RenderedPage {
+        image: DynamicImage::ImageRgb8(final_image),
+        clean_quality,
+        overflows,
+        areas,
+        text_free: false,
+    })
+}
+
+// Draws the credits over a typeset page under "--credits-placement overlay". A page re-rendered
+// onto its base image already carries them.
+fn overlay_credits(config: &Config, page: &mut RgbImage, rerender: bool) {
+    if let Some(credits) = config.credits.as_ref().filter(|_| !rerender) {
+        if credits.placement == CreditsPlacement::Overlay {
+            credits.overlay(page, &config.font);
+        }
+    }
+}
+
+// Copy of a crooked page rotated straight under "--deskew-page". Everything after it, from the
+// detected boxes to the cleaned and typeset pages, is based on the straightened page.
+pub fn straighten_page(config: &Config, image: &DynamicImage) -> Option<DynamicImage> {
+    if !config.deskew_page {
+        return None;
+    }
+
+    let grayscale_image = image.to_luma8();
+    let skew = deskew::estimate_page_skew(&grayscale_image);
+
+    if skew == 0.0 {
+        return None;
+    }
+
+    // The corners that are uncovered by the rotation are filled with the color of the paper
+    let (background, _) = polarity::colors(polarity::is_inverted(&grayscale_image));
+
+    Some(DynamicImage::ImageRgb8(deskew::rotate(
+        &image.to_rgb8(),
+        -skew,
+        background,
+    )))
+}
+
+// Copy of the page with the corrections of "--enhance" applied, if any are set
+fn enhance_page(config: &Config, image: &DynamicImage) -> Result<Option<core::Mat>> {
+    if config.enhance.is_empty() {
+        return Ok(None);
+    }
+
+    let enhanced_image = enhance::enhance(&image.to_rgb8(), &config.enhance);
+
+    Ok(Some(image_conversion::image_buffer_to_mat(enhanced_image)?))
+}
+
+// A page made ready for detection, with the image the pre-detection hooks changed if there are any
+struct PreparedPage {
+    image: core::Mat,
+    enhanced_image: Option<core::Mat>,
+    hooked_image: Option<core::Mat>,
+}
+
+impl PreparedPage {
+    fn detection_image(&self) -> &core::Mat {
+        self.hooked_image
+            .as_ref()
+            .or(self.enhanced_image.as_ref())
+            .unwrap_or(&self.image)
+    }
+}
+
+// Straightens and enhances a page and applies the pre-detection hooks to it
+fn prepare_page(config: &Config, image: &DynamicImage) -> Result<PreparedPage> {
+    let straightened_image = straighten_page(config, image);
+    let image = straightened_image.as_ref().unwrap_or(image);
+
+    let mut page = PreparedPage {
+        enhanced_image: enhance_page(config, image)?,
+        image: image_conversion::dynamic_image_to_mat(image)?,
+        hooked_image: None,
+    };
+
+    if !config.hooks.is_empty() {
+        let mut hooked_image = core::Mat::copy(page.detection_image())?;
+        config.hooks.pre_detection(&mut hooked_image)?;
+        page.hooked_image = Some(hooked_image);
+    }
+
+    Ok(page)
+}
+
+// Runs detection over a batch of prepared pages. Gives the boxes of each page, or why its
+// post-detection hooks failed.
+fn detect_boxes(
+    config: &Config,
+    detection_images: &[&core::Mat],
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<Result<Vec<TextBox>>>> {
+    let mut detector = Detector::new(&config.model_path, config.padding, config.dnn.clone())?;
+    let boxes = detector.detect_batch(detection_images, cancellation_token)?;
+
+    Ok(boxes
+        .into_iter()
+        .map(|boxes| limit_boxes(config, boxes))
+        .collect())
+}
+
+// Applies the post-detection hooks to the boxes of a page and keeps it within --max-detections
+fn limit_boxes(config: &Config, mut boxes: Vec<TextBox>) -> Result<Vec<TextBox>> {
+    config.hooks.post_detection(&mut boxes)?;
+
+    // Every region is an OCR call, so a page that is full of detections would take ages
+    if boxes.len() > config.max_detections {
+        warn!(
+            "Found {} text regions on a page, more than --max-detections allows. Only the {} the model is most confident about are processed. Check the model if this happens on ordinary pages.",
+            boxes.len(),
+            config.max_detections
+        );
+
+        boxes = detection::keep_most_confident(boxes, config.max_detections);
+    }
+
+    Ok(boxes)
+}
+
+// Runs OCR over each region and applies the registered text hooks
+fn recognize_text(
+    config: &Config,
+    text_regions: &TextRegions,
+    cancellation_token: &CancellationToken,
+) -> Result<Vec<String>> {
+    Ok(recognize_regions(config, text_regions, cancellation_token)?.0)
+}
+
+// Same as "recognize_text" but also returns the OCR confidence of each region
+fn recognize_regions(
+    config: &Config,
+    text_regions: &TextRegions,
+    cancellation_token: &CancellationToken,
+) -> Result<(Vec<String>, Vec<u8>)> {
+    // Loading Tesseract takes longer than anything else a text-free page goes through
+    if text_regions.is_empty() {
+        return Ok((Vec::new(), Vec::new()));
+    }
+
+    let mut ocr = Ocr::new(&config.lang, &config.tesseract_data_path)?.with_deskew(config.deskew);
+
+    let (mut text, confidences): (Vec<String>, Vec<u8>) = ocr
+        .recognize(text_regions, cancellation_token)?
+        .into_iter()
+        .map(|recognized| (recognized.text, recognized.confidence))
+        .unzip();
+
+    config.hooks.post_ocr(&mut text)?;
+
+    Ok((text, confidences))
+}
+
+// Flags the regions that are left untouched under "--skip-empty-regions"
+fn empty_regions(config: &Config, text: &[String], confidences: &[u8]) -> Vec<bool> {
+    if !config.skip_empty_regions {
+        return Vec::new();
+    }
+
+    text.iter()
+        .zip(confidences)
+        .map(|(text, confidence)| {
+            extraction::is_empty_region(text, Some(*confidence), config.min_confidence)
+        })
+        .collect()
+}
+
+// Encodes an output page as a PNG, carrying over the resolution and color profile of the input
+pub fn encode_png(image: &DynamicImage, image_bytes: &[u8]) -> Result<Vec<u8>> {
+    metadata::encode_png(image, &ImageMetadata::read(image_bytes))
+}
+
+// Async versions of the handlers above. The work itself is blocking (OpenCV and libtesseract),
+// so it is dispatched onto the rayon pool and the result is sent back over a oneshot channel.
+// Dropping one of these futures cancels the work that is still running in the pool.
+#[cfg(feature = "async")]
+pub async fn extract_text_async(config: Arc<Config>, image_bytes: Vec<u8>) -> Result<Vec<String>> {
+    spawn_blocking(move |token| extract_text(&config, &image_bytes, &token)).await
+}
+
+// Downloads images given by URL off the async runtime, since the download blocks
+#[cfg(feature = "async")]
+pub async fn read_image_source_async(config: Arc<Config>, source: ImageSource) -> Result<Vec<u8>> {
+    spawn_blocking(move |_| source.read(&config)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn clean_image_async(config: Arc<Config>, image_bytes: Vec<u8>) -> Result<Vec<u8>> {
+    spawn_blocking(move |token| clean_image(&config, &image_bytes, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn clean_image_with_mask_async(
+    config: Arc<Config>,
+    image_bytes: Vec<u8>,
+) -> Result<(Vec<u8>, GrayImage)> {
+    spawn_blocking(move |token| clean_image_with_mask(&config, &image_bytes, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn replace_text_async(
+    config: Arc<Config>,
+    image_bytes: Vec<u8>,
+    extraction: Extraction,
+) -> Result<Vec<u8>> {
+    spawn_blocking(move |token| replace_text(&config, &image_bytes, &extraction, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn replace_pages_async(
+    config: Arc<Config>,
+    pages: Vec<NamedPage>,
+    document: Vec<Extraction>,
+) -> Result<Vec<Vec<u8>>> {
+    spawn_blocking(move |token| replace_pages(&config, &pages, &document, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn process_archive_async(
+    config: Arc<Config>,
+    archive: Vec<u8>,
+    mode: ArchiveMode,
+) -> Result<Vec<u8>> {
+    spawn_blocking(move |token| {
+        let output = process_archive(
+            &config,
+            std::io::Cursor::new(archive),
+            std::io::Cursor::new(Vec::new()),
+            mode,
+            &token,
+        )?;
+
+        Ok(output.into_inner())
+    })
+    .await
+}
+
+#[cfg(feature = "async")]
+pub async fn correct_page_async(
+    config: Arc<Config>,
+    image_bytes: Vec<u8>,
+    extraction: Extraction,
+    corrections: Vec<RegionCorrection>,
+) -> Result<(Vec<u8>, Extraction)> {
+    spawn_blocking(move |token| {
+        correct_page(&config, &image_bytes, &extraction, &corrections, &token)
+    })
+    .await
+}
+
+#[cfg(feature = "async")]
+pub async fn rerender_regions_async(
+    config: Arc<Config>,
+    replaced_bytes: Vec<u8>,
+    cleaned_bytes: Vec<u8>,
+    extraction: Extraction,
+    corrections: Vec<RegionCorrection>,
+) -> Result<(Vec<u8>, Extraction)> {
+    spawn_blocking(move |token| {
+        rerender_regions(
+            &config,
+            &replaced_bytes,
+            &cleaned_bytes,
+            &extraction,
+            &corrections,
+            &token,
+        )
+    })
+    .await
+}
+
+#[cfg(feature = "async")]
+pub async fn clean_and_extract_async(
+    config: Arc<Config>,
+    image_bytes: Vec<u8>,
+) -> Result<(Vec<u8>, ExtractedText)> {
+    spawn_blocking(move |token| clean_and_extract(&config, &image_bytes, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn extract_text_in_regions_async(
+    config: Arc<Config>,
+    image_bytes: Vec<u8>,
+    boxes: Vec<TextBox>,
+) -> Result<Vec<String>> {
+    spawn_blocking(move |token| extract_text_in_regions(&config, &image_bytes, &boxes, &token))
+        .await
+}
+
+#[cfg(feature = "async")]
+pub async fn extract_text_from_image_async(
+    config: Arc<Config>,
+    image: DynamicImage,
+) -> Result<Vec<String>> {
+    spawn_blocking(move |token| extract_text_from_image(&config, &image, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn clean_dynamic_image_async(
+    config: Arc<Config>,
+    image: DynamicImage,
+) -> Result<DynamicImage> {
+    spawn_blocking(move |token| clean_dynamic_image(&config, &image, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn clean_and_extract_image_async(
+    config: Arc<Config>,
+    image: DynamicImage,
+) -> Result<(DynamicImage, ExtractedText)> {
+    spawn_blocking(move |token| clean_and_extract_image(&config, &image, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn replace_text_in_image_async(
+    config: Arc<Config>,
+    image: DynamicImage,
+    extraction: Extraction,
+) -> Result<DynamicImage> {
+    spawn_blocking(move |token| replace_text_in_image(&config, &image, &extraction, &token)).await
+}
+
+#[cfg(feature = "async")]
+pub async fn correct_image_async(
+    config: Arc<Config>,
+    image: DynamicImage,
+    extraction: Extraction,
+    corrections: Vec<RegionCorrection>,
+) -> Result<(DynamicImage, Extraction)> {
+    spawn_blocking(move |token| correct_image(&config, &image, &extraction, &corrections, &token))
+        .await
+}
+
+#[cfg(feature = "async")]
+pub async fn rerender_image_regions_async(
+    config: Arc<Config>,
+    replaced_page: DynamicImage,
+    cleaned_page: DynamicImage,
+    extraction: Extraction,
+    corrections: Vec<RegionCorrection>,
+) -> Result<(DynamicImage, Extraction)> {
+    spawn_blocking(move |token| {
+        rerender_image_regions(
+            &config,
+            &replaced_page,
+            &cleaned_page,
+            &extraction,
+            &corrections,
+            &token,
+        )
+    })
+    .await
+}
+
+#[cfg(feature = "async")]
+async fn spawn_blocking<F, R>(job: F) -> Result<R>
+where
+    F: FnOnce(CancellationToken) -> Result<R> + Send + 'static,
+    R: Send + 'static,
+{
+    let (sender, receiver) = tokio::sync::oneshot::channel();
+
+    let cancellation_token = CancellationToken::new();
+    let _guard = CancelOnDrop(cancellation_token.clone());
+
+    rayon::spawn(move || {
+        // A panic inside OpenCV bindings would otherwise abort the whole process
+        let result =
+            std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| job(cancellation_token)))
+                .unwrap_or_else(|_| Err(anyhow!("Handler panicked while processing the image")));
+
+        // If the receiver is gone the caller dropped the future and nobody is waiting on the result
+        let _ = sender.send(result);
+    });
+
+    receiver
+        .await
+        .map_err(|_| anyhow!("Handler exited before returning a result"))?
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::read_entry;
+    use std::io::{Cursor, Write};
+    use zip::write::FileOptions;
+    use zip::{CompressionMethod, ZipArchive, ZipWriter};
+
+    #[test]
+    fn test_read_entry() {
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        archive
+            .start_file("page.png", FileOptions::default())
+            .unwrap();
+        archive.write_all(&[0; 1024]).unwrap();
+        archive
+            .start_file("bomb.png", FileOptions::default())
+            .unwrap();
+        archive.write_all(&[0; 1025]).unwrap();
+        let mut archive = ZipArchive::new(archive.finish().unwrap()).unwrap();
+
+        assert_eq!(read_entry(&mut archive, 0, 1024).unwrap().len(), 1024);
+
+        let error = read_entry(&mut archive, 1, 1024).unwrap_err().to_string();
+        assert!(error.contains("bomb.png"), "{error}");
+    }
+
+    #[test]
+    fn test_read_entry_with_made_up_size() {
+        let options = FileOptions::default().compression_method(CompressionMethod::Stored);
+        let mut archive = ZipWriter::new(Cursor::new(Vec::new()));
+        archive.start_file("bomb.png", options).unwrap();
+        archive.write_all(&[0; 1025]).unwrap();
+        let mut data = archive.finish().unwrap().into_inner();
+
+        // The central directory claims the entry is a single byte
+        let header = data
+            .windows(4)
+            .position(|signature| signature == b"PK\x01\x02")
+            .unwrap();
+        data[header + 24..header + 28].copy_from_slice(&1u32.to_le_bytes());
+        let mut archive = ZipArchive::new(Cursor::new(data)).unwrap();
+
+        let error = read_entry(&mut archive, 0, 1024).unwrap_err().to_string();
+        assert_eq!(
+            error,
+            "bomb.png in the archive is more than the limit of 1024 bytes."
+        );
+    }
+}
@@ -0,0 +1,310 @@
+use crate::jobs::{Job, JobResult, Operation, Outcome};
+use anyhow::{bail, Context, Result};
+use rusqlite::{params, Connection, OptionalExtension};
+use std::path::{Path, PathBuf};
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+// A job of the history, without its result
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HistoryEntry {
+    pub id: String,
+    pub tenant: Option<String>,
+    pub operation: String,
+    // Seconds since the epoch when the job was taken
+    pub started: u64,
+    pub duration_ms: u64,
+    pub status: String,
+    pub error: Option<String>,
+}
+
+// Jobs a worker completed and their results, kept in SQLite for longer than the queue keeps results,
+// so they can still be fetched after a client lost track of a job, and failures can be looked into.
+// Several workers can share one file, which SQLite locks while a job is written.
+pub struct JobHistory {
+    connection: Mutex<Connection>,
+    retention: Duration,
+}
+
+impl JobHistory {
+    pub fn open(path: &Path, retention: Duration) -> Result<JobHistory> {
+        let connection = Connection::open(path)
+            .with_context(|| format!("Could not open the job history {}.", path.display()))?;
+        // Other workers may be writing to the file
+        connection.busy_timeout(Duration::from_secs(5))?;
+        connection.execute_batch(
+            "CREATE TABLE IF NOT EXISTS jobs (
+                id TEXT PRIMARY KEY,
+                tenant TEXT,
+                operation TEXT NOT NULL,
+                started INTEGER NOT NULL,
+                duration_ms INTEGER NOT NULL,
+                status TEXT NOT NULL,
+                error TEXT,
+                result TEXT NOT NULL
+            );
+            CREATE INDEX IF NOT EXISTS jobs_started ON jobs (started);",
+        )?;
+
+        Ok(JobHistory {
+            connection: Mutex::new(connection),
+            retention,
+        })
+    }
+
+    // Records a completed job, replacing an earlier record of it, e.g. from a worker whose lease ran
+    // out, and removes the jobs that are older than the retention
+    pub fn record(
+        &self,
+        job: &Job,
+        result: &JobResult,
+        started: SystemTime,
+        duration: Duration,
+    ) -> Result<()> {
+        let started = seconds_since_epoch(started);
+        let (status, error) = match &result.outcome {
+            Outcome::Extracted { .. } => ("extracted", None),
+            Outcome::Written { .. } => ("written", None),
+            Outcome::Masked { .. } => ("masked", None),
+            Outcome::Failed { error } => ("failed", Some(error)),
+        };
+        let result = serde_json::to_string(result)?;
+
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        connection.execute(
+            "INSERT OR REPLACE INTO jobs (id, tenant, operation, started, duration_ms, status, error, result)
+            VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8)",
+            params![
+                job.id,
+                job.tenant,
+                operation_name(job),
+                started,
+                duration.as_millis() as u64,
+                status,
+                error,
+                result
+            ],
+        )?;
+        connection.execute(
+            "DELETE FROM jobs WHERE started < ?1",
+            params![started.saturating_sub(self.retention.as_secs())],
+        )?;
+
+        Ok(())
+    }
+
+    pub fn result(&self, id: &str) -> Result<Option<JobResult>> {
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let result: Option<String> = connection
+            .query_row(
+                "SELECT result FROM jobs WHERE id = ?1",
+                params![id],
+                |row| row.get(0),
+            )
+            .optional()?;
+
+        match result {
+            Some(result) => Ok(Some(serde_json::from_str(&result)?)),
+            None => Ok(None),
+        }
+    }
+
+    // The last "limit" jobs that failed, newest first
+    pub fn failures(&self, limit: usize) -> Result<Vec<HistoryEntry>> {
+        let connection = self
+            .connection
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let mut statement = connection.prepare(
+            "SELECT id, tenant, operation, started, duration_ms, status, error FROM jobs
+            WHERE status = 'failed' ORDER BY started DESC, rowid DESC LIMIT ?1",
+        )?;
+        let entries = statement
+            .query_map(params![limit as u64], |row| {
+                Ok(HistoryEntry {
+                    id: row.get(0)?,
+                    tenant: row.get(1)?,
+                    operation: row.get(2)?,
+                    started: row.get(3)?,
+                    duration_ms: row.get(4)?,
+                    status: row.get(5)?,
+                    error: row.get(6)?,
+                })
+            })?
+            .collect::<rusqlite::Result<Vec<HistoryEntry>>>()?;
+
+        Ok(entries)
+    }
+}
+
+fn operation_name(job: &Job) -> &'static str {
+    match job.operation {
+        Operation::Extract { .. } => "extract",
+        Operation::Clean { .. } => "clean",
+        Operation::Replace { .. } => "replace",
+    }
+}
+
+fn seconds_since_epoch(time: SystemTime) -> u64 {
+    time.duration_since(UNIX_EPOCH)
+        .map(|elapsed| elapsed.as_secs())
+        .unwrap_or_default()
+}
+
+#[derive(clap::Args, Debug)]
+pub struct HistoryArgs {
+    #[arg(long, help = "Job history of the workers, their --history")]
+    pub db: PathBuf,
+    #[command(subcommand)]
+    pub command: HistoryCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum HistoryCommand {
+    #[command(about = "Print the result of a job as JSON")]
+    Show {
+        #[arg(help = "ID of the job")]
+        id: String,
+    },
+    #[command(about = "List the jobs that failed most recently, newest first")]
+    Failures {
+        #[arg(long, default_value_t = 20, help = "Most jobs listed")]
+        limit: usize,
+    },
+}
+
+impl HistoryArgs {
+    pub fn run(self) -> Result<()> {
+        if !self.db.is_file() {
+            bail!("{} is not a job history.", self.db.display());
+        }
+        // Listing jobs never removes any, whatever the retention of the workers
+        let history = JobHistory::open(&self.db, Duration::MAX)?;
+
+        match self.command {
+            HistoryCommand::Show { id } => match history.result(&id)? {
+                Some(result) => println!("{}", serde_json::to_string_pretty(&result)?),
+                None => bail!("No job with the ID {id} is in the history."),
+            },
+            HistoryCommand::Failures { limit } => {
+                for entry in history.failures(limit)? {
+                    let tenant = match &entry.tenant {
+                        Some(tenant) => format!(" ({tenant})"),
+                        None => String::new(),
+                    };
+
+                    println!(
+                        "{} {}{tenant} at {}, after {} ms: {}",
+                        entry.id,
+                        entry.operation,
+                        entry.started,
+                        entry.duration_ms,
+                        entry.error.as_deref().unwrap_or_default()
+                    );
+                }
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::ImageSource;
+    use crate::history::JobHistory;
+    use crate::jobs::{Job, JobResult, Operation, Outcome};
+    use crate::timings::StageTimings;
+    use std::time::{Duration, UNIX_EPOCH};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_job_history() {
+        let directory = TempDir::new().unwrap();
+        let path = directory.path().join("history.sqlite");
+        let history = JobHistory::open(&path, Duration::from_secs(60 * 60)).unwrap();
+
+        let job = |operation| Job::new(operation, ImageSource::Bytes(Vec::new()), None);
+        let at = |seconds: u64| UNIX_EPOCH + Duration::from_secs(1_700_000_000 + seconds);
+        let result = |job: &Job, outcome| JobResult {
+            id: job.id.clone(),
+            outcome,
+            timings: None,
+        };
+        let failed = |job: &Job, error: &str| {
+            result(
+                job,
+                Outcome::Failed {
+                    error: error.to_string(),
+                },
+            )
+        };
+
+        let old = job(Operation::Clean { mask: None });
+        history
+            .record(&old, &failed(&old, "Timed out."), at(0), Duration::ZERO)
+            .unwrap();
+        let cleaned = job(Operation::Clean { mask: None });
+        let written = JobResult {
+            timings: Some(StageTimings {
+                decode_ms: 40,
+                detect_ms: 610,
+                ..StageTimings::default()
+            }),
+            ..result(
+                &cleaned,
+                Outcome::Written {
+                    output: "out/001.png".to_string(),
+                    mask: None,
+                },
+            )
+        };
+        history
+            .record(&cleaned, &written, at(60), Duration::from_millis(900))
+            .unwrap();
+        let extract = job(Operation::Extract { crops: false }).for_tenant("scanlator-a");
+        history
+            .record(
+                &extract,
+                &failed(&extract, "Could not read the image."),
+                at(120),
+                Duration::from_millis(5),
+            )
+            .unwrap();
+
+        assert_eq!(history.result(&cleaned.id).unwrap(), Some(written));
+        assert_eq!(history.result("missing").unwrap(), None);
+
+        let failures = history.failures(10).unwrap();
+        assert_eq!(failures.len(), 2);
+        assert_eq!(failures[0].id, extract.id);
+        assert_eq!(failures[0].tenant.as_deref(), Some("scanlator-a"));
+        assert_eq!(failures[0].operation, "extract");
+        assert_eq!(
+            failures[0].error.as_deref(),
+            Some("Could not read the image.")
+        );
+        assert_eq!(history.failures(1).unwrap().len(), 1);
+
+        // Jobs older than the retention go, and the history is kept across workers
+        let reopened = JobHistory::open(&path, Duration::from_secs(90)).unwrap();
+        reopened
+            .record(
+                &cleaned,
+                &failed(&cleaned, "Retried."),
+                at(121),
+                Duration::ZERO,
+            )
+            .unwrap();
+        assert_eq!(reopened.result(&old.id).unwrap(), None);
+        assert_eq!(reopened.failures(10).unwrap()[0].id, cleaned.id);
+    }
+}
@@ -0,0 +1,230 @@
+use crate::fonts;
+
+// What happens to Japanese honorifics in translations before they are typeset. Every source of
+// translations (a translator, a spreadsheet, any MT engine) writes them differently, so the policy is
+// applied to all of them the same way.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, clap::ValueEnum)]
+pub enum HonorificPolicy {
+    // Translations are typeset as they were written
+    #[default]
+    Keep,
+    // Honorifics are kept but written the same way everywhere: lowercase and attached to the name
+    // with a hyphen, e.g. "Tanaka San" and "Tanaka-San" both become "Tanaka-san"
+    Preserve,
+    // Honorifics are removed from names. In English translations, kinship terms such as "onii-chan"
+    // are replaced by their meaning.
+    Strip,
+}
+
+// Suffixes that are recognized when attached to a capitalized name with a hyphen
+const SUFFIXES: [&str; 13] = [
+    "san", "kun", "chan", "sama", "senpai", "sempai", "sensei", "dono", "tan", "chin", "han",
+    "shi", "kouhai",
+];
+
+// Suffixes that are also recognized as a word of their own after a name. Short ones like "shi" are
+// left out, since they are as likely to be a word of the translation.
+const SPACED_SUFFIXES: [&str; 8] = [
+    "san", "kun", "chan", "sama", "senpai", "sempai", "sensei", "dono",
+];
+
+// Kinship terms are words of their own rather than suffixes, e.g. "onii" + "chan" is "big brother"
+const KINSHIP_SUFFIXES: [&str; 4] = ["san", "chan", "sama", "kun"];
+const KINSHIP_TERMS: [(&str, &str); 12] = [
+    ("onii", "brother"),
+    ("nii", "brother"),
+    ("onee", "sister"),
+    ("nee", "sister"),
+    ("okaa", "mom"),
+    ("kaa", "mom"),
+    ("otou", "dad"),
+    ("tou", "dad"),
+    ("ojii", "grandpa"),
+    ("obaa", "grandma"),
+    ("oji", "uncle"),
+    ("oba", "auntie"),
+];
+
+impl HonorificPolicy {
+    // Applies the policy to a translation written in the given language
+    pub fn apply(self, text: &str, target_lang: &str) -> String {
+        if self == HonorificPolicy::Keep {
+            return text.to_string();
+        }
+
+        let english = fonts::is_english(target_lang);
+        let mut segments: Vec<String> = Vec::new();
+
+        for segment in split_words(text) {
+            if !is_word(segment) {
+                segments.push(segment.to_string());
+                continue;
+            }
+
+            let lowercase = segment.to_lowercase();
+
+            if let Some((base, suffix, meaning)) = kinship_term(&lowercase) {
+                let replacement = match self {
+                    HonorificPolicy::Strip if english => meaning.to_string(),
+                    HonorificPolicy::Strip => segment.to_string(),
+                    _ => format!("{base}-{suffix}"),
+                };
+
+                segments.push(match_case(segment, &replacement));
+                continue;
+            }
+
+            // Like a spaced suffix, a hyphenated one has to follow a capitalized name, so that
+            // words such as "sun-tan" are left alone
+            if let Some((name, suffix)) = segment.rsplit_once('-') {
+                let suffix = suffix.to_lowercase();
+                let is_name = name.chars().next().is_some_and(char::is_uppercase);
+
+                if is_name && SUFFIXES.contains(&suffix.as_str()) {
+                    segments.push(match self {
+                        HonorificPolicy::Strip => name.to_string(),
+                        _ => format!("{name}-{suffix}"),
+                    });
+                    continue;
+                }
+            }
+
+            // A spaced suffix has to follow a capitalized name directly, as in "Tanaka san"
+            let follows_name = match segments.as_slice() {
+                [.., name, space] => {
+                    space == " " && name.chars().next().is_some_and(char::is_uppercase)
+                }
+                _ => false,
+            };
+
+            if follows_name && SPACED_SUFFIXES.contains(&lowercase.as_str()) {
+                segments.pop();
+
+                if self == HonorificPolicy::Preserve {
+                    if let Some(name) = segments.last_mut() {
+                        name.push('-');
+                        name.push_str(&lowercase);
+                    }
+                }
+
+                continue;
+            }
+
+            segments.push(segment.to_string());
+        }
+
+        segments.concat()
+    }
+}
+
+// Splits text into runs of word characters (letters, digits, hyphens and apostrophes) and runs of
+// everything else, so the text can be put back together unchanged
+fn split_words(text: &str) -> Vec<&str> {
+    let mut segments = Vec::new();
+    let mut start = 0;
+    let mut in_word = None;
+
+    for (index, character) in text.char_indices() {
+        let word_character = is_word_character(character);
+
+        if in_word.is_some_and(|in_word| in_word != word_character) {
+            segments.push(&text[start..index]);
+            start = index;
+        }
+
+        in_word = Some(word_character);
+    }
+
+    if start < text.len() {
+        segments.push(&text[start..]);
+    }
+
+    segments
+}
+
+fn is_word_character(character: char) -> bool {
+    character.is_alphanumeric() || character == '-' || character == '\''
+}
+
+fn is_word(segment: &str) -> bool {
+    segment.chars().any(char::is_alphanumeric)
+}
+
+// Splits e.g. "onii-chan" or "oniichan" into the term, its suffix and its meaning
+fn kinship_term(word: &str) -> Option<(&'static str, &'static str, &'static str)> {
+    KINSHIP_TERMS.iter().find_map(|&(base, meaning)| {
+        let rest = word.strip_prefix(base)?;
+        let rest = rest.strip_prefix('-').unwrap_or(rest);
+
+        KINSHIP_SUFFIXES
+            .iter()
+            .find(|&&suffix| suffix == rest)
+            .map(|&suffix| (base, suffix, meaning))
+    })
+}
+
+// Capitalizes the replacement if the original word was capitalized
+fn match_case(original: &str, replacement: &str) -> String {
+    let mut characters = replacement.chars();
+
+    match (original.chars().next(), characters.next()) {
+        (Some(first), Some(replacement_first)) if first.is_uppercase() => {
+            replacement_first.to_uppercase().chain(characters).collect()
+        }
+        _ => replacement.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::honorifics::HonorificPolicy;
+
+    #[test]
+    fn test_keep() {
+        let text = "Tanaka San, thanks! Onii-chan...";
+
+        assert_eq!(HonorificPolicy::Keep.apply(text, "en"), text);
+    }
+
+    #[test]
+    fn test_preserve() {
+        let preserve = |text| HonorificPolicy::Preserve.apply(text, "en");
+
+        assert_eq!(preserve("Thanks, Tanaka San!"), "Thanks, Tanaka-san!");
+        assert_eq!(
+            preserve("Tanaka-SAMA... Yuki-Chan?"),
+            "Tanaka-sama... Yuki-chan?"
+        );
+        assert_eq!(preserve("Oniichan, wait!"), "Onii-chan, wait!");
+
+        // Suffixes are only attached to capitalized names
+        assert_eq!(preserve("the kun of it"), "the kun of it");
+        assert_eq!(preserve("Hello,  san"), "Hello,  san");
+    }
+
+    #[test]
+    fn test_strip() {
+        let strip = |text| HonorificPolicy::Strip.apply(text, "en");
+
+        assert_eq!(strip("Thanks, Tanaka-san!"), "Thanks, Tanaka!");
+        assert_eq!(strip("Tanaka Sensei said so."), "Tanaka said so.");
+        assert_eq!(
+            strip("Onii-chan! Where's okaasan?"),
+            "Brother! Where's mom?"
+        );
+        assert_eq!(strip("A well-known shi-tzu"), "A well-known shi-tzu");
+
+        // Hyphenated suffixes are only removed from capitalized names
+        assert_eq!(
+            strip("A sun-tan in Kyoto-han with Mei-tan"),
+            "A sun-tan in Kyoto with Mei"
+        );
+        assert_eq!(strip("a goo-chin, a bo-shi"), "a goo-chin, a bo-shi");
+
+        // Other languages keep kinship terms, since their meaning would be in English
+        assert_eq!(
+            HonorificPolicy::Strip.apply("¡Onii-chan! Tanaka-san", "es"),
+            "¡Onii-chan! Tanaka"
+        );
+    }
+}
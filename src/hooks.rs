@@ -0,0 +1,78 @@
+use crate::detection::TextBox;
+use anyhow::Result;
+use opencv::core;
+use std::sync::Arc;
+
+/**
+ * Extension point for custom processing around the pipeline stages.
+ *
+ * Every method has a no-op default, so a hook only implements the stages it cares about.
+ */
+pub trait Hook: Send + Sync {
+    // Transforms the page before detection. The modified image is only used for detection;
+    // OCR and replacement still work on the original page.
+    fn pre_detection(&self, _image: &mut core::Mat) -> Result<()> {
+        Ok(())
+    }
+
+    // Filters or adjusts the detected boxes before the regions are cropped out of the page
+    fn post_detection(&self, _boxes: &mut Vec<TextBox>) -> Result<()> {
+        Ok(())
+    }
+
+    // Post-processes the text recognized in a single region
+    fn post_ocr(&self, _text: &mut String) -> Result<()> {
+        Ok(())
+    }
+}
+
+// Hooks registered on the pipeline, run in registration order
+#[derive(Clone, Default)]
+pub struct Hooks {
+    hooks: Vec<Arc<dyn Hook>>,
+}
+
+impl Hooks {
+    pub fn register<H>(&mut self, hook: H)
+    where
+        H: Hook + 'static,
+    {
+        self.hooks.push(Arc::new(hook));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.hooks.is_empty()
+    }
+
+    pub fn pre_detection(&self, image: &mut core::Mat) -> Result<()> {
+        for hook in self.hooks.iter() {
+            hook.pre_detection(image)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn post_detection(&self, boxes: &mut Vec<TextBox>) -> Result<()> {
+        for hook in self.hooks.iter() {
+            hook.post_detection(boxes)?;
+        }
+
+        Ok(())
+    }
+
+    pub fn post_ocr(&self, text: &mut [String]) -> Result<()> {
+        for region_text in text.iter_mut() {
+            for hook in self.hooks.iter() {
+                hook.post_ocr(region_text)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+impl std::fmt::Debug for Hooks {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "Hooks({})", self.hooks.len())
+    }
+}
@@ -0,0 +1,351 @@
+use anyhow::{ensure, Result};
+use image::{imageops, GrayImage, ImageOutputFormat, Rgb, RgbImage};
+use std::io::Cursor;
+
+#[cfg(feature = "pipeline")]
+use crate::utils::image_conversion;
+#[cfg(feature = "pipeline")]
+use opencv::{core, photo, prelude::*};
+
+// Distance in pixels around a masked pixel that its color is painted in from
+const INPAINT_RADIUS: u32 = 3;
+
+// Pages and regions as the replacer crops, inpaints and composites them. OpenCV's Mat is used when
+// mangatra is built with the "pipeline" feature. RgbImage only needs the image crate, so pages whose
+// boxes are already known can be cleaned and typeset without linking OpenCV.
+pub trait PageImage: Sized {
+    // Width and height in pixels
+    fn size(&self) -> (i32, i32);
+
+    fn to_rgb(&self) -> Result<RgbImage>;
+
+    fn from_rgb(image: RgbImage) -> Result<Self>;
+
+    // A copy that regions can be pasted onto without changing the original
+    fn copy(&self) -> Result<Self>;
+
+    fn crop(&self, x: i32, y: i32, width: i32, height: i32) -> Result<Self>;
+
+    // Pastes a rendered region over the image with its top left corner at "origin"
+    fn paste(&mut self, region: RgbImage, origin: (i32, i32)) -> Result<()>;
+
+    // The image with the pixels that are white in the mask painted in from the ones around them
+    fn inpaint(&self, mask: &GrayImage) -> Result<RgbImage>;
+
+    fn encode(&self, format: ImageOutputFormat) -> Result<Vec<u8>> {
+        let mut bytes = Vec::new();
+        self.to_rgb()?
+            .write_to(&mut Cursor::new(&mut bytes), format)?;
+
+        Ok(bytes)
+    }
+}
+
+impl PageImage for RgbImage {
+    fn size(&self) -> (i32, i32) {
+        let (width, height) = self.dimensions();
+
+        (width as i32, height as i32)
+    }
+
+    fn to_rgb(&self) -> Result<RgbImage> {
+        Ok(self.clone())
+    }
+
+    fn from_rgb(image: RgbImage) -> Result<RgbImage> {
+        Ok(image)
+    }
+
+    fn copy(&self) -> Result<RgbImage> {
+        Ok(self.clone())
+    }
+
+    fn crop(&self, x: i32, y: i32, width: i32, height: i32) -> Result<RgbImage> {
+        check_area(self.size(), x, y, width, height)?;
+
+        Ok(imageops::crop_imm(self, x as u32, y as u32, width as u32, height as u32).to_image())
+    }
+
+    fn paste(&mut self, region: RgbImage, (x, y): (i32, i32)) -> Result<()> {
+        imageops::replace(self, &region, x as i64, y as i64);
+
+        Ok(())
+    }
+
+    fn inpaint(&self, mask: &GrayImage) -> Result<RgbImage> {
+        ensure!(
+            self.dimensions() == mask.dimensions(),
+            "The inpainting mask is {:?} but the image is {:?}.",
+            mask.dimensions(),
+            self.dimensions()
+        );
+
+        Ok(inpaint_rgb(self, mask))
+    }
+}
+
+#[cfg(feature = "pipeline")]
+impl PageImage for core::Mat {
+    fn size(&self) -> (i32, i32) {
+        (self.cols(), self.rows())
+    }
+
+    fn to_rgb(&self) -> Result<RgbImage> {
+        image_conversion::mat_to_image_buffer(self)
+    }
+
+    fn from_rgb(image: RgbImage) -> Result<core::Mat> {
+        image_conversion::image_buffer_to_mat(image)
+    }
+
+    // Regions are pasted by building a new Mat around them, so the pixels are never shared with
+    // the original
+    fn copy(&self) -> Result<core::Mat> {
+        Ok(core::Mat::copy(self)?)
+    }
+
+    fn crop(&self, x: i32, y: i32, width: i32, height: i32) -> Result<core::Mat> {
+        check_area((self.cols(), self.rows()), x, y, width, height)?;
+
+        Ok(core::Mat::roi(
+            self,
+            core::Rect2i::new(x, y, width, height),
+        )?)
+    }
+
+    fn paste(&mut self, region: RgbImage, origin: (i32, i32)) -> Result<()> {
+        let region = image_conversion::image_buffer_to_mat(region)?;
+        *self = replace_region(self, region, origin)?;
+
+        Ok(())
+    }
+
+    fn inpaint(&self, mask: &GrayImage) -> Result<RgbImage> {
+        let mask = image_conversion::gray_image_to_mat(mask)?;
+        let mut inpainted = core::Mat::default();
+
+        photo::inpaint(
+            self,
+            &mask,
+            &mut inpainted,
+            INPAINT_RADIUS as f64,
+            photo::INPAINT_TELEA,
+        )?;
+
+        image_conversion::mat_to_image_buffer(&inpainted)
+    }
+}
+
+fn check_area(
+    (image_width, image_height): (i32, i32),
+    x: i32,
+    y: i32,
+    width: i32,
+    height: i32,
+) -> Result<()> {
+    ensure!(
+        x >= 0
+            && y >= 0
+            && width > 0
+            && height > 0
+            && x + width <= image_width
+            && y + height <= image_height,
+        "The area of {width}x{height} at ({x}, {y}) must lie within the {image_width}x{image_height} image."
+    );
+
+    Ok(())
+}
+
+// Paints the masked pixels in from the outside of the mask inwards, each one the mean color of the
+// pixels within "INPAINT_RADIUS" that are known or were painted before it. OpenCV's Telea inpainting
+// weighs the pixels by their distance and direction as well, which makes little difference over the
+// strokes of text.
+fn inpaint_rgb(image: &RgbImage, mask: &GrayImage) -> RgbImage {
+    let (width, height) = image.dimensions();
+    let mut inpainted = image.clone();
+    let mut known: Vec<bool> = mask.pixels().map(|pixel| pixel.0[0] == 0).collect();
+
+    loop {
+        let mut front = Vec::new();
+
+        for (x, y, pixel) in mask.enumerate_pixels() {
+            if pixel.0[0] == 0 || known[(y * width + x) as usize] {
+                continue;
+            }
+
+            let (mut sum, mut count) = ([0u32; 3], 0u32);
+            for neighbor_y in
+                y.saturating_sub(INPAINT_RADIUS)..=(y + INPAINT_RADIUS).min(height - 1)
+            {
+                for neighbor_x in
+                    x.saturating_sub(INPAINT_RADIUS)..=(x + INPAINT_RADIUS).min(width - 1)
+                {
+                    if known[(neighbor_y * width + neighbor_x) as usize] {
+                        let neighbor = inpainted.get_pixel(neighbor_x, neighbor_y);
+                        for (sum, channel) in sum.iter_mut().zip(neighbor.0) {
+                            *sum += channel as u32;
+                        }
+                        count += 1;
+                    }
+                }
+            }
+
+            if count > 0 {
+                front.push((x, y, Rgb(sum.map(|sum| (sum / count) as u8))));
+            }
+        }
+
+        // Nothing is left to paint, or nothing is known to paint it from
+        if front.is_empty() {
+            return inpainted;
+        }
+
+        for (x, y, color) in front {
+            inpainted.put_pixel(x, y, color);
+            known[(y * width + x) as usize] = true;
+        }
+    }
+}
+
+/**
+ * Replaces a image region within the background image
+ *
+ * @param background The background image that the region comes from
+ * @param region The replacement image region
+ * @param (x, y) The coordinates for the image region in the background image
+ */
+#[cfg(feature = "pipeline")]
+fn replace_region(
+    background: &core::Mat,
+    region: core::Mat,
+    (x, y): (i32, i32),
+) -> Result<core::Mat> {
+    let mut temp_image = core::Mat::copy(background)?;
+    let full_width = background.cols();
+    let full_height = background.rows();
+    let width = region.cols();
+    let height = region.rows();
+
+    // Establish origins for the four panels
+    let (left_panel_x, left_panel_y) = (0, 0);
+    let (top_panel_x, top_panel_y) = (x, 0);
+    let (bottom_panel_x, bottom_panel_y) = (x, y + height);
+    let (right_panel_x, right_panel_y) = (x + width, 0);
+
+    // Establish dimensions for the four panels
+    let (left_panel_width, left_panel_height) = (x, full_height);
+    let (top_panel_width, top_panel_height) = (width, y);
+    let (bottom_panel_width, bottom_panel_height) = (width, full_height - (y + height));
+    let (right_panel_width, right_panel_height) = (full_width - (x + width), full_height);
+
+    let left_panel = core::Mat::roi(
+        &temp_image,
+        core::Rect2i::new(
+            left_panel_x,
+            left_panel_y,
+            left_panel_width,
+            left_panel_height,
+        ),
+    )?;
+
+    let top_panel = core::Mat::roi(
+        &temp_image,
+        core::Rect2i::new(top_panel_x, top_panel_y, top_panel_width, top_panel_height),
+    )?;
+
+    let bottom_panel = core::Mat::roi(
+        &temp_image,
+        core::Rect2i::new(
+            bottom_panel_x,
+            bottom_panel_y,
+            bottom_panel_width,
+            bottom_panel_height,
+        ),
+    )?;
+
+    let right_panel = core::Mat::roi(
+        &temp_image,
+        core::Rect2i::new(
+            right_panel_x,
+            right_panel_y,
+            right_panel_width,
+            right_panel_height,
+        ),
+    )?;
+
+    let mut vertical_panels_vec: core::Vector<core::Mat> = core::Vector::new();
+
+    let mut vertical_panel: core::Mat = core::Mat::default();
+
+    vertical_panels_vec.push(top_panel);
+    vertical_panels_vec.push(region);
+    vertical_panels_vec.push(bottom_panel);
+
+    core::vconcat(&vertical_panels_vec, &mut vertical_panel)?;
+
+    let mut horizontal_panels_vec: core::Vector<core::Mat> = core::Vector::new();
+
+    let mut result: core::Mat = core::Mat::default();
+
+    horizontal_panels_vec.push(left_panel);
+    horizontal_panels_vec.push(vertical_panel);
+    horizontal_panels_vec.push(right_panel);
+
+    core::hconcat(&horizontal_panels_vec, &mut result)?;
+
+    temp_image = result;
+
+    Ok(temp_image)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::imaging::PageImage;
+    use image::{GrayImage, ImageOutputFormat, Luma, Rgb, RgbImage};
+
+    #[test]
+    fn test_rgb_page_image() {
+        let page = RgbImage::from_fn(40, 30, |x, _| Rgb([x as u8 * 5, 0, 0]));
+        assert_eq!(page.size(), (40, 30));
+
+        let region = page.crop(10, 5, 20, 10).unwrap();
+        assert_eq!(region.size(), (20, 10));
+        assert_eq!(region.get_pixel(0, 0), page.get_pixel(10, 5));
+        assert!(page.crop(30, 0, 20, 10).is_err());
+        assert!(page.crop(-1, 0, 5, 5).is_err());
+
+        let mut composited = page.copy().unwrap();
+        composited
+            .paste(RgbImage::from_pixel(5, 5, Rgb([0, 0, 255])), (35, 25))
+            .unwrap();
+        assert_eq!(composited.get_pixel(37, 27), &Rgb([0, 0, 255]));
+        assert_eq!(composited.get_pixel(34, 27), page.get_pixel(34, 27));
+        assert_eq!(page.get_pixel(37, 27), &Rgb([185, 0, 0]));
+
+        let png = page.encode(ImageOutputFormat::Png).unwrap();
+        assert_eq!(image::load_from_memory(&png).unwrap().to_rgb8(), page);
+    }
+
+    #[test]
+    fn test_rgb_inpainting() {
+        // Black strokes over a gray background
+        let mask = GrayImage::from_fn(30, 20, |x, y| {
+            match (10..20).contains(&x) && (5..8).contains(&y) {
+                true => Luma([255]),
+                false => Luma([0]),
+            }
+        });
+        let image = RgbImage::from_fn(30, 20, |x, y| match mask.get_pixel(x, y).0[0] {
+            0 => Rgb([120, 130, 140]),
+            _ => Rgb([0, 0, 0]),
+        });
+
+        let inpainted = image.inpaint(&mask).unwrap();
+        assert!(inpainted
+            .pixels()
+            .all(|pixel| *pixel == Rgb([120, 130, 140])));
+
+        // Masks of another size are a mistake rather than something to paint
+        assert!(image.inpaint(&GrayImage::new(10, 10)).is_err());
+    }
+}
@@ -0,0 +1,1095 @@
+use crate::extraction::Extraction;
+use crate::handlers::ImageSource;
+use crate::timings::StageTimings;
+use crate::utils::mask::RleMask;
+use anyhow::{bail, ensure, Result};
+use serde::{Deserialize, Serialize};
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Condvar, Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+// Version of the job format, written into every job. Jobs and results are read by processes of other
+// releases, e.g. while workers are upgraded one by one, so within a version:
+// - fields are only added, never renamed or removed, and new fields are optional with a default
+//   that keeps the old behavior, so jobs from older releases still read the same
+// - unknown fields are ignored, so older workers can read jobs from newer releases
+// - new operations and outcomes get new names instead of changing what an existing one means
+// Anything else, such as changing the meaning of a field, needs a new version. Workers fail jobs of
+// versions newer than their own instead of guessing at them, and keep processing jobs of older
+// versions for at least one release after a new one is introduced.
+pub const JOB_FORMAT_VERSION: u32 = 1;
+
+// How long a worker may go without renewing the lease of its job before the job is handed to another
+// worker, in case the first one died
+pub const DEFAULT_LEASE: Duration = Duration::from_secs(10 * 60);
+// How long results are kept for clients to pick up
+pub const RESULT_RETENTION: Duration = Duration::from_secs(24 * 60 * 60);
+// How long clients whose job was turned away by a full queue are told to wait before trying again.
+// Workers don't report how long their jobs take, so it can't be estimated from the queue.
+pub const FULL_QUEUE_RETRY: Duration = Duration::from_secs(10);
+
+// What to do with the image of a job
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Operation {
+    Extract {
+        // Also return the crop of every region
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        crops: bool,
+    },
+    Clean {
+        // Also return the mask of the areas that were erased
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mask: Option<MaskRequest>,
+    },
+    Replace {
+        extraction: Extraction,
+        // Name of a font of the worker's font directory to typeset the page in
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        font: Option<String>,
+    },
+}
+
+// How a clean job returns the mask of the areas it erased
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MaskRequest {
+    pub format: MaskFormat,
+    // Return only the mask, without the cleaned page
+    #[serde(default)]
+    pub only: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize, clap::ValueEnum)]
+#[serde(rename_all = "snake_case")]
+pub enum MaskFormat {
+    // Written to the job's output, or next to the cleaned page as "<name>.mask.png"
+    Png,
+    // Run-length encoded in the job's result
+    Rle,
+}
+
+// Mask of the areas a clean job erased, white on black
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "format", rename_all = "snake_case")]
+pub enum Mask {
+    Png { output: String },
+    Rle(RleMask),
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Job {
+    // Jobs from before the version was written are of the first version
+    #[serde(default = "first_version")]
+    pub version: u32,
+    pub id: String,
+    pub operation: Operation,
+    pub image: ImageSource,
+    // Where images made by the job are written: a local path, or an s3:// or gs:// URI
+    pub output: Option<String>,
+    // Tenant of the worker's tenants file whose options the job is processed with
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub tenant: Option<String>,
+    // Also return how long each stage of the job took
+    #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+    pub timings: bool,
+}
+
+fn first_version() -> u32 {
+    1
+}
+
+impl Job {
+    pub fn new(operation: Operation, image: ImageSource, output: Option<String>) -> Job {
+        Job {
+            version: JOB_FORMAT_VERSION,
+            id: format!("{:016x}", fastrand::u64(..)),
+            operation,
+            image,
+            output,
+            tenant: None,
+            timings: false,
+        }
+    }
+
+    pub fn for_tenant(mut self, tenant: &str) -> Job {
+        self.tenant = Some(tenant.to_string());
+        self
+    }
+
+    pub fn with_timings(mut self) -> Job {
+        self.timings = true;
+        self
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "status", rename_all = "snake_case")]
+pub enum Outcome {
+    Extracted {
+        extraction: Extraction,
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        crops: Vec<RegionCrop>,
+    },
+    Written {
+        output: String,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        mask: Option<Mask>,
+    },
+    // Only the mask of a clean job was asked for
+    Masked {
+        mask: Mask,
+    },
+    Failed {
+        error: String,
+    },
+}
+
+// Crop of a region of an extract job's page, encoded as a PNG
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RegionCrop {
+    pub region: String,
+    pub png: Vec<u8>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct JobResult {
+    pub id: String,
+    pub outcome: Outcome,
+    // How long each stage of the job took, for jobs that asked for it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StageTimings>,
+}
+
+// Options workers process jobs with, kept in the queue so they can be changed without restarting
+// the workers. Options that aren't set keep the value the worker was started with. Follows the same
+// rules as jobs, since workers of several releases read them.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Defaults {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub padding: Option<u16>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub max_detections: Option<usize>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub target_lang: Option<String>,
+}
+
+impl Defaults {
+    // These defaults, with the ones that aren't set taken from "fallback"
+    pub fn or(&self, fallback: &Defaults) -> Defaults {
+        Defaults {
+            padding: self.padding.or(fallback.padding),
+            max_detections: self.max_detections.or(fallback.max_detections),
+            target_lang: self
+                .target_lang
+                .clone()
+                .or_else(|| fallback.target_lang.clone()),
+        }
+    }
+}
+
+// Jobs waiting to be processed, shared by the processes that submit them and the workers that run
+// them. A job taken by a worker is leased to it until its result is stored, and the worker renews the
+// lease while it works on the job; jobs whose lease ran out, e.g. because the worker was stopped, are
+// handed out again.
+pub trait JobQueue: Send + Sync {
+    fn push(&self, job: &Job) -> Result<()>;
+
+    // Takes the next job, waiting up to "timeout" for one to be pushed. The job is leased in the
+    // same step, so a worker that dies right after taking it can't leave it without a lease.
+    fn pop(&self, timeout: Duration) -> Result<Option<Job>>;
+
+    // Starts the lease of a job over, for jobs that take longer than one lease. Returns false if the
+    // job isn't leased anymore, e.g. because the lease ran out and it went to another worker.
+    fn renew(&self, job: &Job) -> Result<bool>;
+
+    // Stores the result of a leased job and removes the job from the queue
+    fn complete(&self, result: &JobResult) -> Result<()>;
+
+    // Puts a leased job back in the queue without a result, for a worker that is stopping
+    fn release(&self, job: &Job) -> Result<()>;
+
+    // Puts a leased job back at the end of the queue, for a job this worker can't process but
+    // another may, so the jobs waiting behind it go first
+    fn pass_on(&self, job: &Job) -> Result<()>;
+
+    fn result(&self, id: &str) -> Result<Option<JobResult>>;
+
+    // Puts jobs whose lease ran out back in the queue and returns how many there were
+    fn requeue_expired(&self) -> Result<usize>;
+
+    // Number of jobs waiting to be taken by a worker
+    fn pending(&self) -> Result<usize>;
+
+    fn defaults(&self) -> Result<Defaults>;
+
+    // Replaces the defaults, which workers pick up before their next job
+    fn set_defaults(&self, defaults: &Defaults) -> Result<()>;
+}
+
+// Error of a job turned away because the queue already holds as many jobs as it is allowed to.
+// Callers can find it with "downcast_ref" to tell clients when to try again, e.g. with a 429 and a
+// Retry-After header.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct QueueFull {
+    pub pending: usize,
+    pub retry_after: Duration,
+}
+
+impl std::fmt::Display for QueueFull {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(
+            f,
+            "The queue is full with {} jobs waiting. Try again in {} seconds.",
+            self.pending,
+            self.retry_after.as_secs()
+        )
+    }
+}
+
+impl std::error::Error for QueueFull {}
+
+// Pushes a job unless "max_pending" jobs are already waiting, so a queue that workers can't keep up
+// with turns jobs away instead of making every job wait longer. Jobs pushed at the same time may
+// all be let in, so the queue can go a little over the limit.
+pub fn push_within(queue: &dyn JobQueue, job: &Job, max_pending: usize) -> Result<()> {
+    let pending = queue.pending()?;
+    if pending >= max_pending {
+        return Err(QueueFull {
+            pending,
+            retry_after: FULL_QUEUE_RETRY,
+        }
+        .into());
+    }
+
+    queue.push(job)
+}
+
+// Opens the queue at the URL: "memory://" for a queue within this process, or "redis://host:port"
+// (or "rediss://" for TLS) for one shared between processes and kept across restarts. Redis URLs
+// may end with "?compression=gzip" to store the jobs and results this process writes compressed.
+pub fn open(url: &str, lease: Duration) -> Result<Box<dyn JobQueue>> {
+    if url == "memory://" {
+        return Ok(Box::new(MemoryQueue::new(lease)));
+    }
+
+    if url.starts_with("redis://") || url.starts_with("rediss://") {
+        #[cfg(feature = "redis")]
+        return Ok(Box::new(redis_queue::RedisQueue::open(url, lease)?));
+
+        #[cfg(not(feature = "redis"))]
+        bail!("{url} can't be used since mangatra was built without the \"redis\" feature.");
+    }
+
+    bail!("{url} is not a queue URL. Use memory:// or redis://host:port.")
+}
+
+// Opens a queue for a command that only reaches the workers through it, which a queue within this
+// process can't do
+pub fn open_shared(url: &str, lease: Duration) -> Result<Box<dyn JobQueue>> {
+    ensure!(
+        url != "memory://",
+        "memory:// queues only live within one process, so other processes can't reach it. Use redis://host:port."
+    );
+
+    open(url, lease)
+}
+
+#[derive(Default)]
+struct MemoryState {
+    pending: VecDeque<Job>,
+    leased: HashMap<String, (Job, Instant)>,
+    results: HashMap<String, (JobResult, Instant)>,
+    defaults: Defaults,
+}
+
+// Queue that only lives as long as the process, for running the API and the workers together
+pub struct MemoryQueue {
+    state: Mutex<MemoryState>,
+    pushed: Condvar,
+    lease: Duration,
+}
+
+impl MemoryQueue {
+    pub fn new(lease: Duration) -> MemoryQueue {
+        MemoryQueue {
+            state: Mutex::new(MemoryState::default()),
+            pushed: Condvar::new(),
+            lease,
+        }
+    }
+}
+
+impl JobQueue for MemoryQueue {
+    fn push(&self, job: &Job) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.pending.push_back(job.clone());
+        self.pushed.notify_one();
+
+        Ok(())
+    }
+
+    fn pop(&self, timeout: Duration) -> Result<Option<Job>> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let (mut state, _) = self
+            .pushed
+            .wait_timeout_while(state, timeout, |state| state.pending.is_empty())
+            .unwrap_or_else(PoisonError::into_inner);
+
+        let job = state.pending.pop_front();
+        if let Some(job) = &job {
+            let deadline = Instant::now() + self.lease;
+            state.leased.insert(job.id.clone(), (job.clone(), deadline));
+        }
+
+        Ok(job)
+    }
+
+    fn renew(&self, job: &Job) -> Result<bool> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match state.leased.get_mut(&job.id) {
+            Some((_, deadline)) => {
+                *deadline = Instant::now() + self.lease;
+                Ok(true)
+            }
+            None => Ok(false),
+        }
+    }
+
+    fn complete(&self, result: &JobResult) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+
+        state.leased.remove(&result.id);
+        state
+            .results
+            .retain(|_, (_, completed)| now.duration_since(*completed) < RESULT_RETENTION);
+        state
+            .results
+            .insert(result.id.clone(), (result.clone(), now));
+
+        Ok(())
+    }
+
+    fn release(&self, job: &Job) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if state.leased.remove(&job.id).is_some() {
+            state.pending.push_front(job.clone());
+            self.pushed.notify_one();
+        }
+
+        Ok(())
+    }
+
+    fn pass_on(&self, job: &Job) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        if state.leased.remove(&job.id).is_some() {
+            state.pending.push_back(job.clone());
+            self.pushed.notify_one();
+        }
+
+        Ok(())
+    }
+
+    fn result(&self, id: &str) -> Result<Option<JobResult>> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        Ok(state.results.get(id).map(|(result, _)| result.clone()))
+    }
+
+    fn requeue_expired(&self) -> Result<usize> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        let now = Instant::now();
+
+        let expired: Vec<String> = state
+            .leased
+            .iter()
+            .filter(|(_, (_, deadline))| *deadline <= now)
+            .map(|(id, _)| id.clone())
+            .collect();
+
+        for id in expired.iter() {
+            if let Some((job, _)) = state.leased.remove(id) {
+                state.pending.push_back(job);
+                self.pushed.notify_one();
+            }
+        }
+
+        Ok(expired.len())
+    }
+
+    fn pending(&self) -> Result<usize> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        Ok(state.pending.len())
+    }
+
+    fn defaults(&self) -> Result<Defaults> {
+        let state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+
+        Ok(state.defaults.clone())
+    }
+
+    fn set_defaults(&self, defaults: &Defaults) -> Result<()> {
+        let mut state = self.state.lock().unwrap_or_else(PoisonError::into_inner);
+        state.defaults = defaults.clone();
+
+        Ok(())
+    }
+}
+
+#[cfg(feature = "redis")]
+mod redis_queue {
+    use crate::jobs::{Defaults, Job, JobQueue, JobResult, Outcome, RESULT_RETENTION};
+    use anyhow::{bail, Context, Result};
+    use flate2::read::GzDecoder;
+    use flate2::write::GzEncoder;
+    use redis::{Commands, Connection, Script};
+    use serde::de::DeserializeOwned;
+    use serde::Serialize;
+    use std::io::{Read, Write};
+    use std::thread;
+    use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+    // First bytes of gzip data, which JSON never starts with
+    const GZIP_MAGIC: [u8; 2] = [0x1f, 0x8b];
+
+    // Keys are shared by every version of the job format, since workers check the version of each
+    // job themselves. Changing how the keys are laid out would need a new prefix instead.
+    // IDs of jobs waiting to be taken, oldest at the right
+    const PENDING: &str = "mangatra:jobs:pending";
+    // IDs of jobs taken by a worker
+    const PROCESSING: &str = "mangatra:jobs:processing";
+    // When the lease of each job taken by a worker runs out, in seconds since the epoch
+    const LEASES: &str = "mangatra:jobs:leases";
+    // Defaults of the workers, as JSON
+    const DEFAULTS: &str = "mangatra:defaults";
+
+    // How often an empty queue is checked for new jobs. Scripts can't wait for a job to be pushed
+    // the way BRPOPLPUSH does, and the lease has to be taken in the same script as the job.
+    const POP_INTERVAL: Duration = Duration::from_millis(250);
+
+    // Moves the oldest job to the processing list and leases it until ARGV[1]
+    const POP_SCRIPT: &str = r"
+        local id = redis.call('RPOPLPUSH', KEYS[1], KEYS[2])
+        if id then
+            redis.call('ZADD', KEYS[3], ARGV[1], id)
+        end
+        return id
+    ";
+
+    // Moves the end of the lease of job ARGV[1] to ARGV[2], if it is still leased
+    const RENEW_SCRIPT: &str = r"
+        if redis.call('ZSCORE', KEYS[1], ARGV[1]) then
+            redis.call('ZADD', KEYS[1], ARGV[2], ARGV[1])
+            return 1
+        end
+        return 0
+    ";
+
+    fn job_key(id: &str) -> String {
+        format!("mangatra:job:{id}")
+    }
+
+    fn result_key(id: &str) -> String {
+        format!("mangatra:result:{id}")
+    }
+
+    // Removes the compression option from the query of a queue URL, which Redis doesn't know, and
+    // returns whether it asks for compression
+    pub fn compression_option(url: &str) -> Result<(String, bool)> {
+        let (address, query) = match url.split_once('?') {
+            Some((address, query)) => (address, query),
+            None => return Ok((url.to_string(), false)),
+        };
+
+        let mut compress = false;
+        let mut parameters = Vec::new();
+        for parameter in query.split('&') {
+            match parameter.strip_prefix("compression=") {
+                Some("gzip") => compress = true,
+                Some("none") => compress = false,
+                Some(other) => {
+                    bail!("{other} is not a queue compression. Use compression=gzip or compression=none.")
+                }
+                None => parameters.push(parameter),
+            }
+        }
+
+        let url = match parameters.is_empty() {
+            true => address.to_string(),
+            false => format!("{address}?{}", parameters.join("&")),
+        };
+
+        Ok((url, compress))
+    }
+
+    // JSON of a job or result, compressed with gzip if "compress" is set. Images sent along with jobs
+    // and the crops of results are byte arrays, which are written out as numbers and shrink a lot.
+    pub fn encode<T: Serialize>(value: &T, compress: bool) -> Result<Vec<u8>> {
+        let json = serde_json::to_vec(value)?;
+        if !compress {
+            return Ok(json);
+        }
+
+        let mut encoder = GzEncoder::new(Vec::new(), flate2::Compression::default());
+        encoder.write_all(&json)?;
+
+        Ok(encoder.finish()?)
+    }
+
+    // Reads a job or result whether or not it was compressed, so processes that compress can share
+    // a queue with ones that don't
+    pub fn decode<T: DeserializeOwned>(data: &[u8]) -> Result<T> {
+        if !data.starts_with(&GZIP_MAGIC) {
+            return Ok(serde_json::from_slice(data)?);
+        }
+
+        let mut json = Vec::new();
+        GzDecoder::new(data).read_to_end(&mut json)?;
+
+        Ok(serde_json::from_slice(&json)?)
+    }
+
+    fn now() -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+    }
+
+    // Queue kept in Redis, so any number of processes can share it and jobs outlive the processes.
+    // Jobs are stored under their own keys and only their IDs move between the lists.
+    pub struct RedisQueue {
+        client: redis::Client,
+        lease: Duration,
+        // Whether the jobs and results written are compressed
+        compress: bool,
+    }
+
+    impl RedisQueue {
+        pub fn open(url: &str, lease: Duration) -> Result<RedisQueue> {
+            let (url, compress) = compression_option(url)?;
+            let client = redis::Client::open(url.as_str())?;
+            // Fail early if the server can't be reached
+            client
+                .get_connection()
+                .with_context(|| format!("Could not connect to {url}."))?;
+
+            Ok(RedisQueue {
+                client,
+                lease,
+                compress,
+            })
+        }
+
+        // Connections can't be shared between threads, and a blocking pop would hold one for its
+        // whole timeout anyway. Jobs take seconds, so connecting for every call costs little.
+        fn connection(&self) -> Result<Connection> {
+            Ok(self.client.get_connection()?)
+        }
+    }
+
+    impl JobQueue for RedisQueue {
+        fn push(&self, job: &Job) -> Result<()> {
+            let mut connection = self.connection()?;
+
+            redis::pipe()
+                .atomic()
+                .set(job_key(&job.id), encode(job, self.compress)?)
+                .ignore()
+                .lpush(PENDING, &job.id)
+                .ignore()
+                .query::<()>(&mut connection)?;
+
+            Ok(())
+        }
+
+        fn pop(&self, timeout: Duration) -> Result<Option<Job>> {
+            let mut connection = self.connection()?;
+            let script = Script::new(POP_SCRIPT);
+            let start = Instant::now();
+
+            let id = loop {
+                let id: Option<String> = script
+                    .key(PENDING)
+                    .key(PROCESSING)
+                    .key(LEASES)
+                    .arg(now() + self.lease.as_secs())
+                    .invoke(&mut connection)?;
+
+                match id {
+                    Some(id) => break id,
+                    None if start.elapsed() >= timeout => return Ok(None),
+                    None => {
+                        thread::sleep(POP_INTERVAL.min(timeout.saturating_sub(start.elapsed())))
+                    }
+                }
+            };
+
+            let job: Option<Vec<u8>> = connection.get(job_key(&id))?;
+            match job {
+                Some(job) => {
+                    match decode(&job) {
+                        Ok(job) => Ok(Some(job)),
+                        // Jobs this release can't read, e.g. of an operation added later, fail rather
+                        // than being handed out again and again
+                        Err(e) => {
+                            self.complete(&JobResult {
+                            id,
+                            outcome: Outcome::Failed {
+                                error: format!("The job could not be read, it may need a newer worker: {e}"),
+                            },
+                            timings: None,
+                        })?;
+
+                            Ok(None)
+                        }
+                    }
+                }
+                // Completed by a worker whose lease had run out in the meantime
+                None => {
+                    redis::pipe()
+                        .atomic()
+                        .lrem(PROCESSING, 1, &id)
+                        .ignore()
+                        .zrem(LEASES, &id)
+                        .ignore()
+                        .query::<()>(&mut connection)?;
+
+                    Ok(None)
+                }
+            }
+        }
+
+        fn renew(&self, job: &Job) -> Result<bool> {
+            let mut connection = self.connection()?;
+
+            let renewed: usize = Script::new(RENEW_SCRIPT)
+                .key(LEASES)
+                .arg(&job.id)
+                .arg(now() + self.lease.as_secs())
+                .invoke(&mut connection)?;
+
+            Ok(renewed > 0)
+        }
+
+        fn complete(&self, result: &JobResult) -> Result<()> {
+            let mut connection = self.connection()?;
+
+            redis::pipe()
+                .atomic()
+                .set_ex(
+                    result_key(&result.id),
+                    encode(result, self.compress)?,
+                    RESULT_RETENTION.as_secs() as usize,
+                )
+                .ignore()
+                .lrem(PROCESSING, 1, &result.id)
+                .ignore()
+                .zrem(LEASES, &result.id)
+                .ignore()
+                .del(job_key(&result.id))
+                .ignore()
+                .query::<()>(&mut connection)?;
+
+            Ok(())
+        }
+
+        fn release(&self, job: &Job) -> Result<()> {
+            let mut connection = self.connection()?;
+
+            // Released jobs are taken next, since they have waited the longest
+            let removed: usize = connection.zrem(LEASES, &job.id)?;
+            if removed > 0 {
+                redis::pipe()
+                    .atomic()
+                    .lrem(PROCESSING, 1, &job.id)
+                    .ignore()
+                    .rpush(PENDING, &job.id)
+                    .ignore()
+                    .query::<()>(&mut connection)?;
+            }
+
+            Ok(())
+        }
+
+        fn pass_on(&self, job: &Job) -> Result<()> {
+            let mut connection = self.connection()?;
+
+            let removed: usize = connection.zrem(LEASES, &job.id)?;
+            if removed > 0 {
+                redis::pipe()
+                    .atomic()
+                    .lrem(PROCESSING, 1, &job.id)
+                    .ignore()
+                    .lpush(PENDING, &job.id)
+                    .ignore()
+                    .query::<()>(&mut connection)?;
+            }
+
+            Ok(())
+        }
+
+        fn result(&self, id: &str) -> Result<Option<JobResult>> {
+            let mut connection = self.connection()?;
+
+            let result: Option<Vec<u8>> = connection.get(result_key(id))?;
+            match result {
+                Some(result) => Ok(Some(decode(&result)?)),
+                None => Ok(None),
+            }
+        }
+
+        fn requeue_expired(&self) -> Result<usize> {
+            let mut connection = self.connection()?;
+
+            let expired: Vec<String> = connection.zrangebyscore(LEASES, 0, now())?;
+            let mut requeued = 0;
+
+            for id in expired.iter() {
+                // Whoever removes the lease first requeues the job, so it is only queued once
+                let removed: usize = connection.zrem(LEASES, id)?;
+                if removed == 0 {
+                    continue;
+                }
+
+                redis::pipe()
+                    .atomic()
+                    .lrem(PROCESSING, 1, id)
+                    .ignore()
+                    .rpush(PENDING, id)
+                    .ignore()
+                    .query::<()>(&mut connection)?;
+                requeued += 1;
+            }
+
+            Ok(requeued)
+        }
+
+        fn pending(&self) -> Result<usize> {
+            let mut connection = self.connection()?;
+
+            Ok(connection.llen(PENDING)?)
+        }
+
+        fn defaults(&self) -> Result<Defaults> {
+            let mut connection = self.connection()?;
+
+            let defaults: Option<String> = connection.get(DEFAULTS)?;
+            match defaults {
+                Some(defaults) => Ok(serde_json::from_str(&defaults)?),
+                None => Ok(Defaults::default()),
+            }
+        }
+
+        fn set_defaults(&self, defaults: &Defaults) -> Result<()> {
+            let mut connection = self.connection()?;
+            connection.set::<_, _, ()>(DEFAULTS, serde_json::to_string(defaults)?)?;
+
+            Ok(())
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extraction::Extraction;
+    use crate::handlers::ImageSource;
+    use crate::jobs::{
+        self, Defaults, Job, JobQueue, JobResult, Mask, MaskFormat, MaskRequest, MemoryQueue,
+        Operation, Outcome, QueueFull, RegionCrop, FULL_QUEUE_RETRY, JOB_FORMAT_VERSION,
+    };
+    use crate::timings::StageTimings;
+    use crate::utils::mask::RleMask;
+    use std::time::Duration;
+
+    #[test]
+    fn test_memory_queue() {
+        let queue = MemoryQueue::new(Duration::from_secs(60));
+
+        let first = Job::new(
+            Operation::Extract { crops: false },
+            ImageSource::Url("https://images.example.com/001.png".to_string()),
+            None,
+        );
+        let second = Job::new(
+            Operation::Clean { mask: None },
+            ImageSource::Bytes(vec![1, 2, 3]),
+            Some("s3://releases/002.png".to_string()),
+        );
+        queue.push(&first).unwrap();
+        queue.push(&second).unwrap();
+
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(first.clone()));
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(second.clone()));
+        assert_eq!(queue.pop(Duration::from_millis(10)).unwrap(), None);
+
+        let result = JobResult {
+            id: second.id.clone(),
+            outcome: Outcome::Written {
+                output: "s3://releases/002.png".to_string(),
+                mask: None,
+            },
+            timings: None,
+        };
+        queue.complete(&result).unwrap();
+        assert_eq!(queue.result(&second.id).unwrap(), Some(result));
+        assert_eq!(queue.result(&first.id).unwrap(), None);
+
+        // Leases that haven't run out stay with their worker, and only leased jobs can be renewed
+        assert_eq!(queue.requeue_expired().unwrap(), 0);
+        assert!(queue.renew(&first).unwrap());
+        assert!(!queue.renew(&second).unwrap());
+    }
+
+    #[test]
+    fn test_memory_queue_requeue() {
+        let queue = MemoryQueue::new(Duration::ZERO);
+        let job = Job::new(
+            Operation::Extract { crops: false },
+            ImageSource::Bytes(Vec::new()),
+            None,
+        );
+        queue.push(&job).unwrap();
+
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(job.clone()));
+        assert_eq!(queue.requeue_expired().unwrap(), 1);
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(job.clone()));
+
+        // Released jobs go before the ones that were waiting
+        let next = Job::new(
+            Operation::Clean { mask: None },
+            ImageSource::Bytes(Vec::new()),
+            None,
+        );
+        queue.push(&next).unwrap();
+        queue.release(&job).unwrap();
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(job.clone()));
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(next.clone()));
+
+        // Jobs passed on go after them
+        queue.push(&next).unwrap();
+        queue.pass_on(&job).unwrap();
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(next));
+        assert_eq!(queue.pop(Duration::ZERO).unwrap(), Some(job));
+    }
+
+    #[test]
+    fn test_push_within() {
+        let queue = MemoryQueue::new(Duration::from_secs(60));
+        let job = || {
+            Job::new(
+                Operation::Extract { crops: false },
+                ImageSource::Bytes(Vec::new()),
+                None,
+            )
+        };
+
+        jobs::push_within(&queue, &job(), 2).unwrap();
+        jobs::push_within(&queue, &job(), 2).unwrap();
+        assert_eq!(queue.pending().unwrap(), 2);
+
+        let error = jobs::push_within(&queue, &job(), 2).unwrap_err();
+        assert_eq!(
+            error.downcast_ref::<QueueFull>(),
+            Some(&QueueFull {
+                pending: 2,
+                retry_after: FULL_QUEUE_RETRY,
+            })
+        );
+        assert_eq!(queue.pending().unwrap(), 2);
+
+        // Jobs taken by workers make room
+        queue.pop(Duration::ZERO).unwrap();
+        jobs::push_within(&queue, &job(), 2).unwrap();
+    }
+
+    #[test]
+    fn test_defaults() {
+        let queue = MemoryQueue::new(Duration::from_secs(60));
+        assert_eq!(queue.defaults().unwrap(), Defaults::default());
+
+        let defaults = Defaults {
+            padding: Some(4),
+            max_detections: None,
+            target_lang: Some("es".to_string()),
+        };
+        queue.set_defaults(&defaults).unwrap();
+        assert_eq!(queue.defaults().unwrap(), defaults);
+
+        // Options that aren't set are left out, and keep what the worker was started with
+        let json = serde_json::to_string(&defaults).unwrap();
+        assert_eq!(json, r#"{"padding":4,"target_lang":"es"}"#);
+        assert_eq!(serde_json::from_str::<Defaults>(&json).unwrap(), defaults);
+        let started_with = Defaults {
+            padding: Some(10),
+            max_detections: Some(200),
+            target_lang: Some("en".to_string()),
+        };
+        assert_eq!(
+            defaults.or(&started_with),
+            Defaults {
+                padding: Some(4),
+                max_detections: Some(200),
+                target_lang: Some("es".to_string()),
+            }
+        );
+    }
+
+    #[cfg(feature = "redis")]
+    #[test]
+    fn test_queue_compression() {
+        use crate::jobs::redis_queue::{compression_option, decode, encode};
+
+        assert_eq!(
+            compression_option("redis://queue.internal:6379?compression=gzip").unwrap(),
+            ("redis://queue.internal:6379".to_string(), true)
+        );
+        assert_eq!(
+            compression_option("redis://queue.internal:6379/2?protocol=resp3&compression=gzip")
+                .unwrap(),
+            (
+                "redis://queue.internal:6379/2?protocol=resp3".to_string(),
+                true
+            )
+        );
+        assert_eq!(
+            compression_option("redis://queue.internal:6379").unwrap(),
+            ("redis://queue.internal:6379".to_string(), false)
+        );
+        assert!(compression_option("redis://queue.internal:6379?compression=brotli").is_err());
+
+        // Images sent along with jobs shrink, and both forms are read
+        let job = Job::new(
+            Operation::Clean { mask: None },
+            ImageSource::Bytes(vec![255; 4096]),
+            Some("out/001.png".to_string()),
+        );
+        let compressed = encode(&job, true).unwrap();
+        let plain = encode(&job, false).unwrap();
+        assert!(compressed.len() * 10 < plain.len());
+        assert_eq!(decode::<Job>(&compressed).unwrap(), job);
+        assert_eq!(decode::<Job>(&plain).unwrap(), job);
+    }
+
+    #[test]
+    fn test_job_json() {
+        let job = Job {
+            version: JOB_FORMAT_VERSION,
+            id: "1f".to_string(),
+            operation: Operation::Clean { mask: None },
+            image: ImageSource::Object("s3://raws/001.png".to_string()),
+            output: Some("s3://releases/001_cleaned.png".to_string()),
+            tenant: None,
+            timings: false,
+        };
+        let json = serde_json::to_string(&job).unwrap();
+        assert_eq!(
+            json,
+            r#"{"version":1,"id":"1f","operation":{"type":"clean"},"image":{"object":"s3://raws/001.png"},"output":"s3://releases/001_cleaned.png"}"#
+        );
+        assert_eq!(serde_json::from_str::<Job>(&json).unwrap(), job);
+
+        // Jobs without a version are of the first one, and fields of newer releases are ignored
+        let older = r#"{"id":"1f","operation":{"type":"clean"},"image":{"object":"s3://raws/001.png"},"output":"s3://releases/001_cleaned.png"}"#;
+        assert_eq!(serde_json::from_str::<Job>(older).unwrap(), job);
+        let newer = r#"{"version":1,"id":"1f","operation":{"type":"clean","model":"v2"},"image":{"object":"s3://raws/001.png"},"output":"s3://releases/001_cleaned.png","priority":3}"#;
+        assert_eq!(serde_json::from_str::<Job>(newer).unwrap(), job);
+
+        // Jobs name their tenant, if any
+        let json = serde_json::to_string(&job.clone().for_tenant("scanlator-a")).unwrap();
+        assert!(json.ends_with(r#""tenant":"scanlator-a"}"#));
+        assert_eq!(
+            serde_json::from_str::<Job>(&json)
+                .unwrap()
+                .tenant
+                .as_deref(),
+            Some("scanlator-a")
+        );
+
+        // Timings are only written into the results of jobs that ask for them
+        let json = serde_json::to_string(&job.clone().with_timings()).unwrap();
+        assert!(json.ends_with(r#""timings":true}"#));
+        let result = JobResult {
+            id: "1f".to_string(),
+            outcome: Outcome::Failed {
+                error: "Timed out.".to_string(),
+            },
+            timings: Some(StageTimings {
+                decode_ms: 12,
+                ..StageTimings::default()
+            }),
+        };
+        let json = serde_json::to_string(&result).unwrap();
+        assert!(json.ends_with(
+            r#""timings":{"decode_ms":12,"detect_ms":0,"ocr_ms":0,"layout_ms":0,"encode_ms":0}}"#
+        ));
+        assert_eq!(serde_json::from_str::<JobResult>(&json).unwrap(), result);
+        let older = r#"{"id":"1f","outcome":{"status":"failed","error":"Timed out."}}"#;
+        assert_eq!(
+            serde_json::from_str::<JobResult>(older).unwrap().timings,
+            None
+        );
+
+        // Extract jobs return the crops of their regions when asked to
+        assert_eq!(
+            serde_json::from_str::<Operation>(r#"{"type":"extract"}"#).unwrap(),
+            Operation::Extract { crops: false }
+        );
+        assert_eq!(
+            serde_json::to_string(&Operation::Extract { crops: true }).unwrap(),
+            r#"{"type":"extract","crops":true}"#
+        );
+        let outcome = Outcome::Extracted {
+            extraction: Extraction::new("001", Vec::new()),
+            crops: vec![RegionCrop {
+                region: "001-0".to_string(),
+                png: vec![137, 80],
+            }],
+        };
+        let json = serde_json::to_string(&outcome).unwrap();
+        assert!(json.ends_with(r#""crops":[{"region":"001-0","png":[137,80]}]}"#));
+        assert_eq!(serde_json::from_str::<Outcome>(&json).unwrap(), outcome);
+
+        // Clean jobs that ask for a mask get it in their result
+        let operation: Operation =
+            serde_json::from_str(r#"{"type":"clean","mask":{"format":"rle","only":true}}"#)
+                .unwrap();
+        assert_eq!(
+            operation,
+            Operation::Clean {
+                mask: Some(MaskRequest {
+                    format: MaskFormat::Rle,
+                    only: true
+                })
+            }
+        );
+        let outcome = Outcome::Masked {
+            mask: Mask::Rle(RleMask {
+                size: [2, 1],
+                counts: vec![1, 1],
+            }),
+        };
+        assert_eq!(
+            serde_json::to_string(&outcome).unwrap(),
+            r#"{"status":"masked","mask":{"format":"rle","size":[2,1],"counts":[1,1]}}"#
+        );
+        assert_eq!(
+            serde_json::to_string(&Outcome::Written {
+                output: "s3://releases/001.png".to_string(),
+                mask: Some(Mask::Png {
+                    output: "s3://releases/001.mask.png".to_string()
+                }),
+            })
+            .unwrap(),
+            r#"{"status":"written","output":"s3://releases/001.png","mask":{"format":"png","output":"s3://releases/001.mask.png"}}"#
+        );
+
+        assert!(jobs::open("memory://", Duration::ZERO).is_ok());
+        assert!(jobs::open_shared("memory://", Duration::ZERO).is_err());
+        assert!(jobs::open("amqp://localhost", Duration::ZERO).is_err());
+    }
+}
@@ -0,0 +1,898 @@
+use crate::utils::linebreak::LineBreaking;
+use image::{GrayImage, Rgb, RgbImage};
+use imageproc::{drawing, pixelops};
+use rusttype::{point, Font, PositionedGlyph, Scale};
+
+// Smallest height, in pixels, that translations are shrunk to when they are fitted into regions
+pub const MIN_FONT_SIZE: f32 = 12.0;
+// Factor the size of a translation is multiplied by each time it is found not to fit
+pub const FIT_STEP: f32 = 0.9;
+// Size of the capitals that lowercase letters are drawn as in small caps, relative to full capitals
+pub const SMALL_CAPS_SCALE: f32 = 0.8;
+
+// A font, and whether lowercase letters are drawn in it as small capitals
+#[derive(Clone, Copy)]
+pub struct Face<'f> {
+    pub font: &'f Font<'f>,
+    pub small_caps: bool,
+}
+
+impl<'f> Face<'f> {
+    pub fn new(font: &'f Font<'f>) -> Face<'f> {
+        Face {
+            font,
+            small_caps: false,
+        }
+    }
+
+    // Glyphs of a line of small caps laid out from the origin like "drawing::text_size" lays out
+    // lines: pairs of glyphs of the same size are kerned and every glyph sits on the same baseline
+    fn small_caps_glyphs(&self, scale: Scale, text: &str) -> Vec<PositionedGlyph<'f>> {
+        let ascent = self.font.v_metrics(scale).ascent;
+        let small_scale = scale_by(scale, SMALL_CAPS_SCALE);
+
+        let mut glyphs = Vec::new();
+        let mut caret = 0.0;
+        let mut last = None;
+
+        for c in text.chars() {
+            let small = c.is_lowercase();
+            let glyph_scale = match small {
+                true => small_scale,
+                false => scale,
+            };
+
+            for c in c.to_uppercase() {
+                let glyph = self.font.glyph(c).scaled(glyph_scale);
+
+                if let Some((last_id, last_small)) = last {
+                    if last_small == small {
+                        caret += self.font.pair_kerning(glyph_scale, last_id, glyph.id());
+                    }
+                }
+
+                last = Some((glyph.id(), small));
+                let advance = glyph.h_metrics().advance_width;
+                glyphs.push(glyph.positioned(point(caret, ascent)));
+                caret += advance;
+            }
+        }
+
+        glyphs
+    }
+
+    // Width and height of a line of text as it is drawn by "draw_text_mut"
+    pub fn text_size(&self, scale: Scale, text: &str) -> (i32, i32) {
+        if !self.small_caps {
+            return drawing::text_size(scale, self.font, text);
+        }
+
+        self.small_caps_glyphs(scale, text)
+            .iter()
+            .filter_map(PositionedGlyph::pixel_bounding_box)
+            .fold((0, 0), |(width, height), bounding_box| {
+                (
+                    width.max(bounding_box.max.x),
+                    height.max(bounding_box.max.y),
+                )
+            })
+    }
+
+    pub fn draw_text_mut(
+        &self,
+        canvas: &mut RgbImage,
+        color: Rgb<u8>,
+        x: i32,
+        y: i32,
+        scale: Scale,
+        text: &str,
+    ) {
+        if !self.small_caps {
+            drawing::draw_text_mut(canvas, color, x, y, scale, self.font, text);
+            return;
+        }
+
+        let (width, height) = canvas.dimensions();
+
+        for glyph in self.small_caps_glyphs(scale, text) {
+            let bounding_box = match glyph.pixel_bounding_box() {
+                Some(bounding_box) => bounding_box,
+                None => continue,
+            };
+
+            glyph.draw(|glyph_x, glyph_y, coverage| {
+                let image_x = x + bounding_box.min.x + glyph_x as i32;
+                let image_y = y + bounding_box.min.y + glyph_y as i32;
+
+                if (0..width as i32).contains(&image_x) && (0..height as i32).contains(&image_y) {
+                    let pixel = canvas.get_pixel_mut(image_x as u32, image_y as u32);
+                    *pixel = pixelops::weighted_sum(*pixel, color, 1.0 - coverage, coverage);
+                }
+            });
+        }
+    }
+}
+
+// What lines of text are drawn with
+#[derive(Clone, Copy)]
+pub struct Pen<'f> {
+    pub scale: Scale,
+    pub face: Face<'f>,
+    pub color: Rgb<u8>,
+    // Extra pixels each line is drawn over to the right, to make the text bolder
+    pub weight: i32,
+    // Color of a border drawn around the glyphs, for text set straight onto the artwork
+    pub outline: Option<Rgb<u8>>,
+}
+
+pub fn scale_by(scale: Scale, factor: f32) -> Scale {
+    Scale {
+        x: scale.x * factor,
+        y: scale.y * factor,
+    }
+}
+
+// Size a translation starts out at in a region, from the region's size and the number of words,
+// before it is fitted into the region
+pub fn initial_scale(text: &str, width: u32, height: i32) -> Scale {
+    let num_words = text
+        .split(' ')
+        .map(str::to_string)
+        .collect::<Vec<String>>()
+        .len();
+
+    /*
+        Scaling rules based on width of the region
+        and number of words.
+    */
+    let mut scale = Scale {
+        x: height as f32 / 9.0,
+        y: height as f32 / 12.0,
+    };
+
+    if width < 55 {
+        scale.x = height as f32 / 8.0;
+        scale.y = height as f32 / 12.0;
+    } else if width < 100 {
+        scale.x = height as f32 / 10.0;
+        scale.y = height as f32 / 14.0;
+    }
+    /*
+    if num_words >= 17 {
+        scale.x = height as f32 / 20.0;
+        scale.y = height as f32 / 23.0;
+    }
+    */
+    /*
+    if num_words >= 15 {
+        scale.x = height as f32 / 18.0;
+        scale.y = height as f32 / 21.0;
+    } else
+    */
+    if num_words >= 16 {
+        scale.x = height as f32 / 14.0;
+        scale.y = height as f32 / 16.0;
+    } else if num_words >= 14 {
+        scale.x = height as f32 / 12.0;
+        scale.y = height as f32 / 14.0;
+    } else if num_words >= 12 {
+        scale.x = height as f32 / 10.0;
+        scale.y = height as f32 / 12.0;
+    } else if num_words >= 10 {
+        scale.x = height as f32 / 8.0;
+        scale.y = height as f32 / 10.0;
+    } else if num_words <= 2 {
+        scale.x = height as f32 / 7.0;
+        scale.y = height as f32 / 9.0;
+    }
+
+    scale
+}
+
+// Largest scale, at most "scale", at which the text wraps into lines no wider than "max_width" and
+// a block no taller than "max_height". Text that doesn't fit at "MIN_FONT_SIZE" gets that size.
+pub fn fit_scale(
+    text: &str,
+    breaking: LineBreaking,
+    scale: Scale,
+    face: Face,
+    max_width: i32,
+    max_height: i32,
+) -> Scale {
+    let mut scale = scale;
+
+    loop {
+        let lines = wrap_words(text, breaking, scale, face, max_width);
+        let fits = block_height(&lines, scale, face) <= max_height
+            && lines
+                .iter()
+                .all(|line| face.text_size(scale, line).0 <= max_width);
+
+        if fits || scale.y <= MIN_FONT_SIZE {
+            return scale;
+        }
+
+        scale = scale_by(scale, FIT_STEP);
+    }
+}
+
+// Largest scale, at most "scale", at which the text wraps into at most "max_lines" lines no wider
+// than "max_width". Text that takes more even at "MIN_FONT_SIZE" gets that size.
+pub fn fit_lines(
+    text: &str,
+    breaking: LineBreaking,
+    scale: Scale,
+    face: Face,
+    max_width: i32,
+    max_lines: usize,
+) -> Scale {
+    let mut scale = scale;
+
+    while scale.y > MIN_FONT_SIZE
+        && wrap_words(text, breaking, scale, face, max_width).len() > max_lines
+    {
+        scale = scale_by(scale, FIT_STEP);
+    }
+
+    scale
+}
+
+// Breaks text into lines of whole words that are at most "max_width" wide, where "breaking"
+// allows. Words that are wider on their own get a line of their own.
+pub fn wrap_words(
+    text: &str,
+    breaking: LineBreaking,
+    scale: Scale,
+    face: Face,
+    max_width: i32,
+) -> Vec<String> {
+    let mut lines: Vec<String> = Vec::new();
+    let mut curr_line = String::new();
+
+    for segment in breaking.segments(text) {
+        let candidate = match (curr_line.is_empty(), segment.space_before) {
+            (true, _) => segment.text.clone(),
+            (false, true) => format!("{curr_line} {}", segment.text),
+            (false, false) => format!("{curr_line}{}", segment.text),
+        };
+
+        if !curr_line.is_empty() && face.text_size(scale, &candidate).0 > max_width {
+            lines.push(std::mem::replace(&mut curr_line, segment.text));
+        } else {
+            curr_line = candidate;
+        }
+    }
+
+    if !curr_line.is_empty() {
+        lines.push(curr_line);
+    }
+
+    lines
+}
+
+// Wraps text like "wrap_words", but when that leaves a single short word on the last line, the lines
+// are narrowed as far as they go without needing another one, which moves words down to it
+pub fn wrap_without_widows(
+    text: &str,
+    breaking: LineBreaking,
+    scale: Scale,
+    face: Face,
+    max_width: i32,
+) -> Vec<String> {
+    let lines = wrap_words(text, breaking, scale, face, max_width);
+    if !is_widowed(&lines, scale, face, max_width) {
+        return lines;
+    }
+
+    // The narrowest width that still fits the text in as many lines
+    let (mut narrow, mut wide) = (0, max_width);
+    while wide - narrow > 1 {
+        let width = (narrow + wide) / 2;
+
+        match wrap_words(text, breaking, scale, face, width).len() <= lines.len() {
+            true => wide = width,
+            false => narrow = width,
+        }
+    }
+
+    let balanced = wrap_words(text, breaking, scale, face, wide);
+    match balanced.len() == lines.len() && !is_widowed(&balanced, scale, face, max_width) {
+        true => balanced,
+        false => lines,
+    }
+}
+
+// Whether the last line is a single word shorter than a third of the line, under a line that could
+// have given it another
+fn is_widowed(lines: &[String], scale: Scale, face: Face, max_width: i32) -> bool {
+    match lines {
+        [.., previous, last] => {
+            !last.contains(' ')
+                && previous.contains(' ')
+                && face.text_size(scale, last).0 < max_width / 3
+        }
+        _ => false,
+    }
+}
+
+// Breaks a translation into the lines it is drawn in, at most "max_width" wide. Words too long for a
+// line of their own are hyphenated.
+pub fn break_lines(
+    text: &str,
+    breaking: LineBreaking,
+    scale: Scale,
+    face: Face,
+    max_width: i32,
+    greedy: bool,
+) -> Vec<String> {
+    // Initially break the text segment into lines that fit within the region. Each candidate
+    // line is measured whole, as it is drawn, since the widths of its words don't add up to
+    // its width: spaces have no ink and glyphs are kerned across word boundaries.
+    let temp_lines = match greedy {
+        true => wrap_words(text, breaking, scale, face, max_width),
+        false => wrap_without_widows(text, breaking, scale, face, max_width),
+    };
+
+    #[cfg(feature = "debug")]
+    {
+        println!("lines: {temp_lines:?}");
+    }
+
+    let mut lines: Vec<String> = Vec::new();
+
+    /*
+        Since we sometimes have long words, some lines may still not fit within the region.
+        Now we break up individual words if they are causing their lines to be too long.
+    */
+    for line in temp_lines {
+        let (text_width, _) = face.text_size(scale, &line);
+
+        // Check if a line is still too long
+        if text_width > max_width {
+            let num_words = line
+                .split(' ')
+                .map(str::to_string)
+                .collect::<Vec<String>>()
+                .len();
+
+            /*
+                If the line is a single word and it's still too long,
+                we make a new line at the closest char to the border.
+                If there are multiple words in the line, we find the
+                closest word to the border and make a newline there.
+            */
+            if num_words == 1 {
+                let mut chars: Vec<char> = line.chars().collect();
+                let mut original_line: String = chars.iter().collect();
+                let mut new_line: Vec<char> = Vec::new();
+
+                // Measured with the hyphen, which may be kerned against the last letter
+                while face.text_size(scale, &format!("{original_line}-")).0 > max_width {
+                    // We move the last char from the original line to the beginning of the new line
+                    new_line.insert(
+                        0,
+                        chars
+                            .pop()
+                            .expect("Unexpected error while popping from char vector."),
+                    );
+                    // Rebuild the updated original line for checking.
+                    original_line = chars.iter().collect();
+                }
+
+                // Push the updated original line
+                original_line.push('-');
+                lines.push(original_line);
+
+                // Push the new line
+                if !new_line.is_empty() {
+                    let new_line = new_line.iter().collect();
+
+                    lines.push(new_line);
+                }
+            } else {
+                let mut words: Vec<String> = line.split(' ').map(str::to_string).collect();
+
+                let mut original_line = words.join(" ");
+                let mut new_line: Vec<String> = Vec::new();
+
+                while face.text_size(scale, &original_line).0 > max_width {
+                    new_line.insert(
+                        0,
+                        words
+                            .pop()
+                            .expect("Unexpected error while popping from word vector."),
+                    );
+
+                    original_line = words.join(" ");
+                }
+
+                // Push the updated original line
+                lines.push(original_line);
+
+                // Push the new line
+                if !new_line.is_empty() {
+                    lines.push(new_line.join(" "));
+                }
+            }
+        } else {
+            // If the line is fine, append it and continue
+            if !line.is_empty() {
+                lines.push(line.to_string());
+            }
+        }
+    }
+
+    lines
+}
+
+// Splits a translation that flows across several regions into a part for each, between the pieces
+// lines may be broken between. Each part gets about its share of "weights", such as the areas of the
+// regions, in characters, and every part gets at least one piece while there are enough.
+pub fn split_across(text: &str, breaking: LineBreaking, weights: &[u64]) -> Vec<String> {
+    let segments = breaking.segments(text);
+    let mut parts = vec![String::new(); weights.len()];
+    if parts.is_empty() {
+        return parts;
+    }
+
+    let total_weight = weights.iter().sum::<u64>().max(1);
+    let total_length: u64 = segments
+        .iter()
+        .map(|segment| segment.text.chars().count() as u64)
+        .sum();
+    let (mut part, mut length, mut weight) = (0, 0u64, weights[0]);
+
+    for (index, segment) in segments.iter().enumerate() {
+        let segment_length = segment.text.chars().count() as u64;
+        // The part is done once the middle of the piece would be past its share, or when the pieces
+        // left are only enough for one in each of the parts left
+        let past_share = (2 * length + segment_length) * total_weight > 2 * total_length * weight;
+        let pieces_left = segments.len() - index;
+        if part + 1 < parts.len()
+            && !parts[part].is_empty()
+            && (past_share || pieces_left < parts.len() - part)
+        {
+            part += 1;
+            weight += weights[part];
+        }
+
+        if !parts[part].is_empty() && segment.space_before {
+            parts[part].push(' ');
+        }
+        parts[part].push_str(&segment.text);
+        length += segment_length;
+    }
+
+    parts
+}
+
+// Centroid of the pixels of the text in a region, dark ones unless the region is "inverted"
+pub fn text_centroid(region: &GrayImage, inverted: bool) -> Option<(f32, f32)> {
+    let (mut sum_x, mut sum_y, mut count) = (0u64, 0u64, 0u64);
+
+    for (x, y, pixel) in region.enumerate_pixels() {
+        if (pixel.0[0] < 128) != inverted {
+            sum_x += x as u64;
+            sum_y += y as u64;
+            count += 1;
+        }
+    }
+
+    match count {
+        0 => None,
+        _ => Some((
+            sum_x as f32 / count as f32 + 0.5,
+            sum_y as f32 / count as f32 + 0.5,
+        )),
+    }
+}
+
+// How far a block centered in an area "size" pixels long has to move to be centered on "anchor"
+// instead, moving at most half of the "room" the block leaves in the area either way
+pub fn anchor_offset(anchor: i32, size: i32, room: i32) -> i32 {
+    let slack = (room / 2).max(0);
+
+    (anchor - size / 2).clamp(-slack, slack)
+}
+
+// Height of a block of lines as laid out by "draw_centered_lines"
+pub fn block_height(lines: &[String], scale: Scale, face: Face) -> i32 {
+    match lines.first() {
+        Some(first_line) => lines.len() as i32 * face.text_size(scale, first_line).1,
+        None => 0,
+    }
+}
+
+// Draws lines from "start_y" downwards, each centered horizontally in the area given by its left
+// edge and width
+pub fn draw_centered_lines(
+    canvas: &mut RgbImage,
+    lines: &[String],
+    (left, area_width): (i32, i32),
+    mut start_y: i32,
+    pen: Pen,
+) {
+    for line in lines {
+        let (line_width, line_height) = pen.face.text_size(pen.scale, line);
+        let start_x = left + (area_width - line_width - pen.weight) / 2;
+
+        // The outline is the line drawn in its color at every offset around it, under the line itself
+        if let Some(outline) = pen.outline {
+            let radius = (pen.scale.y / 12.0).max(1.0) as i32;
+
+            for offset_y in -radius..=radius {
+                for offset_x in -radius..=radius + pen.weight {
+                    pen.face.draw_text_mut(
+                        canvas,
+                        outline,
+                        start_x + offset_x,
+                        start_y + offset_y,
+                        pen.scale,
+                        line,
+                    );
+                }
+            }
+        }
+
+        for offset in 0..=pen.weight {
+            pen.face.draw_text_mut(
+                canvas,
+                pen.color,
+                start_x + offset,
+                start_y,
+                pen.scale,
+                line,
+            );
+        }
+
+        start_y += line_height;
+    }
+}
+
+// Draws a block of lines in the middle of the canvas, moved onto "anchor", the centroid of the
+// original text, as far as it stays within the canvas and lines "max_width" wide. Detection boxes
+// often take in more of the bubble on one side than the other, so the middle of the region can be
+// visibly off the middle of the bubble.
+pub fn draw_block(
+    canvas: &mut RgbImage,
+    lines: &[String],
+    pen: Pen,
+    max_width: i32,
+    anchor: Option<(i32, i32)>,
+) {
+    let (width, height) = canvas.dimensions();
+    let (width, height) = (width as i32, height as i32);
+    let block_width = lines
+        .iter()
+        .map(|line| pen.face.text_size(pen.scale, line).0)
+        .max()
+        .unwrap_or(0);
+    let block_height = block_height(lines, pen.scale, pen.face);
+
+    let (offset_x, offset_y) = match anchor {
+        Some((anchor_x, anchor_y)) => (
+            anchor_offset(anchor_x, width, max_width - block_width),
+            anchor_offset(anchor_y, height, height - block_height),
+        ),
+        None => (0, 0),
+    };
+
+    draw_centered_lines(
+        canvas,
+        lines,
+        (offset_x, width),
+        (height - block_height) / 2 + offset_y,
+        pen,
+    );
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::fonts::bundled_font;
+    use crate::layout::{
+        anchor_offset, block_height, break_lines, draw_block, fit_lines, fit_scale, scale_by,
+        split_across, text_centroid, wrap_without_widows, wrap_words, Face, Pen, MIN_FONT_SIZE,
+    };
+    use crate::utils::linebreak::LineBreaking;
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+    use imageproc::drawing;
+    use rusttype::Scale;
+
+    #[test]
+    fn test_fit_scale() {
+        let font = bundled_font();
+        let scale = Scale { x: 40.0, y: 30.0 };
+        let text = "I told you the bridge would not hold if all of us crossed at once";
+
+        // Text that already fits keeps its size
+        assert_eq!(
+            fit_scale(
+                "Run!",
+                LineBreaking::Words,
+                scale,
+                Face::new(&font),
+                300,
+                200
+            ),
+            scale
+        );
+
+        let fitted = fit_scale(text, LineBreaking::Words, scale, Face::new(&font), 150, 160);
+        let lines = wrap_words(text, LineBreaking::Words, fitted, Face::new(&font), 150);
+        assert!(fitted.y < scale.y);
+        assert!(fitted.y >= MIN_FONT_SIZE);
+        assert!((fitted.x / fitted.y - scale.x / scale.y).abs() < 0.001);
+        assert!(block_height(&lines, fitted, Face::new(&font)) <= 160);
+
+        // Text that can't fit stops shrinking at the smallest size
+        let crowded = fit_scale(text, LineBreaking::Words, scale, Face::new(&font), 20, 10);
+        assert!(crowded.y <= MIN_FONT_SIZE);
+        assert!(crowded.y > MIN_FONT_SIZE * 0.9 - 0.001);
+    }
+
+    #[test]
+    fn test_text_anchor() {
+        // Text in the bottom right quarter of a white region, from (60, 40) to (80, 60)
+        let region = GrayImage::from_fn(100, 80, |x, y| {
+            match (60..80).contains(&x) && (40..60).contains(&y) {
+                true => Luma([0]),
+                false => Luma([255]),
+            }
+        });
+        assert_eq!(text_centroid(&region, false), Some((70.0, 50.0)));
+        assert_eq!(
+            text_centroid(&GrayImage::from_pixel(10, 10, Luma([255])), false),
+            None
+        );
+
+        // Light text on a dark background
+        let mut inverted = region.clone();
+        image::imageops::invert(&mut inverted);
+        assert_eq!(text_centroid(&inverted, true), Some((70.0, 50.0)));
+
+        // A block 40 pixels wide moves right onto the text, but no further than the region allows
+        assert_eq!(anchor_offset(70, 100, 60), 20);
+        assert_eq!(anchor_offset(90, 100, 60), 30);
+        assert_eq!(anchor_offset(10, 100, 60), -30);
+        // A block that fills the region stays centered
+        assert_eq!(anchor_offset(70, 100, 0), 0);
+        assert_eq!(anchor_offset(70, 100, -20), 0);
+    }
+
+    #[test]
+    fn test_fit_lines() {
+        let font = bundled_font();
+        let face = Face::new(&font);
+        let scale = Scale::uniform(40.0);
+        let text = "I told you the bridge would not hold if all of us crossed at once";
+
+        assert!(wrap_words(text, LineBreaking::Words, scale, face, 200).len() > 3);
+
+        let fitted = fit_lines(text, LineBreaking::Words, scale, face, 200, 3);
+        assert!(fitted.y < scale.y);
+        assert!(wrap_words(text, LineBreaking::Words, fitted, face, 200).len() <= 3);
+        // Only shrunk as far as it had to be
+        assert!(
+            wrap_words(
+                text,
+                LineBreaking::Words,
+                scale_by(fitted, 1.0 / 0.9),
+                face,
+                200
+            )
+            .len()
+                > 3
+        );
+
+        // Text that takes too many lines even at the smallest size gets that size
+        let crowded = fit_lines(text, LineBreaking::Words, scale, face, 60, 1);
+        assert!(crowded.y <= MIN_FONT_SIZE);
+        assert!(crowded.y > MIN_FONT_SIZE * 0.9 - 0.001);
+    }
+
+    #[test]
+    fn test_wrap_words_kerning() {
+        let font = bundled_font();
+        let scale = Scale::uniform(24.0);
+        let text = "AVA Wave To Yva LTA AWAY Tv yo";
+        let max_width = 90;
+
+        // Lines are as wide as they are drawn, so none overflows however its words are kerned
+        let lines = wrap_words(
+            text,
+            LineBreaking::Words,
+            scale,
+            Face::new(&font),
+            max_width,
+        );
+        assert_eq!(lines.join(" "), text);
+        for line in &lines {
+            assert!(
+                drawing::text_size(scale, &font, line).0 <= max_width,
+                "{line}"
+            );
+        }
+    }
+
+    #[test]
+    fn test_small_caps() {
+        let font = bundled_font();
+        let scale = Scale::uniform(32.0);
+        let small_caps = Face {
+            font: &font,
+            small_caps: true,
+        };
+
+        // Lowercase letters are smaller capitals, capitals stay as they are
+        let (width, height) = small_caps.text_size(scale, "Tokyo");
+        assert!(width < Face::new(&font).text_size(scale, "TOKYO").0);
+        assert_eq!(
+            small_caps.text_size(scale, "TOKYO"),
+            Face::new(&font).text_size(scale, "TOKYO")
+        );
+
+        // The line is drawn within the size it was measured at
+        let mut canvas = RgbImage::from_pixel(200, 60, Rgb([255, 255, 255]));
+        small_caps.draw_text_mut(&mut canvas, Rgb([0, 0, 0]), 0, 0, scale, "Tokyo");
+        let inked = |x: u32, y: u32| canvas.get_pixel(x, y).0[0] < 128;
+        assert!((0..60).any(|y| inked(width as u32 - 1, y)));
+        assert!(!(0..60).any(|y| (width as u32 + 1..200).any(|x| inked(x, y))));
+        assert!(!(height as u32 + 1..60).any(|y| (0..200).any(|x| inked(x, y))));
+    }
+
+    #[test]
+    fn test_wrap_without_widows() {
+        let font = bundled_font();
+        let face = Face::new(&font);
+        let scale = Scale::uniform(24.0);
+        let text = "We have to leave the city before the gates close at dawn";
+        let max_width = ["We have to leave the city", "before the gates close at"]
+            .iter()
+            .map(|line| drawing::text_size(scale, &font, line).0)
+            .max()
+            .unwrap()
+            + 2;
+
+        let greedy = wrap_words(text, LineBreaking::Words, scale, face, max_width);
+        assert_eq!(
+            greedy,
+            vec![
+                "We have to leave the city",
+                "before the gates close at",
+                "dawn"
+            ]
+        );
+
+        // The same number of lines, with words moved down to the stranded one
+        let balanced = wrap_without_widows(text, LineBreaking::Words, scale, face, max_width);
+        assert_eq!(balanced.len(), 3);
+        assert_eq!(balanced.join(" "), text);
+        assert!(balanced[2].contains(' '));
+        for line in &balanced {
+            assert!(drawing::text_size(scale, &font, line).0 <= max_width);
+        }
+
+        // Text without a widow is wrapped as before
+        let text = "We have to leave the city before the gates close";
+        assert_eq!(
+            wrap_without_widows(text, LineBreaking::Words, scale, face, max_width),
+            wrap_words(text, LineBreaking::Words, scale, face, max_width)
+        );
+        assert_eq!(
+            wrap_without_widows("Run", LineBreaking::Words, scale, face, 10),
+            vec!["Run"]
+        );
+    }
+
+    #[test]
+    fn test_wrap_words_punctuation() {
+        let font = bundled_font();
+        let face = Face::new(&font);
+        let scale = Scale::uniform(24.0);
+        let width = |text: &str| drawing::text_size(scale, &font, text).0;
+
+        // The ellipsis stays with "Wait" even though only "Wait" fits
+        assert_eq!(
+            wrap_words(
+                "Wait … who are you?",
+                LineBreaking::Words,
+                scale,
+                face,
+                width("Wait") + 1
+            ),
+            vec!["Wait …", "who", "are", "you?"]
+        );
+
+        // Lines break after the dash rather than before the whole word
+        assert_eq!(
+            wrap_words(
+                "I never—forget it",
+                LineBreaking::Words,
+                scale,
+                face,
+                width("I never—").max(width("forget it")) + 1
+            ),
+            vec!["I never—", "forget it"]
+        );
+    }
+
+    #[test]
+    fn test_break_lines() {
+        let font = bundled_font();
+        let face = Face::new(&font);
+        let scale = Scale::uniform(24.0);
+        let max_width = drawing::text_size(scale, &font, "Supercal").0;
+
+        // A word too long for any line is hyphenated where it reaches the edge
+        let lines = break_lines(
+            "Supercalifragilistic",
+            LineBreaking::Words,
+            scale,
+            face,
+            max_width,
+            true,
+        );
+        assert!(lines.len() > 1);
+        assert!(lines[0].ends_with('-'));
+        assert_eq!(lines.concat().replace('-', ""), "Supercalifragilistic");
+        assert!(face.text_size(scale, &lines[0]).0 <= max_width);
+
+        assert!(break_lines(" ", LineBreaking::Words, scale, face, max_width, false).is_empty());
+    }
+
+    #[test]
+    fn test_draw_block() {
+        let font = bundled_font();
+        let pen = Pen {
+            scale: Scale::uniform(20.0),
+            face: Face::new(&font),
+            color: Rgb([0, 0, 0]),
+            weight: 0,
+            outline: None,
+        };
+        let lines = vec!["Hey".to_string()];
+
+        let draw = |anchor| {
+            let mut canvas = RgbImage::from_pixel(200, 100, Rgb([255, 255, 255]));
+            draw_block(&mut canvas, &lines, pen, 200, anchor);
+
+            let inked = GrayImage::from_fn(200, 100, |x, y| Luma([canvas.get_pixel(x, y).0[0]]));
+            text_centroid(&inked, false).unwrap()
+        };
+
+        // The block is drawn in the middle of the canvas, or moved onto the original text
+        let (centered_x, centered_y) = draw(None);
+        assert!((centered_x - 100.0).abs() < 5.0 && (centered_y - 50.0).abs() < 10.0);
+        let (moved_x, _) = draw(Some((150, 50)));
+        assert!((moved_x - 150.0).abs() < 5.0);
+    }
+
+    #[test]
+    fn test_split_across() {
+        let text = "Don't you dare leave without me, you idiot!";
+
+        assert_eq!(
+            split_across(text, LineBreaking::Words, &[100, 100]),
+            vec!["Don't you dare leave", "without me, you idiot!"]
+        );
+        // The larger region gets more of the sentence
+        assert_eq!(
+            split_across(text, LineBreaking::Words, &[300, 100]),
+            vec!["Don't you dare leave without me,", "you idiot!"]
+        );
+        // Tiny regions still get a word each
+        assert_eq!(
+            split_across(text, LineBreaking::Words, &[1000, 1, 1]),
+            vec!["Don't you dare leave without me,", "you", "idiot!"]
+        );
+        assert_eq!(
+            split_across("待ってよ、行かないで", LineBreaking::Characters, &[50, 50]),
+            vec!["待ってよ、", "行かないで"]
+        );
+        assert_eq!(
+            split_across("Wait", LineBreaking::Words, &[10, 10]),
+            vec!["Wait", ""]
+        );
+        assert!(split_across(text, LineBreaking::Words, &[]).is_empty());
+    }
+}
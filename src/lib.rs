@@ -1,5 +1,66 @@
+#[cfg(feature = "server")]
+pub mod audit;
+#[cfg(feature = "pipeline")]
+pub mod bench;
+#[cfg(feature = "pipeline")]
+pub mod cache;
+#[cfg(feature = "pipeline")]
+pub mod checkpoint;
+#[cfg(feature = "pipeline")]
 pub mod config;
+#[cfg(feature = "pipeline")]
+pub mod credits;
+#[cfg(feature = "pipeline")]
 pub mod detection;
+#[cfg(feature = "pipeline")]
+pub mod diff;
+#[cfg(feature = "pipeline")]
+pub mod evaluation;
+pub mod export;
+pub mod extraction;
+#[cfg(feature = "pipeline")]
+pub mod font_store;
+pub mod fonts;
+#[cfg(feature = "pipeline")]
+pub mod handlers;
+#[cfg(feature = "history")]
+pub mod history;
+#[cfg(feature = "pipeline")]
+pub mod honorifics;
+#[cfg(feature = "pipeline")]
+pub mod hooks;
+pub mod imaging;
+#[cfg(feature = "server")]
+pub mod jobs;
+pub mod layout;
+#[cfg(feature = "pipeline")]
 pub mod ocr;
+pub mod onnx;
+#[cfg(feature = "pipeline")]
+pub mod pipeline;
+#[cfg(feature = "pipeline")]
+pub mod project;
 pub mod replacer;
+#[cfg(feature = "pipeline")]
+pub mod selection;
+#[cfg(feature = "pipeline")]
+pub mod storage;
+pub mod style;
+#[cfg(feature = "pipeline")]
+pub mod summary;
+#[cfg(feature = "pipeline")]
+pub mod synthetic;
+#[cfg(feature = "server")]
+pub mod tenants;
+pub mod text_box;
+pub mod timings;
+#[cfg(feature = "tract")]
+pub mod tract_detection;
 pub mod utils;
+#[cfg(feature = "pipeline")]
+pub mod verify;
+#[cfg(feature = "pipeline")]
+pub mod vocabulary;
+#[cfg(feature = "server")]
+pub mod worker;
+pub mod yolo;
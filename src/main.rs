@@ -1,21 +1,35 @@
-use anyhow::Result;
+use anyhow::{Context, Result};
 use globwalk::GlobWalkerBuilder;
-use indexmap::IndexMap;
-use indicatif::{ParallelProgressIterator, ProgressIterator};
+use image::{DynamicImage, GenericImageView};
+use indicatif::{ProgressBar, ProgressIterator};
 use itertools::{multizip, Itertools};
-use mangatra::config::{Config, InputMode, RuntimeMode};
-use mangatra::detection::Detector;
-use mangatra::ocr::Ocr;
-use mangatra::replacer::Replacer;
-use mangatra::utils::{image_conversion, validation};
-use opencv::core;
+use mangatra::cache::{CachedStage, CachedText, StageCache};
+use mangatra::checkpoint::Checkpoint;
+use mangatra::config::{Config, InputMode, Invocation, RuntimeMode};
+use mangatra::credits::{self, CreditsPlacement};
+use mangatra::detection::{Detector, TextBox};
+use mangatra::export::annotations::AnnotationFormat;
+use mangatra::export::table::{self, TableFormat};
+use mangatra::export::{annotations, dataset, mokuro, review, xliff};
+use mangatra::extraction::{self, page_name, Extraction, PageSize};
+use mangatra::handlers::{self, DetectedPage, ExtractedText, RenderedPage};
+use mangatra::pipeline::{self, Workers};
+use mangatra::project::{PageRecord, PageStatus, Parameters, Project};
+use mangatra::replacer::TextOverflow;
+use mangatra::summary::RunSummary;
+use mangatra::timings::{Stage, StageTimings};
+use mangatra::utils::cancellation::CancellationToken;
+use mangatra::utils::hashing::{self, PageFingerprint};
+use mangatra::utils::image_conversion;
+use mangatra::utils::metadata::{self, ImageMetadata};
+use mangatra::utils::validation;
 use rayon::prelude::*;
-use serde::Deserialize;
-use serde_json::{json, Value};
-use std::path::PathBuf;
-use std::sync::Arc;
+use std::collections::{BTreeSet, HashMap};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread;
 use std::time::Instant;
-use tracing::error;
+use tracing::{error, info};
 use tracing_subscriber;
 
 type InputPaths = Vec<String>;
@@ -30,71 +44,268 @@ struct DirectoryWalkerState {
     pub file_stems: Vec<String>,
 }
 
-#[derive(Deserialize, Debug, Clone)]
-struct Json {
-    #[serde(with = "indexmap::serde_seq")]
-    pub text: IndexMap<String, String>,
-}
-
-impl From<IndexMap<String, String>> for Json {
-    fn from(text: IndexMap<String, String>) -> Json {
-        Json { text }
-    }
-}
-
 // Runtime struct that holds configuration and other needed components for translation
 pub struct Runtime {
     config: Arc<Config>,
+    cancellation_token: CancellationToken,
+    // Cancelled once no more pages should be started, which the cancellation token also implies
+    stopping: CancellationToken,
+    // Pages done in an earlier run that is being resumed
+    resumed: BTreeSet<String>,
+    // Pages whose outputs were written, including the resumed ones
+    completed: Mutex<BTreeSet<String>>,
+    // Progress of the pages, written back to the project file when the run ends
+    project: Option<Mutex<Project>>,
+    // Outcome of every page of a directory run
+    summary: Mutex<RunSummary>,
+    // Detection and OCR results of earlier runs, if "--cache" is set
+    cache: Option<StageCache>,
 }
 
 impl Runtime {
     /**
      * Creates a new runtime context
      */
-    pub fn new() -> Result<Runtime> {
-        let config = Arc::new(Config::parse()?);
+    pub fn new(config: Config) -> Result<Runtime> {
+        let config = Arc::new(config);
+
+        // Pages processed in parallel each run the model, so by default OpenCV doesn't start
+        // threads of its own for every one of them on top of rayon's
+        opencv::core::set_num_threads(config.opencv_threads)?;
 
-        Ok(Runtime { config })
+        // OpenCL kernels sum in whatever order their work groups finish, so the same page can come
+        // out a pixel or a score apart between runs. Pages are still processed in parallel, as each
+        // one is worked on by a single thread and written to its own outputs.
+        if config.deterministic {
+            opencv::core::set_use_opencl(false)?;
+        }
+
+        if let Some(threads) = config.threads {
+            rayon::ThreadPoolBuilder::new()
+                .num_threads(threads)
+                .build_global()?;
+        }
+
+        // Load the model once up front so a wrong or broken model is reported before any page is
+        // processed, along with a model that can't take the batches of "--batch-size"
+        Detector::new(&config.model_path, config.padding, config.dnn.clone())?
+            .validate_batch(config.batch_size)?;
+
+        // In a directory run, the first Ctrl-C keeps new pages from starting and lets the pages in
+        // progress finish, and a second one also stops the per-region work of those. A single page is
+        // stopped right away.
+        let cancellation_token = CancellationToken::new();
+        let stopping = CancellationToken::new();
+        let handler_token = cancellation_token.clone();
+        let handler_stopping = stopping.clone();
+        let directory = config.input_mode == InputMode::Directory;
+        ctrlc::set_handler(move || {
+            if directory && !handler_stopping.is_cancelled() {
+                eprintln!("Finishing the pages in progress. Press Ctrl-C again to stop them too.");
+            } else {
+                handler_token.cancel();
+            }
+
+            handler_stopping.cancel();
+        })?;
+
+        let resumed = match (config.resume, directory) {
+            (true, true) => match Checkpoint::load(Path::new(&config.output_path))? {
+                Some(checkpoint) => {
+                    info!(
+                        "Resuming the run, skipping the {} pages it had done",
+                        checkpoint.completed.len()
+                    );
+                    checkpoint.completed
+                }
+                None => {
+                    info!("The run has no checkpoint to resume from, processing every page");
+                    BTreeSet::new()
+                }
+            },
+            _ => BTreeSet::new(),
+        };
+
+        let project = match &config.project_path {
+            Some(project_path) => Some(Mutex::new(Project::load(project_path)?)),
+            None => None,
+        };
+
+        let cache = match &config.cache_path {
+            Some(cache_path) => Some(StageCache::for_config(cache_path, &config)?),
+            None => None,
+        };
+
+        Ok(Runtime {
+            config,
+            cancellation_token,
+            stopping,
+            completed: Mutex::new(resumed.clone()),
+            resumed,
+            project,
+            summary: Mutex::new(RunSummary::new()),
+            cache,
+        })
     }
 
     pub fn run(&mut self) -> Result<()> {
-        match self.config.runtime_mode {
-            RuntimeMode::Extraction => self.extract_mode()?,
-            RuntimeMode::Replacement => self.replace_mode()?,
+        let result = match self.config.runtime_mode {
+            RuntimeMode::Extraction => self.extract_mode(),
+            RuntimeMode::Replacement => self.replace_mode(),
+        };
+
+        // The progress of the pages that did finish is saved even if the run failed or was cancelled
+        if let (Some(project), Some(project_path)) = (&self.project, &self.config.project_path) {
+            project
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .save(project_path)?;
         }
 
+        if self.config.input_mode == InputMode::Directory {
+            let summary = self.summary.lock().unwrap_or_else(PoisonError::into_inner);
+
+            print!("{}", summary.report());
+
+            if let Some(summary_path) = &self.config.summary_path {
+                summary.write(summary_path)?;
+            }
+
+            self.write_checkpoint(&result)?;
+        }
+
+        result?;
+
+        self.stopping.check()
+    }
+
+    // Keeps the pages that were done in a checkpoint if the run was stopped before the end, and tells
+    // how to go on from there. A run that failed leaves the checkpoint of an earlier one as it was.
+    fn write_checkpoint(&self, result: &Result<()>) -> Result<()> {
+        let output_directory = Path::new(&self.config.output_path);
+
+        if !self.stopping.is_cancelled() {
+            return match result {
+                Ok(()) => Checkpoint::remove(output_directory),
+                Err(_) => Ok(()),
+            };
+        }
+
+        let checkpoint = Checkpoint {
+            completed: self
+                .completed
+                .lock()
+                .unwrap_or_else(PoisonError::into_inner)
+                .clone(),
+        };
+        let path = checkpoint.save(output_directory)?;
+
+        println!(
+            "Stopped with {} pages done, which are listed in {}. Run the same command with --resume to process the rest.",
+            checkpoint.completed.len(),
+            path.display()
+        );
+
         Ok(())
     }
 
+    fn is_resumed(&self, input_path: &str) -> bool {
+        self.resumed.contains(&page_name(input_path))
+    }
+
     // Main function for extraction mode. Depending on input mode, will extract text from a single image or multiple.
     fn extract_mode(&mut self) -> Result<()> {
         if self.config.input_mode == InputMode::Image {
-            let (data_result, cleaned_page) =
-                Self::extract_text(Arc::clone(&self.config), &self.config.input_files_path)?;
+            let (data_result, cleaned_page) = match self
+                .extract_text(&self.config.input_files_path, &mut StageTimings::default())
+            {
+                Ok(extracted) => extracted,
+                Err(e) => {
+                    self.record_error(&self.config.input_files_path, &e);
+                    return Err(e);
+                }
+            };
 
             match cleaned_page {
-                Some(clean_page_mat) if self.config.clean => {
-                    match image_conversion::mat_to_image_buffer(&clean_page_mat) {
-                        Ok(buffer) => {
-                            if let Err(e) = buffer.save(&self.config.cleaned_page_path) {
-                                error!(
-                                    "Error saving cleaned page for {}: {e}",
-                                    self.config.input_files_path
-                                );
-                            }
-                        }
-                        Err(e) => error!(
-                            "Error processing cleaned image for {}: {e}",
+                Some((cleaned_page, image_metadata)) if self.config.clean => {
+                    if let Err(e) = metadata::save_image(
+                        &cleaned_page.image,
+                        &self.config.cleaned_page_path,
+                        &image_metadata,
+                    ) {
+                        error!(
+                            "Error saving cleaned page for {}: {e}",
                             self.config.input_files_path
-                        ),
+                        );
                     }
                 }
                 _ => {}
             }
 
-            std::fs::write(
-                &self.config.output_path,
-                serde_json::to_string_pretty(&data_result)?,
+            self.write_text_file(&data_result, Path::new(&self.config.output_path))?;
+
+            self.record_extracted(
+                &self.config.input_files_path,
+                &data_result,
+                Path::new(&self.config.output_path),
+                Path::new(&self.config.cleaned_page_path),
+            );
+
+            let page = [(data_result, file_name(&self.config.input_files_path))];
+
+            if self.config.mokuro {
+                mokuro::write_volume(
+                    Path::new(&self.config.output_path).with_extension("mokuro"),
+                    &page[0].0.page,
+                    &page,
+                )?;
+            }
+
+            if self.config.xliff {
+                std::fs::write(
+                    Path::new(&self.config.output_path).with_extension("xlf"),
+                    xliff::to_xliff(
+                        &page[0].0,
+                        &page[0].1,
+                        &self.config.lang,
+                        &self.config.target_lang,
+                    ),
+                )?;
+            }
+
+            if let Some(format) = self.config.export_table {
+                table::write_table(
+                    format,
+                    Path::new(&self.config.output_path).with_extension(format.extension()),
+                    std::slice::from_ref(&page[0].0),
+                )?;
+            }
+
+            if let Some(format) = self.config.export_annotations {
+                let output_directory = match Path::new(&self.config.output_path).parent() {
+                    Some(parent) => parent.join("annotations"),
+                    None => PathBuf::from("annotations"),
+                };
+
+                annotations::write_annotations(
+                    format,
+                    &output_directory,
+                    &page,
+                    self.config.annotation_min_score,
+                )?;
+
+                if format == AnnotationFormat::Yolo {
+                    self.copy_annotated_images(
+                        std::slice::from_ref(&self.config.input_files_path),
+                        &[PathBuf::from(&self.config.output_path)],
+                        &output_directory,
+                    )?;
+                }
+            }
+
+            self.write_region_crops(
+                std::slice::from_ref(&self.config.input_files_path),
+                &[PathBuf::from(&self.config.output_path)],
             )?;
         } else {
             let DirectoryWalkerState {
@@ -104,60 +315,205 @@ impl Runtime {
                 ..
             } = self.walk_directories()?;
 
+            // Near-duplicate pages are left out of the batch and are given the text of the page they repeat afterwards
+            let duplicates = self.find_duplicate_pages(&input_image_paths);
+
+            let pages: Vec<(String, PathBuf, PathBuf)> = multizip((
+                input_image_paths.iter().cloned(),
+                output_paths.iter().cloned(),
+                cleaned_page_paths.iter().cloned(),
+            ))
+            .zip(&duplicates)
+            .filter(|((input_path, _, _), original)| {
+                original.is_none() && !self.is_resumed(input_path)
+            })
+            .map(|(page, _)| page)
+            .collect();
+
             let extraction_closure =
                 |(input_path, output_path, cleaned_page_path): (String, PathBuf, PathBuf)| {
-                    match Self::extract_text(Arc::clone(&self.config), &input_path) {
+                    if self.stopping.is_cancelled() {
+                        return;
+                    }
+
+                    let mut timings = StageTimings::default();
+
+                    match self.extract_text(&input_path, &mut timings) {
                         Ok((data_result, cleaned_page)) => {
                             // If a cleaned page was return, write it to the cleaned_page location
-                            match cleaned_page {
-                                Some(clean_page_mat) if self.config.clean => {
-                                    match image_conversion::mat_to_image_buffer(&clean_page_mat) {
-                                        Ok(buffer) => {
-                                            if let Err(e) = buffer.save(cleaned_page_path) {
-                                                error!(
-                                                "Error saving cleaned page for {input_path}: {e}"
-                                            )
-                                            }
-                                        }
-                                        Err(e) => error!(
-                                            "Error processing cleaned image for {input_path}: {e}"
-                                        ),
+                            match &cleaned_page {
+                                Some((cleaned_page, image_metadata)) if self.config.clean => {
+                                    if let Err(e) = timings.time(Stage::Encode, || {
+                                        metadata::save_image(
+                                            &cleaned_page.image,
+                                            &cleaned_page_path,
+                                            image_metadata,
+                                        )
+                                    }) {
+                                        error!("Error saving cleaned page for {input_path}: {e}")
                                     }
                                 }
                                 _ => {}
                             }
 
                             // Write the text to a json file
-                            match serde_json::to_string_pretty(&data_result) {
-                                Ok(json_data) => {
-                                    if let Err(e) = std::fs::write(&output_path, json_data) {
-                                        error!(
-                                        "Error writing extracted text to {} for {input_path}: {e}",
-                                        output_path.display()
-                                    )
-                                    }
+                            match timings.time(Stage::Encode, || {
+                                self.write_text_file(&data_result, &output_path)
+                            }) {
+                                Ok(()) => {
+                                    self.record_extracted(
+                                        &input_path,
+                                        &data_result,
+                                        &output_path,
+                                        &cleaned_page_path,
+                                    );
+                                    self.summarize_page(
+                                        &data_result,
+                                        clean_quality(&cleaned_page),
+                                        &[],
+                                        data_result.regions.is_empty(),
+                                        Some(timings),
+                                    );
+                                }
+                                Err(e) => {
+                                    error!("Error writing extracted text for {input_path}: {e}");
+                                    self.record_error(&input_path, &e);
                                 }
-                                Err(e) => error!(
-                                    "Error converting JSON value to string for {input_path}: {e}"
-                                ),
                             }
                         }
                         Err(e) => {
-                            error!("Error extracting text for {input_path}: {e}")
+                            error!("Error extracting text for {input_path}: {e}");
+                            self.record_error(&input_path, &e);
                         }
                     }
                 };
 
             if self.config.single {
-                multizip((input_image_paths, output_paths, cleaned_page_paths))
-                    .progress()
-                    .for_each(extraction_closure)
+                pages.into_iter().progress().for_each(extraction_closure)
             } else {
-                let total_length = input_image_paths.len() as u64;
+                self.extract_pipelined(pages)
+            }
+
+            for (index, original) in duplicates.into_iter().enumerate() {
+                if let Some(original) = original {
+                    if self.stopping.is_cancelled() {
+                        break;
+                    }
+
+                    let input_path = &input_image_paths[index];
+                    if self.is_resumed(input_path) {
+                        continue;
+                    }
+                    let original_path = &input_image_paths[original];
+
+                    info!("{input_path} is a duplicate of {original_path}, reusing its text");
 
-                multizip((input_image_paths, output_paths, cleaned_page_paths))
-                    .progress_count(total_length)
-                    .for_each(extraction_closure)
+                    match self.link_duplicate_page(
+                        (
+                            original_path,
+                            &output_paths[original],
+                            &cleaned_page_paths[original],
+                        ),
+                        (input_path, &output_paths[index], &cleaned_page_paths[index]),
+                    ) {
+                        Ok(extraction) => {
+                            self.record_extracted(
+                                input_path,
+                                &extraction,
+                                &output_paths[index],
+                                &cleaned_page_paths[index],
+                            );
+                            self.summarize_page(
+                                &extraction,
+                                &[],
+                                &[],
+                                self.is_text_free(original_path),
+                                None,
+                            );
+                        }
+                        Err(e) => {
+                            error!(
+                                "Error reusing the text of {original_path} for {input_path}: {e}"
+                            );
+                            self.record_error(input_path, &e);
+                        }
+                    }
+                }
+            }
+
+            let exporting = self.config.mokuro
+                || self.config.xliff
+                || self.config.export_table.is_some()
+                || self.config.export_annotations.is_some();
+
+            if exporting && !self.stopping.is_cancelled() {
+                let pages = read_batch_extractions(&input_image_paths, &output_paths);
+
+                // Volume-wide exports are named after the input directory
+                let title = page_name(&self.config.input_files_path);
+
+                if self.config.mokuro {
+                    mokuro::write_volume(
+                        Path::new(&self.config.output_path).join(format!("{title}.mokuro")),
+                        &title,
+                        &pages,
+                    )?;
+                }
+
+                if self.config.xliff {
+                    // Kept apart from the JSONs so a text directory never has two files for one page
+                    let xliff_directory = Path::new(&self.config.output_path).join("xliff");
+                    std::fs::create_dir_all(&xliff_directory)?;
+
+                    for (extraction, file_name) in pages.iter() {
+                        std::fs::write(
+                            xliff_directory.join(format!("{}.xlf", extraction.page)),
+                            xliff::to_xliff(
+                                extraction,
+                                file_name,
+                                &self.config.lang,
+                                &self.config.target_lang,
+                            ),
+                        )?;
+                    }
+                }
+
+                if let Some(format) = self.config.export_table {
+                    let extractions: Vec<Extraction> = pages
+                        .iter()
+                        .map(|(extraction, _)| extraction.clone())
+                        .collect();
+
+                    table::write_table(
+                        format,
+                        Path::new(&self.config.output_path)
+                            .join(format!("{title}.{}", format.extension())),
+                        &extractions,
+                    )?;
+                }
+
+                if let Some(format) = self.config.export_annotations {
+                    let output_directory = Path::new(&self.config.output_path).join("annotations");
+
+                    annotations::write_annotations(
+                        format,
+                        &output_directory,
+                        &pages,
+                        self.config.annotation_min_score,
+                    )?;
+
+                    if format == AnnotationFormat::Yolo {
+                        self.copy_annotated_images(
+                            &input_image_paths,
+                            &output_paths,
+                            &output_directory,
+                        )?;
+                    }
+                }
+            }
+
+            if !self.stopping.is_cancelled() {
+                self.write_region_crops(&input_image_paths, &output_paths)?;
             }
         }
 
@@ -168,17 +524,40 @@ impl Runtime {
     fn replace_mode(&mut self) -> Result<()> {
         if self.config.input_mode == InputMode::Image {
             // Validation of single image paths is done during configuration
-            let data = std::fs::read_to_string(&self.config.text_files_path)?;
+            let data = extraction::read_text_file(
+                Path::new(&self.config.text_files_path),
+                &page_name(&self.config.input_files_path),
+            )?;
 
-            let data = serde_json::from_str::<Json>(&data)?;
+            let replaced = self
+                .replace_text(
+                    &data,
+                    &self.config.input_files_path,
+                    &mut StageTimings::default(),
+                )
+                .and_then(|(final_image, image_metadata)| {
+                    metadata::save_image(
+                        &final_image.image,
+                        &self.config.output_path,
+                        &image_metadata,
+                    )
+                });
 
-            let final_image = Self::replace_text(
-                Arc::clone(&self.config),
-                &data,
+            match replaced {
+                Ok(()) => self.record_typeset(
+                    &self.config.input_files_path,
+                    Path::new(&self.config.output_path),
+                ),
+                Err(e) => {
+                    self.record_error(&self.config.input_files_path, &e);
+                    return Err(e);
+                }
+            }
+
+            self.append_credits(
                 &self.config.input_files_path,
+                Path::new(&self.config.output_path),
             )?;
-
-            image_conversion::mat_to_image_buffer(&final_image)?.save(&self.config.output_path)?;
         } else {
             let DirectoryWalkerState {
                 input_image_paths,
@@ -186,114 +565,905 @@ impl Runtime {
                 cleaned_page_paths: _,
                 file_stems,
             } = self.walk_directories()?;
-            let text_data = walk_text_directory(&self.config.text_files_path, file_stems)?;
-
-            let replacement_closure = |((input_path, data), output_path): (
-                (String, IndexMap<String, String>),
-                PathBuf,
-            )| {
-                let image_data =
-                    Self::replace_text(Arc::clone(&self.config), &Json::from(data), &input_path);
-
-                match (image_data, output_path.to_str()) {
-                    // Write to output path
-                    (Ok(data), Some(path)) => {
-                        match image_conversion::mat_to_image_buffer(&data) {
-                            Ok(buffer) => {
-                                if let Err(e) = buffer.save(path) {
-                                    error!("Error processing {input_path}: {e}")
+            let text_path = Path::new(&self.config.text_files_path);
+
+            // The credits page goes after the page whose name sorts last
+            let last_page = input_image_paths
+                .iter()
+                .zip(output_paths.iter())
+                .max_by_key(|(input_path, _)| page_name(input_path))
+                .map(|(input_path, output_path)| (input_path.clone(), output_path.clone()));
+
+            // A single table can hold the translations for the whole directory. Pages without any
+            // text are text-free under "--skip-empty-text-files".
+            let text_free_allowed = self.config.skip_empty_text_files;
+            let text_data = match TableFormat::from_path(text_path) {
+                Some(format) if text_path.is_file() => {
+                    read_text_table(format, text_path, file_stems, text_free_allowed)?
+                }
+                _ => walk_text_directory(
+                    &self.config.text_files_path,
+                    file_stems,
+                    text_free_allowed,
+                )?,
+            };
+
+            let replacement_closure =
+                |(input_path, extraction, output_path): (String, Extraction, PathBuf)| {
+                    if self.stopping.is_cancelled() {
+                        return;
+                    }
+
+                    let mut timings = StageTimings::default();
+                    let image_data = self.replace_text(&extraction, &input_path, &mut timings);
+
+                    match (image_data, output_path.to_str()) {
+                        // Write to output path
+                        (Ok((data, image_metadata)), Some(path)) => {
+                            match timings.time(Stage::Encode, || {
+                                metadata::save_image(&data.image, path, &image_metadata)
+                            }) {
+                                Ok(()) => {
+                                    self.record_typeset(&input_path, &output_path);
+                                    self.summarize_page(
+                                        &extraction,
+                                        &data.clean_quality,
+                                        &data.overflows,
+                                        data.text_free,
+                                        Some(timings),
+                                    );
+                                }
+                                Err(e) => {
+                                    error!("Error processing {input_path}: {e}");
+                                    self.record_error(&input_path, &e);
                                 }
                             }
-                            Err(e) => error!("Error processing {input_path}: {e}"),
-                        };
-                    }
+                        }
 
-                    // Catches errors in translating the image (OpenCV and libtesseract errors)
-                    (Err(e), _) => error!("OpenCV/Tesseract error with {input_path}: {e}"),
+                        // Catches errors in translating the image (OpenCV and libtesseract errors)
+                        (Err(e), _) => {
+                            error!("OpenCV/Tesseract error with {input_path}: {e}");
+                            self.record_error(&input_path, &e);
+                        }
 
-                    // Catches errors with path not being in UTF-8
-                    (_, None) => {
-                        let file_name = output_path.display();
-                        error!("{file_name} must be UTF-8 compatible.")
+                        // Catches errors with path not being in UTF-8
+                        (_, None) => {
+                            let file_name = output_path.display();
+                            error!("{file_name} must be UTF-8 compatible.");
+                            self.record_error(
+                                &input_path,
+                                &anyhow::anyhow!("{file_name} must be UTF-8 compatible."),
+                            );
+                        }
                     }
-                }
-            };
+                };
+
+            let pages: Vec<(String, Extraction, PathBuf)> =
+                multizip((input_image_paths, text_data, output_paths))
+                    .filter(|(input_path, _, _)| !self.is_resumed(input_path))
+                    .collect();
 
             if self.config.single {
-                input_image_paths
-                    .into_iter()
-                    .zip(text_data)
-                    .zip(output_paths)
-                    .progress()
-                    .for_each(replacement_closure)
+                pages.into_iter().progress().for_each(replacement_closure)
             } else {
-                let total_length = input_image_paths.len() as u64;
+                self.replace_pipelined(pages)
+            }
 
-                input_image_paths
-                    .into_par_iter()
-                    .zip(text_data.into_par_iter())
-                    .zip(output_paths.into_par_iter())
-                    .progress_count(total_length)
-                    .for_each(replacement_closure)
+            if let Some((input_path, output_path)) = last_page {
+                if !self.stopping.is_cancelled() {
+                    self.append_credits(&input_path, &output_path)?;
+                }
             }
         }
 
         Ok(())
     }
 
-    // Text extraction helper function to extract and return text from a single image
-    fn extract_text(config: Arc<Config>, input: &str) -> Result<(Value, Option<core::Mat>)> {
-        let mut detector = Detector::new(&config.model_path, config.padding)?;
-        let mut ocr = Ocr::new(&config.lang, &config.tesseract_data_path)?;
+    // Writes the credits page after the output of the last page under "--credits-placement append".
+    // The page gets the size of the last page, so it doesn't stand out in a reader.
+    fn append_credits(&self, last_input: &str, last_output: &Path) -> Result<()> {
+        let credits = match &self.config.credits {
+            Some(credits) if credits.placement == CreditsPlacement::Append => credits,
+            _ => return Ok(()),
+        };
 
-        let (text_regions, origins) = detector.run_inference(input)?;
+        let (width, height) = match image::image_dimensions(last_output) {
+            Ok(dimensions) => dimensions,
+            // The last page failed, but its input still has the size
+            Err(_) => decode_page(last_input, self.config.max_pixels)?
+                .image
+                .dimensions(),
+        };
 
-        let extracted_text = ocr.extract_text(&text_regions)?;
+        let output_directory = match last_output.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        let credits_path = credits::credits_page_path(output_directory, &page_name(last_input));
 
-        let text_pairs: IndexMap<&str, &str> =
-            extracted_text
-                .iter()
-                .fold(IndexMap::new(), |mut acc, text| {
-                    acc.insert(text.as_str(), "");
-                    acc
+        credits
+            .render_page(width, height, &self.config.font)
+            .save(&credits_path)
+            .with_context(|| {
+                format!(
+                    "Could not write the credits page to {}.",
+                    credits_path.display()
+                )
+            })?;
+
+        info!("Wrote the credits page to {}", credits_path.display());
+
+        Ok(())
+    }
+
+    // Copies the images of the extracted pages next to their YOLO labels, as labeling and training tools
+    // expect them. Under "--deskew-page" the labels refer to the straightened page, so that is written instead.
+    fn copy_annotated_images(
+        &self,
+        input_image_paths: &[String],
+        output_paths: &[PathBuf],
+        directory: &Path,
+    ) -> Result<()> {
+        for (input_path, output_path) in input_image_paths.iter().zip(output_paths) {
+            if !output_path.is_file() {
+                continue;
+            }
+
+            let image_path = directory.join(file_name(input_path));
+
+            if self.config.deskew_page {
+                let page = decode_page(input_path, self.config.max_pixels)?.image;
+                let page = handlers::straighten_page(&self.config, &page).unwrap_or(page);
+
+                page.save(&image_path)?;
+            } else {
+                std::fs::copy(input_path, &image_path)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // Crops the regions of the extracted pages that OCR or detection were unsure about into the
+    // review bundle under "--review", and every region into the dataset under "--dump-regions". Pages
+    // are read again for the crops, and pages that failed to extract are left out.
+    fn write_region_crops(
+        &self,
+        input_image_paths: &[String],
+        output_paths: &[PathBuf],
+    ) -> Result<()> {
+        let review_directory = self.config.review_path.as_deref();
+        let dataset_directory = self.config.dump_regions_path.as_deref();
+        let threshold = self.config.review_threshold;
+
+        if review_directory.is_none() && dataset_directory.is_none() {
+            return Ok(());
+        }
+
+        for directory in review_directory.iter().chain(dataset_directory.iter()) {
+            std::fs::create_dir_all(directory).with_context(|| {
+                format!("Could not create the directory {}.", directory.display())
+            })?;
+        }
+
+        let mut review_items = Vec::new();
+        let mut dataset_items = Vec::new();
+
+        for (input_path, output_path) in input_image_paths.iter().zip(output_paths) {
+            let extraction = match std::fs::read_to_string(output_path) {
+                Ok(data) => Extraction::from_json(&data, &page_name(input_path))?,
+                Err(_) => continue,
+            };
+
+            let needs_review = review_directory.is_some()
+                && extraction
+                    .regions
+                    .iter()
+                    .any(|region| review::needs_review(region, threshold));
+
+            if !needs_review && dataset_directory.is_none() {
+                continue;
+            }
+
+            // The boxes were found on the straightened page under "--deskew-page"
+            let page = decode_page(input_path, self.config.max_pixels)?.image;
+            let page = handlers::straighten_page(&self.config, &page).unwrap_or(page);
+
+            if let Some(directory) = review_directory.filter(|_| needs_review) {
+                review_items.extend(review::add_page(
+                    directory,
+                    &extraction,
+                    &file_name(input_path),
+                    &page,
+                    threshold,
+                )?);
+            }
+
+            if let Some(directory) = dataset_directory {
+                dataset_items.extend(dataset::add_page(
+                    directory,
+                    &extraction,
+                    &file_name(input_path),
+                    &page,
+                )?);
+            }
+        }
+
+        if let Some(directory) = review_directory {
+            review::write_index(directory, threshold, &review_items)?;
+
+            info!(
+                "{} regions need review, see {}",
+                review_items.len(),
+                directory.join("review.json").display()
+            );
+        }
+
+        if let Some(directory) = dataset_directory {
+            dataset::write_index(directory, &dataset_items)?;
+
+            info!(
+                "Cropped {} regions into {}",
+                dataset_items.len(),
+                directory.join("dataset.json").display()
+            );
+        }
+
+        Ok(())
+    }
+
+    // Text extraction helper function to extract and return text from a single image, adding the
+    // time each stage took to "timings"
+    fn extract_text(
+        &self,
+        input: &str,
+        timings: &mut StageTimings,
+    ) -> Result<(Extraction, Option<(RenderedPage, ImageMetadata)>)> {
+        let page = timings.time(Stage::Decode, || decode_page(input, self.config.max_pixels))?;
+        let size = page_size(&page.image);
+
+        let detected_page = timings.time(Stage::Detect, || self.detect_page(&page))?;
+        let extracted_text = timings.time(Stage::Ocr, || {
+            self.recognize_page(&detected_page, page.hash)
+        })?;
+
+        // Detection only runs once when we also need the cleaned page
+        let cleaned_page = match self.config.clean {
+            true => Some((
+                timings.time(Stage::Layout, || {
+                    handlers::clean_detected_page(
+                        &self.config,
+                        detected_page,
+                        Some(&extracted_text),
+                        &self.cancellation_token,
+                    )
+                })?,
+                page.metadata,
+            )),
+            false => None,
+        };
+
+        Ok((
+            page_extraction(&self.config, input, extracted_text, size),
+            cleaned_page,
+        ))
+    }
+
+    fn detect_page(&self, page: &DecodedPage) -> Result<DetectedPage> {
+        self.detect_pages(&[page]).remove(0)
+    }
+
+    // Runs detection on a batch of pages. With a cache, the boxes found in an earlier run on the same
+    // page with the same parameters are used instead, and the other pages run through the model
+    // together. Each page fails on its own, unless the model fails, which fails all of them.
+    fn detect_pages(&self, pages: &[&DecodedPage]) -> Vec<Result<DetectedPage>> {
+        let cached_boxes: Vec<Option<Vec<TextBox>>> = pages
+            .iter()
+            .map(|page| {
+                self.cache
+                    .as_ref()?
+                    .get::<Vec<TextBox>>(CachedStage::Detection, page.hash)
+            })
+            .collect();
+
+        let uncached_images: Vec<&DynamicImage> = pages
+            .iter()
+            .zip(&cached_boxes)
+            .filter(|(_, boxes)| boxes.is_none())
+            .map(|(page, _)| &page.image)
+            .collect();
+        let mut detected_pages = match uncached_images.is_empty() {
+            true => Vec::new(),
+            false => {
+                handlers::detect_pages(&self.config, &uncached_images, &self.cancellation_token)
+            }
+        }
+        .into_iter();
+
+        pages
+            .iter()
+            .zip(cached_boxes)
+            .map(|(page, boxes)| match boxes {
+                Some(boxes) => {
+                    handlers::crop_page(&self.config, &page.image, &boxes, &self.cancellation_token)
+                }
+                None => {
+                    let detected_page = detected_pages
+                        .next()
+                        .context("The model returned fewer pages than it was given.")??;
+
+                    // The page itself is fine, so failing to cache it doesn't fail it
+                    if let Some(cache) = &self.cache {
+                        if let Err(e) =
+                            cache.put(CachedStage::Detection, page.hash, &detected_page.boxes())
+                        {
+                            error!("Error caching the detected regions: {e}");
+                        }
+                    }
+
+                    Ok(detected_page)
+                }
+            })
+            .collect()
+    }
+
+    // Detects a batch of pages of a pipeline, passing on the pages that failed to decode. The pages
+    // the model runs over together share the time it took.
+    fn detect_batch<P>(
+        &self,
+        batch: Vec<(P, StageTimings, Result<DecodedPage>)>,
+    ) -> Vec<(P, StageTimings, Result<(DetectedPage, DecodedPage)>)> {
+        let decoded_pages: Vec<&DecodedPage> = batch
+            .iter()
+            .filter_map(|(_, _, decoded)| decoded.as_ref().ok())
+            .collect();
+
+        let start = Instant::now();
+        let mut detected_pages = self.detect_pages(&decoded_pages).into_iter();
+        let elapsed = start.elapsed() / decoded_pages.len().max(1) as u32;
+
+        batch
+            .into_iter()
+            .map(|(page, mut timings, decoded)| {
+                let detected = decoded.and_then(|decoded_page| {
+                    timings.add(Stage::Detect, elapsed);
+
+                    let detected_page = detected_pages
+                        .next()
+                        .context("The model returned fewer pages than it was given.")??;
+
+                    Ok((detected_page, decoded_page))
                 });
 
-        let data = json!(text_pairs);
+                (page, timings, detected)
+            })
+            .collect()
+    }
 
-        if config.clean {
-            let original_image =
-                image_conversion::image_buffer_to_mat(image::open(input)?.to_rgb8())?;
-            let replacer: Replacer<'_, String> =
-                Replacer::new(text_regions, None, origins, original_image, config.padding)?;
+    // Runs OCR on the regions of a page, or takes the text from the cache like "detect_page"
+    fn recognize_page(
+        &self,
+        detected_page: &DetectedPage,
+        page_hash: u64,
+    ) -> Result<ExtractedText> {
+        let cache = match &self.cache {
+            Some(cache) => cache,
+            None => {
+                return handlers::recognize_page(
+                    &self.config,
+                    detected_page,
+                    &self.cancellation_token,
+                )
+            }
+        };
 
-            let cleaned_page = replacer.clean_page()?;
+        match cache.get::<CachedText>(CachedStage::Ocr, page_hash) {
+            Some(cached) if cached.text.len() == detected_page.num_regions() => {
+                return Ok(ExtractedText {
+                    text: cached.text,
+                    boxes: detected_page.boxes(),
+                    confidences: cached.confidences,
+                })
+            }
+            _ => {}
+        }
 
-            Ok((data, Some(cleaned_page)))
-        } else {
-            Ok((data, None))
+        let extracted_text =
+            handlers::recognize_page(&self.config, detected_page, &self.cancellation_token)?;
+
+        let cached = CachedText {
+            text: extracted_text.text.clone(),
+            confidences: extracted_text.confidences.clone(),
+        };
+
+        if let Err(e) = cache.put(CachedStage::Ocr, page_hash, &cached) {
+            error!("Error caching the recognized text: {e}");
+        }
+
+        Ok(extracted_text)
+    }
+
+    // Extracts the pages of a directory in a pipeline of stages that each run on threads of their own, so
+    // reading, detection, OCR, cleaning and writing of different pages overlap. The stages share the
+    // threads of "--threads" and only a few pages wait between two stages at a time, so at most
+    // "Workers::max_pages" pages are held however large the directory is.
+    fn extract_pipelined(&self, pages: Vec<(String, PathBuf, PathBuf)>) {
+        let config = self.config.as_ref();
+        let cancellation_token = &self.cancellation_token;
+        let stopping = &self.stopping;
+        let workers = self.pipeline_workers();
+        let progress = ProgressBar::new(pages.len() as u64);
+
+        thread::scope(|scope| {
+            let pages = pipeline::source(
+                scope,
+                pages.into_iter().take_while(|_| !stopping.is_cancelled()),
+            );
+
+            let decoded = pipeline::stage(
+                scope,
+                pages,
+                workers.decode,
+                |page: (String, PathBuf, PathBuf)| {
+                    let mut timings = StageTimings::default();
+                    let decoded =
+                        timings.time(Stage::Decode, || decode_page(&page.0, config.max_pixels));
+
+                    (page, timings, decoded)
+                },
+            );
+
+            let detected = pipeline::batched_stage(
+                scope,
+                decoded,
+                workers.detect,
+                config.batch_size,
+                |batch| {
+                    self.detect_batch(batch)
+                        .into_iter()
+                        .map(|(page, timings, detected)| {
+                            let detected = detected.map(|(detected_page, decoded_page)| {
+                                (
+                                    detected_page,
+                                    page_size(&decoded_page.image),
+                                    decoded_page.metadata,
+                                    decoded_page.hash,
+                                )
+                            });
+
+                            (page, timings, detected)
+                        })
+                        .collect()
+                },
+            );
+
+            let cleaned = pipeline::stage(
+                scope,
+                detected,
+                workers.process,
+                |(page, mut timings, detected)| {
+                    let cleaned =
+                        detected.and_then(|(detected_page, size, image_metadata, hash)| {
+                            let extracted_text = timings
+                                .time(Stage::Ocr, || self.recognize_page(&detected_page, hash))?;
+
+                            let cleaned_page = match config.clean {
+                                true => Some((
+                                    timings.time(Stage::Layout, || {
+                                        handlers::clean_detected_page(
+                                            config,
+                                            detected_page,
+                                            Some(&extracted_text),
+                                            cancellation_token,
+                                        )
+                                    })?,
+                                    image_metadata,
+                                )),
+                                false => None,
+                            };
+
+                            Ok((
+                                page_extraction(config, &page.0, extracted_text, size),
+                                cleaned_page,
+                            ))
+                        });
+
+                    (page, timings, cleaned)
+                },
+            );
+
+            let written = pipeline::stage(
+                scope,
+                cleaned,
+                workers.write,
+                |(page, mut timings, cleaned)| {
+                    let (input_path, output_path, cleaned_page_path) = &page;
+
+                    let written = cleaned.and_then(|(extraction, cleaned_page)| {
+                        timings.time(Stage::Encode, || {
+                            if let Some((cleaned_page, image_metadata)) = &cleaned_page {
+                                if let Err(e) = metadata::save_image(
+                                    &cleaned_page.image,
+                                    cleaned_page_path,
+                                    image_metadata,
+                                ) {
+                                    error!("Error saving cleaned page for {input_path}: {e}")
+                                }
+                            }
+
+                            self.write_text_file(&extraction, output_path)
+                        })?;
+
+                        Ok((extraction, clean_quality(&cleaned_page).to_vec()))
+                    });
+
+                    (page, timings, written)
+                },
+            );
+
+            // The project and summary are updated from this thread as the pages come out of the pipeline
+            for ((input_path, output_path, cleaned_page_path), timings, written) in written {
+                progress.inc(1);
+
+                match written {
+                    Ok((extraction, clean_quality)) => {
+                        self.record_extracted(
+                            &input_path,
+                            &extraction,
+                            &output_path,
+                            &cleaned_page_path,
+                        );
+                        self.summarize_page(
+                            &extraction,
+                            &clean_quality,
+                            &[],
+                            extraction.regions.is_empty(),
+                            Some(timings),
+                        );
+                    }
+                    Err(e) => {
+                        error!("Error extracting text for {input_path}: {e}");
+                        self.record_error(&input_path, &e);
+                    }
+                }
+            }
+        });
+
+        progress.finish();
+    }
+
+    // Fingerprints every page of the batch and returns, for each page, the index of the earlier page it
+    // repeats. Pages that can't be read are left to fail during extraction as usual.
+    fn find_duplicate_pages(&self, input_image_paths: &[String]) -> Vec<Option<usize>> {
+        let mut duplicates = vec![None; input_image_paths.len()];
+
+        if !self.config.skip_duplicates {
+            return duplicates;
         }
+
+        let (indices, fingerprints): (Vec<usize>, Vec<PageFingerprint>) = input_image_paths
+            .par_iter()
+            .enumerate()
+            .filter_map(|(index, input_path)| {
+                let image_bytes = std::fs::read(input_path).ok()?;
+                let image =
+                    image_conversion::load_image_within(&image_bytes, self.config.max_pixels)
+                        .ok()?;
+
+                Some((index, PageFingerprint::new(&image)))
+            })
+            .unzip();
+
+        let originals = hashing::find_duplicates(&fingerprints, hashing::DUPLICATE_DISTANCE);
+
+        for (index, original) in indices.iter().zip(originals) {
+            duplicates[*index] = original.map(|original| indices[original]);
+        }
+
+        duplicates
     }
 
-    // Replacement helper function to replace text in single image and return a OpenCV Mat
-    fn replace_text(config: Arc<Config>, data: &Json, input: &str) -> Result<core::Mat> {
-        let mut detector = Detector::new(&config.model_path, config.padding)?;
+    // Writes the text (and cleaned page) of an already extracted page as the outputs of its duplicate.
+    // Regions get IDs for the duplicate's own page name so its translations are matched independently.
+    fn link_duplicate_page(
+        &self,
+        (original_path, original_output, original_cleaned_page): (&str, &PathBuf, &PathBuf),
+        (input_path, output_path, cleaned_page_path): (&str, &PathBuf, &PathBuf),
+    ) -> Result<Extraction> {
+        // A text-free original may have been left without a text file
+        let original = match self.is_text_free(original_path) && !original_output.is_file() {
+            true => Extraction::new(&page_name(original_path), Vec::new()),
+            false => {
+                let data = std::fs::read_to_string(original_output)?;
+                Extraction::from_json(&data, &page_name(original_path))?
+            }
+        };
+
+        let text = original
+            .regions
+            .iter()
+            .map(|region| region.text.clone())
+            .collect();
 
-        let (text_regions, origins) = detector.run_inference(input)?;
+        let page = page_name(input_path);
+        let mut extraction = Extraction::new(&page, text);
+        extraction.size = original.size;
 
-        let original_image = image::open(input)?;
-        let original_image = image_conversion::image_buffer_to_mat(original_image.to_rgb8())?;
+        for (region, original_region) in extraction.regions.iter_mut().zip(&original.regions) {
+            region.text_box = original_region.text_box;
+            region.confidence = original_region.confidence;
+            // Linked regions are named by their index on the duplicate as well
+            region.linked = original_region
+                .linked
+                .iter()
+                .filter_map(|id| original.regions.iter().position(|region| region.id == *id))
+                .map(|index| extraction::region_id(&page, index))
+                .collect();
+        }
 
-        let replacer = Replacer::new(
-            text_regions,
-            Some(&data.text),
-            origins,
-            original_image,
-            config.padding,
-        )?;
+        self.write_text_file(&extraction, output_path)?;
 
-        let final_image = replacer.replace_text_regions()?;
+        if self.config.clean {
+            std::fs::copy(original_cleaned_page, cleaned_page_path)?;
+        }
 
-        Ok(final_image)
+        Ok(extraction)
+    }
+
+    // Replacement helper function to replace text in single image and return the rendered page with the metadata of the input
+    fn replace_text(
+        &self,
+        data: &Extraction,
+        input: &str,
+        timings: &mut StageTimings,
+    ) -> Result<(RenderedPage, ImageMetadata)> {
+        let page = timings.time(Stage::Decode, || decode_page(input, self.config.max_pixels))?;
+        let detected_page = timings.time(Stage::Detect, || self.detect_page(&page))?;
+
+        let final_image = timings.time(Stage::Layout, || {
+            handlers::typeset_detected_page(
+                &self.config,
+                detected_page,
+                data,
+                &self.cancellation_token,
+            )
+        })?;
+
+        Ok((final_image, page.metadata))
+    }
+
+    // Replaces the text of the pages of a directory in a pipeline, the same as "extract_pipelined"
+    fn replace_pipelined(&self, pages: Vec<(String, Extraction, PathBuf)>) {
+        let config = self.config.as_ref();
+        let cancellation_token = &self.cancellation_token;
+        let stopping = &self.stopping;
+        let workers = self.pipeline_workers();
+        let progress = ProgressBar::new(pages.len() as u64);
+
+        thread::scope(|scope| {
+            let pages = pipeline::source(
+                scope,
+                pages.into_iter().take_while(|_| !stopping.is_cancelled()),
+            );
+
+            let decoded = pipeline::stage(
+                scope,
+                pages,
+                workers.decode,
+                |page: (String, Extraction, PathBuf)| {
+                    let mut timings = StageTimings::default();
+                    let decoded =
+                        timings.time(Stage::Decode, || decode_page(&page.0, config.max_pixels));
+
+                    (page, timings, decoded)
+                },
+            );
+
+            let detected = pipeline::batched_stage(
+                scope,
+                decoded,
+                workers.detect,
+                config.batch_size,
+                |batch| {
+                    self.detect_batch(batch)
+                        .into_iter()
+                        .map(|(page, timings, detected)| {
+                            let detected = detected.map(|(detected_page, decoded_page)| {
+                                (detected_page, decoded_page.metadata)
+                            });
+
+                            (page, timings, detected)
+                        })
+                        .collect()
+                },
+            );
+
+            let rendered = pipeline::stage(
+                scope,
+                detected,
+                workers.process,
+                |(page, mut timings, detected)| {
+                    let rendered = detected.and_then(|(detected_page, image_metadata)| {
+                        let final_image = timings.time(Stage::Layout, || {
+                            handlers::typeset_detected_page(
+                                config,
+                                detected_page,
+                                &page.1,
+                                cancellation_token,
+                            )
+                        })?;
+
+                        Ok((final_image, image_metadata))
+                    });
+
+                    (page, timings, rendered)
+                },
+            );
+
+            let written = pipeline::stage(
+                scope,
+                rendered,
+                workers.write,
+                |(page, mut timings, rendered)| {
+                    let written = rendered.and_then(|(final_image, image_metadata)| {
+                        timings.time(Stage::Encode, || {
+                            metadata::save_image(&final_image.image, &page.2, &image_metadata)
+                        })?;
+
+                        Ok((
+                            final_image.clean_quality,
+                            final_image.overflows,
+                            final_image.text_free,
+                        ))
+                    });
+
+                    (page, timings, written)
+                },
+            );
+
+            for ((input_path, extraction, output_path), timings, written) in written {
+                progress.inc(1);
+
+                match written {
+                    Ok((clean_quality, overflows, text_free)) => {
+                        self.record_typeset(&input_path, &output_path);
+                        self.summarize_page(
+                            &extraction,
+                            &clean_quality,
+                            &overflows,
+                            text_free,
+                            Some(timings),
+                        );
+                    }
+                    Err(e) => {
+                        error!("Error processing {input_path}: {e}");
+                        self.record_error(&input_path, &e);
+                    }
+                }
+            }
+        });
+
+        progress.finish();
+    }
+
+    // Threads of the stages of a pipeline, split from "--threads". OpenCV is kept to one thread per
+    // page (see "--opencv-threads"), so with as many threads as cores the machine is kept busy.
+    fn pipeline_workers(&self) -> Workers {
+        Workers::split(
+            self.config
+                .threads
+                .unwrap_or_else(rayon::current_num_threads),
+            self.config.batch_size,
+        )
+    }
+
+    // Applies an update to the project record of a page, if the input has a project file
+    fn record_page<F>(&self, input_path: &str, update: F)
+    where
+        F: FnOnce(&mut PageRecord),
+    {
+        if let Some(project) = &self.project {
+            let mut project = project.lock().unwrap_or_else(PoisonError::into_inner);
+
+            update(project.page_mut(&page_name(input_path), &file_name(input_path)));
+        }
+    }
+
+    // Counts a page as done for the checkpoint
+    fn record_completed(&self, input_path: &str) {
+        self.completed
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .insert(page_name(input_path));
+    }
+
+    fn record_extracted(
+        &self,
+        input_path: &str,
+        extraction: &Extraction,
+        output_path: &Path,
+        cleaned_page_path: &Path,
+    ) {
+        self.record_completed(input_path);
+
+        let parameters = Parameters {
+            model: self.config.model_path.clone(),
+            lang: self.config.lang.clone(),
+            padding: self.config.padding,
+        };
+        let clean = self.config.clean;
+        let text_file = self.writes_text_file(extraction);
+
+        self.record_page(input_path, |record| {
+            record.set_status(PageStatus::Extracted);
+            record.parameters = Some(parameters);
+            record.artifacts.text = text_file.then(|| output_path.display().to_string());
+
+            if clean {
+                record.artifacts.cleaned_page = Some(cleaned_page_path.display().to_string());
+            }
+        });
+    }
+
+    fn record_typeset(&self, input_path: &str, output_path: &Path) {
+        self.record_completed(input_path);
+
+        self.record_page(input_path, |record| {
+            record.set_status(PageStatus::Typeset);
+            record.artifacts.output = Some(output_path.display().to_string());
+        });
+    }
+
+    fn record_error(&self, input_path: &str, error: &anyhow::Error) {
+        self.record_page(input_path, |record| record.set_error(&error.to_string()));
+
+        self.summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .add_failure(&page_name(input_path), error);
+    }
+
+    // Timings are only kept under "--timings", as they differ from run to run
+    fn summarize_page(
+        &self,
+        extraction: &Extraction,
+        clean_quality: &[Option<u8>],
+        overflows: &[TextOverflow],
+        text_free: bool,
+        timings: Option<StageTimings>,
+    ) {
+        let timings = timings.filter(|_| self.config.timings);
+        let mut summary = self.summary.lock().unwrap_or_else(PoisonError::into_inner);
+
+        match text_free {
+            true => summary.add_text_free_page(&extraction.page, timings),
+            false => summary.add_page(extraction, clean_quality, overflows, timings),
+        }
+    }
+
+    fn is_text_free(&self, input_path: &str) -> bool {
+        self.summary
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .is_text_free(&page_name(input_path))
+    }
+
+    // Text-free pages get no text file under "--skip-empty-text-files"
+    fn writes_text_file(&self, extraction: &Extraction) -> bool {
+        !(self.config.skip_empty_text_files && extraction.regions.is_empty())
+    }
+
+    fn write_text_file(&self, extraction: &Extraction, output_path: &Path) -> Result<()> {
+        if !self.writes_text_file(extraction) {
+            return Ok(());
+        }
+
+        std::fs::write(output_path, serde_json::to_string_pretty(extraction)?).with_context(|| {
+            format!(
+                "Could not write the extracted text to {}",
+                output_path.display()
+            )
+        })
     }
 
     fn walk_directories(&self) -> Result<DirectoryWalkerState> {
@@ -322,13 +1492,14 @@ fn walk_image_directory(
     cleaned_page_path: &String,
     clean_pages: bool,
 ) -> Result<(InputPaths, OutputPaths, CleanPagePaths, FileStems)> {
-    // Build a directory walker for the input path
-    let image_walker = GlobWalkerBuilder::from_patterns(
-        input_files_path,
-        &["*{jpg,JPG,jpeg,JPEG,png,PNG,webp,WEBP,tiff,TIFF}"],
-    )
-    .follow_links(false)
-    .build()?;
+    // Build a directory walker for the input path. Pages are taken in name order rather than the
+    // order the file system lists them in, so that which of two duplicates is the original and the
+    // page order of volume-wide exports are the same on every run.
+    let image_walker =
+        GlobWalkerBuilder::from_patterns(input_files_path, &[validation::image_glob_pattern()])
+            .follow_links(false)
+            .sort_by(|a, b| a.file_name().cmp(b.file_name()))
+            .build()?;
 
     /*
         Walks the input directory and creates three vecs:
@@ -400,14 +1571,47 @@ fn walk_image_directory(
         .multiunzip::<(InputPaths, OutputPaths, CleanPagePaths, FileStems)>())
 }
 
-// Get text data from text directory for replacement
+// Get text data from a table for replacement, in the same order as the input stems
+fn read_text_table(
+    format: TableFormat,
+    text_path: &Path,
+    input_stems: Vec<String>,
+    text_free_allowed: bool,
+) -> Result<Vec<Extraction>> {
+    let pages = table::read_translations(format, text_path)?;
+
+    if !text_free_allowed {
+        let page_names: Vec<&str> = pages.iter().map(|page| page.page.as_str()).collect();
+        validation::validate_table_pages(input_stems.clone(), &page_names)?;
+    }
+
+    let mut pages: HashMap<String, Extraction> = pages
+        .into_iter()
+        .map(|page| (page.page.clone(), page))
+        .collect();
+
+    // Validation guarantees that every input stem has rows in the table unless text-free pages are
+    // allowed, which have none
+    Ok(input_stems
+        .iter()
+        .map(|input_stem| {
+            pages
+                .remove(input_stem)
+                .unwrap_or_else(|| Extraction::new(input_stem, Vec::new()))
+        })
+        .collect())
+}
+
+// Get text data from text directory for replacement, in the same order as the input stems
 fn walk_text_directory(
     text_files_path: &String,
     input_stems: Vec<String>,
-) -> Result<Vec<IndexMap<String, String>>> {
-    let text_walker = GlobWalkerBuilder::from_patterns(text_files_path, &["*{json,JSON}"])
-        .follow_links(false)
-        .build()?;
+    text_free_allowed: bool,
+) -> Result<Vec<Extraction>> {
+    let text_walker =
+        GlobWalkerBuilder::from_patterns(text_files_path, &["*{json,JSON,xlf,XLF,xliff,XLIFF}"])
+            .follow_links(false)
+            .build()?;
 
     let text_paths = text_walker
         .into_iter()
@@ -429,29 +1633,106 @@ fn walk_text_directory(
         })
         .collect::<Vec<PathBuf>>();
 
-    validation::validate_replace_mode(input_stems, &text_paths)?;
+    if !text_free_allowed {
+        validation::validate_replace_mode(input_stems.clone(), &text_paths)?;
+    }
 
-    let mut text_data: Vec<IndexMap<String, String>> = Vec::new();
+    let text_paths: HashMap<String, PathBuf> = text_paths
+        .into_iter()
+        .filter_map(|path| {
+            let stem = path.file_stem()?.to_str()?.to_string();
+            Some((stem, path))
+        })
+        .collect();
 
-    for text_path in text_paths.iter() {
-        match text_path.to_str() {
-            Some(path_string) => {
-                let data = serde_json::from_str::<IndexMap<String, String>>(
-                    &std::fs::read_to_string(path_string)?,
-                )?;
+    let mut text_data: Vec<Extraction> = Vec::new();
 
-                text_data.push(data);
-            }
-            None => {
-                let bad_path = text_path.display();
-                error!("{bad_path} needs to have a UTF-8 compatible name");
-            }
-        }
+    // Validation guarantees that every input stem has a text file unless text-free pages are
+    // allowed, which have none
+    for input_stem in input_stems.iter() {
+        text_data.push(match text_paths.get(input_stem) {
+            Some(text_path) => extraction::read_text_file(text_path, input_stem)?,
+            None => Extraction::new(input_stem, Vec::new()),
+        });
     }
 
     Ok(text_data)
 }
 
+// A page read from disk, with the metadata to write back into its outputs
+struct DecodedPage {
+    image: DynamicImage,
+    metadata: ImageMetadata,
+    // Hash of the file's bytes, which identifies the page in the cache
+    hash: u64,
+}
+
+// Reads and decodes a page. Pages above the pixel limit are refused before they are decoded.
+fn decode_page(input: &str, max_pixels: u64) -> Result<DecodedPage> {
+    let image_bytes = std::fs::read(input)?;
+    let image = image_conversion::load_image_within(&image_bytes, max_pixels)?;
+
+    Ok(DecodedPage {
+        image,
+        metadata: ImageMetadata::read(&image_bytes),
+        hash: hashing::content_hash(&[&image_bytes]),
+    })
+}
+
+fn page_size(image: &DynamicImage) -> PageSize {
+    PageSize {
+        width: image.width(),
+        height: image.height(),
+    }
+}
+
+fn page_extraction(
+    config: &Config,
+    input: &str,
+    extracted_text: ExtractedText,
+    size: PageSize,
+) -> Extraction {
+    handlers::page_extraction(config, &page_name(input), extracted_text, size)
+}
+
+// Cleaning quality of the regions of a page that was cleaned along with its extraction
+fn clean_quality(cleaned_page: &Option<(RenderedPage, ImageMetadata)>) -> &[Option<u8>] {
+    match cleaned_page {
+        Some((cleaned_page, _)) => &cleaned_page.clean_quality,
+        None => &[],
+    }
+}
+
+// Reads back the extraction JSONs written for a batch, paired with the file names of their images.
+// Pages that failed to extract, and text-free pages without a text file, are left out.
+fn read_batch_extractions(
+    input_image_paths: &[String],
+    output_paths: &[PathBuf],
+) -> Vec<(Extraction, String)> {
+    input_image_paths
+        .iter()
+        .zip(output_paths)
+        .filter_map(|(input_path, output_path)| {
+            let data = std::fs::read_to_string(output_path).ok()?;
+
+            match Extraction::from_json(&data, &page_name(input_path)) {
+                Ok(extraction) => Some((extraction, file_name(input_path))),
+                Err(e) => {
+                    error!("Error reading extracted text for {input_path}: {e}");
+                    None
+                }
+            }
+        })
+        .collect()
+}
+
+fn file_name(input: &str) -> String {
+    match Path::new(input).file_name() {
+        Some(file_name) => file_name.to_string_lossy().to_string(),
+        None => input.to_string(),
+    }
+}
+
 fn main() -> Result<()> {
     tracing_subscriber::fmt()
         .event_format(
@@ -463,9 +1744,29 @@ fn main() -> Result<()> {
 
     let before = Instant::now();
 
+    let config = match Config::parse() {
+        Ok(Invocation::Run(config)) => config,
+        Ok(Invocation::Command(command)) => {
+            // Commands like "verify" are run from scripts, which need to see that they failed
+            if let Err(e) = command.run() {
+                error!("{e}");
+                std::process::exit(1);
+            }
+
+            return Ok(());
+        }
+        Err(e) => {
+            error!("{e}");
+
+            return Ok(());
+        }
+    };
+
     let run = || -> Result<()> {
-        let mut runtime = Runtime::new()?;
+        let mut runtime = Runtime::new(*config)?;
         runtime.run()?;
+        // Outputs going into a bucket are only uploaded once every page is done
+        runtime.config.staging.finish()?;
         Ok(())
     };
 
@@ -1,49 +1,130 @@
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::{deskew, image_conversion, polarity, resolution};
 use anyhow::Result;
+use image::{imageops, Rgb};
 use leptess::{LepTess, Variable};
 use opencv::{core, imgcodecs};
 
+// Text recognized in a region and how confident tesseract is about it, from 0 to 100
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RecognizedText {
+    pub text: String,
+    pub confidence: u8,
+}
+
 pub struct Ocr {
     leptess: LepTess,
+    deskew: bool,
+    // Every region is encoded into this buffer, so its memory is reused across regions
+    tiff_buffer: core::Vector<u8>,
 }
 
 impl Ocr {
     pub fn new(lang: &str, data_path: &str) -> Result<Ocr> {
         let leptess = LepTess::new(Some(data_path), lang)?;
 
-        Ok(Ocr { leptess })
+        Ok(Ocr {
+            leptess,
+            deskew: false,
+            tiff_buffer: core::Vector::new(),
+        })
+    }
+
+    // Straightens each region before recognizing it. Vertical text in particular is misread
+    // as soon as its columns are slightly tilted.
+    pub fn with_deskew(mut self, deskew: bool) -> Ocr {
+        self.deskew = deskew;
+        self
+    }
+
+    pub fn extract_text(
+        &mut self,
+        text_boxes: &core::Vector<core::Mat>,
+        cancellation_token: &CancellationToken,
+    ) -> Result<Vec<String>> {
+        Ok(self
+            .recognize(text_boxes, cancellation_token)?
+            .into_iter()
+            .map(|recognized| recognized.text)
+            .collect())
     }
 
-    pub fn extract_text(&mut self, text_boxes: &core::Vector<core::Mat>) -> Result<Vec<String>> {
+    // Same as "extract_text" but also returns the confidence for each region
+    pub fn recognize(
+        &mut self,
+        text_boxes: &core::Vector<core::Mat>,
+        cancellation_token: &CancellationToken,
+    ) -> Result<Vec<RecognizedText>> {
         self.leptess
             .set_variable(Variable::TesseditPagesegMode, "5")?;
 
-        let mut extracted_text: Vec<String> = Vec::new();
+        let mut extracted_text: Vec<RecognizedText> = Vec::new();
 
         // Iterate over each text region and extract the text
         for bbox in text_boxes.into_iter() {
-            let encoded_data = Self::encode_in_tiff(&bbox)?;
+            cancellation_token.check()?;
+
+            match self.prepare(&bbox)? {
+                Some(prepared) => Self::encode_in_tiff(&prepared, &mut self.tiff_buffer)?,
+                None => Self::encode_in_tiff(&bbox, &mut self.tiff_buffer)?,
+            }
+
+            // Without a hint, tesseract assumes a low resolution for small bubbles. Zero unsets the
+            // hint of the previous region for regions without text.
+            let dpi = resolution::estimate_dpi(&imageops::grayscale(
+                &image_conversion::mat_to_image_buffer(&bbox)?,
+            ));
+            self.leptess
+                .set_variable(Variable::UserDefinedDpi, &dpi.unwrap_or(0).to_string())?;
 
-            self.leptess.set_image_from_mem(&encoded_data[..])?;
+            self.leptess
+                .set_image_from_mem(self.tiff_buffer.as_slice())?;
             self.leptess.set_fallback_source_resolution(70);
 
             let mut text = self.leptess.get_utf8_text()?;
             text = text.replace('\n', "");
 
-            extracted_text.push(text);
+            // Only valid after recognition, which "get_utf8_text" runs
+            let confidence = self.leptess.mean_text_conf().clamp(0, 100) as u8;
+
+            extracted_text.push(RecognizedText { text, confidence });
         }
 
         Ok(extracted_text)
     }
 
-    // The Tesseract API only accepts in-memory files in the TIFF format;
-    // We encode each text region as a TIFF file
-    fn encode_in_tiff(data: &core::Mat) -> Result<Vec<u8>> {
-        let mut buffer: core::Vector<u8> = core::Vector::new();
-        imgcodecs::imencode(".tiff", &data, &mut buffer, &core::Vector::new())?;
+    // Brings a region into the form tesseract reads best: dark text on a light background,
+    // and with deskewing enabled, text that lines up with the pixel grid.
+    // Returns None when the region is already in that form.
+    fn prepare(&self, region: &core::Mat) -> Result<Option<core::Mat>> {
+        let mut image_buffer = image_conversion::mat_to_image_buffer(region)?;
+        let mut changed = false;
 
-        let mut copied_buffer: Vec<u8> = vec![0; buffer.len()];
-        copied_buffer[..].copy_from_slice(buffer.as_slice());
+        if polarity::is_inverted(&imageops::grayscale(&image_buffer)) {
+            imageops::invert(&mut image_buffer);
+            changed = true;
+        }
+
+        if self.deskew {
+            let skew = deskew::estimate_skew(&imageops::grayscale(&image_buffer));
+
+            if skew != 0.0 {
+                image_buffer = deskew::rotate(&image_buffer, -skew, Rgb([255, 255, 255]));
+                changed = true;
+            }
+        }
+
+        match changed {
+            true => Ok(Some(image_conversion::image_buffer_to_mat(image_buffer)?)),
+            false => Ok(None),
+        }
+    }
+
+    // The Tesseract API only accepts in-memory files in the TIFF format;
+    // We encode each text region as a TIFF file, overwriting what was in the buffer
+    fn encode_in_tiff(data: &core::Mat, buffer: &mut core::Vector<u8>) -> Result<()> {
+        imgcodecs::imencode(".tiff", &data, buffer, &core::Vector::new())?;
 
-        Ok(copied_buffer)
+        Ok(())
     }
 }
@@ -0,0 +1,256 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::collections::HashSet;
+use std::path::Path;
+
+// Field numbers of the parts of an ONNX model (onnx.proto) that lead to the shape of its input
+const MODEL_GRAPH: u64 = 7;
+const GRAPH_INITIALIZER: u64 = 5;
+const GRAPH_INPUT: u64 = 11;
+const TENSOR_NAME: u64 = 8;
+const VALUE_INFO_NAME: u64 = 1;
+const VALUE_INFO_TYPE: u64 = 2;
+const TYPE_TENSOR: u64 = 1;
+const TENSOR_TYPE_SHAPE: u64 = 2;
+const SHAPE_DIM: u64 = 1;
+const DIM_VALUE: u64 = 1;
+
+// Value of a field of a protobuf message, by its wire type
+enum FieldValue<'a> {
+    Varint(u64),
+    Bytes(&'a [u8]),
+    // Fixed-size 32 and 64-bit values, which none of the fields read here are
+    Fixed,
+}
+
+// Reads the fields of a protobuf message in order, as pairs of their number and value
+struct Fields<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Fields<'a> {
+    fn new(bytes: &'a [u8]) -> Fields<'a> {
+        Fields { bytes }
+    }
+
+    fn next_field(&mut self) -> Result<(u64, FieldValue<'a>)> {
+        let key = self.varint()?;
+        let value = match key & 0x7 {
+            0 => FieldValue::Varint(self.varint()?),
+            1 => {
+                self.take(8)?;
+                FieldValue::Fixed
+            }
+            2 => {
+                let length = self.varint()?;
+                FieldValue::Bytes(self.take(length.try_into()?)?)
+            }
+            5 => {
+                self.take(4)?;
+                FieldValue::Fixed
+            }
+            wire_type => bail!("The model isn't a valid ONNX file (wire type {wire_type})."),
+        };
+
+        Ok((key >> 3, value))
+    }
+
+    fn varint(&mut self) -> Result<u64> {
+        let mut value = 0;
+
+        for shift in (0..64).step_by(7) {
+            let (&byte, rest) = self
+                .bytes
+                .split_first()
+                .context("The model isn't a valid ONNX file (truncated).")?;
+            self.bytes = rest;
+            value |= ((byte & 0x7f) as u64) << shift;
+
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+
+        bail!("The model isn't a valid ONNX file (overlong varint).")
+    }
+
+    fn take(&mut self, length: usize) -> Result<&'a [u8]> {
+        ensure!(
+            length <= self.bytes.len(),
+            "The model isn't a valid ONNX file (truncated)."
+        );
+        let (taken, rest) = self.bytes.split_at(length);
+        self.bytes = rest;
+
+        Ok(taken)
+    }
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Result<(u64, FieldValue<'a>)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.bytes.is_empty() {
+            true => None,
+            false => Some(self.next_field()),
+        }
+    }
+}
+
+// Contents of every embedded message or string of the given field
+fn bytes_fields(message: &[u8], number: u64) -> impl Iterator<Item = Result<&[u8]>> {
+    Fields::new(message).filter_map(move |field| match field {
+        Ok((field_number, FieldValue::Bytes(bytes))) if field_number == number => Some(Ok(bytes)),
+        Ok(_) => None,
+        Err(e) => Some(Err(e)),
+    })
+}
+
+fn first_bytes_field(message: &[u8], number: u64) -> Result<Option<&[u8]>> {
+    bytes_fields(message, number).next().transpose()
+}
+
+// Shape of the first input of an ONNX model, in the order of its dimensions. Dimensions the model
+// leaves open, such as a dynamic batch size, are None. Older exports also list their weights as
+// inputs, which are skipped.
+pub fn input_shape(model: &[u8]) -> Result<Vec<Option<usize>>> {
+    let graph = first_bytes_field(model, MODEL_GRAPH)?.context("The model has no graph to run.")?;
+
+    let mut weights = HashSet::new();
+    for initializer in bytes_fields(graph, GRAPH_INITIALIZER) {
+        if let Some(name) = first_bytes_field(initializer?, TENSOR_NAME)? {
+            weights.insert(name);
+        }
+    }
+
+    for input in bytes_fields(graph, GRAPH_INPUT) {
+        let input = input?;
+        let name = first_bytes_field(input, VALUE_INFO_NAME)?.unwrap_or_default();
+        if weights.contains(name) {
+            continue;
+        }
+
+        let shape = first_bytes_field(input, VALUE_INFO_TYPE)?
+            .map(|input_type| first_bytes_field(input_type, TYPE_TENSOR))
+            .transpose()?
+            .flatten()
+            .map(|tensor_type| first_bytes_field(tensor_type, TENSOR_TYPE_SHAPE))
+            .transpose()?
+            .flatten()
+            .context("The model's input isn't a tensor of a known shape.")?;
+
+        return bytes_fields(shape, SHAPE_DIM)
+            .map(|dimension| {
+                // Dimensions are either a size or the name of a size that is given at runtime
+                let size = Fields::new(dimension?).find_map(|field| match field {
+                    Ok((DIM_VALUE, FieldValue::Varint(size))) => Some(Ok(size)),
+                    Ok(_) => None,
+                    Err(e) => Some(Err(e)),
+                });
+
+                // Sizes are signed, and some exporters mark open dimensions with -1
+                Ok(size
+                    .transpose()?
+                    .map(|size| size as i64)
+                    .filter(|size| *size > 0)
+                    .map(|size| size as usize))
+            })
+            .collect();
+    }
+
+    bail!("The model has no inputs.")
+}
+
+pub fn read_input_shape(model_path: &Path) -> Result<Vec<Option<usize>>> {
+    let model = std::fs::read(model_path).with_context(|| {
+        format!(
+            "Could not read the detection model at {}.",
+            model_path.display()
+        )
+    })?;
+
+    input_shape(&model).with_context(|| {
+        format!(
+            "Could not read the input of the detection model at {}.",
+            model_path.display()
+        )
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::onnx::input_shape;
+
+    fn varint(mut value: u64) -> Vec<u8> {
+        let mut bytes = Vec::new();
+
+        loop {
+            let byte = (value & 0x7f) as u8;
+            value >>= 7;
+
+            if value == 0 {
+                bytes.push(byte);
+                return bytes;
+            }
+            bytes.push(byte | 0x80);
+        }
+    }
+
+    fn message(number: u64, contents: &[u8]) -> Vec<u8> {
+        [
+            varint(number << 3 | 2),
+            varint(contents.len() as u64),
+            contents.to_vec(),
+        ]
+        .concat()
+    }
+
+    fn input(name: &str, dimensions: &[Vec<u8>]) -> Vec<u8> {
+        let shape = message(2, &dimensions.concat());
+        let tensor_type = [varint(1 << 3), varint(1), shape].concat();
+
+        [
+            message(1, name.as_bytes()),
+            message(2, &message(1, &tensor_type)),
+        ]
+        .concat()
+    }
+
+    #[test]
+    fn test_input_shape() {
+        let size = |size: u64| message(1, &[varint(1 << 3), varint(size)].concat());
+        let named = |name: &str| message(1, &message(2, name.as_bytes()));
+
+        // A YOLO export with a dynamic batch size and a 1280 x 736 input, listing its weights as inputs
+        // before the image, as exports of older versions of ONNX do
+        let weight = [message(8, b"conv.weight"), message(9, &[0; 64])].concat();
+        let graph = [
+            message(1, b"some node"),
+            message(5, &weight),
+            message(11, &input("conv.weight", &[size(16), size(3)])),
+            message(
+                11,
+                &input("images", &[named("batch"), size(3), size(736), size(1280)]),
+            ),
+        ]
+        .concat();
+        let model = [
+            varint(1 << 3),
+            varint(8),
+            message(2, b"pytorch"),
+            message(7, &graph),
+        ]
+        .concat();
+
+        assert_eq!(
+            input_shape(&model).unwrap(),
+            [None, Some(3), Some(736), Some(1280)]
+        );
+
+        let open = message(11, &input("images", &[size(-1i64 as u64), size(3)]));
+        assert_eq!(input_shape(&message(7, &open)).unwrap(), [None, Some(3)]);
+
+        assert!(input_shape(&model[..model.len() - 10]).is_err());
+        assert!(input_shape(&message(2, b"pytorch")).is_err());
+        assert!(input_shape(&message(7, &message(1, b"node"))).is_err());
+    }
+}
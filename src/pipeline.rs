@@ -0,0 +1,256 @@
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex, PoisonError};
+use std::thread::Scope;
+
+// Items that may wait between two stages. Once a stage falls behind, the stages before it block
+// instead of piling up decoded pages in memory.
+pub const STAGE_CAPACITY: usize = 2;
+
+// Workers of the stages of a page pipeline, split from one budget of threads so the whole pipeline
+// runs on about that many threads and holds about that many pages, however many cores there are
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Workers {
+    pub decode: usize,
+    // Each detection worker takes a whole batch of pages under "--batch-size"
+    pub detect: usize,
+    // OCR and cleaning when extracting, typesetting when replacing
+    pub process: usize,
+    pub write: usize,
+}
+
+impl Workers {
+    // Decoding and writing are quick next to detection and OCR, so they get an eighth of the budget
+    // each and the rest is split between detection and the stage after it
+    pub fn split(threads: usize, batch_size: usize) -> Workers {
+        let io = (threads / 8).max(1);
+        let work = threads.saturating_sub(2 * io).max(2);
+
+        Workers {
+            decode: io,
+            detect: (work / 2 / batch_size.max(1)).max(1),
+            process: work - work / 2,
+            write: io,
+        }
+    }
+
+    // Most decoded pages the pipeline holds at once: one for every worker, a batch for every
+    // detection worker, and STAGE_CAPACITY waiting in front of each stage after decoding
+    pub fn max_pages(&self, batch_size: usize) -> usize {
+        self.decode
+            + self.detect * batch_size.max(1)
+            + self.process
+            + self.write
+            + 3 * STAGE_CAPACITY
+    }
+}
+
+// Starts feeding items into a pipeline from a thread of its own
+pub fn source<'scope, I, T>(scope: &'scope Scope<'scope, '_>, items: I) -> Receiver<T>
+where
+    I: IntoIterator<Item = T> + Send + 'scope,
+    T: Send + 'scope,
+{
+    let (sender, receiver) = mpsc::sync_channel(STAGE_CAPACITY);
+
+    scope.spawn(move || {
+        for item in items {
+            // Nobody is listening anymore once the later stages stopped
+            if sender.send(item).is_err() {
+                break;
+            }
+        }
+    });
+
+    receiver
+}
+
+// Starts a stage of "workers" threads that each take the next item from the previous stage, run it
+// through "run" and pass the result on. Results come out in the order they finish, not the order
+// they went in. The returned receiver ends once the input has ended and every worker is done.
+pub fn stage<'scope, T, U, F>(
+    scope: &'scope Scope<'scope, '_>,
+    input: Receiver<T>,
+    workers: usize,
+    run: F,
+) -> Receiver<U>
+where
+    T: Send + 'scope,
+    U: Send + 'scope,
+    F: Fn(T) -> U + Send + Sync + 'scope,
+{
+    let (sender, receiver) = mpsc::sync_channel(STAGE_CAPACITY);
+    let input = Arc::new(Mutex::new(input));
+    let run = Arc::new(run);
+
+    for _ in 0..workers.max(1) {
+        let (input, sender, run) = (Arc::clone(&input), sender.clone(), Arc::clone(&run));
+
+        scope.spawn(move || loop {
+            // The lock is only held while waiting for the next item, not while running it
+            let item = match input.lock().unwrap_or_else(PoisonError::into_inner).recv() {
+                Ok(item) => item,
+                Err(_) => break,
+            };
+
+            if sender.send(run(item)).is_err() {
+                break;
+            }
+        });
+    }
+
+    receiver
+}
+
+// Same as "stage", but each worker takes up to "batch_size" items at a time and runs them through
+// "run" together, e.g. to detect several pages in one pass of the model. A worker waits until its
+// batch is full or the input has ended, and "run" returns a result for every item of the batch.
+pub fn batched_stage<'scope, T, U, F>(
+    scope: &'scope Scope<'scope, '_>,
+    input: Receiver<T>,
+    workers: usize,
+    batch_size: usize,
+    run: F,
+) -> Receiver<U>
+where
+    T: Send + 'scope,
+    U: Send + 'scope,
+    F: Fn(Vec<T>) -> Vec<U> + Send + Sync + 'scope,
+{
+    let (sender, receiver) = mpsc::sync_channel(STAGE_CAPACITY);
+    let input = Arc::new(Mutex::new(input));
+    let run = Arc::new(run);
+
+    for _ in 0..workers.max(1) {
+        let (input, sender, run) = (Arc::clone(&input), sender.clone(), Arc::clone(&run));
+
+        scope.spawn(move || loop {
+            let batch: Vec<T> = {
+                let input = input.lock().unwrap_or_else(PoisonError::into_inner);
+                input.iter().take(batch_size.max(1)).collect()
+            };
+
+            if batch.is_empty() {
+                break;
+            }
+
+            for result in run(batch) {
+                if sender.send(result).is_err() {
+                    return;
+                }
+            }
+        });
+    }
+
+    receiver
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::pipeline::{batched_stage, source, stage, Workers, STAGE_CAPACITY};
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_pipeline() {
+        let in_flight = AtomicUsize::new(0);
+        let most_in_flight = AtomicUsize::new(0);
+
+        let mut results: Vec<usize> = thread::scope(|scope| {
+            let numbers = source(scope, 0..100);
+
+            let doubled = stage(scope, numbers, 4, |number: usize| {
+                let count = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                most_in_flight.fetch_max(count, Ordering::SeqCst);
+
+                number * 2
+            });
+
+            let results = stage(scope, doubled, 1, |number: usize| {
+                in_flight.fetch_sub(1, Ordering::SeqCst);
+
+                number + 1
+            });
+
+            results.iter().collect()
+        });
+
+        results.sort();
+
+        assert_eq!(
+            results,
+            (0..100).map(|number| number * 2 + 1).collect::<Vec<_>>()
+        );
+
+        // Four workers plus what fits into the channels between them
+        assert!(most_in_flight.load(Ordering::SeqCst) <= 4 + 2 * 2 + 1);
+    }
+
+    #[test]
+    fn test_workers() {
+        let workers = Workers::split(16, 1);
+
+        assert_eq!(
+            workers,
+            Workers {
+                decode: 2,
+                detect: 6,
+                process: 6,
+                write: 2,
+            }
+        );
+        assert_eq!(workers.max_pages(1), 16 + 3 * STAGE_CAPACITY);
+
+        // Batches take the place of detection workers
+        assert_eq!(Workers::split(16, 4).detect, 1);
+        assert_eq!(Workers::split(16, 4).max_pages(4), 14 + 3 * STAGE_CAPACITY);
+
+        // Every stage gets a worker however small the budget
+        assert_eq!(
+            Workers::split(1, 8),
+            Workers {
+                decode: 1,
+                detect: 1,
+                process: 1,
+                write: 1,
+            }
+        );
+    }
+
+    #[test]
+    fn test_batched_stage() {
+        let mut batches: Vec<Vec<usize>> = thread::scope(|scope| {
+            let numbers = source(scope, 0..10);
+
+            let batched = batched_stage(scope, numbers, 1, 4, |batch: Vec<usize>| vec![batch]);
+
+            batched.iter().collect()
+        });
+
+        batches.sort();
+
+        // The last batch takes what is left once the input ends
+        assert_eq!(
+            batches,
+            vec![vec![0, 1, 2, 3], vec![4, 5, 6, 7], vec![8, 9]]
+        );
+
+        let mut results: Vec<usize> = thread::scope(|scope| {
+            let numbers = source(scope, 0..100);
+
+            let doubled = batched_stage(scope, numbers, 3, 8, |batch: Vec<usize>| {
+                assert!(!batch.is_empty() && batch.len() <= 8);
+
+                batch.into_iter().map(|number| number * 2).collect()
+            });
+
+            doubled.iter().collect()
+        });
+
+        results.sort();
+
+        assert_eq!(
+            results,
+            (0..100).map(|number| number * 2).collect::<Vec<_>>()
+        );
+    }
+}
@@ -0,0 +1,397 @@
+use crate::utils::validation;
+use crate::vocabulary::Vocabulary;
+use anyhow::{bail, ensure, Context, Result};
+use indexmap::IndexMap;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+// Name of the project file inside a volume's directory
+pub const PROJECT_FILE_NAME: &str = "mangatra.project.json";
+
+// How far a page has come. Later stages imply the earlier ones.
+#[derive(
+    clap::ValueEnum, Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord,
+)]
+#[serde(rename_all = "lowercase")]
+pub enum PageStatus {
+    New,
+    Detected,
+    Extracted,
+    Translated,
+    Typeset,
+}
+
+impl std::fmt::Display for PageStatus {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match self {
+            PageStatus::New => write!(f, "new"),
+            PageStatus::Detected => write!(f, "detected"),
+            PageStatus::Extracted => write!(f, "extracted"),
+            PageStatus::Translated => write!(f, "translated"),
+            PageStatus::Typeset => write!(f, "typeset"),
+        }
+    }
+}
+
+// Settings a page was last processed with, so differently processed pages can be found and redone
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Parameters {
+    pub model: String,
+    pub lang: String,
+    pub padding: u16,
+}
+
+// Files written for a page, as the paths they were written to
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Artifacts {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub text: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub cleaned_page: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct PageRecord {
+    // File name of the page image inside the volume's directory
+    pub image: String,
+    pub status: PageStatus,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub parameters: Option<Parameters>,
+    #[serde(default)]
+    pub artifacts: Artifacts,
+    // Error of the last run that failed on the page
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+    // Seconds since the Unix epoch
+    #[serde(default)]
+    pub updated: u64,
+}
+
+impl PageRecord {
+    pub fn new(image: &str) -> PageRecord {
+        PageRecord {
+            image: image.to_string(),
+            status: PageStatus::New,
+            parameters: None,
+            artifacts: Artifacts::default(),
+            error: None,
+            updated: now(),
+        }
+    }
+
+    pub fn set_status(&mut self, status: PageStatus) {
+        self.status = status;
+        self.error = None;
+        self.updated = now();
+    }
+
+    // Keeps the status the page had before, since the outputs of the earlier run are still there
+    pub fn set_error(&mut self, error: &str) {
+        self.error = Some(error.to_string());
+        self.updated = now();
+    }
+}
+
+// A volume's pages and how far each of them has been processed, keyed by page name
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq, Eq)]
+pub struct Project {
+    pub title: String,
+    // Words and patterns OCR is biased towards on every page of the volume
+    #[serde(default, skip_serializing_if = "Vocabulary::is_empty")]
+    pub vocabulary: Vocabulary,
+    pub pages: IndexMap<String, PageRecord>,
+}
+
+impl Project {
+    pub fn new(title: &str) -> Project {
+        Project {
+            title: title.to_string(),
+            vocabulary: Vocabulary::default(),
+            pages: IndexMap::new(),
+        }
+    }
+
+    // Creates a project for the page images of a directory and writes it into the directory
+    pub fn init(directory: &Path, title: Option<&str>) -> Result<Project> {
+        ensure!(
+            directory.is_dir(),
+            "{} must be a directory of pages to create a project for it.",
+            directory.display()
+        );
+
+        let path = directory.join(PROJECT_FILE_NAME);
+        ensure!(
+            !path.exists(),
+            "A project already exists at {}.",
+            path.display()
+        );
+
+        let title = match title {
+            Some(title) => title.to_string(),
+            None => match directory.canonicalize()?.file_name() {
+                Some(name) => name.to_string_lossy().to_string(),
+                None => bail!("Give the project for {} a title.", directory.display()),
+            },
+        };
+
+        let images = validation::images_in_directory(directory)?;
+
+        let mut project = Project::new(&title);
+
+        for image in images {
+            if let (Some(stem), Some(file_name)) = (image.file_stem(), image.file_name()) {
+                project.page_mut(&stem.to_string_lossy(), &file_name.to_string_lossy());
+            }
+        }
+
+        project.save(&path)?;
+
+        Ok(project)
+    }
+
+    // Accepts the project file itself or the directory containing it
+    pub fn locate(path: &Path) -> Option<PathBuf> {
+        let path = match path.is_dir() {
+            true => path.join(PROJECT_FILE_NAME),
+            false => path.to_path_buf(),
+        };
+
+        path.is_file().then_some(path)
+    }
+
+    pub fn load(path: &Path) -> Result<Project> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the project file {}.", path.display()))?;
+
+        serde_json::from_str(&data)
+            .with_context(|| format!("{} is not a valid project file.", path.display()))
+    }
+
+    // Writes to a temporary file first so an interrupted save never leaves a truncated project behind
+    pub fn save(&self, path: &Path) -> Result<()> {
+        let temporary_path = path.with_extension("json.tmp");
+
+        std::fs::write(&temporary_path, serde_json::to_string_pretty(self)?)?;
+        std::fs::rename(&temporary_path, path)?;
+
+        Ok(())
+    }
+
+    // Returns the record of a page, adding pages that were put into the directory after the project was created
+    pub fn page_mut(&mut self, page: &str, image: &str) -> &mut PageRecord {
+        self.pages
+            .entry(page.to_string())
+            .or_insert_with(|| PageRecord::new(image))
+    }
+
+    pub fn set_status(&mut self, pages: &[String], status: PageStatus) -> Result<()> {
+        for page in pages {
+            match self.pages.get_mut(page) {
+                Some(record) => record.set_status(status),
+                None => bail!("{page} is not a page of the project \"{}\".", self.title),
+            }
+        }
+
+        Ok(())
+    }
+
+    // Number of pages in each status, in pipeline order
+    pub fn summary(&self) -> Vec<(PageStatus, usize)> {
+        let mut counts: BTreeMap<PageStatus, usize> = BTreeMap::new();
+
+        for record in self.pages.values() {
+            *counts.entry(record.status).or_default() += 1;
+        }
+
+        counts.into_iter().collect()
+    }
+
+    // One line per page, optionally only the pages in the given status, followed by the totals
+    pub fn report(&self, status: Option<PageStatus>) -> String {
+        let mut report = format!("{} ({} pages)\n", self.title, self.pages.len());
+
+        for (page, record) in self.pages.iter() {
+            if status.is_some_and(|status| status != record.status) {
+                continue;
+            }
+
+            report.push_str(&format!("  {page:<24} {:<10}", record.status));
+
+            if let Some(error) = &record.error {
+                report.push_str(&format!(" last run failed: {error}"));
+            }
+
+            report.push('\n');
+        }
+
+        let summary = self
+            .summary()
+            .iter()
+            .map(|(status, count)| format!("{count} {status}"))
+            .collect::<Vec<String>>()
+            .join(", ");
+
+        report.push_str(&format!("Total: {summary}\n"));
+
+        report
+    }
+}
+
+// Subcommands for working with a project file from the command line
+#[derive(clap::Subcommand, Debug)]
+pub enum ProjectCommand {
+    #[command(about = "Create a project file that tracks the pages of a directory")]
+    Init {
+        #[arg(help = "Directory of page images")]
+        directory: PathBuf,
+        #[arg(long, help = "Title of the volume. Defaults to the directory name")]
+        title: Option<String>,
+    },
+    #[command(about = "Show the status of every page of a project")]
+    Status {
+        #[arg(help = "Project file or the directory containing it")]
+        project: PathBuf,
+        #[arg(long, value_enum, help = "Only list pages in this status")]
+        status: Option<PageStatus>,
+        #[arg(long, help = "Print the project as JSON")]
+        json: bool,
+    },
+    #[command(about = "Set the status of pages, e.g. after translating their text")]
+    Set {
+        #[arg(help = "Project file or the directory containing it")]
+        project: PathBuf,
+        #[arg(long, value_enum, help = "New status of the pages")]
+        status: PageStatus,
+        #[arg(
+            required = true,
+            help = "Names of the pages (their file names without extension)"
+        )]
+        pages: Vec<String>,
+    },
+}
+
+impl ProjectCommand {
+    pub fn run(self) -> Result<()> {
+        match self {
+            ProjectCommand::Init { directory, title } => {
+                let project = Project::init(&directory, title.as_deref())?;
+
+                println!(
+                    "Created a project for {} pages at {}",
+                    project.pages.len(),
+                    directory.join(PROJECT_FILE_NAME).display()
+                );
+            }
+            ProjectCommand::Status {
+                project,
+                status,
+                json,
+            } => {
+                let project = Project::load(&locate(&project)?)?;
+
+                match json {
+                    true => println!("{}", serde_json::to_string_pretty(&project)?),
+                    false => print!("{}", project.report(status)),
+                }
+            }
+            ProjectCommand::Set {
+                project: path,
+                status,
+                pages,
+            } => {
+                let path = locate(&path)?;
+                let mut project = Project::load(&path)?;
+
+                project.set_status(&pages, status)?;
+                project.save(&path)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+fn locate(path: &Path) -> Result<PathBuf> {
+    match Project::locate(path) {
+        Some(path) => Ok(path),
+        None => bail!(
+            "No project file found at {}. Create one with \"mangatra project init\".",
+            path.display()
+        ),
+    }
+}
+
+fn now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .map(|duration| duration.as_secs())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::project::{PageStatus, Project, PROJECT_FILE_NAME};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_project_file() {
+        let directory = TempDir::new().unwrap();
+
+        for file_name in ["002.png", "001.jpg", "notes.txt"] {
+            std::fs::write(directory.path().join(file_name), []).unwrap();
+        }
+
+        let mut project = Project::init(directory.path(), Some("Volume 1")).unwrap();
+
+        assert_eq!(
+            project.pages.keys().collect::<Vec<&String>>(),
+            vec!["001", "002"]
+        );
+        assert_eq!(project.pages["001"].image, "001.jpg");
+        assert!(Project::init(directory.path(), None).is_err());
+
+        project
+            .page_mut("002", "002.png")
+            .set_status(PageStatus::Extracted);
+        project
+            .page_mut("003", "003.png")
+            .set_error("Could not read 003.png");
+        project
+            .set_status(&["001".to_string()], PageStatus::Typeset)
+            .unwrap();
+
+        let error = project
+            .set_status(&["004".to_string()], PageStatus::Typeset)
+            .unwrap_err();
+
+        assert_eq!(
+            format!("{error}"),
+            "004 is not a page of the project \"Volume 1\"."
+        );
+
+        let path = Project::locate(directory.path()).unwrap();
+        assert_eq!(path, directory.path().join(PROJECT_FILE_NAME));
+
+        project.save(&path).unwrap();
+        assert_eq!(Project::load(&path).unwrap(), project);
+
+        assert_eq!(
+            project.summary(),
+            vec![
+                (PageStatus::New, 1),
+                (PageStatus::Extracted, 1),
+                (PageStatus::Typeset, 1)
+            ]
+        );
+
+        let report = project.report(Some(PageStatus::New));
+
+        assert!(report.contains("last run failed: Could not read 003.png"));
+        assert!(!report.contains("002"));
+        assert!(report.ends_with("Total: 1 new, 1 extracted, 1 typeset\n"));
+    }
+}
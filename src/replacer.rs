@@ -1,10 +1,24 @@
-use crate::utils::image_conversion;
-use anyhow::{anyhow, Result};
-use image::{self, Rgb};
+use crate::extraction::{Annotation, AnnotationPlacement};
+use crate::fonts;
+use crate::imaging::PageImage;
+use crate::layout::{
+    block_height, break_lines, draw_block, draw_centered_lines, fit_lines, fit_scale,
+    initial_scale, scale_by, text_centroid, wrap_words, Face, Pen, FIT_STEP, MIN_FONT_SIZE,
+};
+use crate::style::{self, Case, MaxLines, ShoutStyle};
+use crate::text_box::TextBox;
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::floating;
+use crate::utils::linebreak::LineBreaking;
+use crate::utils::palette::Palette;
+use crate::utils::{deskew, polarity, quality};
+use anyhow::{anyhow, bail, Context, Result};
+use image::{self, imageops, GrayImage, Rgb, RgbImage};
+#[cfg(feature = "debug")]
 use imageproc::drawing;
-use indexmap::IndexMap;
-use opencv::{core, prelude::*};
+use imageproc::rect::Rect;
 use rusttype::{Font, Scale};
+use std::str::FromStr;
 
 type Coordinates = (i32, i32);
 type Width = i32;
@@ -15,597 +29,1489 @@ enum DiagOrientation {
     TopRightBottomLeft,
 }
 
-struct ReplacementMat {
-    pub mat: core::Mat,
+// How far text regions are grown to cover the bubble around them before cleaning or typesetting
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub struct Expansion {
+    // Largest difference per color channel at which a pixel still counts as the bubble's background.
+    // Zero only expands over pixels of exactly the same color, which JPEG noise and screentone prevent.
+    pub tolerance: u8,
+    // Largest number of pixels a corner of the region may move, unlimited if not set
+    pub max_distance: Option<u32>,
+}
+
+impl Expansion {
+    fn matches(&self, pixel: Rgb<u8>, reference: Rgb<u8>) -> bool {
+        pixel
+            .0
+            .iter()
+            .zip(reference.0)
+            .all(|(channel, reference)| channel.abs_diff(reference) <= self.tolerance)
+    }
+}
+
+// Space kept free between a translation and the left and right edges of its region
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InnerMargin {
+    Pixels(u32),
+    // Percentage of the region's width
+    Percent(f32),
+}
+
+impl InnerMargin {
+    // Width taken by the margins on both sides of a region "width" pixels wide
+    fn total(&self, width: u32) -> u32 {
+        let margin = match *self {
+            InnerMargin::Pixels(pixels) => pixels,
+            InnerMargin::Percent(percent) => (width as f32 * percent / 100.0) as u32,
+        };
+
+        margin.saturating_mul(2).min(width)
+    }
+}
+
+// "12" for pixels, "5%" for a percentage of the region's width
+impl FromStr for InnerMargin {
+    type Err = anyhow::Error;
+
+    fn from_str(margin: &str) -> Result<InnerMargin> {
+        let margin = margin.trim();
+
+        match margin.strip_suffix('%') {
+            Some(percent) => {
+                let percent: f32 = percent
+                    .trim()
+                    .parse()
+                    .with_context(|| format!("{margin} is not a percentage."))?;
+                if !(0.0..50.0).contains(&percent) {
+                    bail!(
+                        "The margin must be at least 0% and less than 50% of the region's width."
+                    );
+                }
+
+                Ok(InnerMargin::Percent(percent))
+            }
+            None => Ok(InnerMargin::Pixels(margin.parse().with_context(|| {
+                format!("{margin} is neither a number of pixels nor a percentage.")
+            })?)),
+        }
+    }
+}
+
+// A region drawn over the page, cleaned or with its translation
+struct RenderedRegion {
+    pub image: RgbImage,
     pub origin: Coordinates,
     pub diag: DiagOrientation,
+    // Index of the text region the image replaces
+    pub index: usize,
+    // How well the image cleans the region, from 0 to 100, as scored before any text is drawn on it
+    pub clean_quality: u8,
+    // How far the translation drawn on the image goes past the area it had
+    pub overflow: Option<TextOverflow>,
+}
+
+// A translation that doesn't fit in its region even at the size it was fitted to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TextOverflow {
+    // Index of the region
+    pub index: usize,
+    // Width and height in pixels of the typeset text, along with its annotation
+    pub required: (i32, i32),
+    // Width and height in pixels of the area within the region's padding and margins
+    pub available: (i32, i32),
+    // Lines the translation was broken into, and the most it was allowed
+    pub lines: usize,
+    pub max_lines: Option<usize>,
+}
+
+// A region's translation and canvas, laid out but not drawn yet
+struct RegionLayout {
+    index: usize,
+    text: String,
+    shout: bool,
+    small_caps: bool,
+    origin: Coordinates,
+    diag: DiagOrientation,
+    canvas: RgbImage,
+    background: Rgb<u8>,
+    text_color: Rgb<u8>,
+    outline: Option<Rgb<u8>>,
+    skew: f32,
+    clean_quality: u8,
+    // Size the translation is set in, before shouts are enlarged
+    scale: Scale,
+    max_lines: Option<usize>,
+    // Centroid of the original text on the canvas, which the translation is centered on
+    anchor: Option<Coordinates>,
+}
+
+// Regions drawn for a page by "Replacer::typeset", ready to be composited onto it
+pub struct RenderedRegions {
+    regions: Vec<RenderedRegion>,
+}
+
+impl RenderedRegions {
+    pub fn len(&self) -> usize {
+        self.regions.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.regions.is_empty()
+    }
 }
 
-pub struct Replacer<'a, T>
+pub struct Replacer<'a, T, I>
 where
     T: AsRef<str>,
+    I: PageImage,
 {
-    original_text_regions: core::Vector<core::Mat>,
-    text_pairs: Option<&'a IndexMap<T, T>>,
+    original_text_regions: Vec<I>,
+    translations: Option<&'a [Option<T>]>,
     origins: Vec<(i32, i32)>,
-    original_image: core::Mat,
+    original_image: I,
     padding: u16,
+    expansion: Expansion,
+    // Set translations at the skew of the text they replace
+    deskew: bool,
+    // Regions that are neither cleaned nor typeset, by index
+    skipped_regions: Vec<bool>,
+    font: Font<'static>,
+    // Notes set along with the translations, by index
+    annotations: Vec<Option<Annotation>>,
+    // Padding of the regions that don't use the page's, by index
+    region_padding: Vec<Option<u16>>,
+    // Margin on each side of the translations, a sixteenth of the width in total if not set
+    inner_margin: Option<InnerMargin>,
+    // Set every translation of the page in the same size
+    uniform_font_size: bool,
+    // Fill every line as far as it goes, even when that leaves a single word on the last one
+    greedy_wrapping: bool,
+    // Where lines of the translations may be broken, which depends on their language
+    line_breaking: LineBreaking,
+    // Case the translations are set in, and shouts unless their style sets another
+    case: Case,
+    max_lines: MaxLines,
+    shout_style: ShoutStyle,
+    // Page the rendered regions are composited onto instead of the original one
+    base_image: Option<I>,
+    // Paint the text out of bubbles and take the colors of translations from them, for color pages
+    color_aware: bool,
 }
 
-impl<'a, T> Replacer<'a, T>
+impl<'a, T, I> Replacer<'a, T, I>
 where
     T: AsRef<str>,
+    I: PageImage,
 {
     pub fn new(
-        original_text_regions: core::Vector<core::Mat>,
-        text_pairs: Option<&'a IndexMap<T, T>>,
+        original_text_regions: impl IntoIterator<Item = I>,
+        translations: Option<&'a [Option<T>]>,
         origins: Vec<(i32, i32)>,
-        original_image: core::Mat,
+        original_image: I,
         padding: u16,
-    ) -> Result<Replacer<'a, T>> {
+        expansion: Expansion,
+        deskew: bool,
+    ) -> Result<Replacer<'a, T, I>> {
         Ok(Replacer {
-            original_text_regions,
-            text_pairs,
+            original_text_regions: original_text_regions.into_iter().collect(),
+            translations,
             origins,
             original_image,
             padding,
+            expansion,
+            deskew,
+            skipped_regions: Vec::new(),
+            font: fonts::bundled_font(),
+            annotations: Vec::new(),
+            region_padding: Vec::new(),
+            inner_margin: None,
+            uniform_font_size: false,
+            greedy_wrapping: false,
+            line_breaking: LineBreaking::default(),
+            case: Case::default(),
+            max_lines: MaxLines::default(),
+            shout_style: ShoutStyle::default(),
+            base_image: None,
+            color_aware: false,
         })
     }
 
-    pub fn clean_page(&self) -> Result<core::Mat> {
-        let mut temp_image = core::Mat::copy(&self.original_image)?;
-        let blank_mats = self.get_blank_mats()?;
+    // Replacer for the regions of a page within boxes that were detected before, e.g. the ones of a
+    // project file, cropped from the page in the order of the boxes
+    pub fn from_boxes(
+        page: I,
+        boxes: &[TextBox],
+        translations: Option<&'a [Option<T>]>,
+        padding: u16,
+        expansion: Expansion,
+        deskew: bool,
+    ) -> Result<Replacer<'a, T, I>> {
+        let text_regions = boxes
+            .iter()
+            .map(|text_box| {
+                let (x, y, width, height) = text_box.geometry();
+                page.crop(x, y, width, height)
+            })
+            .collect::<Result<Vec<I>>>()?;
+        let origins = boxes
+            .iter()
+            .map(|text_box| (text_box.x, text_box.y))
+            .collect();
+
+        Replacer::new(
+            text_regions,
+            translations,
+            origins,
+            page,
+            padding,
+            expansion,
+            deskew,
+        )
+    }
 
-        for ReplacementMat {
-            mat: region,
-            origin: (x, y),
-            diag: diag_orientation,
-        } in blank_mats
-        {
-            temp_image = replace_region(&temp_image, region, (x, y), diag_orientation)?;
+    // Leaves the regions flagged in "skipped_regions" as they are on the page
+    pub fn with_skipped_regions(mut self, skipped_regions: Vec<bool>) -> Replacer<'a, T, I> {
+        self.skipped_regions = skipped_regions;
+        self
+    }
+
+    // Typesets the translations in the given font instead of the bundled one
+    pub fn with_font(mut self, font: Font<'static>) -> Replacer<'a, T, I> {
+        self.font = font;
+        self
+    }
+
+    // Sets the annotations of the regions, in the same order as the translations
+    pub fn with_annotations(mut self, annotations: Vec<Option<Annotation>>) -> Replacer<'a, T, I> {
+        self.annotations = annotations;
+        self
+    }
+
+    // Sets the padding of the regions, in the same order as the translations. Regions without one
+    // use the padding given to "new".
+    pub fn with_region_padding(mut self, region_padding: Vec<Option<u16>>) -> Replacer<'a, T, I> {
+        self.region_padding = region_padding;
+        self
+    }
+
+    // Sets how close the translations come to the left and right edges of their regions
+    pub fn with_inner_margin(mut self, inner_margin: InnerMargin) -> Replacer<'a, T, I> {
+        self.inner_margin = Some(inner_margin);
+        self
+    }
+
+    // Sets every translation in the largest size that fits all regions of the page, instead of sizing
+    // each one for its own region
+    pub fn with_uniform_font_size(mut self) -> Replacer<'a, T, I> {
+        self.uniform_font_size = true;
+        self
+    }
+
+    // Breaks lines as late as possible, without moving words down to keep a short word from ending
+    // up alone on the last line
+    pub fn with_greedy_wrapping(mut self) -> Replacer<'a, T, I> {
+        self.greedy_wrapping = true;
+        self
+    }
+
+    // Sets where lines of the translations may be broken, e.g. between any two characters of
+    // Chinese text, which has no spaces
+    pub fn with_line_breaking(mut self, line_breaking: LineBreaking) -> Replacer<'a, T, I> {
+        self.line_breaking = line_breaking;
+        self
+    }
+
+    // Sets the case the translations are set in
+    pub fn with_case(mut self, case: Case) -> Replacer<'a, T, I> {
+        self.case = case;
+        self
+    }
+
+    // Sets the most lines the translations of regions may be broken into
+    pub fn with_max_lines(mut self, max_lines: MaxLines) -> Replacer<'a, T, I> {
+        self.max_lines = max_lines;
+        self
+    }
+
+    // Sets how the translations of regions that are classified as shouts are set
+    pub fn with_shout_style(mut self, shout_style: ShoutStyle) -> Replacer<'a, T, I> {
+        self.shout_style = shout_style;
+        self
+    }
+
+    // Composites the rendered regions onto another page of the same size, e.g. one where the other
+    // regions were already typeset
+    pub fn with_base_image(mut self, base_image: I) -> Replacer<'a, T, I> {
+        self.base_image = Some(base_image);
+        self
+    }
+
+    // Cleans regions by painting their text out, which keeps the gradients and screentone of the bubble
+    // behind it, and sets translations in the colors of the text and bubble they replace instead of
+    // black on white
+    pub fn with_color_aware(mut self) -> Replacer<'a, T, I> {
+        self.color_aware = true;
+        self
+    }
+
+    fn is_skipped(&self, index: usize) -> bool {
+        self.skipped_regions.get(index).copied().unwrap_or(false)
+    }
+
+    fn padding(&self, index: usize) -> u16 {
+        self.region_padding
+            .get(index)
+            .copied()
+            .flatten()
+            .unwrap_or(self.padding)
+    }
+
+    // Width taken by the margins on both sides of a region "width" pixels wide
+    fn inner_margin(&self, width: u32) -> u32 {
+        match &self.inner_margin {
+            Some(inner_margin) => inner_margin.total(width),
+            None => width / 16,
         }
+    }
 
-        Ok(temp_image)
+    // Width of the column on the right of a region that its annotation is set in, if it has one there
+    fn column_width(&self, index: usize, width: u32) -> i32 {
+        match self.annotations.get(index).and_then(Option::as_ref) {
+            Some(annotation) if annotation.placement == AnnotationPlacement::Beside => {
+                width as i32 / 4
+            }
+            _ => 0,
+        }
     }
 
-    pub fn replace_text_regions(&self) -> Result<core::Mat> {
-        let translated_mats = self.write_text()?;
-        let mut temp_image = core::Mat::copy(&self.original_image)?;
+    // Widest a line of the translation of a region "width" pixels wide may be
+    fn max_line_width(&self, index: usize, width: u32) -> i32 {
+        (width - self.inner_margin(width)) as i32
+            - self.padding(index) as i32
+            - self.column_width(index, width)
+    }
 
-        for ReplacementMat {
-            mat: text_region,
-            origin: (x, y),
+    pub fn clean_page(&self, cancellation_token: &CancellationToken) -> Result<I> {
+        let blank_regions = self.clean(cancellation_token)?;
+
+        self.composite(blank_regions, cancellation_token)
+    }
+
+    // Draws the blank regions that "clean_page" composites onto the page
+    pub fn clean(&self, cancellation_token: &CancellationToken) -> Result<RenderedRegions> {
+        Ok(RenderedRegions {
+            regions: self.get_blank_regions(cancellation_token)?,
+        })
+    }
+
+    // Cleaning score of every region by index, missing for the regions that weren't rendered
+    pub fn clean_quality(&self, rendered_regions: &RenderedRegions) -> Vec<Option<u8>> {
+        let mut clean_quality = vec![None; self.original_text_regions.len()];
+
+        for rendered_region in &rendered_regions.regions {
+            if let Some(score) = clean_quality.get_mut(rendered_region.index) {
+                *score = Some(rendered_region.clean_quality);
+            }
+        }
+
+        clean_quality
+    }
+
+    // Areas of the page the rendered regions are pasted over
+    pub fn areas(&self, rendered_regions: &RenderedRegions) -> Vec<Rect> {
+        rendered_regions
+            .regions
+            .iter()
+            .map(|rendered_region| {
+                let (x, y) = rendered_region.origin;
+                let (width, height) = rendered_region.image.dimensions();
+
+                Rect::at(x, y).of_size(width, height)
+            })
+            .collect()
+    }
+
+    // Translations of the rendered regions that don't fit in their regions, by index
+    pub fn overflows(&self, rendered_regions: &RenderedRegions) -> Vec<TextOverflow> {
+        rendered_regions
+            .regions
+            .iter()
+            .filter_map(|rendered_region| rendered_region.overflow)
+            .collect()
+    }
+
+    pub fn replace_text_regions(&self, cancellation_token: &CancellationToken) -> Result<I> {
+        let translated_regions = self.typeset(cancellation_token)?;
+
+        self.composite(translated_regions, cancellation_token)
+    }
+
+    // Lays out and draws the translation of every region. Together with "composite" this is
+    // "replace_text_regions" split into its two stages, so they can be timed on their own.
+    pub fn typeset(&self, cancellation_token: &CancellationToken) -> Result<RenderedRegions> {
+        Ok(RenderedRegions {
+            regions: self.write_text(cancellation_token)?,
+        })
+    }
+
+    // Pastes rendered regions onto a copy of the page, or of the base image if one is set
+    #[allow(unused_variables)]
+    pub fn composite(
+        &self,
+        rendered_regions: RenderedRegions,
+        cancellation_token: &CancellationToken,
+    ) -> Result<I> {
+        let mut temp_image = self
+            .base_image
+            .as_ref()
+            .unwrap_or(&self.original_image)
+            .copy()?;
+
+        for RenderedRegion {
+            image: region,
+            origin,
             diag: diag_orientation,
-        } in translated_mats
+            ..
+        } in rendered_regions.regions
         {
-            temp_image = replace_region(&temp_image, text_region, (x, y), diag_orientation)?;
+            cancellation_token.check()?;
+
+            let (width, height) = region.dimensions();
+            temp_image.paste(region, origin)?;
+
+            #[cfg(feature = "debug")]
+            {
+                let mut temp_image_buffer = temp_image.to_rgb()?;
+                mark_region(
+                    &mut temp_image_buffer,
+                    origin,
+                    (width, height),
+                    diag_orientation,
+                );
+                temp_image = I::from_rgb(temp_image_buffer)?;
+            }
         }
 
         Ok(temp_image)
     }
 
-    fn get_blank_mats(&self) -> Result<Vec<ReplacementMat>> {
-        let mut blank_mats: Vec<ReplacementMat> = Vec::new();
+    fn get_blank_regions(
+        &self,
+        cancellation_token: &CancellationToken,
+    ) -> Result<Vec<RenderedRegion>> {
+        let mut blank_regions: Vec<RenderedRegion> = Vec::new();
+        let page = self.original_image.to_rgb()?;
+        let grayscale_page = imageops::grayscale(&page);
+
+        for (index, ((x, y), region)) in self
+            .origins
+            .iter()
+            .zip(self.original_text_regions.iter())
+            .enumerate()
+        {
+            cancellation_token.check()?;
 
-        for ((x, y), region) in self.origins.iter().zip(self.original_text_regions.iter()) {
-            let width = region.cols();
-            let height = region.rows();
+            if self.is_skipped(index) {
+                continue;
+            }
 
-            let ((x, y), _width, _height, diag_orientation) =
-                expand_text_region((*x, *y), width, height, &self.original_image)?;
+            let (width, height) = region.size();
+            let text_area = ((*x, *y), width, height);
 
-            let blank_mat = image_conversion::image_buffer_to_mat(
-                image_conversion::get_blank_buffer(&region)?,
-            )?;
-            blank_mats.push(ReplacementMat {
-                mat: blank_mat,
-                origin: (x, y),
+            let grayscale_region = imageops::grayscale(&region.to_rgb()?);
+
+            // Text over artwork has no bubble to fill, so the art is painted back in where it was
+            if let Some(floating_text) = floating::detect(&grayscale_region) {
+                let inpainted = region.inpaint(&floating_text.mask)?;
+
+                blank_regions.push(RenderedRegion {
+                    clean_quality: clean_quality(&grayscale_page, text_area, &inpainted, (*x, *y)),
+                    image: inpainted,
+                    origin: (*x, *y),
+                    diag: DiagOrientation::TopLeftBottomRight,
+                    index,
+                    overflow: None,
+                });
+                continue;
+            }
+
+            // Dark bubbles are cleaned to black rather than white
+            let (background, _) = polarity::colors(polarity::is_inverted(&grayscale_region));
+
+            let ((expanded_x, expanded_y), expanded_width, expanded_height, diag_orientation) =
+                expand_region_in_buffer((*x, *y), width, height, &page, self.expansion);
+
+            // The blank covers the whole expanded region, since its origin may have moved up and to the left
+            let blank = match self.color_aware {
+                true => self.paint_out_text(
+                    &floating::text_mask(&grayscale_region).0,
+                    (*x, *y),
+                    ((expanded_x, expanded_y), expanded_width, expanded_height),
+                )?,
+                false => {
+                    RgbImage::from_pixel(expanded_width as u32, expanded_height as u32, background)
+                }
+            };
+            blank_regions.push(RenderedRegion {
+                clean_quality: clean_quality(
+                    &grayscale_page,
+                    text_area,
+                    &blank,
+                    (expanded_x, expanded_y),
+                ),
+                image: blank,
+                origin: (expanded_x, expanded_y),
                 diag: diag_orientation,
+                index,
+                overflow: None,
             });
         }
 
-        Ok(blank_mats)
+        Ok(blank_regions)
     }
 
     /**
-     * Takes the stored translated text and writes them onto blank (white) Mats
+     * Takes the stored translated text and writes them onto blank canvases in the background color of each region
+     *
+     * * Regions without a translation are skipped and keep their original contents
+     * * Floating text is written over its inpainted region instead, with an outline
      */
-    fn write_text(&self) -> Result<Vec<ReplacementMat>> {
-        let mut translated_mats: Vec<ReplacementMat> = Vec::new();
-
-        let translated_text = match self.text_pairs {
-            Some(text_map) => text_map
-                .values()
-                .map(|text| text.as_ref())
-                .collect::<Vec<&str>>(),
+    fn write_text(&self, cancellation_token: &CancellationToken) -> Result<Vec<RenderedRegion>> {
+        let mut translated_regions: Vec<RenderedRegion> = Vec::new();
+
+        let translated_text = match self.translations {
+            Some(translations) => translations,
             None => return Err(anyhow!("Translated text is missing")),
         };
 
-        /*
-            We iterate through the different each text region and draw its respective translation
-            onto a blank, white canvas.
-        */
+        let shouts = self.shout_regions()?;
+        let page = self.original_image.to_rgb()?;
+        let grayscale_page = imageops::grayscale(&page);
+
+        // Every region is laid out before any is drawn, so that the size of the translations can be
+        // chosen for the whole page
+        let mut layouts: Vec<RegionLayout> = Vec::new();
+
         for (i, text) in translated_text.iter().enumerate() {
-            let (x, y) = self.origins[i];
-            let region = self.original_text_regions.get(i)?;
+            cancellation_token.check()?;
 
-            let width = region.cols();
-            let height = region.rows();
+            let text = match text {
+                Some(text) if !self.is_skipped(i) => text.as_ref(),
+                _ => continue,
+            };
 
-            let ((x, y), width, height, diag_orientation) =
-                expand_text_region((x, y), width, height, &self.original_image)?;
+            let shout = shouts.get(i).copied().unwrap_or(false);
+            let case = match shout {
+                true => self.shout_style.case(self.case),
+                false => self.case,
+            };
+            let text = case.apply(text);
 
-            let region =
-                core::Mat::roi(&self.original_image, core::Rect2i::new(x, y, width, height))?;
+            let (x, y) = self.origins[i];
+            let region = self
+                .original_text_regions
+                .get(i)
+                .with_context(|| format!("Text region {i} is missing."))?;
+
+            let (width, height) = region.size();
+            let text_area = ((x, y), width, height);
+
+            // Light text on a dark background is typeset the same way
+            let region_image = region.to_rgb()?;
+            let mut grayscale_region = imageops::grayscale(&region_image);
+            let inverted = polarity::is_inverted(&grayscale_region);
+
+            // On color pages, the translation takes the colors of the text and bubble it replaces
+            let text_mask = match self.color_aware {
+                true => Some(floating::text_mask(&grayscale_region).0),
+                false => None,
+            };
+            let palette = text_mask.as_ref().map(|mask| {
+                let (strokes, _) = floating::text_strokes(&grayscale_region);
 
-            // Get blank, white canvas to draw translated text on
-            let mut canvas = image_conversion::get_blank_buffer(&region)?;
-            let (width, height) = canvas.dimensions();
-            let height = height as i32;
+                Palette::sample(&region_image, &strokes, mask)
+            });
+            let (background, text_color) = match palette {
+                Some(palette) => (palette.background, palette.text),
+                None => polarity::colors(inverted),
+            };
 
-            let stop_x = width - (width / 16);
+            // Centroid of the original text on the page
+            let centroid = text_centroid(&grayscale_region, inverted)
+                .map(|(centroid_x, centroid_y)| (x as f32 + centroid_x, y as f32 + centroid_y));
 
-            // Load manga font from assets
-            let font = Vec::from(include_bytes!("../assets/wildwordsroman.ttf") as &[u8]);
-            let font = Font::try_from_vec(font).expect("Could not unwrap Font.");
+            let floating_text = floating::detect(&grayscale_region);
+            let floating = floating_text.is_some();
 
-            let mut curr_line_size = 0;
+            // Floating text is outlined in the opposite color so it reads over any artwork
+            let (text_color, outline) = match (&floating_text, palette) {
+                (Some(_), Some(palette)) => (text_color, Some(palette.outline())),
+                (Some(floating_text), None) => {
+                    let (light, dark) = polarity::colors(false);
 
-            let split_text = text.split(' ');
+                    match floating_text.dark_text {
+                        true => (dark, Some(light)),
+                        false => (light, Some(dark)),
+                    }
+                }
+                (None, _) => (text_color, None),
+            };
 
-            let mut temp_lines: Vec<String> = Vec::new();
+            let skew = match self.deskew {
+                // Turning the canvas of floating text would turn the artwork under it as well
+                true if floating_text.is_none() => {
+                    // The skew is measured on dark text
+                    if inverted {
+                        imageops::invert(&mut grayscale_region);
+                    }
 
-            let num_words = split_text
-                .clone()
-                .map(str::to_string)
-                .collect::<Vec<String>>()
-                .len();
+                    deskew::estimate_skew(&grayscale_region)
+                }
+                _ => 0.0,
+            };
 
-            /*
-                Scaling rules based on width of the region
-                and number of words.
-            */
-            let mut scale = Scale {
-                x: height as f32 / 9.0,
-                y: height as f32 / 12.0,
+            // Floating text is drawn over the inpainted artwork of its own region, since there is no
+            // bubble to grow into. Other text gets a blank canvas in the background color of its
+            // expanded region.
+            let ((x, y), width, height, diag_orientation) = match floating_text {
+                Some(_) => ((x, y), width, height, DiagOrientation::TopLeftBottomRight),
+                None => expand_region_in_buffer((x, y), width, height, &page, self.expansion),
             };
 
-            if width < 55 {
-                scale.x = height as f32 / 8.0;
-                scale.y = height as f32 / 12.0;
-            } else if width < 100 {
-                scale.x = height as f32 / 10.0;
-                scale.y = height as f32 / 14.0;
-            }
-            /*
-            if num_words >= 17 {
-                scale.x = height as f32 / 20.0;
-                scale.y = height as f32 / 23.0;
-            }
-            */
-            /*
-            if num_words >= 15 {
-                scale.x = height as f32 / 18.0;
-                scale.y = height as f32 / 21.0;
-            } else
-            */
-            if num_words >= 16 {
-                scale.x = height as f32 / 14.0;
-                scale.y = height as f32 / 16.0;
-            } else if num_words >= 14 {
-                scale.x = height as f32 / 12.0;
-                scale.y = height as f32 / 14.0;
-            } else if num_words >= 12 {
-                scale.x = height as f32 / 10.0;
-                scale.y = height as f32 / 12.0;
-            } else if num_words >= 10 {
-                scale.x = height as f32 / 8.0;
-                scale.y = height as f32 / 10.0;
-            } else if num_words <= 2 {
-                scale.x = height as f32 / 7.0;
-                scale.y = height as f32 / 9.0;
-            }
+            let canvas = match (&floating_text, &text_mask) {
+                (Some(floating_text), _) => region.inpaint(&floating_text.mask)?,
+                (None, Some(text_mask)) => {
+                    self.paint_out_text(text_mask, text_area.0, ((x, y), width, height))?
+                }
+                (None, None) => RgbImage::from_pixel(width as u32, height as u32, background),
+            };
+            let clean_quality = clean_quality(&grayscale_page, text_area, &canvas, (x, y));
+            let (width, height) = canvas.dimensions();
+            let max_lines = self.max_lines.of_region(shout, floating, height);
+            let height = height as i32;
+            let anchor = centroid.map(|(centroid_x, centroid_y)| {
+                (
+                    (centroid_x - x as f32).round() as i32,
+                    (centroid_y - y as f32).round() as i32,
+                )
+            });
+
+            let scale = initial_scale(&text, width, height);
 
-            let mut curr_line = String::new();
-
-            let width_of_space = drawing::text_size(scale, &font, " ").0;
-
-            // Initially break the text segment into lines that fit within the region
-            for word in split_text {
-                let (text_width, _) = drawing::text_size(scale, &font, word);
-
-                if curr_line_size + text_width + width_of_space
-                    > stop_x as i32 - self.padding as i32
-                {
-                    temp_lines.push(curr_line);
-                    curr_line = String::from(word);
-                    curr_line_size = text_width;
-                } else if temp_lines.is_empty() && curr_line.is_empty() {
-                    curr_line.push_str(word);
-                } else {
-                    curr_line.push(' ');
-                    curr_line.push_str(word);
-                    curr_line_size += width_of_space;
-                    curr_line_size += text_width;
+            layouts.push(RegionLayout {
+                index: i,
+                text,
+                shout,
+                small_caps: case == Case::SmallCaps,
+                origin: (x, y),
+                diag: diag_orientation,
+                canvas,
+                background,
+                text_color,
+                outline,
+                skew,
+                clean_quality,
+                scale,
+                max_lines,
+                anchor,
+            });
+        }
+
+        if self.uniform_font_size {
+            if let Some(scale) = self.uniform_scale(&layouts) {
+                for layout in &mut layouts {
+                    layout.scale = scale;
                 }
             }
+        }
 
-            #[cfg(feature = "debug")]
-            {
-                println!("lines: {temp_lines:?}");
-            }
+        /*
+            We iterate through the different each text region and draw its respective translation
+            onto a blank, white canvas.
+        */
+        for layout in layouts {
+            cancellation_token.check()?;
+
+            let RegionLayout {
+                index: i,
+                text,
+                shout,
+                small_caps,
+                origin: (x, y),
+                diag: diag_orientation,
+                mut canvas,
+                background,
+                text_color,
+                outline,
+                skew,
+                clean_quality,
+                mut scale,
+                max_lines,
+                anchor,
+            } = layout;
 
-            temp_lines.push(curr_line);
-
-            let mut lines: Vec<String> = Vec::new();
-
-            /*
-                Since we sometimes have long words, some lines may still not fit within the region.
-                Now we break up individual words if they are causing their lines to be too long.
-            */
-            for line in temp_lines {
-                let (text_width, _) = drawing::text_size(scale, &font, &line);
-
-                // Check if a line is still too long
-                if text_width > stop_x as i32 - self.padding as i32 {
-                    let num_words = line
-                        .split(' ')
-                        .map(str::to_string)
-                        .collect::<Vec<String>>()
-                        .len();
-
-                    /*
-                        If the line is a single word and it's still too long,
-                        we make a new line at the closest char to the border.
-                        If there are multiple words in the line, we find the
-                        closest word to the border and make a newline there.
-                    */
-                    if num_words == 1 {
-                        let mut chars: Vec<char> = line.chars().collect();
-                        let mut original_line: String = chars.iter().collect();
-                        let mut new_line: Vec<char> = Vec::new();
-
-                        let hypen_width = drawing::text_size(scale, &font, "-").0;
-
-                        while drawing::text_size(scale, &font, &original_line).0 + hypen_width
-                            > stop_x as i32 - self.padding as i32
-                        {
-                            // We move the last char from the original line to the beginning of the new line
-                            new_line.insert(
-                                0,
-                                chars
-                                    .pop()
-                                    .expect("Unexpected error while popping from char vector."),
-                            );
-                            // Rebuild the updated original line for checking.
-                            original_line = chars.iter().collect();
-                        }
+            let (width, height) = canvas.dimensions();
+            let height = height as i32;
 
-                        // Push the updated original line
-                        original_line.push('-');
-                        lines.push(original_line);
+            let font = &self.font;
+            let face = Face { font, small_caps };
 
-                        // Push the new line
-                        if !new_line.is_empty() {
-                            let new_line = new_line.iter().collect();
+            if shout {
+                scale.x *= self.shout_style.scale;
+                scale.y *= self.shout_style.scale;
+            }
 
-                            lines.push(new_line);
-                        }
-                    } else {
-                        let mut words: Vec<String> = line.split(' ').map(str::to_string).collect();
-
-                        let mut original_line = words.join(" ");
-                        let mut new_line: Vec<String> = Vec::new();
-
-                        while drawing::text_size(scale, &font, &original_line).0
-                            > stop_x as i32 - self.padding as i32
-                        {
-                            new_line.insert(
-                                0,
-                                words
-                                    .pop()
-                                    .expect("Unexpected error while popping from word vector."),
-                            );
-
-                            original_line = words.join(" ");
-                        }
+            // Annotations beside the translation get a column of their own on the right of the region
+            let column_width = self.column_width(i, width);
 
-                        // Push the updated original line
-                        lines.push(original_line);
+            let max_line_width = self.max_line_width(i, width);
 
-                        // Push the new line
-                        if !new_line.is_empty() {
-                            lines.push(new_line.join(" "));
-                        }
-                    }
-                } else {
-                    // If the line is fine, append it and continue
-                    if !line.is_empty() {
-                        lines.push(line.to_string());
-                    }
-                }
+            // Text that would be broken into more lines than the region allows is set smaller
+            if let Some(max_lines) = max_lines {
+                scale = fit_lines(
+                    &text,
+                    self.line_breaking,
+                    scale,
+                    face,
+                    max_line_width,
+                    max_lines,
+                );
+            }
+
+            let annotation = self.annotations.get(i).and_then(Option::as_ref);
+
+            // Above and below, the annotation shares the height of the region with the translation,
+            // so both are set smaller together until they fit
+            if let Some(annotation) =
+                annotation.filter(|annotation| annotation.placement != AnnotationPlacement::Beside)
+            {
+                scale = self.fit_stacked(&text, annotation, scale, face, max_line_width, height);
             }
 
-            // Center the text
-            let num_lines = lines.len() as i32;
-            if num_lines != 0 {
-                let first_line_height = drawing::text_size(scale, &font, &lines[0]).1;
-                let mut start_y = (height - (num_lines * first_line_height)) / 2;
+            // Bold is imitated by drawing each line a few times, a pixel further right every time
+            let pen = Pen {
+                scale,
+                face,
+                color: text_color,
+                outline,
+                weight: match shout && self.shout_style.bold {
+                    true => (scale.y / 24.0).max(1.0) as i32,
+                    false => 0,
+                },
+            };
 
-                for line in lines {
-                    let (line_width, line_height) = drawing::text_size(scale, &font, &line);
-                    let start_x = (width as i32 - line_width) / 2;
-                    drawing::draw_text_mut(
+            let lines = break_lines(
+                &text,
+                self.line_breaking,
+                scale,
+                face,
+                max_line_width,
+                self.greedy_wrapping,
+            );
+
+            let text_height = block_height(&lines, scale, face);
+            let required_width = lines
+                .iter()
+                .map(|line| face.text_size(scale, line).0)
+                .max()
+                .unwrap_or(0);
+
+            // Height of the translation along with its annotation
+            let required_height = match annotation {
+                Some(annotation) => {
+                    let annotation_scale = annotation_scale(scale, annotation);
+                    let annotation_width = match annotation.placement {
+                        AnnotationPlacement::Beside => {
+                            column_width - self.inner_margin(width) as i32
+                        }
+                        _ => max_line_width,
+                    };
+                    // Notes are set as they were written
+                    let annotation_face = Face::new(font);
+                    let annotation_lines = wrap_words(
+                        &annotation.text,
+                        self.line_breaking,
+                        annotation_scale,
+                        annotation_face,
+                        annotation_width,
+                    );
+                    let annotation_height =
+                        block_height(&annotation_lines, annotation_scale, annotation_face);
+
+                    let gap = annotation_gap(&lines, &annotation_lines, annotation_scale);
+
+                    // Above and below, both blocks are centered together; beside, each in its own column
+                    let (text_area, text_y, annotation_area, annotation_y) =
+                        match annotation.placement {
+                            // Blocks that don't fit even at the smallest size keep their top on the canvas
+                            AnnotationPlacement::Above => {
+                                let start_y =
+                                    ((height - (annotation_height + gap + text_height)) / 2).max(0);
+
+                                (
+                                    (0, width as i32),
+                                    start_y + annotation_height + gap,
+                                    (0, width as i32),
+                                    start_y,
+                                )
+                            }
+                            AnnotationPlacement::Below => {
+                                let start_y =
+                                    ((height - (text_height + gap + annotation_height)) / 2).max(0);
+
+                                (
+                                    (0, width as i32),
+                                    start_y,
+                                    (0, width as i32),
+                                    start_y + text_height + gap,
+                                )
+                            }
+                            AnnotationPlacement::Beside => {
+                                let text_width = width as i32 - column_width;
+
+                                (
+                                    (0, text_width),
+                                    (height - text_height) / 2,
+                                    (text_width, column_width),
+                                    (height - annotation_height) / 2,
+                                )
+                            }
+                        };
+
+                    let annotation_pen = Pen {
+                        scale: annotation_scale,
+                        face: annotation_face,
+                        weight: 0,
+                        ..pen
+                    };
+
+                    draw_centered_lines(&mut canvas, &lines, text_area, text_y, pen);
+                    draw_centered_lines(
                         &mut canvas,
-                        Rgb([0u8, 0u8, 0u8]),
-                        start_x,
-                        start_y,
-                        scale,
-                        &font,
-                        &line,
+                        &annotation_lines,
+                        annotation_area,
+                        annotation_y,
+                        annotation_pen,
                     );
 
-                    start_y += line_height;
+                    match annotation.placement {
+                        AnnotationPlacement::Beside => text_height.max(annotation_height),
+                        _ => annotation_height + gap + text_height,
+                    }
                 }
+                None => {
+                    draw_block(&mut canvas, &lines, pen, max_line_width, anchor);
+
+                    text_height
+                }
+            };
+
+            let too_many_lines = max_lines.is_some_and(|max_lines| lines.len() > max_lines);
+            let overflow =
+                (required_width > max_line_width || required_height > height || too_many_lines)
+                    .then_some(TextOverflow {
+                        index: i,
+                        required: (required_width, required_height),
+                        available: (max_line_width, height),
+                        lines: lines.len(),
+                        max_lines,
+                    });
+
+            // Translations that don't fit are framed in red
+            #[cfg(feature = "debug")]
+            if overflow.is_some() {
+                drawing::draw_hollow_rect_mut(
+                    &mut canvas,
+                    Rect::at(0, 0).of_size(width, height as u32),
+                    Rgb([255, 0, 0]),
+                );
+            }
+
+            // The text was laid out straight, so it is turned to match the original text's skew
+            if skew != 0.0 {
+                canvas = deskew::rotate(&canvas, skew, background);
             }
 
-            translated_mats.push(ReplacementMat {
-                mat: image_conversion::image_buffer_to_mat(canvas)?,
+            translated_regions.push(RenderedRegion {
+                image: canvas,
                 origin: (x, y),
                 diag: diag_orientation,
+                index: i,
+                clean_quality,
+                overflow,
             });
         }
 
-        Ok(translated_mats)
+        Ok(translated_regions)
     }
-}
-
-/**
- * Expands a text region to fit a text bubble
- *
- * * Returns new (x, y) coordinates for the region origin, width/height, and which diagonal was chosen to expand to
- */
-fn expand_text_region(
-    (tl_x, tl_y): Coordinates,
-    old_width: Width,
-    old_height: Height,
-    original: &core::Mat,
-) -> Result<(Coordinates, Width, Height, DiagOrientation)> {
-    let (mut tl_x, mut tl_y) = (tl_x as u32, tl_y as u32);
-    let old_width = old_width as u32;
-    let old_height = old_height as u32;
-
-    let image_buffer = image_conversion::mat_to_image_buffer(original)?;
-    let (mut tr_x, mut tr_y) = (tl_x + old_width, tl_y);
-    let (mut bl_x, mut bl_y) = (tl_x, tl_y + old_height);
-    let (mut br_x, mut br_y) = (bl_x + old_width, bl_y);
-
-    let mut tl_length = 0;
-    let mut tr_length = 0;
-    let mut bl_length = 0;
-    let mut br_length = 0;
-
-    // Expand the top left corner
-    let ori_pixel = image_buffer.get_pixel(tl_x, tl_y);
-    while tl_x - 1 > 0 && tl_y - 1 > 0 {
-        if let Some(pixel) = image_buffer.get_pixel_checked(tl_x - 1, tl_y - 1) {
-            if pixel != ori_pixel {
-                break;
-            }
 
-            tl_x -= 1;
-            tl_y -= 1;
-            tl_length += 1;
-        } else {
-            break;
+    // Largest scale, at most "scale", at which a translation and an annotation set above or below it
+    // fit in "max_height" together. Pairs that don't fit even at "MIN_FONT_SIZE" get that size.
+    fn fit_stacked(
+        &self,
+        text: &str,
+        annotation: &Annotation,
+        scale: Scale,
+        face: Face,
+        max_line_width: i32,
+        max_height: i32,
+    ) -> Scale {
+        let mut scale = scale;
+
+        while scale.y > MIN_FONT_SIZE
+            && self.stacked_height(text, annotation, scale, face, max_line_width) > max_height
+        {
+            scale = scale_by(scale, FIT_STEP);
         }
+
+        scale
     }
 
-    // Expand the top right corner
-    while tr_x < old_width && tr_y > 0 {
-        if let Some(pixel) = image_buffer.get_pixel_checked(tr_x + 1, tr_y - 1) {
-            if pixel != ori_pixel {
-                break;
-            }
+    // Height of a translation together with an annotation set above or below it, at the scale of
+    // the translation
+    fn stacked_height(
+        &self,
+        text: &str,
+        annotation: &Annotation,
+        scale: Scale,
+        face: Face,
+        max_line_width: i32,
+    ) -> i32 {
+        let lines = break_lines(
+            text,
+            self.line_breaking,
+            scale,
+            face,
+            max_line_width,
+            self.greedy_wrapping,
+        );
 
-            tr_x += 1;
-            tr_y -= 1;
-            tr_length += 1;
-        } else {
-            break;
-        }
+        let annotation_scale = annotation_scale(scale, annotation);
+        let annotation_face = Face::new(&self.font);
+        let annotation_lines = wrap_words(
+            &annotation.text,
+            self.line_breaking,
+            annotation_scale,
+            annotation_face,
+            max_line_width,
+        );
+
+        block_height(&lines, scale, face)
+            + annotation_gap(&lines, &annotation_lines, annotation_scale)
+            + block_height(&annotation_lines, annotation_scale, annotation_face)
     }
 
-    // Expand the bottom left corner
-    while bl_x > 0 && bl_y + 1 < old_height {
-        if let Some(pixel) = image_buffer.get_pixel_checked(bl_x - 1, bl_y + 1) {
-            if pixel != ori_pixel {
-                break;
-            }
+    // Scale of the translations of every region on the page: the smallest one that a region's
+    // translation fits in, but no smaller than "MIN_FONT_SIZE", so that a single crowded bubble
+    // doesn't make the whole page unreadable
+    fn uniform_scale(&self, layouts: &[RegionLayout]) -> Option<Scale> {
+        layouts
+            .iter()
+            .map(|layout| {
+                let (width, height) = layout.canvas.dimensions();
+                // Shouts are fitted in the size they are set in
+                let shout_scale = match layout.shout {
+                    true => self.shout_style.scale,
+                    false => 1.0,
+                };
+
+                let face = Face {
+                    font: &self.font,
+                    small_caps: layout.small_caps,
+                };
+                let scale = fit_scale(
+                    &layout.text,
+                    self.line_breaking,
+                    scale_by(layout.scale, shout_scale),
+                    face,
+                    self.max_line_width(layout.index, width),
+                    height as i32,
+                );
 
-            bl_x -= 1;
-            bl_y += 1;
-            bl_length += 1;
-        } else {
-            break;
-        }
+                scale_by(scale, 1.0 / shout_scale)
+            })
+            .min_by(|a, b| a.y.total_cmp(&b.y))
+            .map(|scale| match scale.y < MIN_FONT_SIZE {
+                true => scale_by(scale, MIN_FONT_SIZE / scale.y),
+                false => scale,
+            })
     }
 
-    // Expand the bottom right corner
-    while br_x + 1 < old_width && br_y + 1 < old_height {
-        if let Some(pixel) = image_buffer.get_pixel_checked(br_x + 1, br_y + 1) {
-            if pixel != ori_pixel {
-                break;
+    // The expanded area of a text region with the pixels under the region's text mask painted in from
+    // the bubble around them
+    fn paint_out_text(
+        &self,
+        text_mask: &GrayImage,
+        (text_x, text_y): Coordinates,
+        ((x, y), width, height): (Coordinates, Width, Height),
+    ) -> Result<RgbImage> {
+        let mut mask = GrayImage::new(width as u32, height as u32);
+        imageops::replace(
+            &mut mask,
+            text_mask,
+            (text_x - x) as i64,
+            (text_y - y) as i64,
+        );
+
+        self.original_image
+            .crop(x, y, width, height)?
+            .inpaint(&mask)
+    }
+
+    // Flags the regions whose source text is set much larger than the rest of the page
+    fn shout_regions(&self) -> Result<Vec<bool>> {
+        if self.shout_style.min_glyph_ratio <= 0.0 {
+            return Ok(Vec::new());
+        }
+
+        let mut glyph_sizes = Vec::new();
+
+        for region in &self.original_text_regions {
+            let mut grayscale_region = imageops::grayscale(&region.to_rgb()?);
+
+            // Glyphs are measured on dark text
+            if polarity::is_inverted(&grayscale_region) {
+                imageops::invert(&mut grayscale_region);
             }
 
-            br_x += 1;
-            br_y += 1;
-            br_length += 1;
-        } else {
-            break;
+            glyph_sizes.push(style::glyph_size(&grayscale_region));
         }
+
+        Ok(style::shout_regions(
+            &glyph_sizes,
+            self.shout_style.min_glyph_ratio,
+        ))
     }
+}
 
-    let new_width;
-    let new_height;
-    let mut diag_orientation = DiagOrientation::TopLeftBottomRight;
+// Scale of an annotation, relative to the scale the translation of its region is set in
+fn annotation_scale(scale: Scale, annotation: &Annotation) -> Scale {
+    // Tiny notes can't be read and notes larger than the translation would be mistaken for it
+    let factor = annotation.scale.clamp(10, 100) as f32 / 100.0;
 
-    // Determine which pair of opposite corners is smaller
-    if (tl_length + br_length) < (bl_length + tr_length) {
-        new_width = br_x - tl_x;
-        new_height = br_y - tl_y;
-    } else {
-        new_width = tr_x - bl_x;
-        new_height = bl_y - tr_y;
+    Scale {
+        x: scale.x * factor,
+        y: scale.y * factor,
+    }
+}
 
-        tl_x = tr_x - new_width;
-        tl_y = tr_y;
-        diag_orientation = DiagOrientation::TopRightBottomLeft;
+// Half a line of the annotation separates it from the translation
+fn annotation_gap(lines: &[String], annotation_lines: &[String], annotation_scale: Scale) -> i32 {
+    match lines.is_empty() || annotation_lines.is_empty() {
+        true => 0,
+        false => annotation_scale.y as i32 / 2,
     }
+}
+
+// Scores how well a canvas pasted at "origin" cleans the text region it replaces, see
+// "quality::clean_quality"
+fn clean_quality(
+    page: &GrayImage,
+    ((text_x, text_y), text_width, text_height): (Coordinates, Width, Height),
+    canvas: &RgbImage,
+    (x, y): Coordinates,
+) -> u8 {
+    quality::clean_quality(
+        page,
+        (
+            text_x as u32,
+            text_y as u32,
+            text_width as u32,
+            text_height as u32,
+        ),
+        &imageops::grayscale(canvas),
+        (x as u32, y as u32),
+    )
+}
+
+// Area of the bubble around a text region of a page, as far as cleaning and typesetting would expand it
+pub fn bubble_area(
+    origin: Coordinates,
+    width: Width,
+    height: Height,
+    page: &RgbImage,
+    expansion: Expansion,
+) -> (Coordinates, Width, Height) {
+    let (origin, width, height, _) =
+        expand_region_in_buffer(origin, width, height, page, expansion);
+
+    (origin, width, height)
+}
+
+// Walks each corner of the region outwards along its diagonal for as long as the pixels match the
+// region's top left pixel, then keeps the pair of opposite corners that moved the least
+fn expand_region_in_buffer(
+    (x, y): Coordinates,
+    old_width: Width,
+    old_height: Height,
+    image_buffer: &RgbImage,
+    expansion: Expansion,
+) -> (Coordinates, Width, Height, DiagOrientation) {
+    let (x, y) = (x as i64, y as i64);
+    let (old_width, old_height) = (old_width as i64, old_height as i64);
+
+    let ori_pixel = *image_buffer.get_pixel(x as u32, y as u32);
+
+    let tl_length = walk_diagonal(image_buffer, (x, y), (-1, -1), ori_pixel, expansion);
+    let tr_length = walk_diagonal(
+        image_buffer,
+        (x + old_width, y),
+        (1, -1),
+        ori_pixel,
+        expansion,
+    );
+    let bl_length = walk_diagonal(
+        image_buffer,
+        (x, y + old_height),
+        (-1, 1),
+        ori_pixel,
+        expansion,
+    );
+    let br_length = walk_diagonal(
+        image_buffer,
+        (x + old_width, y + old_height),
+        (1, 1),
+        ori_pixel,
+        expansion,
+    );
+
+    // Determine which pair of opposite corners is smaller
+    let (origin, new_width, new_height, diag_orientation) =
+        if (tl_length + br_length) < (bl_length + tr_length) {
+            (
+                (x - tl_length, y - tl_length),
+                old_width + tl_length + br_length,
+                old_height + tl_length + br_length,
+                DiagOrientation::TopLeftBottomRight,
+            )
+        } else {
+            (
+                (x - bl_length, y - tr_length),
+                old_width + bl_length + tr_length,
+                old_height + bl_length + tr_length,
+                DiagOrientation::TopRightBottomLeft,
+            )
+        };
 
-    Ok((
-        (tl_x as i32, tl_y as i32),
+    (
+        (origin.0 as i32, origin.1 as i32),
         new_width as i32,
         new_height as i32,
         diag_orientation,
-    ))
+    )
+}
+
+// Number of steps a corner can take in the given direction before leaving the page, reaching a pixel
+// that differs from the reference by more than the tolerance, or hitting the expansion cap
+fn walk_diagonal(
+    image_buffer: &RgbImage,
+    (x, y): (i64, i64),
+    (step_x, step_y): (i64, i64),
+    reference: Rgb<u8>,
+    expansion: Expansion,
+) -> i64 {
+    let mut length = 0;
+
+    while expansion
+        .max_distance
+        .is_none_or(|max_distance| length < max_distance as i64)
+    {
+        let (next_x, next_y) = (x + step_x * (length + 1), y + step_y * (length + 1));
+
+        if next_x < 0 || next_y < 0 {
+            break;
+        }
+
+        match image_buffer.get_pixel_checked(next_x as u32, next_y as u32) {
+            Some(pixel) if expansion.matches(*pixel, reference) => length += 1,
+            _ => break,
+        }
+    }
+
+    length
 }
 
-/**
- * Replaces a image region within the background image
- *
- * @param background The background image that the region comes from
- * @param region The replacement image region
- * @param (x, y) The coordinates for the image region in the background image
- */
-#[allow(unused_variables)]
-fn replace_region(
-    background: &core::Mat,
-    region: core::Mat,
+// Frames a pasted region in green and marks the corners it was expanded to in red
+#[cfg(feature = "debug")]
+fn mark_region(
+    page: &mut RgbImage,
     (x, y): Coordinates,
+    (width, height): (u32, u32),
     diag_orientation: DiagOrientation,
-) -> Result<core::Mat> {
-    let mut temp_image = core::Mat::copy(background)?;
-    let full_width = background.cols();
-    let full_height = background.rows();
-    let width = region.cols();
-    let height = region.rows();
-
-    // Establish origins for the four panels
-    let (left_panel_x, left_panel_y) = (0, 0);
-    let (top_panel_x, top_panel_y) = (x, 0);
-    let (bottom_panel_x, bottom_panel_y) = (x, y + height);
-    let (right_panel_x, right_panel_y) = (x + width, 0);
-
-    // Establish dimensions for the four panels
-    let (left_panel_width, left_panel_height) = (x, full_height);
-    let (top_panel_width, top_panel_height) = (width, y);
-    let (bottom_panel_width, bottom_panel_height) = (width, full_height - (y + height));
-    let (right_panel_width, right_panel_height) = (full_width - (x + width), full_height);
-
-    let left_panel = core::Mat::roi(
-        &temp_image,
-        core::Rect2i::new(
-            left_panel_x,
-            left_panel_y,
-            left_panel_width,
-            left_panel_height,
-        ),
-    )?;
-
-    let top_panel = core::Mat::roi(
-        &temp_image,
-        core::Rect2i::new(top_panel_x, top_panel_y, top_panel_width, top_panel_height),
-    )?;
-
-    let bottom_panel = core::Mat::roi(
-        &temp_image,
-        core::Rect2i::new(
-            bottom_panel_x,
-            bottom_panel_y,
-            bottom_panel_width,
-            bottom_panel_height,
-        ),
-    )?;
-
-    let right_panel = core::Mat::roi(
-        &temp_image,
-        core::Rect2i::new(
-            right_panel_x,
-            right_panel_y,
-            right_panel_width,
-            right_panel_height,
-        ),
-    )?;
+) {
+    drawing::draw_hollow_rect_mut(
+        page,
+        Rect::at(x, y).of_size(width, height),
+        Rgb([0, 255, 0]),
+    );
+
+    let corners = match diag_orientation {
+        DiagOrientation::TopLeftBottomRight => [(x, y), (x + width as i32, y + height as i32)],
+        DiagOrientation::TopRightBottomLeft => [(x + width as i32, y), (x, y + height as i32)],
+    };
+
+    for corner in corners {
+        drawing::draw_filled_circle_mut(page, corner, 5, Rgb([255, 0, 0]));
+    }
+}
 
-    let mut vertical_panels_vec: core::Vector<core::Mat> = core::Vector::new();
+#[cfg(test)]
+mod tests {
+    use crate::extraction::{Annotation, AnnotationPlacement};
+    use crate::fonts::bundled_font;
+    use crate::layout::{block_height, break_lines, wrap_words, Face};
+    use crate::replacer::{
+        annotation_scale, expand_region_in_buffer, DiagOrientation, Expansion, InnerMargin,
+        Replacer,
+    };
+    use crate::text_box::TextBox;
+    use crate::utils::cancellation::CancellationToken;
+    use crate::utils::linebreak::LineBreaking;
+    use image::{Rgb, RgbImage};
+    use rusttype::Scale;
+
+    // A noisy white bubble spanning (20, 20) to (80, 80) on a black page
+    fn bubble() -> RgbImage {
+        RgbImage::from_fn(100, 100, |x, y| {
+            if (20..80).contains(&x) && (20..80).contains(&y) {
+                Rgb([250 - ((x + 2 * y) % 5) as u8; 3])
+            } else {
+                Rgb([0, 0, 0])
+            }
+        })
+    }
 
-    let mut vertical_panel: core::Mat = core::Mat::default();
+    #[test]
+    fn test_expansion_tolerance() {
+        let image = bubble();
 
-    vertical_panels_vec.push(top_panel);
-    vertical_panels_vec.push(region);
-    vertical_panels_vec.push(bottom_panel);
+        let exact = expand_region_in_buffer((40, 40), 20, 20, &image, Expansion::default());
+        assert_eq!((exact.0, exact.1, exact.2), ((40, 40), 20, 20));
 
-    core::vconcat(&vertical_panels_vec, &mut vertical_panel)?;
+        let tolerant = Expansion {
+            tolerance: 8,
+            max_distance: None,
+        };
+        let (origin, width, height, diag_orientation) =
+            expand_region_in_buffer((40, 40), 20, 20, &image, tolerant);
+
+        assert_eq!((origin, width, height), ((21, 21), 58, 58));
+        assert!(matches!(
+            diag_orientation,
+            DiagOrientation::TopRightBottomLeft
+        ));
+
+        let capped = Expansion {
+            tolerance: 8,
+            max_distance: Some(5),
+        };
+        let (origin, width, height, _) = expand_region_in_buffer((40, 40), 20, 20, &image, capped);
 
-    let mut horizontal_panels_vec: core::Vector<core::Mat> = core::Vector::new();
+        assert_eq!((origin, width, height), ((35, 35), 30, 30));
+    }
 
-    let mut result: core::Mat = core::Mat::default();
+    #[test]
+    fn test_annotation_layout() {
+        let font = bundled_font();
+        let scale = Scale::uniform(20.0);
 
-    horizontal_panels_vec.push(left_panel);
-    horizontal_panels_vec.push(vertical_panel);
-    horizontal_panels_vec.push(right_panel);
+        let annotation = Annotation {
+            text: "an older schoolmate".to_string(),
+            placement: AnnotationPlacement::Above,
+            scale: 50,
+        };
+        assert_eq!(annotation_scale(scale, &annotation), Scale::uniform(10.0));
 
-    core::hconcat(&horizontal_panels_vec, &mut result)?;
+        // Scales outside of 10% to 100% are clamped
+        let oversized = Annotation {
+            scale: 250,
+            ..annotation.clone()
+        };
+        assert_eq!(annotation_scale(scale, &oversized), scale);
+
+        let wide = wrap_words(
+            &annotation.text,
+            LineBreaking::Words,
+            scale,
+            Face::new(&font),
+            1000,
+        );
+        assert_eq!(wide, vec!["an older schoolmate"]);
+
+        // Every word gets a line of its own when no two fit together, even if a word alone is too wide
+        let narrow = wrap_words(
+            "an  older\nschoolmate",
+            LineBreaking::Words,
+            scale,
+            Face::new(&font),
+            1,
+        );
+        assert_eq!(narrow, vec!["an", "older", "schoolmate"]);
 
-    temp_image = result;
+        assert!(wrap_words(" ", LineBreaking::Words, scale, Face::new(&font), 100).is_empty());
+    }
 
-    #[cfg(feature = "debug")]
-    {
-        use imageproc::rect::Rect;
+    #[test]
+    fn test_stacked_annotation_fit() {
+        let page = RgbImage::from_pixel(200, 200, Rgb([255, 255, 255]));
+        let boxes = [TextBox {
+            x: 0,
+            y: 0,
+            width: 200,
+            height: 200,
+            score: None,
+        }];
+        let replacer: Replacer<'_, &str, RgbImage> =
+            Replacer::from_boxes(page, &boxes, None, 0, Expansion::default(), false).unwrap();
+        let face = Face::new(&replacer.font);
+
+        let annotation = Annotation {
+            text: "an older schoolmate who looks after the younger ones".to_string(),
+            placement: AnnotationPlacement::Below,
+            scale: 80,
+        };
+        let text = "I never thought it would come to this";
+        let scale = Scale::uniform(20.0);
+
+        // The translation alone fits in 100 pixels, but not along with its annotation
+        assert!(
+            block_height(
+                &break_lines(text, LineBreaking::Words, scale, face, 180, false),
+                scale,
+                face
+            ) <= 100
+        );
+        assert!(replacer.stacked_height(text, &annotation, scale, face, 180) > 100);
 
-        let mut temp_image_buffer = image_conversion::mat_to_image_buffer(&temp_image)?;
+        let fitted = replacer.fit_stacked(text, &annotation, scale, face, 180, 100);
+        assert!(fitted.y < scale.y);
+        assert!(replacer.stacked_height(text, &annotation, fitted, face, 180) <= 100);
 
-        drawing::draw_hollow_rect_mut(
-            &mut temp_image_buffer,
-            Rect::at(x, y).of_size(width as u32, height as u32),
-            Rgb([0, 255, 0]),
+        // Pairs that fit keep their size
+        assert_eq!(
+            replacer.fit_stacked(text, &annotation, scale, face, 180, 1000),
+            scale
         );
+    }
 
-        match diag_orientation {
-            DiagOrientation::TopLeftBottomRight => {
-                drawing::draw_filled_circle_mut(
-                    &mut temp_image_buffer,
-                    (x, y),
-                    5,
-                    Rgb([255, 0, 0]),
-                );
+    #[test]
+    fn test_inner_margin() {
+        let margin = |margin: &str| margin.parse::<InnerMargin>();
 
-                drawing::draw_filled_circle_mut(
-                    &mut temp_image_buffer,
-                    (x + width as i32, y + height as i32),
-                    5,
-                    Rgb([255, 0, 0]),
-                );
-            }
-            DiagOrientation::TopRightBottomLeft => {
-                drawing::draw_filled_circle_mut(
-                    &mut temp_image_buffer,
-                    (x + width as i32, y),
-                    5,
-                    Rgb([255, 0, 0]),
-                );
-                drawing::draw_filled_circle_mut(
-                    &mut temp_image_buffer,
-                    (x, y + height as i32),
-                    5,
-                    Rgb([255, 0, 0]),
-                );
-            }
-        }
+        assert_eq!(margin("12").unwrap(), InnerMargin::Pixels(12));
+        assert_eq!(margin(" 7.5% ").unwrap(), InnerMargin::Percent(7.5));
+        assert!(margin("50%").is_err());
+        assert!(margin("-3").is_err());
+        assert!(margin("wide").is_err());
+
+        // Both sides of the region have the margin, and it never takes more than the whole region
+        assert_eq!(InnerMargin::Pixels(12).total(200), 24);
+        assert_eq!(InnerMargin::Percent(7.5).total(200), 30);
+        assert_eq!(InnerMargin::Pixels(120).total(200), 200);
+    }
 
-        temp_image = image_conversion::image_buffer_to_mat(temp_image_buffer)?;
+    #[test]
+    fn test_rgb_image_replacement() {
+        // Black strokes in a white bubble
+        let page = RgbImage::from_fn(120, 80, |x, y| {
+            match (40..80).contains(&x) && (30..50).contains(&y) && x % 4 == 0 {
+                true => Rgb([0, 0, 0]),
+                false => Rgb([255, 255, 255]),
+            }
+        });
+        let boxes = [TextBox {
+            x: 36,
+            y: 26,
+            width: 48,
+            height: 28,
+            score: None,
+        }];
+        let cancellation_token = CancellationToken::new();
+
+        let replacer: Replacer<'_, &str, RgbImage> =
+            Replacer::from_boxes(page.clone(), &boxes, None, 0, Expansion::default(), false)
+                .unwrap();
+        let cleaned = replacer.clean_page(&cancellation_token).unwrap();
+        assert_eq!(cleaned.dimensions(), page.dimensions());
+        assert!(cleaned.pixels().all(|pixel| *pixel == Rgb([255, 255, 255])));
+
+        let translations = [Some("Hey!")];
+        let replacer = Replacer::from_boxes(
+            page.clone(),
+            &boxes,
+            Some(translations.as_slice()),
+            0,
+            Expansion::default(),
+            false,
+        )
+        .unwrap();
+        let typeset = replacer.replace_text_regions(&cancellation_token).unwrap();
+        let inked = |x: u32, y: u32| typeset.get_pixel(x, y).0[0] < 128;
+        assert!((36..84).any(|x| (26..54).any(|y| inked(x, y))));
+        assert!(!(0..120).any(|x| (0..20).any(|y| inked(x, y))));
+
+        // Boxes that don't lie within the page can't be cropped
+        let outside = [TextBox { x: 100, ..boxes[0] }];
+        assert!(Replacer::<&str, RgbImage>::from_boxes(
+            page,
+            &outside,
+            None,
+            0,
+            Expansion::default(),
+            false
+        )
+        .is_err());
     }
 
-    Ok(temp_image)
+    #[test]
+    fn test_color_aware_replacement() {
+        // Dark blue strokes in a bubble shaded from pink to white
+        let background = |x: u32| Rgb([255, 100 + x as u8, 200]);
+        let page = RgbImage::from_fn(120, 80, |x, y| {
+            match (40..80).contains(&x) && (30..50).contains(&y) && x % 4 == 0 {
+                true => Rgb([20, 20, 90]),
+                false => background(x),
+            }
+        });
+        let boxes = [TextBox {
+            x: 36,
+            y: 26,
+            width: 48,
+            height: 28,
+            score: None,
+        }];
+        let cancellation_token = CancellationToken::new();
+
+        // The text is painted out and the shading is kept, within a few levels where the strokes
+        // were thickest
+        let replacer: Replacer<'_, &str, RgbImage> =
+            Replacer::from_boxes(page.clone(), &boxes, None, 0, Expansion::default(), false)
+                .unwrap()
+                .with_color_aware();
+        let cleaned = replacer.clean_page(&cancellation_token).unwrap();
+        assert!(cleaned.enumerate_pixels().all(|(x, _, pixel)| {
+            pixel
+                .0
+                .iter()
+                .zip(background(x).0)
+                .all(|(channel, expected)| channel.abs_diff(expected) <= 12)
+        }));
+
+        // The translation is set in the color of the text it replaces, which is bluer than the bubble
+        // and never as dark as black
+        let translations = [Some("Hey!")];
+        let replacer = Replacer::from_boxes(
+            page,
+            &boxes,
+            Some(translations.as_slice()),
+            0,
+            Expansion::default(),
+            false,
+        )
+        .unwrap()
+        .with_color_aware();
+        let typeset = replacer.replace_text_regions(&cancellation_token).unwrap();
+        assert!(typeset.pixels().any(|pixel| pixel.0[2] > pixel.0[0]));
+        assert!(typeset.pixels().all(|pixel| pixel.0[0] >= 20));
+    }
 }
@@ -0,0 +1,304 @@
+use crate::detection::{
+    Detector, Device, DnnBackend, DnnOptions, DnnTarget, InferenceEngine, TextBox, MIN_SCORE,
+};
+use crate::export::annotations::{self, AnnotationFormat};
+use crate::extraction::{Extraction, PageSize};
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::{image_conversion, validation};
+use anyhow::{bail, ensure, Context, Result};
+use image::GenericImageView;
+use serde::Serialize;
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+// Boxes the model scored less than this far above the lowest score it keeps are ones it was unsure of
+const NEAR_THRESHOLD_MARGIN: u8 = 20;
+
+// How informative labeling a page would be for the detection model
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct PageUncertainty {
+    pub page: String,
+    pub boxes: usize,
+    // Boxes scored close to the lowest score the model keeps
+    pub near_threshold: usize,
+    // Candidate boxes merged away by non-maximum suppression
+    pub suppressed: usize,
+    pub score: f64,
+}
+
+// Scores a page by the boxes the model was unsure of: each box near the threshold counts fully, and
+// every box counts as much as the share of candidates that were suppressed, since heavy suppression
+// means the model proposed many competing boxes for the same text. Unscored boxes are left out.
+pub fn uncertainty(page: &str, boxes: &[TextBox], num_candidates: usize) -> PageUncertainty {
+    let near_threshold = boxes
+        .iter()
+        .filter_map(|text_box| text_box.score)
+        .filter(|score| *score < MIN_SCORE.saturating_add(NEAR_THRESHOLD_MARGIN))
+        .count();
+
+    let suppressed = num_candidates.saturating_sub(boxes.len());
+    let suppression = match num_candidates {
+        0 => 0.0,
+        candidates => suppressed as f64 / candidates as f64,
+    };
+
+    PageUncertainty {
+        page: page.to_string(),
+        boxes: boxes.len(),
+        near_threshold,
+        suppressed,
+        score: near_threshold as f64 + boxes.len() as f64 * suppression,
+    }
+}
+
+// Indices of the "count" most uncertain pages, most uncertain first. Of pages with the same score,
+// the earlier ones come first.
+pub fn most_uncertain(pages: &[PageUncertainty], count: usize) -> Vec<usize> {
+    let mut ranking: Vec<usize> = (0..pages.len()).collect();
+    ranking.sort_by(|a, b| pages[*b].score.total_cmp(&pages[*a].score));
+    ranking.truncate(count);
+
+    ranking
+}
+
+#[derive(Serialize)]
+struct SelectionIndex<'a> {
+    pages: Vec<&'a PageUncertainty>,
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SelectArgs {
+    #[arg(short, long, help = "Directory of pages to select from")]
+    pub input: PathBuf,
+    #[arg(
+        short,
+        long,
+        help = "Directory the selected pages are copied into, along with selection.json"
+    )]
+    pub output: PathBuf,
+    #[arg(
+        short,
+        long,
+        help = "Path to the YOLOv5, YOLOv8 or YOLOv11 detection weights (ONNX format)"
+    )]
+    pub model: PathBuf,
+    #[arg(
+        short = 'n',
+        long,
+        default_value_t = 50,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of pages to select"
+    )]
+    pub count: u32,
+    #[arg(
+        long,
+        value_enum,
+        help = "[Optional] Write the detected boxes of the selected pages as annotations in the given format, to correct instead of labeling from scratch"
+    )]
+    pub annotations: Option<AnnotationFormat>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = InferenceEngine::Opencv,
+        help = "Runtime the detection model is run with. ONNX Runtime (ort) loads models that OpenCV can't and is usually faster on the CPU, but needs mangatra built with the \"ort\" feature. --dnn-backend and --dnn-target only apply to OpenCV"
+    )]
+    pub inference_engine: InferenceEngine,
+    #[arg(
+        long,
+        value_enum,
+        conflicts_with_all = ["dnn_backend", "dnn_target"],
+        help = "Device the detection model runs on, instead of picking --dnn-backend and --dnn-target. Running on CUDA needs OpenCV or ONNX Runtime built with CUDA, and the model runs on the CPU where it isn't available"
+    )]
+    pub device: Option<Device>,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnBackend::Default,
+        help = "Backend OpenCV runs the detection model with"
+    )]
+    pub dnn_backend: DnnBackend,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = DnnTarget::Cpu,
+        help = "Device OpenCV runs the detection model on"
+    )]
+    pub dnn_target: DnnTarget,
+    #[arg(
+        long,
+        help = "Run the detection model with TensorRT, which needs --inference-engine ort and mangatra built with the \"ort-tensorrt\" feature. The engines TensorRT builds from the model are kept in this directory, so only the first run spends minutes building them"
+    )]
+    pub tensorrt_cache: Option<PathBuf>,
+}
+
+impl SelectArgs {
+    pub fn run(self) -> Result<()> {
+        validation::validate_model(&self.model)?;
+
+        ensure!(
+            self.input.is_dir(),
+            "{} is not a directory of pages.",
+            self.input.display()
+        );
+        let pages = validation::images_in_directory(&self.input)?;
+        ensure!(
+            !pages.is_empty(),
+            "There are no pages to select from in {}.",
+            self.input.display()
+        );
+
+        let model_path = match self.model.to_str() {
+            Some(model_path) => model_path,
+            None => bail!("The model path must be UTF-8 compatible."),
+        };
+
+        // Labels need the boxes as the model found them, so they aren't padded
+        let mut detector = Detector::new(
+            model_path,
+            0,
+            DnnOptions::new(
+                self.inference_engine,
+                self.device,
+                self.dnn_backend,
+                self.dnn_target,
+                self.tensorrt_cache.clone(),
+            ),
+        )?;
+        detector.validate()?;
+
+        let cancellation_token = CancellationToken::new();
+
+        let mut scanned = Vec::new();
+        let mut uncertainties = Vec::new();
+
+        for page in pages.iter() {
+            match detect_page(&mut detector, page, &cancellation_token) {
+                Ok((boxes, size, num_candidates)) => {
+                    uncertainties.push(uncertainty(&file_name(page), &boxes, num_candidates));
+                    scanned.push((page, boxes, size));
+                }
+                Err(error) => warn!("Skipping {}: {error:#}", page.display()),
+            }
+        }
+        ensure!(
+            !scanned.is_empty(),
+            "None of the pages in {} could be read.",
+            self.input.display()
+        );
+
+        std::fs::create_dir_all(&self.output).with_context(|| {
+            format!("Could not create the directory {}.", self.output.display())
+        })?;
+
+        let selected = most_uncertain(&uncertainties, self.count as usize);
+        let mut labels = Vec::new();
+
+        for index in selected.iter() {
+            let (page, boxes, size) = &scanned[*index];
+            let name = file_name(page);
+
+            std::fs::copy(page, self.output.join(&name))
+                .with_context(|| format!("Could not copy {}.", page.display()))?;
+
+            let stem = Path::new(&name)
+                .file_stem()
+                .and_then(|stem| stem.to_str())
+                .unwrap_or(&name);
+            let extraction =
+                Extraction::new(stem, vec![String::new(); boxes.len()]).with_geometry(*size, boxes);
+
+            labels.push((extraction, name));
+        }
+
+        let index = SelectionIndex {
+            pages: selected
+                .iter()
+                .map(|index| &uncertainties[*index])
+                .collect(),
+        };
+        std::fs::write(
+            self.output.join("selection.json"),
+            serde_json::to_string_pretty(&index)?,
+        )?;
+
+        if let Some(format) = self.annotations {
+            // In the same directory as the pages, where labeling tools look for them
+            annotations::write_annotations(format, &self.output, &labels, 0)?;
+        }
+
+        println!(
+            "Selected {} of {} pages into {}",
+            selected.len(),
+            pages.len(),
+            self.output.display()
+        );
+
+        Ok(())
+    }
+}
+
+fn detect_page(
+    detector: &mut Detector,
+    page: &Path,
+    cancellation_token: &CancellationToken,
+) -> Result<(Vec<TextBox>, PageSize, usize)> {
+    let image = image_conversion::open_image(page)?;
+    let size = PageSize {
+        width: image.width(),
+        height: image.height(),
+    };
+    let image = image_conversion::dynamic_image_to_mat(&image)?;
+
+    let (boxes, num_candidates) = detector.detect_with_candidates(&image, cancellation_token)?;
+
+    Ok((boxes, size, num_candidates))
+}
+
+fn file_name(page: &Path) -> String {
+    page.file_name()
+        .map(|name| name.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detection::TextBox;
+    use crate::selection::{most_uncertain, uncertainty};
+
+    fn boxes(scores: &[u8]) -> Vec<TextBox> {
+        scores
+            .iter()
+            .map(|score| TextBox {
+                x: 0,
+                y: 0,
+                width: 10,
+                height: 10,
+                score: Some(*score),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_uncertainty() {
+        // Confident boxes with the usual few candidates each
+        let clear = uncertainty("001.png", &boxes(&[95, 90, 88]), 6);
+        assert_eq!(clear.near_threshold, 0);
+        assert_eq!(clear.suppressed, 3);
+        assert_eq!(clear.score, 1.5);
+
+        let unsure = uncertainty("002.png", &boxes(&[45, 59, 60, 92]), 8);
+        assert_eq!(unsure.near_threshold, 2);
+        assert_eq!(unsure.score, 4.0);
+
+        let crowded = uncertainty("003.png", &boxes(&[95, 95]), 20);
+        assert_eq!(crowded.suppressed, 18);
+        assert!((crowded.score - 1.8).abs() < 1e-9);
+
+        let empty = uncertainty("004.png", &[], 0);
+        assert_eq!(empty.score, 0.0);
+
+        let pages = [clear, unsure, crowded, empty];
+        assert_eq!(most_uncertain(&pages, 3), vec![1, 2, 0]);
+        assert_eq!(most_uncertain(&pages, 10).len(), 4);
+    }
+}
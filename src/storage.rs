@@ -0,0 +1,473 @@
+use anyhow::{bail, ensure, Context, Result};
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+// Object storage services pages can be read from and written to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Provider {
+    S3,
+    Gcs,
+}
+
+impl Provider {
+    fn scheme(self) -> &'static str {
+        match self {
+            Provider::S3 => "s3",
+            Provider::Gcs => "gs",
+        }
+    }
+}
+
+// An object, or a prefix of objects, in a bucket, written as "s3://bucket/key" or "gs://bucket/key"
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ObjectUri {
+    pub provider: Provider,
+    pub bucket: String,
+    // Without leading or trailing slashes. Empty for the whole bucket.
+    pub key: String,
+}
+
+impl ObjectUri {
+    // None for anything that isn't an object storage URI, such as a local path
+    pub fn parse(uri: &str) -> Result<Option<ObjectUri>> {
+        let (provider, rest) = if let Some(rest) = uri.strip_prefix("s3://") {
+            (Provider::S3, rest)
+        } else if let Some(rest) = uri.strip_prefix("gs://") {
+            (Provider::Gcs, rest)
+        } else {
+            return Ok(None);
+        };
+
+        let (bucket, key) = rest.split_once('/').unwrap_or((rest, ""));
+        ensure!(!bucket.is_empty(), "{uri} doesn't name a bucket.");
+
+        Ok(Some(ObjectUri {
+            provider,
+            bucket: bucket.to_string(),
+            key: key.trim_matches('/').to_string(),
+        }))
+    }
+
+    pub fn from_path(path: &Path) -> Result<Option<ObjectUri>> {
+        match path.to_str() {
+            Some(path) => ObjectUri::parse(path),
+            None => Ok(None),
+        }
+    }
+
+    pub fn join(&self, name: &str) -> ObjectUri {
+        let name = name.trim_matches('/');
+        let key = match self.key.is_empty() {
+            true => name.to_string(),
+            false => format!("{}/{name}", self.key),
+        };
+
+        ObjectUri {
+            key,
+            ..self.clone()
+        }
+    }
+
+    pub fn parent(&self) -> ObjectUri {
+        let key = match self.key.rsplit_once('/') {
+            Some((parent, _)) => parent.to_string(),
+            None => String::new(),
+        };
+
+        ObjectUri {
+            key,
+            ..self.clone()
+        }
+    }
+
+    // Last segment of the key, the bucket's name for the whole bucket
+    pub fn file_name(&self) -> &str {
+        match self.key.rsplit_once('/') {
+            Some((_, name)) => name,
+            None if self.key.is_empty() => &self.bucket,
+            None => &self.key,
+        }
+    }
+
+    // Keys with an extension are single objects, the rest are prefixes of objects
+    pub fn is_object(&self) -> bool {
+        !self.key.is_empty() && Path::new(self.file_name()).extension().is_some()
+    }
+
+    // The bucket without a key, e.g. "s3://raws"
+    pub fn bucket_uri(&self) -> String {
+        format!("{}://{}", self.provider.scheme(), self.bucket)
+    }
+}
+
+impl fmt::Display for ObjectUri {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.key.is_empty() {
+            true => write!(f, "{}", self.bucket_uri()),
+            false => write!(f, "{}/{}", self.bucket_uri(), self.key),
+        }
+    }
+}
+
+// A bucket of an object storage service. Credentials and the region are read from the environment
+// the way the providers' own tools read them, e.g. AWS_ACCESS_KEY_ID and AWS_REGION for S3 and
+// GOOGLE_SERVICE_ACCOUNT for GCS.
+#[cfg(feature = "cloud")]
+pub struct Bucket {
+    store: Box<dyn object_store::ObjectStore>,
+    runtime: tokio::runtime::Runtime,
+}
+
+#[cfg(feature = "cloud")]
+impl Bucket {
+    pub fn open(uri: &ObjectUri) -> Result<Bucket> {
+        use object_store::aws::AmazonS3Builder;
+        use object_store::gcp::GoogleCloudStorageBuilder;
+
+        let store: Box<dyn object_store::ObjectStore> = match uri.provider {
+            Provider::S3 => Box::new(
+                AmazonS3Builder::from_env()
+                    .with_bucket_name(&uri.bucket)
+                    .build()?,
+            ),
+            Provider::Gcs => Box::new(
+                GoogleCloudStorageBuilder::from_env()
+                    .with_bucket_name(&uri.bucket)
+                    .build()?,
+            ),
+        };
+
+        let runtime = tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()?;
+
+        Ok(Bucket { store, runtime })
+    }
+
+    // Keys of every object under the prefix, in any order
+    pub fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        use futures::TryStreamExt;
+
+        let prefix = object_store::path::Path::from(prefix);
+        let objects: Vec<_> = self.block_on(async {
+            self.store
+                .list(Some(&prefix))
+                .await?
+                .try_collect::<Vec<_>>()
+                .await
+        })?;
+
+        Ok(objects
+            .into_iter()
+            .map(|object| object.location.to_string())
+            .collect())
+    }
+
+    pub fn size(&self, key: &str) -> Result<u64> {
+        let location = object_store::path::Path::from(key);
+        let meta = self.block_on(self.store.head(&location))?;
+
+        Ok(meta.size as u64)
+    }
+
+    pub fn get(&self, key: &str) -> Result<Vec<u8>> {
+        let location = object_store::path::Path::from(key);
+        let data = self.block_on(async { self.store.get(&location).await?.bytes().await })?;
+
+        Ok(data.to_vec())
+    }
+
+    pub fn put(&self, key: &str, data: Vec<u8>) -> Result<()> {
+        let location = object_store::path::Path::from(key);
+        self.block_on(self.store.put(&location, data.into()))?;
+
+        Ok(())
+    }
+
+    // Runs the request on a thread of its own, since the caller may already be on an async runtime,
+    // such as the blocking pool of the async handlers, where the bucket's runtime can't be entered
+    fn block_on<F>(&self, future: F) -> F::Output
+    where
+        F: std::future::Future + Send,
+        F::Output: Send,
+    {
+        std::thread::scope(|scope| {
+            scope
+                .spawn(|| self.runtime.block_on(future))
+                .join()
+                .unwrap_or_else(|panic| std::panic::resume_unwind(panic))
+        })
+    }
+}
+
+// Without the "cloud" feature no bucket can be opened, so none of the methods can be reached
+#[cfg(not(feature = "cloud"))]
+pub enum Bucket {}
+
+#[cfg(not(feature = "cloud"))]
+impl Bucket {
+    pub fn open(uri: &ObjectUri) -> Result<Bucket> {
+        bail!("{uri} can't be read or written since mangatra was built without the \"cloud\" feature.")
+    }
+
+    pub fn list(&self, _prefix: &str) -> Result<Vec<String>> {
+        match *self {}
+    }
+
+    pub fn size(&self, _key: &str) -> Result<u64> {
+        match *self {}
+    }
+
+    pub fn get(&self, _key: &str) -> Result<Vec<u8>> {
+        match *self {}
+    }
+
+    pub fn put(&self, _key: &str, _data: Vec<u8>) -> Result<()> {
+        match *self {}
+    }
+}
+
+// Reads a single object, refusing ones larger than "max_bytes" before downloading them
+pub fn get_object(uri: &ObjectUri, max_bytes: u64) -> Result<Vec<u8>> {
+    let bucket = Bucket::open(uri)?;
+
+    let size = bucket
+        .size(&uri.key)
+        .with_context(|| format!("Could not find {uri}."))?;
+    ensure!(
+        size <= max_bytes,
+        "{uri} is {size} bytes, which is more than the limit of {max_bytes} bytes."
+    );
+
+    bucket
+        .get(&uri.key)
+        .with_context(|| format!("Could not read {uri}."))
+}
+
+pub fn put_object(uri: &ObjectUri, data: Vec<u8>) -> Result<()> {
+    Bucket::open(uri)?
+        .put(&uri.key, data)
+        .with_context(|| format!("Could not write {uri}."))
+}
+
+// Downloads an object into the directory, or every object under a prefix into a subdirectory of it
+// named after the prefix, and returns the path of what was downloaded
+pub fn download(uri: &ObjectUri, directory: &Path) -> Result<PathBuf> {
+    let bucket = Bucket::open(uri)?;
+    let local = directory.join(uri.file_name());
+
+    if uri.is_object() {
+        let data = bucket
+            .get(&uri.key)
+            .with_context(|| format!("Could not read {uri}."))?;
+        write_file(&local, &data)?;
+
+        return Ok(local);
+    }
+
+    // Matches whole segments, so "raws/ch1" doesn't pick up "raws/ch10"
+    let prefix = match uri.key.is_empty() {
+        true => String::new(),
+        false => format!("{}/", uri.key),
+    };
+    let keys = bucket
+        .list(&uri.key)
+        .with_context(|| format!("Could not list {uri}."))?;
+
+    let mut downloaded = 0;
+    for key in keys.iter() {
+        let relative = match key.strip_prefix(&prefix) {
+            Some(relative) if !relative.is_empty() && !relative.ends_with('/') => relative,
+            _ => continue,
+        };
+
+        let data = bucket
+            .get(key)
+            .with_context(|| format!("Could not read {}.", uri.join(relative)))?;
+        write_file(&local.join(relative), &data)?;
+        downloaded += 1;
+    }
+    ensure!(downloaded > 0, "There are no objects under {uri}.");
+
+    Ok(local)
+}
+
+// Uploads a file to the URI, or every file in a directory under it, and returns how many were uploaded
+pub fn upload(local: &Path, uri: &ObjectUri) -> Result<usize> {
+    let bucket = Bucket::open(uri)?;
+
+    let files = match local.is_dir() {
+        true => files_in(local)?,
+        false => vec![local.to_path_buf()],
+    };
+
+    for file in files.iter() {
+        let destination = match file.strip_prefix(local) {
+            Ok(relative) if !relative.as_os_str().is_empty() => {
+                let relative: Vec<_> = relative
+                    .components()
+                    .map(|component| component.as_os_str().to_string_lossy())
+                    .collect();
+                uri.join(&relative.join("/"))
+            }
+            _ => uri.clone(),
+        };
+
+        let data =
+            std::fs::read(file).with_context(|| format!("Could not read {}.", file.display()))?;
+        bucket
+            .put(&destination.key, data)
+            .with_context(|| format!("Could not write {destination}."))?;
+    }
+
+    Ok(files.len())
+}
+
+fn write_file(path: &Path, data: &[u8]) -> Result<()> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+
+    std::fs::write(path, data).with_context(|| format!("Could not write {}.", path.display()))
+}
+
+fn files_in(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut files = Vec::new();
+
+    for entry in std::fs::read_dir(directory)? {
+        let path = entry?.path();
+
+        match path.is_dir() {
+            true => files.extend(files_in(&path)?),
+            false => files.push(path),
+        }
+    }
+
+    Ok(files)
+}
+
+// Local copies of the inputs and outputs of a run that live in buckets, since the pipeline works on
+// files. Outputs are uploaded once the run is done, and the copies are removed when it's dropped.
+#[derive(Debug, Default)]
+pub struct Staging {
+    directory: Option<PathBuf>,
+    // Directory of the staged outputs and the prefix it is uploaded to
+    upload: Option<(PathBuf, ObjectUri)>,
+}
+
+impl Staging {
+    // Downloads the input if it is in a bucket, returning the local path to read it from
+    pub fn input(&mut self, path: PathBuf, name: &str) -> Result<PathBuf> {
+        let uri = match ObjectUri::from_path(&path)? {
+            Some(uri) => uri,
+            None => return Ok(path),
+        };
+
+        let directory = self.directory()?.join(name);
+        download(&uri, &directory)
+    }
+
+    // Local path to write an output that goes into a bucket. Its directory, along with the cleaned
+    // pages written next to it, is uploaded to the prefix the output is in.
+    pub fn output(&mut self, path: PathBuf) -> Result<PathBuf> {
+        let uri = match ObjectUri::from_path(&path)? {
+            Some(uri) => uri,
+            None => return Ok(path),
+        };
+        ensure!(
+            !uri.key.is_empty(),
+            "Give the prefix in the bucket to write to, e.g. {uri}/chapter-01."
+        );
+        ensure!(
+            self.upload.is_none(),
+            "Only one output can be written to a bucket."
+        );
+
+        let directory = self.directory()?.join("output");
+        let local = directory.join(uri.file_name());
+
+        // The pipeline expects directory outputs to exist already
+        match uri.is_object() {
+            true => std::fs::create_dir_all(&directory)?,
+            false => std::fs::create_dir_all(&local)?,
+        }
+
+        self.upload = Some((directory, uri.parent()));
+
+        Ok(local)
+    }
+
+    // Uploads the staged outputs, if there are any
+    pub fn finish(&self) -> Result<()> {
+        if let Some((directory, uri)) = &self.upload {
+            let uploaded = upload(directory, uri)?;
+            println!("Uploaded {uploaded} files to {uri}");
+        }
+
+        Ok(())
+    }
+
+    fn directory(&mut self) -> Result<PathBuf> {
+        if let Some(directory) = &self.directory {
+            return Ok(directory.clone());
+        }
+
+        let directory = std::env::temp_dir().join(format!("mangatra-{}", std::process::id()));
+        if directory.exists() {
+            bail!(
+                "{} is left over from an earlier run. Remove it and try again.",
+                directory.display()
+            );
+        }
+        std::fs::create_dir_all(&directory)?;
+
+        self.directory = Some(directory.clone());
+
+        Ok(directory)
+    }
+}
+
+impl Drop for Staging {
+    fn drop(&mut self) {
+        if let Some(directory) = &self.directory {
+            let _ = std::fs::remove_dir_all(directory);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::storage::{ObjectUri, Provider};
+    use std::path::Path;
+
+    #[test]
+    fn test_object_uri() {
+        let uri = ObjectUri::parse("s3://raws/volume-01/chapter-01/")
+            .unwrap()
+            .unwrap();
+        assert_eq!(uri.provider, Provider::S3);
+        assert_eq!(uri.bucket, "raws");
+        assert_eq!(uri.key, "volume-01/chapter-01");
+        assert_eq!(uri.file_name(), "chapter-01");
+        assert!(!uri.is_object());
+        assert_eq!(uri.to_string(), "s3://raws/volume-01/chapter-01");
+        assert_eq!(uri.parent().to_string(), "s3://raws/volume-01");
+        assert_eq!(
+            uri.join("001.png").to_string(),
+            "s3://raws/volume-01/chapter-01/001.png"
+        );
+        assert!(uri.join("001.png").is_object());
+
+        let bucket = ObjectUri::parse("gs://releases").unwrap().unwrap();
+        assert_eq!(bucket.provider, Provider::Gcs);
+        assert_eq!(bucket.key, "");
+        assert_eq!(bucket.file_name(), "releases");
+        assert_eq!(bucket.parent(), bucket);
+        assert_eq!(bucket.join("001.png").to_string(), "gs://releases/001.png");
+        assert_eq!(bucket.bucket_uri(), "gs://releases");
+
+        assert_eq!(ObjectUri::parse("./pages/001.png").unwrap(), None);
+        assert_eq!(ObjectUri::from_path(Path::new("pages")).unwrap(), None);
+        assert!(ObjectUri::parse("s3:///001.png").is_err());
+    }
+}
@@ -0,0 +1,335 @@
+use anyhow::{Context, Result};
+use image::GrayImage;
+use serde::{Deserialize, Serialize};
+use std::path::Path;
+
+// Typesetting rules read from a style preset file (--style). Every field has a default, so a preset
+// only needs to contain the rules it changes.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct StylePreset {
+    // Case the translations are set in
+    pub case: Case,
+    pub max_lines: MaxLines,
+    pub shout: ShoutStyle,
+}
+
+// Most lines the translation of a region may be broken into, by the kind and size of the region.
+// Translations that would take more are set smaller until they fit in fewer lines.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct MaxLines {
+    // For every region
+    pub all: Option<usize>,
+    // For shouts
+    pub shout: Option<usize>,
+    // For text over artwork
+    pub floating: Option<usize>,
+    // One line for every this many pixels of a region's height, so small bubbles get few lines
+    pub pixels_per_line: Option<u32>,
+}
+
+impl MaxLines {
+    // Most lines of a region "height" pixels tall: the fewest that any rule for it allows
+    pub fn of_region(&self, shout: bool, floating: bool, height: u32) -> Option<usize> {
+        let by_size = self
+            .pixels_per_line
+            .map(|pixels_per_line| (height / pixels_per_line.max(1)).max(1) as usize);
+
+        [
+            self.all,
+            self.shout.filter(|_| shout),
+            self.floating.filter(|_| floating),
+            by_size,
+        ]
+        .into_iter()
+        .flatten()
+        .min()
+    }
+}
+
+// Case translations are set in, whatever case they were written in
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq, Default)]
+#[serde(rename_all = "snake_case")]
+pub enum Case {
+    #[default]
+    AsWritten,
+    Upper,
+    // The first letter of every word in uppercase, the others as written
+    Title,
+    // Lowercase letters drawn as smaller capitals
+    SmallCaps,
+}
+
+impl Case {
+    // Text in this case. Small caps are made when the text is drawn, so the text keeps telling the
+    // lowercase letters apart.
+    pub fn apply(&self, text: &str) -> String {
+        match self {
+            Case::AsWritten | Case::SmallCaps => text.to_string(),
+            Case::Upper => text.to_uppercase(),
+            Case::Title => {
+                let mut title = String::with_capacity(text.len());
+                let mut word_start = true;
+
+                for c in text.chars() {
+                    match word_start && c.is_alphabetic() {
+                        true => title.extend(c.to_uppercase()),
+                        false => title.push(c),
+                    }
+
+                    // Letters after apostrophes and hyphens ("don't", "so-called") are within a word
+                    word_start = c.is_whitespace() || (word_start && !c.is_alphanumeric());
+                }
+
+                title
+            }
+        }
+    }
+}
+
+// How the translations of shouts are set. A region is a shout when the glyphs of its source text
+// are much larger than those of the other regions on the page.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ShoutStyle {
+    // How many times larger than the median of the page a region's glyphs have to be for it to be a
+    // shout. Zero turns shout styling off.
+    pub min_glyph_ratio: f32,
+    // Factor the size of the translation is multiplied by
+    pub scale: f32,
+    pub bold: bool,
+    pub uppercase: bool,
+    // Case of shouts, instead of the page's. "uppercase" is the same as "upper".
+    pub case: Option<Case>,
+}
+
+impl Default for ShoutStyle {
+    fn default() -> ShoutStyle {
+        ShoutStyle {
+            min_glyph_ratio: 1.6,
+            scale: 1.25,
+            bold: true,
+            uppercase: false,
+            case: None,
+        }
+    }
+}
+
+impl ShoutStyle {
+    // Case shouts are set in on a page set in "case"
+    pub fn case(&self, case: Case) -> Case {
+        match (self.case, self.uppercase) {
+            (Some(case), _) => case,
+            (None, true) => Case::Upper,
+            (None, false) => case,
+        }
+    }
+}
+
+impl StylePreset {
+    pub fn load(path: &Path) -> Result<StylePreset> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the style preset {}.", path.display()))?;
+
+        serde_json::from_str(&data)
+            .with_context(|| format!("{} is not a valid style preset.", path.display()))
+    }
+}
+
+// Estimated size in pixels of the glyphs in a region of dark text on a light background. Lines or
+// columns of text show up as runs of rows or columns that contain ink; whichever direction the text
+// runs in, the shorter of the two median run lengths is the size of a glyph.
+pub fn glyph_size(region: &GrayImage) -> Option<f32> {
+    let (width, height) = region.dimensions();
+
+    let mut column_ink = vec![0u32; width as usize];
+    let mut row_ink = vec![0u32; height as usize];
+
+    for (x, y, pixel) in region.enumerate_pixels() {
+        if pixel.0[0] < 128 {
+            column_ink[x as usize] += 1;
+            row_ink[y as usize] += 1;
+        }
+    }
+
+    // A few stray pixels of noise don't make a row or column part of the text
+    let column_runs = ink_runs(&column_ink, (height / 50).max(1));
+    let row_runs = ink_runs(&row_ink, (width / 50).max(1));
+
+    match (median(column_runs), median(row_runs)) {
+        (Some(columns), Some(rows)) => Some(columns.min(rows)),
+        _ => None,
+    }
+}
+
+// Lengths of the runs of entries with at least "min_ink" pixels of ink
+fn ink_runs(ink: &[u32], min_ink: u32) -> Vec<f32> {
+    let mut runs = Vec::new();
+    let mut run = 0;
+
+    for &count in ink.iter().chain([&0]) {
+        if count >= min_ink {
+            run += 1;
+        } else if run > 0 {
+            runs.push(run as f32);
+            run = 0;
+        }
+    }
+
+    runs
+}
+
+// Lower median, so that of two regions the smaller one is the reference
+fn median(mut values: Vec<f32>) -> Option<f32> {
+    if values.is_empty() {
+        return None;
+    }
+
+    values.sort_by(f32::total_cmp);
+
+    Some(values[(values.len() - 1) / 2])
+}
+
+// Flags the regions whose glyphs are at least "min_glyph_ratio" times the median glyph size of the
+// page. A page needs at least two regions with text to tell which ones stand out.
+pub fn shout_regions(glyph_sizes: &[Option<f32>], min_glyph_ratio: f32) -> Vec<bool> {
+    let sizes: Vec<f32> = glyph_sizes.iter().flatten().copied().collect();
+
+    let median = match median(sizes.clone()) {
+        Some(median) if min_glyph_ratio > 0.0 && sizes.len() >= 2 => median,
+        _ => return vec![false; glyph_sizes.len()],
+    };
+
+    glyph_sizes
+        .iter()
+        .map(|size| size.is_some_and(|size| size >= median * min_glyph_ratio))
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::style::{glyph_size, shout_regions, Case, MaxLines, ShoutStyle, StylePreset};
+    use image::{GrayImage, Luma};
+
+    // Columns of square glyphs of the given size, as in vertical Japanese text
+    fn columns(glyph: u32, num_columns: u32) -> GrayImage {
+        let gap = glyph / 2;
+
+        GrayImage::from_fn(num_columns * (glyph + gap) + gap, 5 * glyph, |x, y| {
+            let in_column = x >= gap && (x - gap) % (glyph + gap) < glyph;
+
+            match in_column && y >= glyph / 2 && y < 4 * glyph {
+                true => Luma([0]),
+                false => Luma([255]),
+            }
+        })
+    }
+
+    #[test]
+    fn test_glyph_size() {
+        assert_eq!(glyph_size(&columns(12, 3)), Some(12.0));
+        assert_eq!(glyph_size(&columns(30, 2)), Some(30.0));
+        assert_eq!(
+            glyph_size(&GrayImage::from_pixel(20, 20, Luma([255]))),
+            None
+        );
+    }
+
+    #[test]
+    fn test_shout_regions() {
+        let sizes = [Some(12.0), Some(13.0), None, Some(30.0), Some(11.0)];
+
+        assert_eq!(
+            shout_regions(&sizes, 1.6),
+            vec![false, false, false, true, false]
+        );
+        assert_eq!(shout_regions(&sizes, 0.0), vec![false; 5]);
+        assert_eq!(
+            shout_regions(&[Some(12.0), Some(30.0)], 1.6),
+            vec![false, true]
+        );
+
+        // A lone region can't be compared with anything
+        assert_eq!(shout_regions(&[Some(30.0), None], 1.6), vec![false; 2]);
+    }
+
+    #[test]
+    fn test_style_preset() {
+        let preset: StylePreset =
+            serde_json::from_str(r#"{"shout": {"uppercase": true, "scale": 1.5}}"#).unwrap();
+
+        assert_eq!(
+            preset.shout,
+            ShoutStyle {
+                uppercase: true,
+                scale: 1.5,
+                ..ShoutStyle::default()
+            }
+        );
+        assert_eq!(
+            serde_json::from_str::<StylePreset>("{}").unwrap(),
+            StylePreset::default()
+        );
+        assert!(serde_json::from_str::<StylePreset>(r#"{"shout": {"italic": true}}"#).is_err());
+    }
+
+    #[test]
+    fn test_case() {
+        let text = "don't touch the so-called 'treasure', Mr. NASA!";
+
+        assert_eq!(Case::AsWritten.apply(text), text);
+        assert_eq!(Case::SmallCaps.apply(text), text);
+        assert_eq!(
+            Case::Upper.apply(text),
+            "DON'T TOUCH THE SO-CALLED 'TREASURE', MR. NASA!"
+        );
+        assert_eq!(
+            Case::Title.apply(text),
+            "Don't Touch The So-called 'Treasure', Mr. NASA!"
+        );
+
+        let preset: StylePreset =
+            serde_json::from_str(r#"{"case": "small_caps", "shout": {"uppercase": true}}"#)
+                .unwrap();
+        assert_eq!(preset.case, Case::SmallCaps);
+        assert_eq!(preset.shout.case(preset.case), Case::Upper);
+
+        // Shouts without a case of their own are set like the rest of the page
+        let shout = ShoutStyle::default();
+        assert_eq!(shout.case(Case::Title), Case::Title);
+        assert_eq!(
+            ShoutStyle {
+                uppercase: true,
+                case: Some(Case::AsWritten),
+                ..shout
+            }
+            .case(Case::Title),
+            Case::AsWritten
+        );
+    }
+
+    #[test]
+    fn test_max_lines() {
+        let preset: StylePreset =
+            serde_json::from_str(r#"{"max_lines": {"all": 6, "shout": 2, "pixels_per_line": 40}}"#)
+                .unwrap();
+        let max_lines = preset.max_lines;
+
+        assert_eq!(max_lines.of_region(false, false, 400), Some(6));
+        assert_eq!(max_lines.of_region(true, false, 400), Some(2));
+        // Small regions get fewer lines, but always at least one
+        assert_eq!(max_lines.of_region(false, true, 130), Some(3));
+        assert_eq!(max_lines.of_region(false, false, 20), Some(1));
+
+        assert_eq!(MaxLines::default().of_region(true, true, 100), None);
+        assert_eq!(
+            MaxLines {
+                floating: Some(3),
+                ..MaxLines::default()
+            }
+            .of_region(false, true, 100),
+            Some(3)
+        );
+    }
+}
@@ -0,0 +1,486 @@
+use crate::extraction::{self, Extraction};
+use crate::replacer::TextOverflow;
+use crate::timings::{Stage, StageTimings};
+use anyhow::{Context, Result};
+use serde::Serialize;
+use std::path::Path;
+
+// OCR confidences are counted in buckets of this size, the last one also holding 100
+const CONFIDENCE_BUCKET_SIZE: u8 = 20;
+const NUM_CONFIDENCE_BUCKETS: usize = 5;
+
+// Regions that were cleaned with a lower score than this are reported for manual touch-up
+const MIN_CLEAN_QUALITY: u8 = 80;
+
+// Outcome of a single page of a directory run
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PageSummary {
+    pub page: String,
+    // Missing if the page failed before its regions were known
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub regions: Option<usize>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub confidences: Vec<u8>,
+    // Nothing was detected on the page, so it was passed through without OCR, cleaning or
+    // typesetting
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub text_free: bool,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub poorly_cleaned: Vec<PoorlyCleanedRegion>,
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub overflowing: Vec<OverflowingRegion>,
+    // Only under --timings
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub timings: Option<StageTimings>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// A region whose cleaning scored below "MIN_CLEAN_QUALITY"
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct PoorlyCleanedRegion {
+    pub region: String,
+    // Cleaning quality from 0 to 100
+    pub quality: u8,
+}
+
+// A region whose translation doesn't fit in it, with the size in pixels the translation needs and
+// the size it had, and the lines it was broken into and the most it was allowed
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct OverflowingRegion {
+    pub region: String,
+    pub required_width: i32,
+    pub required_height: i32,
+    pub available_width: i32,
+    pub available_height: i32,
+    pub lines: usize,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_lines: Option<usize>,
+}
+
+// Totals over all pages, written alongside them
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+struct Totals {
+    pages: usize,
+    failed: usize,
+    regions: usize,
+    // Pages whose text has no regions, though something was detected on them
+    pages_without_regions: Vec<String>,
+    text_free_pages: Vec<String>,
+    // Number of regions per confidence bucket: 0-19, 20-39, 40-59, 60-79 and 80-100
+    confidence_distribution: [usize; NUM_CONFIDENCE_BUCKETS],
+    poorly_cleaned: usize,
+    overflowing: usize,
+    // Time the pages with timings spent in each stage, added up
+    #[serde(skip_serializing_if = "Option::is_none")]
+    timings: Option<StageTimings>,
+}
+
+#[derive(Serialize)]
+struct SummaryFile<'a> {
+    totals: Totals,
+    pages: &'a [PageSummary],
+}
+
+// Collects the outcome of every page of a directory run, so a run where half the volume came out
+// empty doesn't finish as silently as one that went fine
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct RunSummary {
+    pages: Vec<PageSummary>,
+}
+
+impl RunSummary {
+    pub fn new() -> RunSummary {
+        RunSummary::default()
+    }
+
+    // Records a page whose text was extracted or typeset, along with the cleaning quality of its
+    // regions by index if it was cleaned, the translations that didn't fit if it was typeset and
+    // the time each stage took if it was timed
+    pub fn add_page(
+        &mut self,
+        extraction: &Extraction,
+        clean_quality: &[Option<u8>],
+        overflows: &[TextOverflow],
+        timings: Option<StageTimings>,
+    ) {
+        let poorly_cleaned = clean_quality
+            .iter()
+            .enumerate()
+            .filter_map(|(index, quality)| match quality {
+                Some(quality) if *quality < MIN_CLEAN_QUALITY => Some(PoorlyCleanedRegion {
+                    region: extraction::region_id(&extraction.page, index),
+                    quality: *quality,
+                }),
+                _ => None,
+            })
+            .collect();
+
+        let overflowing = overflows
+            .iter()
+            .map(|overflow| OverflowingRegion {
+                region: extraction::region_id(&extraction.page, overflow.index),
+                required_width: overflow.required.0,
+                required_height: overflow.required.1,
+                available_width: overflow.available.0,
+                available_height: overflow.available.1,
+                lines: overflow.lines,
+                max_lines: overflow.max_lines,
+            })
+            .collect();
+
+        self.pages.push(PageSummary {
+            page: extraction.page.clone(),
+            regions: Some(extraction.regions.len()),
+            confidences: extraction
+                .regions
+                .iter()
+                .filter_map(|region| region.confidence)
+                .collect(),
+            text_free: false,
+            poorly_cleaned,
+            overflowing,
+            timings,
+            error: None,
+        });
+    }
+
+    // Records a page where nothing was detected, which was passed through as it was
+    pub fn add_text_free_page(&mut self, page: &str, timings: Option<StageTimings>) {
+        self.pages.push(PageSummary {
+            page: page.to_string(),
+            regions: Some(0),
+            confidences: Vec::new(),
+            text_free: true,
+            poorly_cleaned: Vec::new(),
+            overflowing: Vec::new(),
+            timings,
+            error: None,
+        });
+    }
+
+    pub fn is_text_free(&self, page: &str) -> bool {
+        self.pages
+            .iter()
+            .any(|summary| summary.page == page && summary.text_free)
+    }
+
+    pub fn add_failure(&mut self, page: &str, error: &anyhow::Error) {
+        self.pages.push(PageSummary {
+            page: page.to_string(),
+            regions: None,
+            confidences: Vec::new(),
+            text_free: false,
+            poorly_cleaned: Vec::new(),
+            overflowing: Vec::new(),
+            timings: None,
+            error: Some(error.to_string()),
+        });
+    }
+
+    // Pages in name order, since parallel runs finish them in any order
+    pub fn pages(&self) -> Vec<&PageSummary> {
+        let mut pages: Vec<&PageSummary> = self.pages.iter().collect();
+        pages.sort_by(|a, b| a.page.cmp(&b.page));
+        pages
+    }
+
+    fn totals(&self) -> Totals {
+        let mut confidence_distribution = [0; NUM_CONFIDENCE_BUCKETS];
+
+        for confidence in self.pages.iter().flat_map(|page| page.confidences.iter()) {
+            let bucket = (confidence / CONFIDENCE_BUCKET_SIZE) as usize;
+            confidence_distribution[bucket.min(NUM_CONFIDENCE_BUCKETS - 1)] += 1;
+        }
+
+        Totals {
+            pages: self.pages.len(),
+            failed: self
+                .pages
+                .iter()
+                .filter(|page| page.error.is_some())
+                .count(),
+            regions: self.pages.iter().filter_map(|page| page.regions).sum(),
+            pages_without_regions: self
+                .pages()
+                .into_iter()
+                .filter(|page| page.regions == Some(0) && !page.text_free)
+                .map(|page| page.page.clone())
+                .collect(),
+            text_free_pages: self
+                .pages()
+                .into_iter()
+                .filter(|page| page.text_free)
+                .map(|page| page.page.clone())
+                .collect(),
+            confidence_distribution,
+            poorly_cleaned: self
+                .pages
+                .iter()
+                .map(|page| page.poorly_cleaned.len())
+                .sum(),
+            overflowing: self.pages.iter().map(|page| page.overflowing.len()).sum(),
+            timings: self.pages.iter().filter_map(|page| page.timings).reduce(
+                |mut total, timings| {
+                    total += timings;
+                    total
+                },
+            ),
+        }
+    }
+
+    // Human readable summary for the end of a run
+    pub fn report(&self) -> String {
+        let totals = self.totals();
+        let succeeded = totals.pages - totals.failed;
+
+        let mut report = format!(
+            "Pages processed: {} ({succeeded} succeeded, {} failed)\n",
+            totals.pages, totals.failed
+        );
+
+        let region_counts: Vec<usize> = self.pages.iter().filter_map(|page| page.regions).collect();
+
+        if let (Some(min), Some(max)) = (region_counts.iter().min(), region_counts.iter().max()) {
+            report.push_str(&format!(
+                "Regions detected: {} ({:.1} per page, min {min}, max {max})\n",
+                totals.regions,
+                totals.regions as f64 / region_counts.len() as f64
+            ));
+        }
+
+        if totals
+            .confidence_distribution
+            .iter()
+            .any(|count| *count > 0)
+        {
+            report.push_str("OCR confidence:\n");
+
+            for (bucket, count) in totals.confidence_distribution.iter().enumerate() {
+                let low = bucket as u8 * CONFIDENCE_BUCKET_SIZE;
+                let high = match bucket == NUM_CONFIDENCE_BUCKETS - 1 {
+                    true => 100,
+                    false => low + CONFIDENCE_BUCKET_SIZE - 1,
+                };
+
+                report.push_str(&format!("  {:>7}: {count}\n", format!("{low}-{high}")));
+            }
+        }
+
+        if !totals.text_free_pages.is_empty() {
+            report.push_str(&format!(
+                "Text-free pages passed through ({}): {}\n",
+                totals.text_free_pages.len(),
+                totals.text_free_pages.join(", ")
+            ));
+        }
+
+        if !totals.pages_without_regions.is_empty() {
+            report.push_str(&format!(
+                "Pages without regions ({}): {}\n",
+                totals.pages_without_regions.len(),
+                totals.pages_without_regions.join(", ")
+            ));
+        }
+
+        if totals.poorly_cleaned > 0 {
+            report.push_str(&format!(
+                "Regions that may need touch-up ({}):\n",
+                totals.poorly_cleaned
+            ));
+
+            for page in self.pages() {
+                if page.poorly_cleaned.is_empty() {
+                    continue;
+                }
+
+                let regions: Vec<String> = page
+                    .poorly_cleaned
+                    .iter()
+                    .map(|region| format!("{} ({})", region.region, region.quality))
+                    .collect();
+
+                report.push_str(&format!("  {}: {}\n", page.page, regions.join(", ")));
+            }
+        }
+
+        if totals.overflowing > 0 {
+            report.push_str(&format!(
+                "Translations that don't fit their regions ({}):\n",
+                totals.overflowing
+            ));
+
+            for page in self.pages() {
+                if page.overflowing.is_empty() {
+                    continue;
+                }
+
+                let regions: Vec<String> = page
+                    .overflowing
+                    .iter()
+                    .map(|region| {
+                        let lines = match region.max_lines {
+                            Some(max_lines) if region.lines > max_lines => {
+                                format!(", {} lines of {max_lines}", region.lines)
+                            }
+                            _ => String::new(),
+                        };
+
+                        format!(
+                            "{} ({}x{} in {}x{}{lines})",
+                            region.region,
+                            region.required_width,
+                            region.required_height,
+                            region.available_width,
+                            region.available_height
+                        )
+                    })
+                    .collect();
+
+                report.push_str(&format!("  {}: {}\n", page.page, regions.join(", ")));
+            }
+        }
+
+        if let Some(timings) = totals.timings {
+            let timed_pages = self
+                .pages
+                .iter()
+                .filter(|page| page.timings.is_some())
+                .count();
+            report.push_str(&format!("Time per stage ({timed_pages} pages):\n"));
+
+            for stage in Stage::ALL {
+                report.push_str(&format!(
+                    "  {:>7}: {} ms ({:.1} ms per page)\n",
+                    stage.name(),
+                    timings.get(stage),
+                    timings.get(stage) as f64 / timed_pages as f64
+                ));
+            }
+        }
+
+        if totals.failed > 0 {
+            report.push_str(&format!("Failures ({}):\n", totals.failed));
+
+            for page in self.pages() {
+                if let Some(error) = &page.error {
+                    report.push_str(&format!("  {}: {error}\n", page.page));
+                }
+            }
+        }
+
+        report
+    }
+
+    pub fn write(&self, path: &Path) -> Result<()> {
+        let pages: Vec<PageSummary> = self.pages().into_iter().cloned().collect();
+
+        let summary_file = SummaryFile {
+            totals: self.totals(),
+            pages: &pages,
+        };
+
+        std::fs::write(path, serde_json::to_string_pretty(&summary_file)?)
+            .with_context(|| format!("Could not write the run summary to {}.", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::extraction::Extraction;
+    use crate::replacer::TextOverflow;
+    use crate::summary::RunSummary;
+    use crate::timings::StageTimings;
+    use anyhow::anyhow;
+
+    #[test]
+    fn test_report() {
+        let mut summary = RunSummary::new();
+
+        summary.add_page(
+            &Extraction::new("002", Vec::new()),
+            &[],
+            &[],
+            Some(StageTimings {
+                decode_ms: 10,
+                detect_ms: 200,
+                ocr_ms: 0,
+                layout_ms: 0,
+                encode_ms: 5,
+            }),
+        );
+        summary.add_page(
+            &Extraction::new(
+                "001",
+                vec!["a".to_string(), "b".to_string(), "c".to_string()],
+            )
+            .with_confidences(&[95, 100, 12]),
+            &[Some(45), None, Some(96)],
+            &[
+                TextOverflow {
+                    index: 1,
+                    required: (140, 120),
+                    available: (150, 200),
+                    lines: 5,
+                    max_lines: Some(3),
+                },
+                TextOverflow {
+                    index: 2,
+                    required: (180, 240),
+                    available: (150, 200),
+                    lines: 8,
+                    max_lines: None,
+                },
+            ],
+            Some(StageTimings {
+                decode_ms: 30,
+                detect_ms: 400,
+                ocr_ms: 120,
+                layout_ms: 0,
+                encode_ms: 15,
+            }),
+        );
+        summary.add_failure("003", &anyhow!("Could not read the image."));
+        summary.add_text_free_page("004", None);
+
+        assert_eq!(
+            summary.report(),
+            "Pages processed: 4 (3 succeeded, 1 failed)\n\
+             Regions detected: 3 (1.0 per page, min 0, max 3)\n\
+             OCR confidence:\n\
+             \x20    0-19: 1\n\
+             \x20   20-39: 0\n\
+             \x20   40-59: 0\n\
+             \x20   60-79: 0\n\
+             \x20  80-100: 2\n\
+             Text-free pages passed through (1): 004\n\
+             Pages without regions (1): 002\n\
+             Regions that may need touch-up (1):\n\
+             \x20 001: 001-0 (45)\n\
+             Translations that don't fit their regions (2):\n\
+             \x20 001: 001-1 (140x120 in 150x200, 5 lines of 3), 001-2 (180x240 in 150x200)\n\
+             Time per stage (2 pages):\n\
+             \x20  decode: 40 ms (20.0 ms per page)\n\
+             \x20  detect: 600 ms (300.0 ms per page)\n\
+             \x20     ocr: 120 ms (60.0 ms per page)\n\
+             \x20  layout: 0 ms (0.0 ms per page)\n\
+             \x20  encode: 20 ms (10.0 ms per page)\n\
+             Failures (1):\n\
+             \x20 003: Could not read the image.\n"
+        );
+
+        let pages: Vec<&str> = summary
+            .pages()
+            .iter()
+            .map(|page| page.page.as_str())
+            .collect();
+
+        assert_eq!(pages, vec!["001", "002", "003", "004"]);
+        assert!(summary.is_text_free("004"));
+        assert!(!summary.is_text_free("002"));
+        assert_eq!(
+            RunSummary::new().report(),
+            "Pages processed: 0 (0 succeeded, 0 failed)\n"
+        );
+    }
+}
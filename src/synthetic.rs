@@ -0,0 +1,427 @@
+use crate::detection::{self, TextBox};
+use crate::export::annotations::{self, AnnotationFormat};
+use crate::extraction::{self, Extraction, PageSize};
+use crate::fonts;
+use crate::replacer::{self, Expansion};
+use crate::utils::{image_conversion, polarity, validation};
+use anyhow::{ensure, Context, Result};
+use image::{imageops, RgbImage};
+use imageproc::drawing;
+use rusttype::{Font, Scale};
+use std::path::{Path, PathBuf};
+use tracing::warn;
+
+// Share of the regions set vertically, as most Japanese text in manga is
+const VERTICAL_SHARE: f32 = 0.8;
+// Smallest glyphs drawn, in pixels. Smaller text isn't legible in a scan either.
+const MIN_GLYPH_SIZE: i32 = 12;
+// Largest difference per color channel at which a pixel of a cleaned page still counts as the bubble
+const BUBBLE_TOLERANCE: u8 = 8;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Orientation {
+    Horizontal,
+    // Columns from right to left
+    Vertical,
+}
+
+// Glyphs laid out on a grid of square cells, each given by the top left corner of its cell
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Block {
+    pub cells: Vec<(i32, i32)>,
+    pub width: i32,
+    pub height: i32,
+}
+
+// Lays out up to "num_glyphs" glyphs of "size" pixels in an area, in as few lines (or columns) as fit
+// with the glyphs spread evenly over them. Lines are a quarter of a glyph apart. Glyphs that don't fit
+// are left out, and None is returned if not even one does.
+pub fn layout(
+    num_glyphs: usize,
+    size: i32,
+    (width, height): (i32, i32),
+    orientation: Orientation,
+) -> Option<Block> {
+    let (along, across) = match orientation {
+        Orientation::Horizontal => (width, height),
+        Orientation::Vertical => (height, width),
+    };
+
+    if size <= 0 || num_glyphs == 0 {
+        return None;
+    }
+
+    let gap = size / 4;
+    let per_line = (along / size).max(0) as usize;
+    let max_lines = ((across + gap) / (size + gap)).max(0) as usize;
+
+    if per_line == 0 || max_lines == 0 {
+        return None;
+    }
+
+    let num_lines = num_glyphs.div_ceil(per_line).min(max_lines);
+    let num_glyphs = num_glyphs.min(num_lines * per_line);
+    let per_line = num_glyphs.div_ceil(num_lines);
+
+    let block_along = per_line as i32 * size;
+    let block_across = num_lines as i32 * (size + gap) - gap;
+
+    let cells = (0..num_glyphs)
+        .map(|index| {
+            let line = (index / per_line) as i32;
+            let position = (index % per_line) as i32;
+
+            match orientation {
+                Orientation::Horizontal => (position * size, line * (size + gap)),
+                Orientation::Vertical => (
+                    (num_lines as i32 - 1 - line) * (size + gap),
+                    position * size,
+                ),
+            }
+        })
+        .collect();
+
+    let (width, height) = match orientation {
+        Orientation::Horizontal => (block_along, block_across),
+        Orientation::Vertical => (block_across, block_along),
+    };
+
+    Some(Block {
+        cells,
+        width,
+        height,
+    })
+}
+
+// Fills every bubble of a cleaned page with a random line of the corpus, returning the page and the
+// box and text of every block drawn. Bubbles are found by expanding the page's original text regions
+// the way typesetting does.
+pub fn render_page(
+    page: &RgbImage,
+    boxes: &[TextBox],
+    corpus: &[String],
+    fonts: &[Font<'static>],
+    rng: &mut fastrand::Rng,
+) -> (RgbImage, Vec<(TextBox, String)>) {
+    let mut canvas = page.clone();
+    let mut regions = Vec::new();
+
+    let expansion = Expansion {
+        tolerance: BUBBLE_TOLERANCE,
+        max_distance: None,
+    };
+
+    for text_box in detection::sanitize_boxes(boxes, page.width() as i32, page.height() as i32) {
+        let ((x, y), width, height) = replacer::bubble_area(
+            (text_box.x, text_box.y),
+            text_box.width,
+            text_box.height,
+            page,
+            expansion,
+        );
+
+        // Keeps the text off the outline of the bubble
+        let (margin_x, margin_y) = (width / 10, height / 10);
+        let (x, y) = (x + margin_x, y + margin_y);
+        let (width, height) = (width - margin_x * 2, height - margin_y * 2);
+
+        if width.min(height) < MIN_GLYPH_SIZE {
+            continue;
+        }
+
+        let font = &fonts[rng.usize(..fonts.len())];
+        let line = &corpus[rng.usize(..corpus.len())];
+        let missing = fonts::missing_glyphs(font, line);
+        let glyphs: Vec<char> = line
+            .chars()
+            .filter(|glyph| !glyph.is_whitespace() && !missing.contains(glyph))
+            .collect();
+
+        let orientation = match rng.f32() < VERTICAL_SHARE {
+            true => Orientation::Vertical,
+            false => Orientation::Horizontal,
+        };
+        let size = rng.i32(MIN_GLYPH_SIZE..=(width.min(height) / 3).max(MIN_GLYPH_SIZE));
+
+        let block = match layout(glyphs.len(), size, (width, height), orientation) {
+            Some(block) => block,
+            None => continue,
+        };
+
+        let area = imageops::crop_imm(page, x as u32, y as u32, width as u32, height as u32);
+        let inverted = polarity::is_inverted(&imageops::grayscale(&area.to_image()));
+        let (_, text_color) = polarity::colors(inverted);
+
+        // Centered in the bubble
+        let left = x + (width - block.width) / 2;
+        let top = y + (height - block.height) / 2;
+
+        for (glyph, (cell_x, cell_y)) in glyphs.iter().zip(&block.cells) {
+            drawing::draw_text_mut(
+                &mut canvas,
+                text_color,
+                left + cell_x,
+                top + cell_y,
+                Scale::uniform(size as f32),
+                font,
+                &glyph.to_string(),
+            );
+        }
+
+        let text = glyphs.iter().take(block.cells.len()).collect();
+
+        regions.push((
+            TextBox {
+                x: left,
+                y: top,
+                width: block.width,
+                height: block.height,
+                score: None,
+            },
+            text,
+        ));
+    }
+
+    (canvas, regions)
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SynthesizeArgs {
+    #[arg(
+        short,
+        long,
+        help = "Directory of cleaned pages, as written by extraction with --clean"
+    )]
+    pub pages: PathBuf,
+    #[arg(
+        short,
+        long,
+        help = "Directory of the text files extracted from the pages, whose boxes locate the bubbles"
+    )]
+    pub text: PathBuf,
+    #[arg(
+        short,
+        long,
+        help = "Text file with a line of Japanese text to draw into a bubble on every line"
+    )]
+    pub corpus: PathBuf,
+    #[arg(short, long, help = "Directory the generated pages are written into")]
+    pub output: PathBuf,
+    #[arg(
+        long = "font",
+        help = "[Optional] Font to draw the text with, picked at random for every bubble. Can be given several times. Defaults to an installed CJK font"
+    )]
+    pub fonts: Vec<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = 3,
+        value_parser = clap::value_parser!(u32).range(1..),
+        help = "Number of pages generated from every cleaned page"
+    )]
+    pub variants: u32,
+    #[arg(
+        long,
+        default_value_t = 0,
+        help = "Seed of the random choices, so the same seed generates the same pages"
+    )]
+    pub seed: u64,
+    #[arg(
+        long,
+        value_enum,
+        default_value_t = AnnotationFormat::Yolo,
+        help = "Format of the ground truth boxes written next to the pages"
+    )]
+    pub format: AnnotationFormat,
+}
+
+impl SynthesizeArgs {
+    pub fn run(self) -> Result<()> {
+        let corpus: Vec<String> = std::fs::read_to_string(&self.corpus)
+            .with_context(|| format!("Could not read the corpus {}.", self.corpus.display()))?
+            .lines()
+            .map(str::trim)
+            .filter(|line| !line.is_empty())
+            .map(str::to_string)
+            .collect();
+        ensure!(
+            !corpus.is_empty(),
+            "The corpus {} has no text.",
+            self.corpus.display()
+        );
+
+        let fonts = match self.fonts.is_empty() {
+            true => vec![fonts::default_font("ja")],
+            false => self
+                .fonts
+                .iter()
+                .map(|path| fonts::load_font(path))
+                .collect::<Result<Vec<Font>>>()?,
+        };
+
+        let pages = validation::images_in_directory(&self.pages)?;
+        ensure!(
+            !pages.is_empty(),
+            "There are no cleaned pages in {}.",
+            self.pages.display()
+        );
+
+        std::fs::create_dir_all(&self.output).with_context(|| {
+            format!("Could not create the directory {}.", self.output.display())
+        })?;
+
+        let mut rng = fastrand::Rng::with_seed(self.seed);
+        let mut generated = Vec::new();
+        let mut num_regions = 0;
+
+        for path in pages.iter() {
+            let (page, boxes) = match self.read_page(path) {
+                Ok(page) => page,
+                Err(error) => {
+                    warn!("Skipping {}: {error:#}", path.display());
+                    continue;
+                }
+            };
+            let stem = page_stem(path);
+
+            for variant in 1..=self.variants {
+                let (image, regions) = render_page(&page, &boxes, &corpus, &fonts, &mut rng);
+
+                let name = format!("{stem}-{variant}");
+                let file_name = format!("{name}.png");
+                image
+                    .save(self.output.join(&file_name))
+                    .with_context(|| format!("Could not write {file_name}."))?;
+
+                let (boxes, text): (Vec<TextBox>, Vec<String>) = regions.into_iter().unzip();
+                num_regions += boxes.len();
+
+                let size = PageSize {
+                    width: image.width(),
+                    height: image.height(),
+                };
+                generated.push((
+                    Extraction::new(&name, text).with_geometry(size, &boxes),
+                    file_name,
+                ));
+            }
+        }
+
+        ensure!(
+            !generated.is_empty(),
+            "None of the cleaned pages in {} could be read.",
+            self.pages.display()
+        );
+
+        annotations::write_annotations(self.format, &self.output, &generated, 0)?;
+
+        println!(
+            "Generated {} pages with {num_regions} regions into {}",
+            generated.len(),
+            self.output.display()
+        );
+
+        Ok(())
+    }
+
+    // Reads a cleaned page and the boxes of the text that was cleaned from it
+    fn read_page(&self, path: &Path) -> Result<(RgbImage, Vec<TextBox>)> {
+        let stem = page_stem(path);
+        let text_path = self.text.join(format!("{stem}.json"));
+        ensure!(
+            text_path.is_file(),
+            "There is no text file for it at {}.",
+            text_path.display()
+        );
+
+        let extraction = extraction::read_text_file(&text_path, &stem)?;
+        let boxes = extraction
+            .regions
+            .iter()
+            .filter_map(|region| region.text_box)
+            .collect();
+
+        let page = image_conversion::open_image(path)?.to_rgb8();
+
+        Ok((page, boxes))
+    }
+}
+
+// Name of the page a cleaned page was written for
+fn page_stem(path: &Path) -> String {
+    let stem = path
+        .file_stem()
+        .map(|stem| stem.to_string_lossy().into_owned())
+        .unwrap_or_default();
+
+    match stem.strip_suffix("_cleaned") {
+        Some(stem) => stem.to_string(),
+        None => stem,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::detection::TextBox;
+    use crate::fonts;
+    use crate::synthetic::{layout, render_page, Orientation};
+    use image::{Rgb, RgbImage};
+
+    #[test]
+    fn test_layout() {
+        // Seven glyphs in columns of up to four, spread evenly over two columns
+        let block = layout(7, 20, (60, 80), Orientation::Vertical).unwrap();
+        assert_eq!((block.width, block.height), (45, 80));
+        assert_eq!(block.cells[0], (25, 0));
+        assert_eq!(block.cells[3], (25, 60));
+        assert_eq!(block.cells[4], (0, 0));
+
+        let block = layout(5, 20, (200, 100), Orientation::Horizontal).unwrap();
+        assert_eq!((block.width, block.height), (100, 20));
+        assert_eq!(block.cells[4], (80, 0));
+
+        // Glyphs that don't fit are left out
+        let block = layout(30, 20, (40, 40), Orientation::Vertical).unwrap();
+        assert_eq!(block.cells.len(), 2);
+
+        assert_eq!(layout(3, 50, (40, 200), Orientation::Vertical), None);
+        assert_eq!(layout(0, 20, (40, 200), Orientation::Vertical), None);
+    }
+
+    #[test]
+    fn test_render_page() {
+        // A white bubble with its text cleaned out, on a gray page
+        let page = RgbImage::from_fn(300, 300, |x, y| {
+            match (50..250).contains(&x) && (50..250).contains(&y) {
+                true => Rgb([255, 255, 255]),
+                false => Rgb([128, 128, 128]),
+            }
+        });
+        let text_box = TextBox {
+            x: 120,
+            y: 120,
+            width: 60,
+            height: 60,
+            score: Some(90),
+        };
+
+        let corpus = vec!["あいうえお".to_string()];
+        let fonts = vec![fonts::bundled_font()];
+        let mut rng = fastrand::Rng::with_seed(7);
+
+        let (canvas, regions) = render_page(&page, &[text_box], &corpus, &fonts, &mut rng);
+
+        // The bundled font has no kana, so there is nothing to draw
+        assert!(regions.is_empty());
+        assert_eq!(canvas, page);
+
+        let corpus = vec!["MANGA".to_string()];
+        let (canvas, regions) = render_page(&page, &[text_box], &corpus, &fonts, &mut rng);
+
+        assert_eq!(regions.len(), 1);
+        let (text_box, text) = &regions[0];
+        assert!(text_box.x >= 50 && text_box.x + text_box.width <= 250);
+        assert!(text_box.y >= 50 && text_box.y + text_box.height <= 250);
+        assert!(!text.is_empty() && "MANGA".starts_with(text.as_str()));
+        assert_ne!(canvas, page);
+    }
+}
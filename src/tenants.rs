@@ -0,0 +1,159 @@
+use crate::config::{Config, ServiceArgs};
+use anyhow::{ensure, Context, Result};
+use serde::Deserialize;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+// Options of a tenant that differ from the ones the worker was started with, such as the model of a
+// group that trained its own. Options that aren't set are the worker's.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TenantOptions {
+    pub model: Option<PathBuf>,
+    pub data: Option<PathBuf>,
+    pub lang: Option<String>,
+    pub target_lang: Option<String>,
+    pub font: Option<PathBuf>,
+    pub padding: Option<u16>,
+}
+
+impl TenantOptions {
+    fn apply(&self, args: &mut ServiceArgs) {
+        if let Some(model) = &self.model {
+            args.model = model.clone();
+        }
+        if let Some(data) = &self.data {
+            args.data = Some(data.clone());
+        }
+        if let Some(lang) = &self.lang {
+            args.lang = lang.clone();
+        }
+        if let Some(target_lang) = &self.target_lang {
+            args.target_lang = target_lang.clone();
+            // The font of the worker may not have the letters of the tenant's language
+            if self.font.is_none() {
+                args.font = None;
+            }
+        }
+        if let Some(font) = &self.font {
+            args.font = Some(font.clone());
+        }
+        if let Some(padding) = self.padding {
+            args.padding = padding;
+        }
+    }
+}
+
+// Tenants a worker serves by name, read from a JSON file such as
+// {"scanlator-a": {"model": "a.onnx", "lang": "jpn"}, "scanlator-b": {"lang": "kor", "target_lang": "es"}}
+#[derive(Clone, Debug, Default, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Tenants {
+    pub tenants: HashMap<String, TenantOptions>,
+}
+
+impl Tenants {
+    pub fn load(path: &Path) -> Result<Tenants> {
+        let data = std::fs::read_to_string(path)
+            .with_context(|| format!("Could not read the tenants file {}.", path.display()))?;
+
+        let tenants: Tenants = serde_json::from_str(&data)
+            .with_context(|| format!("{} is not a valid tenants file.", path.display()))?;
+        ensure!(
+            tenants.tenants.keys().all(|name| !name.is_empty()),
+            "{} has a tenant without a name.",
+            path.display()
+        );
+
+        Ok(tenants)
+    }
+
+    // Options the jobs of each tenant are processed with: the worker's, changed by the tenant's
+    pub fn service_args(&self, worker: &ServiceArgs) -> HashMap<String, ServiceArgs> {
+        self.tenants
+            .iter()
+            .map(|(name, options)| {
+                let mut args = worker.clone();
+                options.apply(&mut args);
+
+                (name.clone(), args)
+            })
+            .collect()
+    }
+
+    // Configs the jobs of each tenant are processed with. Every tenant's model, language data and
+    // font are checked up front, so a mistake in the file stops the worker instead of failing jobs.
+    pub fn configs(&self, worker: &ServiceArgs) -> Result<HashMap<String, Config>> {
+        let mut configs = HashMap::new();
+
+        for (name, args) in self.service_args(worker) {
+            let config = args
+                .config()
+                .with_context(|| format!("The options of the tenant {name} are not valid."))?;
+            configs.insert(name, config);
+        }
+
+        Ok(configs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::config::ServiceArgs;
+    use crate::tenants::{TenantOptions, Tenants};
+    use clap::Parser;
+    use std::path::PathBuf;
+
+    #[derive(Parser)]
+    struct Args {
+        #[command(flatten)]
+        service: ServiceArgs,
+    }
+
+    #[test]
+    fn test_tenants() {
+        let tenants: Tenants = serde_json::from_str(
+            r#"{
+                "scanlator-a": {"model": "a.onnx", "padding": 4},
+                "scanlator-b": {"lang": "kor", "target_lang": "es"}
+            }"#,
+        )
+        .unwrap();
+        assert_eq!(
+            tenants.tenants["scanlator-a"],
+            TenantOptions {
+                model: Some(PathBuf::from("a.onnx")),
+                padding: Some(4),
+                ..TenantOptions::default()
+            }
+        );
+
+        let worker = Args::parse_from([
+            "worker",
+            "--model",
+            "model.onnx",
+            "--lang",
+            "jpn",
+            "--font",
+            "wild-words.ttf",
+        ])
+        .service;
+        let args = tenants.service_args(&worker);
+
+        // Options the tenant doesn't set are the worker's
+        let a = &args["scanlator-a"];
+        assert_eq!(a.model, PathBuf::from("a.onnx"));
+        assert_eq!(a.padding, 4);
+        assert_eq!(a.lang, "jpn");
+        assert_eq!(a.font, Some(PathBuf::from("wild-words.ttf")));
+
+        // A tenant writing another language gets its default font instead of the worker's
+        let b = &args["scanlator-b"];
+        assert_eq!(b.model, PathBuf::from("model.onnx"));
+        assert_eq!((b.lang.as_str(), b.target_lang.as_str()), ("kor", "es"));
+        assert_eq!(b.font, None);
+
+        // Misspelled options aren't silently ignored
+        assert!(serde_json::from_str::<Tenants>(r#"{"a": {"modle": "a.onnx"}}"#).is_err());
+    }
+}
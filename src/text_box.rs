@@ -0,0 +1,407 @@
+use crate::fonts;
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+// Location of a detected text region within the page
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TextBox {
+    pub x: i32,
+    pub y: i32,
+    pub width: i32,
+    pub height: i32,
+    // Confidence of the detection model from 0 to 100. Missing for boxes that weren't found by the
+    // model, e.g. ones given by a hook, and in files written before it was recorded.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub score: Option<u8>,
+}
+
+impl TextBox {
+    // Position and size of the box, leaving out its score
+    pub fn geometry(self) -> (i32, i32, i32, i32) {
+        (self.x, self.y, self.width, self.height)
+    }
+
+    // Cuts the box down to the part that lies within an image of the given size.
+    // Returns None if nothing of it is left.
+    pub fn clamp(self, image_width: i32, image_height: i32) -> Option<TextBox> {
+        let left = self.x.clamp(0, image_width);
+        let top = self.y.clamp(0, image_height);
+        let right = self.x.saturating_add(self.width).clamp(0, image_width);
+        let bottom = self.y.saturating_add(self.height).clamp(0, image_height);
+
+        (right > left && bottom > top).then_some(TextBox {
+            x: left,
+            y: top,
+            width: right - left,
+            height: bottom - top,
+            score: self.score,
+        })
+    }
+
+    pub fn contains(&self, other: &TextBox) -> bool {
+        self.x <= other.x
+            && self.y <= other.y
+            && self.x + self.width >= other.x + other.width
+            && self.y + self.height >= other.y + other.height
+    }
+
+    // Area of the overlap of two boxes over the area they cover together, from 0 to 1
+    pub fn iou(&self, other: &TextBox) -> f64 {
+        let width = (self.x + self.width).min(other.x + other.width) - self.x.max(other.x);
+        let height = (self.y + self.height).min(other.y + other.height) - self.y.max(other.y);
+
+        if width <= 0 || height <= 0 {
+            return 0.0;
+        }
+
+        let intersection = width as f64 * height as f64;
+        let union = self.width as f64 * self.height as f64
+            + other.width as f64 * other.height as f64
+            - intersection;
+
+        intersection / union
+    }
+
+    // Distance in pixels between the edges of two boxes, the larger of the horizontal and the
+    // vertical one. 0 if they touch or overlap.
+    pub fn gap(&self, other: &TextBox) -> i32 {
+        let horizontal = (other.x - (self.x + self.width)).max(self.x - (other.x + other.width));
+        let vertical = (other.y - (self.y + self.height)).max(self.y - (other.y + other.height));
+
+        horizontal.max(vertical).max(0)
+    }
+
+    pub fn area(&self) -> u64 {
+        self.width.max(0) as u64 * self.height.max(0) as u64
+    }
+
+    // The box grown by "padding" on every side. Boxes that touch the top or left edge of an image of
+    // the given size, or would become as large as it, are left as they are.
+    pub fn padded(self, padding: u16, image_width: i32, image_height: i32) -> TextBox {
+        let padding = padding as i32;
+
+        match self.width + padding * 2 < image_width
+            && self.height + padding * 2 < image_height
+            && self.x - padding > 0
+            && self.y - padding > 0
+        {
+            true => TextBox {
+                x: self.x - padding,
+                y: self.y - padding,
+                width: self.width + padding * 2,
+                height: self.height + padding * 2,
+                ..self
+            },
+            false => self,
+        }
+    }
+}
+
+// Order the bubbles of a row are read in
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ReadingDirection {
+    LeftToRight,
+    // Manga
+    RightToLeft,
+}
+
+impl ReadingDirection {
+    // Reading direction of pages in the language they were written in, from its ISO 639 code
+    pub fn of_language(lang: &str) -> ReadingDirection {
+        match fonts::is_japanese(lang) {
+            true => ReadingDirection::RightToLeft,
+            false => ReadingDirection::LeftToRight,
+        }
+    }
+}
+
+// Makes boxes safe to crop: clamps them to the image, drops the ones that end up empty,
+// and removes boxes that lie entirely within another one. Of identical boxes, the first is kept.
+// The order of the remaining boxes doesn't change, since region IDs are based on it.
+pub fn sanitize_boxes(boxes: &[TextBox], image_width: i32, image_height: i32) -> Vec<TextBox> {
+    let clamped: Vec<TextBox> = boxes
+        .iter()
+        .filter_map(|bbox| bbox.clamp(image_width, image_height))
+        .collect();
+
+    clamped
+        .iter()
+        .enumerate()
+        .filter(|(index, bbox)| {
+            !clamped.iter().enumerate().any(|(other_index, other)| {
+                other_index != *index
+                    && other.contains(bbox)
+                    && (other.geometry() != bbox.geometry() || other_index < *index)
+            })
+        })
+        .map(|(_, bbox)| *bbox)
+        .collect()
+}
+
+// Cuts boxes given by a caller down to the image, keeping one box for every given one so they stay
+// aligned with whatever the caller matches them to. Unlike "sanitize_boxes", nothing is dropped, and
+// a box that lies entirely outside the image is an error.
+pub fn clamp_boxes(boxes: &[TextBox], image_width: i32, image_height: i32) -> Result<Vec<TextBox>> {
+    boxes
+        .iter()
+        .map(|bbox| {
+            bbox.clamp(image_width, image_height)
+                .with_context(|| format!("Box {bbox:?} must be non-empty and lie on the page."))
+        })
+        .collect()
+}
+
+// Keeps the "max_boxes" boxes the model is most confident about, in their original order. Boxes
+// without a score were added by hooks or by hand and rank above all detected ones.
+pub fn keep_most_confident(boxes: Vec<TextBox>, max_boxes: usize) -> Vec<TextBox> {
+    if boxes.len() <= max_boxes {
+        return boxes;
+    }
+
+    let mut ranking: Vec<usize> = (0..boxes.len()).collect();
+    // Stable, so of boxes with the same score the earlier ones are kept
+    ranking.sort_by_key(|index| std::cmp::Reverse(boxes[*index].score.unwrap_or(u8::MAX)));
+
+    let mut kept = vec![false; boxes.len()];
+    for index in ranking.into_iter().take(max_boxes) {
+        kept[index] = true;
+    }
+
+    boxes
+        .into_iter()
+        .zip(kept)
+        .filter(|(_, kept)| *kept)
+        .map(|(text_box, _)| text_box)
+        .collect()
+}
+
+// Groups of boxes that touch, overlap or lie within "max_gap" pixels of each other, such as bubbles
+// joined by a connector, as indices in reading order. Boxes that are on their own aren't in any group.
+pub fn linked_groups(
+    boxes: &[TextBox],
+    max_gap: i32,
+    direction: ReadingDirection,
+) -> Vec<Vec<usize>> {
+    let mut grouped = vec![false; boxes.len()];
+    let mut groups = Vec::new();
+
+    for first in 0..boxes.len() {
+        if grouped[first] {
+            continue;
+        }
+        grouped[first] = true;
+
+        // Boxes near any box of the group join it, so chains of bubbles end up in one group
+        let mut group = vec![first];
+        let mut next = 0;
+        while let Some(current) = group.get(next).map(|index| boxes[*index]) {
+            for (index, text_box) in boxes.iter().enumerate() {
+                if !grouped[index] && current.gap(text_box) <= max_gap {
+                    grouped[index] = true;
+                    group.push(index);
+                }
+            }
+            next += 1;
+        }
+
+        if group.len() > 1 {
+            groups.push(reading_order(boxes, group, direction));
+        }
+    }
+
+    groups
+}
+
+// Sorts boxes into rows from top to bottom, then each row in the reading direction. A box starts a
+// new row when its top is below the middle of the first box of the current row.
+fn reading_order(
+    boxes: &[TextBox],
+    mut indices: Vec<usize>,
+    direction: ReadingDirection,
+) -> Vec<usize> {
+    indices.sort_by_key(|index| (boxes[*index].y, boxes[*index].x));
+
+    let mut rows: Vec<Vec<usize>> = Vec::new();
+    for index in indices {
+        let text_box = boxes[index];
+        match rows.last_mut() {
+            Some(row) if text_box.y < boxes[row[0]].y + boxes[row[0]].height / 2 => row.push(index),
+            _ => rows.push(vec![index]),
+        }
+    }
+
+    for row in rows.iter_mut() {
+        match direction {
+            ReadingDirection::LeftToRight => row.sort_by_key(|index| boxes[*index].x),
+            ReadingDirection::RightToLeft => {
+                row.sort_by_key(|index| std::cmp::Reverse(boxes[*index].x + boxes[*index].width))
+            }
+        }
+    }
+
+    rows.concat()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_box::{
+        clamp_boxes, keep_most_confident, linked_groups, sanitize_boxes, ReadingDirection, TextBox,
+    };
+
+    fn text_box(x: i32, y: i32, width: i32, height: i32) -> TextBox {
+        TextBox {
+            x,
+            y,
+            width,
+            height,
+            score: None,
+        }
+    }
+
+    #[test]
+    fn test_padded_box() {
+        assert_eq!(
+            text_box(20, 30, 40, 10).padded(10, 100, 100),
+            text_box(10, 20, 60, 30)
+        );
+        // Padding would take the box past the top of the page
+        assert_eq!(
+            text_box(20, 5, 40, 10).padded(10, 100, 100),
+            text_box(20, 5, 40, 10)
+        );
+        assert_eq!(
+            text_box(20, 30, 85, 10).padded(10, 100, 100),
+            text_box(20, 30, 85, 10)
+        );
+    }
+
+    #[test]
+    fn test_box_sanitization() {
+        let boxes = [
+            // Past the bottom right corner
+            text_box(80, 90, 40, 20),
+            // Past the top left corner
+            text_box(-10, -5, 30, 15),
+            // Entirely outside of the image
+            text_box(150, 10, 20, 20),
+            // Degenerate
+            text_box(30, 30, 0, 10),
+            text_box(40, 40, 30, 30),
+            // Within the previous box
+            text_box(45, 45, 10, 10),
+            // Duplicate of the box before the previous one
+            text_box(40, 40, 30, 30),
+        ];
+
+        assert_eq!(
+            sanitize_boxes(&boxes, 100, 100),
+            vec![
+                text_box(80, 90, 20, 10),
+                text_box(0, 0, 20, 10),
+                text_box(40, 40, 30, 30),
+            ]
+        );
+
+        // Scores are kept, and don't make a duplicate a box of its own
+        let scored = TextBox {
+            score: Some(80),
+            ..text_box(10, 10, 20, 20)
+        };
+        let rescored = TextBox {
+            score: Some(60),
+            ..scored
+        };
+        assert_eq!(sanitize_boxes(&[scored, rescored], 100, 100), vec![scored]);
+    }
+
+    #[test]
+    fn test_box_clamping() {
+        // Boxes within others and duplicates are kept, so every box has a region
+        let boxes = [
+            text_box(80, 90, 40, 20),
+            text_box(40, 40, 30, 30),
+            text_box(45, 45, 10, 10),
+            text_box(40, 40, 30, 30),
+        ];
+
+        assert_eq!(
+            clamp_boxes(&boxes, 100, 100).unwrap(),
+            vec![
+                text_box(80, 90, 20, 10),
+                text_box(40, 40, 30, 30),
+                text_box(45, 45, 10, 10),
+                text_box(40, 40, 30, 30),
+            ]
+        );
+
+        assert!(clamp_boxes(&[text_box(150, 10, 20, 20)], 100, 100).is_err());
+        assert!(clamp_boxes(&[text_box(30, 30, 0, 10)], 100, 100).is_err());
+    }
+
+    #[test]
+    fn test_keep_most_confident() {
+        let scored = |x: i32, score: Option<u8>| TextBox {
+            score,
+            ..text_box(x, 0, 10, 10)
+        };
+        let boxes = vec![
+            scored(0, Some(40)),
+            scored(10, Some(90)),
+            scored(20, None),
+            scored(30, Some(40)),
+            scored(40, Some(75)),
+        ];
+
+        assert_eq!(
+            keep_most_confident(boxes.clone(), 3),
+            vec![boxes[1], boxes[2], boxes[4]]
+        );
+        assert_eq!(
+            keep_most_confident(boxes.clone(), 4),
+            vec![boxes[0], boxes[1], boxes[2], boxes[4]]
+        );
+        assert_eq!(keep_most_confident(boxes.clone(), 5), boxes);
+    }
+
+    #[test]
+    fn test_linked_groups() {
+        let boxes = [
+            // Two bubbles joined by a connector, the second one lower and to the left
+            text_box(200, 10, 80, 60),
+            text_box(120, 30, 80, 60),
+            // A bubble on its own
+            text_box(10, 200, 50, 50),
+            // A chain of three going down the right of the page
+            text_box(220, 150, 60, 40),
+            text_box(225, 195, 50, 40),
+            text_box(215, 240, 70, 40),
+        ];
+
+        assert_eq!(text_box(0, 0, 10, 10).gap(&text_box(15, 3, 10, 10)), 5);
+        assert_eq!(text_box(0, 0, 10, 10).gap(&text_box(5, 5, 10, 10)), 0);
+        assert_eq!(text_box(0, 0, 10, 10).area(), 100);
+
+        assert_eq!(
+            linked_groups(&boxes, 10, ReadingDirection::RightToLeft),
+            vec![vec![0, 1], vec![3, 4, 5]]
+        );
+        // The pair shares a row, so it is read the other way left to right
+        assert_eq!(
+            linked_groups(&boxes, 10, ReadingDirection::LeftToRight),
+            vec![vec![1, 0], vec![3, 4, 5]]
+        );
+        // Nothing is close enough without a gap
+        assert_eq!(
+            linked_groups(&boxes, 0, ReadingDirection::RightToLeft),
+            vec![vec![0, 1]]
+        );
+        assert_eq!(
+            ReadingDirection::of_language("jpn_vert"),
+            ReadingDirection::RightToLeft
+        );
+        assert_eq!(
+            ReadingDirection::of_language("kor"),
+            ReadingDirection::LeftToRight
+        );
+    }
+}
@@ -0,0 +1,127 @@
+use serde::{Deserialize, Serialize};
+use std::ops::AddAssign;
+use std::time::{Duration, Instant};
+
+// Stages a page goes through, in order
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    Decode,
+    // Detection, along with straightening and enhancing the page under --deskew-page and --enhance
+    Detect,
+    Ocr,
+    // Cleaning the regions and typesetting the translations
+    Layout,
+    // Encoding and writing the outputs
+    Encode,
+}
+
+impl Stage {
+    pub const ALL: [Stage; 5] = [
+        Stage::Decode,
+        Stage::Detect,
+        Stage::Ocr,
+        Stage::Layout,
+        Stage::Encode,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Decode => "decode",
+            Stage::Detect => "detect",
+            Stage::Ocr => "ocr",
+            Stage::Layout => "layout",
+            Stage::Encode => "encode",
+        }
+    }
+}
+
+// Milliseconds spent in each stage of a page, or of several pages added up. Stages a page skipped,
+// such as layout for an extraction, are 0.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct StageTimings {
+    pub decode_ms: u64,
+    pub detect_ms: u64,
+    pub ocr_ms: u64,
+    pub layout_ms: u64,
+    pub encode_ms: u64,
+}
+
+impl StageTimings {
+    // Runs "run" and adds the time it took to the stage
+    pub fn time<T>(&mut self, stage: Stage, run: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = run();
+        self.add(stage, start.elapsed());
+
+        result
+    }
+
+    pub fn add(&mut self, stage: Stage, elapsed: Duration) {
+        *self.stage_mut(stage) += elapsed.as_millis() as u64;
+    }
+
+    pub fn get(&self, stage: Stage) -> u64 {
+        match stage {
+            Stage::Decode => self.decode_ms,
+            Stage::Detect => self.detect_ms,
+            Stage::Ocr => self.ocr_ms,
+            Stage::Layout => self.layout_ms,
+            Stage::Encode => self.encode_ms,
+        }
+    }
+
+    pub fn total_ms(&self) -> u64 {
+        Stage::ALL.iter().map(|stage| self.get(*stage)).sum()
+    }
+
+    fn stage_mut(&mut self, stage: Stage) -> &mut u64 {
+        match stage {
+            Stage::Decode => &mut self.decode_ms,
+            Stage::Detect => &mut self.detect_ms,
+            Stage::Ocr => &mut self.ocr_ms,
+            Stage::Layout => &mut self.layout_ms,
+            Stage::Encode => &mut self.encode_ms,
+        }
+    }
+}
+
+impl AddAssign for StageTimings {
+    fn add_assign(&mut self, other: StageTimings) {
+        for stage in Stage::ALL {
+            *self.stage_mut(stage) += other.get(stage);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::timings::{Stage, StageTimings};
+    use std::time::Duration;
+
+    #[test]
+    fn test_stage_timings() {
+        let mut timings = StageTimings::default();
+        assert_eq!(timings.time(Stage::Ocr, || 42), 42);
+
+        timings.add(Stage::Detect, Duration::from_micros(12_900));
+        timings.add(Stage::Detect, Duration::from_millis(30));
+        timings.add(Stage::Encode, Duration::from_millis(5));
+        assert_eq!(timings.detect_ms, 42);
+        assert_eq!(timings.get(Stage::Encode), 5);
+        assert_eq!(timings.layout_ms, 0);
+
+        let mut total = timings;
+        total += timings;
+        assert_eq!(total.detect_ms, 84);
+        assert_eq!(total.total_ms(), 2 * timings.total_ms());
+
+        assert_eq!(
+            serde_json::to_string(&StageTimings {
+                decode_ms: 3,
+                ..StageTimings::default()
+            })
+            .unwrap(),
+            r#"{"decode_ms":3,"detect_ms":0,"ocr_ms":0,"layout_ms":0,"encode_ms":0}"#
+        );
+    }
+}
@@ -0,0 +1,87 @@
+use crate::onnx;
+use crate::text_box::TextBox;
+use crate::utils::cancellation::CancellationToken;
+use crate::yolo::{self, InputSize, OutputLayout};
+use anyhow::{Context, Result};
+use image::RgbImage;
+use ndarray as nd;
+use std::path::Path;
+use tract_onnx::prelude::*;
+
+// Text detection with tract, an ONNX runtime written in Rust, for builds without OpenCV. Pages are
+// prepared and the predictions decoded with the image crate, the same way the OpenCV detector does,
+// so both find the same boxes up to rounding.
+pub struct TractDetector {
+    model: TypedRunnableModel<TypedModel>,
+    input_size: InputSize,
+    padding: u16,
+}
+
+impl TractDetector {
+    pub fn new(model_path: &str, padding: u16) -> Result<TractDetector> {
+        let input_size =
+            InputSize::from_dimensions(&onnx::read_input_shape(Path::new(model_path))?)?;
+        let (width, height) = (input_size.width as usize, input_size.height as usize);
+        let model = tract_onnx::onnx()
+            .model_for_path(model_path)
+            .with_context(|| format!("Could not load the detection model at {model_path}."))?
+            .with_input_fact(0, f32::fact([1, 3, height, width]).into())?
+            .into_optimized()?
+            .into_runnable()?;
+
+        Ok(TractDetector {
+            model,
+            input_size,
+            padding,
+        })
+    }
+
+    pub fn padding(&self) -> u16 {
+        self.padding
+    }
+
+    // Runs the model over a page and returns the padded boxes of every text region
+    pub fn detect(
+        &self,
+        page: &RgbImage,
+        cancellation_token: &CancellationToken,
+    ) -> Result<Vec<TextBox>> {
+        cancellation_token.check()?;
+
+        let blob = yolo::input_blob(page, self.input_size);
+        let shape = blob.shape().to_vec();
+        let input = Tensor::from_shape(&shape, &blob.into_raw_vec())?;
+        let outputs = self.model.run(tvec!(input.into()))?;
+
+        // The forward pass itself can't be interrupted, so check again once it returns
+        cancellation_token.check()?;
+
+        let output = outputs
+            .first()
+            .context("The detection model has no outputs.")?;
+        let output = nd::ArrayViewD::from_shape(output.shape(), output.as_slice::<f32>()?)?;
+        let layout = OutputLayout::from_shape(output.shape())?;
+        let output = output.into_dimensionality::<nd::Ix3>()?;
+
+        // Predictions are in the coordinates of the page padded to a square
+        let (width, height) = (page.width() as i32, page.height() as i32);
+        let side = width.max(height);
+        let candidates = yolo::candidates(
+            layout,
+            output.index_axis(nd::Axis(0), 0),
+            self.input_size,
+            (side, side),
+        );
+
+        Ok(yolo::non_max_suppression(candidates)
+            .into_iter()
+            .map(|(text_box, score)| {
+                TextBox {
+                    score: Some(yolo::score_percent(score)),
+                    ..text_box
+                }
+                .padded(self.padding, width, height)
+            })
+            .collect())
+    }
+}
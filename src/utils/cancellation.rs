@@ -0,0 +1,42 @@
+use anyhow::{bail, Result};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+
+// Shared flag used to stop long-running work (detection, OCR, replacement) part way through.
+// Clones share the same flag, so cancelling any clone cancels all of them.
+#[derive(Clone, Debug, Default)]
+pub struct CancellationToken {
+    cancelled: Arc<AtomicBool>,
+}
+
+impl CancellationToken {
+    pub fn new() -> CancellationToken {
+        CancellationToken::default()
+    }
+
+    pub fn cancel(&self) {
+        self.cancelled.store(true, Ordering::SeqCst);
+    }
+
+    pub fn is_cancelled(&self) -> bool {
+        self.cancelled.load(Ordering::SeqCst)
+    }
+
+    // Returns an error if the token has been cancelled so loops can bail out with "?"
+    pub fn check(&self) -> Result<()> {
+        if self.is_cancelled() {
+            bail!("Operation was cancelled.");
+        }
+
+        Ok(())
+    }
+}
+
+// Cancels the token when dropped. Used to stop work once the owner of a future goes away.
+pub struct CancelOnDrop(pub CancellationToken);
+
+impl Drop for CancelOnDrop {
+    fn drop(&mut self) {
+        self.0.cancel();
+    }
+}
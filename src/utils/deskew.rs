@@ -0,0 +1,159 @@
+use image::imageops::{self, FilterType};
+use image::{GrayImage, Rgb, RgbImage};
+use imageproc::contrast;
+use imageproc::geometric_transformations::{self, Interpolation};
+
+// Largest skew that is looked for, in degrees. Anything beyond this is drawn that way on purpose.
+pub const MAX_SKEW: f32 = 10.0;
+// Resolution of the search, in degrees
+const SKEW_STEP: f32 = 0.5;
+// Longest side pages are scaled down to before their skew is measured. That is enough detail to find
+// the angle, and the search would take seconds on a full-resolution scan.
+const PAGE_MEASURE_SIZE: u32 = 1000;
+
+// Estimates by how many degrees (clockwise) the text in a region is rotated, using projection profiles:
+// once the text is straightened, its lines (or columns, for vertical text) line up with the pixel rows
+// (or columns), which makes the histograms of dark pixels per row and column as peaked as they get.
+pub fn estimate_skew(region: &GrayImage) -> f32 {
+    let threshold = contrast::otsu_level(region);
+
+    let (center_x, center_y) = (region.width() as f32 / 2.0, region.height() as f32 / 2.0);
+
+    let points: Vec<(f32, f32)> = region
+        .enumerate_pixels()
+        .filter(|(_, _, pixel)| pixel.0[0] < threshold)
+        .map(|(x, y, _)| (x as f32 - center_x, y as f32 - center_y))
+        .collect();
+
+    if points.len() < 2 {
+        return 0.0;
+    }
+
+    // Rotated points stay within the circle around the center that touches the corners
+    let radius = center_x.hypot(center_y).ceil() as usize;
+    let mut rows = vec![0u64; 2 * radius + 1];
+    let mut columns = vec![0u64; 2 * radius + 1];
+
+    let num_steps = (MAX_SKEW / SKEW_STEP).round() as i32;
+
+    let mut best_skew = 0.0;
+    let mut best_score = 0;
+
+    // Trying the straight angle first keeps regions without a clear skew as they are
+    for step in (0..=num_steps).flat_map(|step| [step, -step]).skip(1) {
+        let skew = step as f32 * SKEW_STEP;
+        let (sin, cos) = (-skew.to_radians()).sin_cos();
+
+        rows.fill(0);
+        columns.fill(0);
+
+        for (x, y) in points.iter() {
+            let rotated_x = x * cos - y * sin;
+            let rotated_y = x * sin + y * cos;
+
+            columns[(rotated_x.round() as i64 + radius as i64) as usize] += 1;
+            rows[(rotated_y.round() as i64 + radius as i64) as usize] += 1;
+        }
+
+        let score: u64 = rows
+            .iter()
+            .chain(columns.iter())
+            .map(|count| count * count)
+            .sum();
+
+        if score > best_score {
+            best_score = score;
+            best_skew = skew;
+        }
+    }
+
+    best_skew
+}
+
+// Same as "estimate_skew" but for a whole scanned page, where the lines of text and panel borders
+// give away the angle the page was scanned at
+pub fn estimate_page_skew(page: &GrayImage) -> f32 {
+    let (width, height) = page.dimensions();
+    let longest_side = width.max(height);
+
+    if longest_side <= PAGE_MEASURE_SIZE {
+        return estimate_skew(page);
+    }
+
+    let scale = |side: u32| (side as u64 * PAGE_MEASURE_SIZE as u64 / longest_side as u64).max(1);
+
+    estimate_skew(&imageops::resize(
+        page,
+        scale(width) as u32,
+        scale(height) as u32,
+        FilterType::Triangle,
+    ))
+}
+
+// Rotates an image clockwise about its center, filling the uncovered corners with the background color
+pub fn rotate(image: &RgbImage, degrees: f32, background: Rgb<u8>) -> RgbImage {
+    geometric_transformations::rotate_about_center(
+        image,
+        degrees.to_radians(),
+        Interpolation::Bilinear,
+        background,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::deskew::{estimate_page_skew, estimate_skew, rotate};
+    use image::{DynamicImage, Rgb, RgbImage};
+
+    // Three dark lines of "text" on a white region
+    fn region() -> RgbImage {
+        RgbImage::from_fn(200, 120, |x, y| {
+            if (20..180).contains(&x)
+                && [30, 58, 86]
+                    .iter()
+                    .any(|line| (*line..*line + 8).contains(&y))
+            {
+                Rgb([0, 0, 0])
+            } else {
+                Rgb([255, 255, 255])
+            }
+        })
+    }
+
+    #[test]
+    fn test_skew_estimation() {
+        let straight = DynamicImage::ImageRgb8(region()).to_luma8();
+        assert_eq!(estimate_skew(&straight), 0.0);
+
+        for degrees in [4.0, -6.0] {
+            let skewed =
+                DynamicImage::ImageRgb8(rotate(&region(), degrees, Rgb([255; 3]))).to_luma8();
+            assert!((estimate_skew(&skewed) - degrees).abs() <= 0.5);
+        }
+
+        let blank = DynamicImage::ImageRgb8(RgbImage::from_pixel(10, 10, Rgb([255; 3]))).to_luma8();
+        assert_eq!(estimate_skew(&blank), 0.0);
+    }
+
+    #[test]
+    fn test_page_skew_estimation() {
+        // A page with a panel border and lines of text, larger than the size it is measured at
+        let page = RgbImage::from_fn(840, 1200, |x, y| {
+            let border = (60..780).contains(&x)
+                && (60..1140).contains(&y)
+                && !((66..774).contains(&x) && (66..1134).contains(&y));
+            let text = (180..660).contains(&x) && (240..960).contains(&y) && y % 36 < 10;
+
+            match border || text {
+                true => Rgb([0, 0, 0]),
+                false => Rgb([255, 255, 255]),
+            }
+        });
+
+        let straight = DynamicImage::ImageRgb8(page.clone()).to_luma8();
+        assert_eq!(estimate_page_skew(&straight), 0.0);
+
+        let crooked = DynamicImage::ImageRgb8(rotate(&page, -3.0, Rgb([255; 3]))).to_luma8();
+        assert!((estimate_page_skew(&crooked) + 3.0).abs() <= 0.5);
+    }
+}
@@ -0,0 +1,158 @@
+use image::{imageops, RgbImage};
+use imageproc::filter;
+
+// Share of the darkest and brightest pixels that is clipped when stretching the levels, so a few
+// specks of dust or glare don't decide the black and white points
+const CLIP_FRACTION: f64 = 0.005;
+
+// Corrections for poor scans, applied to the page that detection and OCR see. The page that is
+// cleaned and typeset is left as it was scanned.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, clap::ValueEnum)]
+pub enum EnhanceStep {
+    // Scales each color channel so the paper comes out white instead of yellowed or tinted
+    WhiteBalance,
+    // Stretches the brightness so the darkest ink is black and the paper is white
+    Levels,
+    // Smooths out screentone dots and compression noise with a small median filter
+    Denoise,
+}
+
+// Applies the steps in a fixed order that doesn't depend on the order they were given in: the
+// color cast is removed before the levels are measured, and noise is removed last so it doesn't
+// get stretched back in.
+pub fn enhance(image: &RgbImage, steps: &[EnhanceStep]) -> RgbImage {
+    let mut image = image.clone();
+
+    if steps.contains(&EnhanceStep::WhiteBalance) {
+        white_balance(&mut image);
+    }
+
+    if steps.contains(&EnhanceStep::Levels) {
+        stretch_levels(&mut image);
+    }
+
+    if steps.contains(&EnhanceStep::Denoise) {
+        image = filter::median_filter(&image, 1, 1);
+    }
+
+    image
+}
+
+fn white_balance(image: &mut RgbImage) {
+    let mut histograms = [[0usize; 256]; 3];
+
+    for pixel in image.pixels() {
+        for (channel, value) in pixel.0.iter().enumerate() {
+            histograms[channel][*value as usize] += 1;
+        }
+    }
+
+    let num_pixels = image.width() as usize * image.height() as usize;
+    let white_points = histograms.map(|histogram| upper_percentile(&histogram, num_pixels));
+
+    for pixel in image.pixels_mut() {
+        for (value, white_point) in pixel.0.iter_mut().zip(white_points) {
+            *value = stretch(*value, 0, white_point);
+        }
+    }
+}
+
+fn stretch_levels(image: &mut RgbImage) {
+    let mut histogram = [0usize; 256];
+
+    for pixel in imageops::grayscale(image).pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let num_pixels = image.width() as usize * image.height() as usize;
+    let black_point = lower_percentile(&histogram, num_pixels);
+    let white_point = upper_percentile(&histogram, num_pixels);
+
+    for pixel in image.pixels_mut() {
+        for value in pixel.0.iter_mut() {
+            *value = stretch(*value, black_point, white_point);
+        }
+    }
+}
+
+// Maps "black_point" to 0 and "white_point" to 255. Ranges that are too narrow to stretch (a blank
+// page, a channel without any signal) are left alone.
+fn stretch(value: u8, black_point: u8, white_point: u8) -> u8 {
+    if white_point <= black_point || white_point - black_point < 16 {
+        return value;
+    }
+
+    let range = (white_point - black_point) as f32;
+    let stretched = (value.saturating_sub(black_point)) as f32 * 255.0 / range;
+
+    stretched.round().min(255.0) as u8
+}
+
+fn lower_percentile(histogram: &[usize; 256], num_pixels: usize) -> u8 {
+    let clipped = (num_pixels as f64 * CLIP_FRACTION) as usize;
+    let mut count = 0;
+
+    for (value, pixels) in histogram.iter().enumerate() {
+        count += pixels;
+
+        if count > clipped {
+            return value as u8;
+        }
+    }
+
+    0
+}
+
+fn upper_percentile(histogram: &[usize; 256], num_pixels: usize) -> u8 {
+    let clipped = (num_pixels as f64 * CLIP_FRACTION) as usize;
+    let mut count = 0;
+
+    for (value, pixels) in histogram.iter().enumerate().rev() {
+        count += pixels;
+
+        if count > clipped {
+            return value as u8;
+        }
+    }
+
+    255
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::enhance::{enhance, EnhanceStep};
+    use image::{Rgb, RgbImage};
+
+    // Gray text on yellowed paper, as on an old magazine scan
+    fn washed_out_scan() -> RgbImage {
+        RgbImage::from_fn(100, 100, |x, y| match (30..70).contains(&x) && y % 10 < 3 {
+            true => Rgb([90, 85, 70]),
+            false => Rgb([220, 210, 170]),
+        })
+    }
+
+    #[test]
+    fn test_enhance() {
+        let scan = washed_out_scan();
+
+        assert_eq!(enhance(&scan, &[]), scan);
+
+        let balanced = enhance(&scan, &[EnhanceStep::WhiteBalance]);
+        assert_eq!(*balanced.get_pixel(0, 5), Rgb([255, 255, 255]));
+
+        let enhanced = enhance(&scan, &[EnhanceStep::Levels, EnhanceStep::WhiteBalance]);
+        assert_eq!(*enhanced.get_pixel(0, 5), Rgb([255, 255, 255]));
+        assert!(enhanced.get_pixel(50, 0).0.iter().all(|value| *value < 8));
+
+        // Isolated dots are removed, while the lines of text survive
+        let mut speckled = enhanced.clone();
+        speckled.put_pixel(10, 5, Rgb([0, 0, 0]));
+        let denoised = enhance(&speckled, &[EnhanceStep::Denoise]);
+        assert_eq!(*denoised.get_pixel(10, 5), Rgb([255, 255, 255]));
+        assert_eq!(denoised.get_pixel(50, 1), enhanced.get_pixel(50, 1));
+
+        // A blank page has nothing to stretch
+        let blank = RgbImage::from_pixel(10, 10, Rgb([200, 200, 200]));
+        assert_eq!(enhance(&blank, &[EnhanceStep::Levels]), blank);
+    }
+}
@@ -0,0 +1,139 @@
+use anyhow::{ensure, Context, Result};
+use reqwest::blocking::Client;
+use reqwest::{redirect, Url};
+use std::io::Read;
+use std::time::Duration;
+
+// Largest image downloaded by default. Even uncompressed double-page spreads are smaller.
+pub const DEFAULT_MAX_DOWNLOAD_BYTES: u64 = 64 * 1024 * 1024;
+// Longest a download may take, from connecting to reading the last byte
+const DOWNLOAD_TIMEOUT: Duration = Duration::from_secs(60);
+const MAX_REDIRECTS: usize = 5;
+
+// Which URLs images may be downloaded from and how large they may be. Nothing can be downloaded
+// until a host is allowed, so a request can't make the server fetch from internal addresses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct FetchPolicy {
+    // Hosts images may be downloaded from. An entry starting with a dot also allows every subdomain
+    // of it, e.g. ".s3.amazonaws.com" for the buckets of S3.
+    pub allowed_hosts: Vec<String>,
+    pub max_bytes: u64,
+}
+
+impl Default for FetchPolicy {
+    fn default() -> FetchPolicy {
+        FetchPolicy {
+            allowed_hosts: Vec::new(),
+            max_bytes: DEFAULT_MAX_DOWNLOAD_BYTES,
+        }
+    }
+}
+
+impl FetchPolicy {
+    // HTTP(S) URLs on an allowed host
+    pub fn allows(&self, url: &Url) -> bool {
+        if !matches!(url.scheme(), "http" | "https") {
+            return false;
+        }
+
+        let host = match url.host_str() {
+            Some(host) => host.to_ascii_lowercase(),
+            None => return false,
+        };
+
+        self.allowed_hosts.iter().any(|allowed| {
+            let allowed = allowed.to_ascii_lowercase();
+
+            match allowed.strip_prefix('.') {
+                Some(domain) => host == domain || host.ends_with(&allowed),
+                None => host == allowed,
+            }
+        })
+    }
+
+    // Downloads an image, refusing URLs and redirects to hosts that aren't allowed and images larger
+    // than "max_bytes", whether or not the server says how large they are up front
+    pub fn fetch(&self, url: &str) -> Result<Vec<u8>> {
+        let url = Url::parse(url).with_context(|| format!("{url} is not a valid URL."))?;
+        ensure!(
+            self.allows(&url),
+            "Images can't be downloaded from {}. Allow its host with --allowed-image-hosts.",
+            url.host_str().unwrap_or_else(|| url.as_str())
+        );
+
+        let policy = self.clone();
+        let client = Client::builder()
+            .timeout(DOWNLOAD_TIMEOUT)
+            .redirect(redirect::Policy::custom(move |attempt| {
+                if attempt.previous().len() >= MAX_REDIRECTS {
+                    attempt.error("too many redirects")
+                } else if policy.allows(attempt.url()) {
+                    attempt.follow()
+                } else {
+                    let error = format!("redirected to {}, which isn't allowed", attempt.url());
+                    attempt.error(error)
+                }
+            }))
+            .build()?;
+
+        let response = client.get(url).send()?.error_for_status()?;
+
+        if let Some(length) = response.content_length() {
+            ensure!(
+                length <= self.max_bytes,
+                "The image is {length} bytes, which is more than the limit of {} bytes.",
+                self.max_bytes
+            );
+        }
+
+        // One byte more than allowed is read to tell whether the image goes past the limit
+        let mut data = Vec::new();
+        response.take(self.max_bytes + 1).read_to_end(&mut data)?;
+
+        ensure!(
+            data.len() as u64 <= self.max_bytes,
+            "The image is more than the limit of {} bytes.",
+            self.max_bytes
+        );
+
+        Ok(data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::fetch::FetchPolicy;
+    use reqwest::Url;
+
+    #[test]
+    fn test_fetch_policy() {
+        let policy = FetchPolicy {
+            allowed_hosts: vec![
+                "images.example.com".to_string(),
+                ".S3.amazonaws.com".to_string(),
+            ],
+            ..FetchPolicy::default()
+        };
+        let allows = |url: &str| policy.allows(&Url::parse(url).unwrap());
+
+        assert!(allows("https://images.example.com/001.png"));
+        assert!(allows("http://IMAGES.example.com:8080/001.png"));
+        assert!(allows(
+            "https://bucket.s3.amazonaws.com/chapter/001.png?X-Amz-Signature=abc"
+        ));
+        assert!(allows("https://s3.amazonaws.com/bucket/001.png"));
+
+        assert!(!allows("https://example.com/001.png"));
+        assert!(!allows("https://images.example.com.evil.net/001.png"));
+        assert!(!allows("https://evils3.amazonaws.com/001.png"));
+        assert!(!allows("ftp://images.example.com/001.png"));
+        assert!(!allows("file:///etc/passwd"));
+        assert!(!allows("http://169.254.169.254/latest/meta-data/"));
+
+        // Nothing is allowed by default, and refused URLs fail before anything is requested
+        assert!(FetchPolicy::default()
+            .fetch("https://images.example.com/001.png")
+            .is_err());
+        assert!(policy.fetch("not a url").is_err());
+    }
+}
@@ -0,0 +1,121 @@
+use image::{GrayImage, Luma};
+use imageproc::contrast;
+use imageproc::distance_transform::Norm;
+use imageproc::morphology;
+
+// Standard deviation of the brightness around the text above which a region's background is taken
+// to be artwork rather than a bubble. The paper of a bubble only varies by its grain and JPEG noise.
+const MAX_BUBBLE_DEVIATION: f64 = 20.0;
+
+// How many pixels the mask reaches past the strokes of the text, so their anti-aliased edges and any
+// outline around them are covered as well
+const MASK_MARGIN: u8 = 2;
+
+// Text drawn straight onto the artwork, with no bubble behind it to clean to a solid color
+#[derive(Clone, Debug, PartialEq)]
+pub struct FloatingText {
+    // White where the text is, for inpainting it away
+    pub mask: GrayImage,
+    pub dark_text: bool,
+}
+
+// Returns the text of a region if the background around it varies too much to be a bubble
+pub fn detect(region: &GrayImage) -> Option<FloatingText> {
+    let num_pixels = region.width() as usize * region.height() as usize;
+
+    if num_pixels == 0 {
+        return None;
+    }
+
+    let (mask, dark_text) = text_mask(region);
+
+    let background: Vec<f64> = region
+        .pixels()
+        .zip(mask.pixels())
+        .filter(|(_, masked)| masked.0[0] == 0)
+        .map(|(pixel, _)| pixel.0[0] as f64)
+        .collect();
+
+    // Text that fills the whole region leaves nothing to judge the background by
+    if background.len() < num_pixels / 10 {
+        return None;
+    }
+
+    let mean = background.iter().sum::<f64>() / background.len() as f64;
+    let variance = background
+        .iter()
+        .map(|value| (value - mean).powi(2))
+        .sum::<f64>()
+        / background.len() as f64;
+
+    match variance.sqrt() > MAX_BUBBLE_DEVIATION {
+        true => Some(FloatingText { mask, dark_text }),
+        false => None,
+    }
+}
+
+// The strokes of a region's text grown by "MASK_MARGIN", and whether the text is the dark side
+pub fn text_mask(region: &GrayImage) -> (GrayImage, bool) {
+    let (strokes, dark_text) = text_strokes(region);
+
+    (
+        morphology::dilate(&strokes, Norm::LInf, MASK_MARGIN),
+        dark_text,
+    )
+}
+
+// Separates a region into text and background at Otsu's threshold, taking the text to be the side
+// with fewer pixels. Returns an image that is white on the strokes of the text, and whether the text
+// is the dark side.
+pub fn text_strokes(region: &GrayImage) -> (GrayImage, bool) {
+    let level = contrast::otsu_level(region);
+    let num_dark = region.pixels().filter(|pixel| pixel.0[0] <= level).count();
+    let dark_text = num_dark * 2 <= region.width() as usize * region.height() as usize;
+
+    let strokes = GrayImage::from_fn(region.width(), region.height(), |x, y| {
+        match (region.get_pixel(x, y).0[0] <= level) == dark_text {
+            true => Luma([255]),
+            false => Luma([0]),
+        }
+    });
+
+    (strokes, dark_text)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::floating::detect;
+    use image::{GrayImage, Luma};
+
+    // Lines of text in the given brightness over the given background
+    fn text_over(background: impl Fn(u32, u32) -> u8, text: u8) -> GrayImage {
+        GrayImage::from_fn(120, 60, |x, y| match (10..110).contains(&x) && y % 12 < 3 {
+            true => Luma([text]),
+            false => Luma([background(x, y)]),
+        })
+    }
+
+    #[test]
+    fn test_floating_text() {
+        // Noisy paper is still a bubble
+        let bubble = text_over(|x, y| 245 + ((x + 3 * y) % 7) as u8, 10);
+        assert_eq!(detect(&bubble), None);
+        assert_eq!(detect(&text_over(|_, _| 20, 240)), None);
+
+        // Dark text over a shaded background
+        let shaded = text_over(|x, _| 120 + x as u8, 10);
+        let floating = detect(&shaded).unwrap();
+        assert!(floating.dark_text);
+        assert_eq!(floating.mask.get_pixel(50, 1).0, [255]);
+        assert_eq!(floating.mask.get_pixel(50, 3).0, [255]);
+        assert_eq!(floating.mask.get_pixel(50, 7).0, [0]);
+        assert_eq!(floating.mask.get_pixel(5, 1).0, [0]);
+
+        // Light text over dark artwork
+        let night = text_over(|x, y| ((x * 7 + y * 13) % 90) as u8, 250);
+        assert!(!detect(&night).unwrap().dark_text);
+
+        assert_eq!(detect(&GrayImage::new(0, 0)), None);
+        assert_eq!(detect(&GrayImage::from_pixel(10, 10, Luma([128]))), None);
+    }
+}
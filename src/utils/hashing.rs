@@ -0,0 +1,208 @@
+use image::{imageops::FilterType, DynamicImage};
+
+// Largest hamming distance at which two pages are still treated as the same page
+pub const DUPLICATE_DISTANCE: u32 = 4;
+
+// 64-bit perceptual hash of an image. Similar images have hashes with a small hamming distance.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct ImageHash(pub u64);
+
+impl ImageHash {
+    pub fn distance(self, other: ImageHash) -> u32 {
+        (self.0 ^ other.0).count_ones()
+    }
+}
+
+// Difference hash: compares the brightness of horizontally adjacent pixels of a 9x8 thumbnail
+pub fn dhash(image: &DynamicImage) -> ImageHash {
+    let thumbnail = image.resize_exact(9, 8, FilterType::Triangle).to_luma8();
+
+    let mut hash = 0u64;
+
+    for y in 0..8 {
+        for x in 0..8 {
+            let left = thumbnail.get_pixel(x, y).0[0];
+            let right = thumbnail.get_pixel(x + 1, y).0[0];
+
+            hash = (hash << 1) | (left < right) as u64;
+        }
+    }
+
+    ImageHash(hash)
+}
+
+// Perceptual hash: compares the lowest 8x8 frequencies of a 32x32 thumbnail's DCT against their median.
+// Slower than "dhash" but robust to rescaling, recompression and small brightness shifts.
+pub fn phash(image: &DynamicImage) -> ImageHash {
+    const SIZE: usize = 32;
+    const HASH_SIZE: usize = 8;
+
+    let thumbnail = image
+        .resize_exact(SIZE as u32, SIZE as u32, FilterType::Triangle)
+        .to_luma8();
+
+    let pixels: Vec<f64> = thumbnail.pixels().map(|pixel| pixel.0[0] as f64).collect();
+
+    // The 2D DCT is separable, so it is applied to the rows and then to the columns
+    let cosines: Vec<f64> = (0..SIZE * SIZE)
+        .map(|index| {
+            let (frequency, sample) = (index / SIZE, index % SIZE);
+            ((2 * sample + 1) as f64 * frequency as f64 * std::f64::consts::PI / (2 * SIZE) as f64)
+                .cos()
+        })
+        .collect();
+
+    let mut rows = vec![0.0; SIZE * SIZE];
+
+    for y in 0..SIZE {
+        for u in 0..HASH_SIZE {
+            rows[y * SIZE + u] = (0..SIZE)
+                .map(|x| pixels[y * SIZE + x] * cosines[u * SIZE + x])
+                .sum();
+        }
+    }
+
+    let mut coefficients = Vec::with_capacity(HASH_SIZE * HASH_SIZE);
+
+    for v in 0..HASH_SIZE {
+        for u in 0..HASH_SIZE {
+            coefficients.push(
+                (0..SIZE)
+                    .map(|y| rows[y * SIZE + u] * cosines[v * SIZE + y])
+                    .sum::<f64>(),
+            );
+        }
+    }
+
+    // The DC coefficient only reflects the average brightness, so it is left out of the median
+    let mut sorted = coefficients[1..].to_vec();
+    sorted.sort_by(|a, b| a.total_cmp(b));
+    let median = sorted[sorted.len() / 2];
+
+    let hash = coefficients.iter().fold(0u64, |hash, coefficient| {
+        (hash << 1) | (*coefficient > median) as u64
+    });
+
+    ImageHash(hash)
+}
+
+// 64-bit FNV-1a hash of a sequence of byte strings. Unlike std's hasher it is the same on every
+// platform and Rust version, so it can name files that are kept between runs. The length of every
+// part is hashed too, so ["ab", "c"] and ["a", "bc"] hash differently.
+pub fn content_hash(parts: &[&[u8]]) -> u64 {
+    const OFFSET_BASIS: u64 = 0xcbf29ce484222325;
+    const PRIME: u64 = 0x100000001b3;
+
+    let mut hash = OFFSET_BASIS;
+
+    for part in parts {
+        for byte in (part.len() as u64).to_le_bytes().iter().chain(part.iter()) {
+            hash ^= *byte as u64;
+            hash = hash.wrapping_mul(PRIME);
+        }
+    }
+
+    hash
+}
+
+// Both hashes of a page. Pages only count as duplicates when both agree, which keeps mostly blank
+// pages with different text from being mistaken for each other.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PageFingerprint {
+    pub phash: ImageHash,
+    pub dhash: ImageHash,
+}
+
+impl PageFingerprint {
+    pub fn new(image: &DynamicImage) -> PageFingerprint {
+        PageFingerprint {
+            phash: phash(image),
+            dhash: dhash(image),
+        }
+    }
+
+    pub fn is_duplicate_of(&self, other: &PageFingerprint, max_distance: u32) -> bool {
+        self.phash.distance(other.phash) <= max_distance
+            && self.dhash.distance(other.dhash) <= max_distance
+    }
+}
+
+// For every page, returns the index of the first earlier page it duplicates, or "None" for unique pages.
+// Duplicates always point at a unique page, so their outputs can be copied from it directly.
+pub fn find_duplicates(fingerprints: &[PageFingerprint], max_distance: u32) -> Vec<Option<usize>> {
+    let mut unique_pages: Vec<usize> = Vec::new();
+
+    fingerprints
+        .iter()
+        .enumerate()
+        .map(|(index, fingerprint)| {
+            let original = unique_pages
+                .iter()
+                .copied()
+                .find(|unique| fingerprint.is_duplicate_of(&fingerprints[*unique], max_distance));
+
+            if original.is_none() {
+                unique_pages.push(index);
+            }
+
+            original
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::hashing::{
+        content_hash, dhash, find_duplicates, phash, PageFingerprint, DUPLICATE_DISTANCE,
+    };
+    use image::{DynamicImage, GrayImage, Luma};
+
+    #[test]
+    fn test_content_hash() {
+        assert_eq!(content_hash(&[]), 0xcbf29ce484222325);
+        assert_eq!(content_hash(&[b"page"]), content_hash(&[b"page"]));
+
+        assert_ne!(content_hash(&[b"page"]), content_hash(&[b"pagf"]));
+        assert_ne!(content_hash(&[b"ab", b"c"]), content_hash(&[b"a", b"bc"]));
+        assert_ne!(content_hash(&[b"page", b""]), content_hash(&[b"page"]));
+    }
+
+    // A page with a shaded panel and a text block whose position depends on "offset"
+    fn page(offset: u32, brightness: u8) -> DynamicImage {
+        DynamicImage::ImageLuma8(GrayImage::from_fn(200, 300, |x, y| {
+            if (offset..offset + 60).contains(&x) && (40..120).contains(&y) {
+                Luma([brightness / 3])
+            } else if y > 180 {
+                Luma([(brightness as u32 * x / 200) as u8])
+            } else {
+                Luma([brightness])
+            }
+        }))
+    }
+
+    #[test]
+    fn test_hash_distance() {
+        let original = page(30, 250);
+        let resized = original.resize_exact(400, 600, image::imageops::FilterType::Triangle);
+        let darker = page(30, 235);
+        let different = page(120, 250);
+
+        assert_eq!(phash(&original).distance(phash(&resized)), 0);
+        assert!(phash(&original).distance(phash(&darker)) <= DUPLICATE_DISTANCE);
+        assert!(dhash(&original).distance(dhash(&darker)) <= DUPLICATE_DISTANCE);
+        assert!(phash(&original).distance(phash(&different)) > DUPLICATE_DISTANCE);
+    }
+
+    #[test]
+    fn test_find_duplicates() {
+        let fingerprints: Vec<PageFingerprint> = [page(30, 250), page(120, 250), page(30, 240)]
+            .iter()
+            .map(PageFingerprint::new)
+            .collect();
+
+        assert_eq!(
+            find_duplicates(&fingerprints, DUPLICATE_DISTANCE),
+            vec![None, None, Some(0)]
+        );
+    }
+}
@@ -1,7 +1,121 @@
-use anyhow::Result;
-use image::{self, ImageBuffer, Rgb};
-use opencv::{self as cv, core, prelude::*};
-use std::slice;
+use anyhow::{bail, ensure, Result};
+use image::{self, DynamicImage, GrayImage, ImageBuffer, Rgb};
+use opencv::{self as cv, core, imgproc, prelude::*};
+use std::io::Cursor;
+#[cfg(feature = "jxl")]
+use std::io::Read;
+use std::path::Path;
+
+// Opens an input image from disk. JPEG XL isn't supported by the image crate, so it goes through jxl-oxide.
+pub fn open_image<P: AsRef<Path>>(path: P) -> Result<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    if is_jxl_path(path.as_ref()) {
+        return decode_jxl(std::fs::File::open(path)?);
+    }
+
+    Ok(image::open(path)?)
+}
+
+// Decodes an encoded input image, guessing the format from its contents
+pub fn load_image_from_memory(image_bytes: &[u8]) -> Result<DynamicImage> {
+    #[cfg(feature = "jxl")]
+    if is_jxl_data(image_bytes) {
+        return decode_jxl(image_bytes);
+    }
+
+    Ok(image::load_from_memory(image_bytes)?)
+}
+
+// Largest page decoded by default. A 12000x8000 double-page spread fits, but a page of this size
+// already takes a few hundred megabytes for each copy the pipeline makes of it.
+pub const DEFAULT_MAX_PIXELS: u64 = 100_000_000;
+
+// Reads the width and height of an encoded image from its header, without decoding the pixels.
+// JPEG XL images are only measured once decoded, so None is returned for them.
+pub fn image_dimensions(image_bytes: &[u8]) -> Result<Option<(u32, u32)>> {
+    #[cfg(feature = "jxl")]
+    if is_jxl_data(image_bytes) {
+        return Ok(None);
+    }
+
+    let dimensions = image::io::Reader::new(Cursor::new(image_bytes))
+        .with_guessed_format()?
+        .into_dimensions()?;
+
+    Ok(Some(dimensions))
+}
+
+// Fails for images with more than "max_pixels" pixels
+pub fn check_pixels(width: u32, height: u32, max_pixels: u64) -> Result<()> {
+    let pixels = width as u64 * height as u64;
+
+    ensure!(
+        pixels <= max_pixels,
+        "The image is {width}x{height} ({pixels} pixels), which is more than the limit of {max_pixels} pixels. Raise the limit with --max-pixels or split the image."
+    );
+
+    Ok(())
+}
+
+// Same as "load_image_from_memory" but refuses images larger than "max_pixels" before decoding them
+pub fn load_image_within(image_bytes: &[u8], max_pixels: u64) -> Result<DynamicImage> {
+    if let Some((width, height)) = image_dimensions(image_bytes)? {
+        check_pixels(width, height, max_pixels)?;
+    }
+
+    let image = load_image_from_memory(image_bytes)?;
+    check_pixels(image.width(), image.height(), max_pixels)?;
+
+    Ok(image)
+}
+
+#[cfg(feature = "jxl")]
+fn is_jxl_path(path: &Path) -> bool {
+    path.extension()
+        .and_then(|extension| extension.to_str())
+        .is_some_and(|extension| extension.eq_ignore_ascii_case("jxl"))
+}
+
+// JPEG XL files are either a bare codestream or wrapped in an ISOBMFF container
+#[cfg(feature = "jxl")]
+fn is_jxl_data(image_bytes: &[u8]) -> bool {
+    image_bytes.starts_with(&[0xff, 0x0a])
+        || image_bytes.starts_with(&[
+            0, 0, 0, 0x0c, b'J', b'X', b'L', b' ', 0x0d, 0x0a, 0x87, 0x0a,
+        ])
+}
+
+// Renders the first frame of a JPEG XL image. Grayscale pages are expanded to RGB and
+// any alpha channel is kept so it can be composited like other transparent inputs.
+#[cfg(feature = "jxl")]
+fn decode_jxl<R: Read>(reader: R) -> Result<DynamicImage> {
+    let image = jxl_oxide::JxlImage::builder().read(reader)?;
+    let render = image.render_frame(0)?;
+    let frame = render.image_all_channels();
+
+    let width = frame.width() as u32;
+    let height = frame.height() as u32;
+    let channels = frame.channels();
+
+    let mut samples = Vec::with_capacity((width * height * 4) as usize);
+
+    for pixel in frame.buf().chunks_exact(channels) {
+        let rgba = match *pixel {
+            [gray] => [gray, gray, gray, 1.0],
+            [gray, alpha] => [gray, gray, gray, alpha],
+            [red, green, blue] => [red, green, blue, 1.0],
+            [red, green, blue, alpha, ..] => [red, green, blue, alpha],
+            _ => bail!("Unsupported number of image channels ({channels}) in JPEG XL image."),
+        };
+
+        samples.extend(rgba.map(|sample| sample.clamp(0.0, 1.0)));
+    }
+
+    match ImageBuffer::from_raw(width, height, samples) {
+        Some(image_buffer) => Ok(DynamicImage::ImageRgba32F(image_buffer)),
+        None => bail!("Could not decode a {width}x{height} JPEG XL image."),
+    }
+}
 
 // Create a white rectangle in the same dimensions as the input Mat (Used for create writing canvas in replacement)
 pub fn get_blank_buffer(image: &core::Mat) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
@@ -13,26 +127,32 @@ pub fn get_blank_buffer(image: &core::Mat) -> Result<ImageBuffer<Rgb<u8>, Vec<u8
     Ok(converted_image_buffer)
 }
 
-// Convert CV mats back into image buffers
+// Convert CV mats back into image buffers. Grayscale, RGBA and 16-bit Mats are converted to 8-bit RGB first.
 // Credit to https://github.com/jerry73204/rust-cv-convert
 pub fn mat_to_image_buffer(image: &core::Mat) -> Result<ImageBuffer<Rgb<u8>, Vec<u8>>> {
+    if image.typ() != core::CV_8UC3 {
+        return mat_to_image_buffer(&to_rgb8_mat(image)?);
+    }
+
     let width: u32 = image.cols() as u32;
     let height: u32 = image.rows() as u32;
 
-    let shape: Vec<usize> = image
-        .mat_size()
-        .iter()
-        .map(|&dim| dim as usize)
-        .chain([image.channels() as usize])
-        .collect();
-
-    let numel = shape.iter().product();
-    let ptr = image.ptr(0)?;
+    // Mats made by "Mat::roi" are views into their parent, so each row is followed by the rest of
+    // the parent's row (the step is larger than the row). Cloning compacts them into a continuous buffer.
+    let compacted_image;
+    let image = if image.is_continuous() {
+        image
+    } else {
+        compacted_image = image.try_clone()?;
+        &compacted_image
+    };
 
-    let slice = unsafe { slice::from_raw_parts(ptr, numel) };
+    let data = image.data_bytes()?.to_vec();
 
-    Ok(ImageBuffer::from_vec(width, height, slice.to_vec())
-        .expect("Could not convert to image buffer"))
+    match ImageBuffer::from_vec(width, height, data) {
+        Some(image_buffer) => Ok(image_buffer),
+        None => bail!("Could not convert a {width}x{height} Mat to an image buffer."),
+    }
 }
 
 // Helper function to convert image buffers to OpenCV Mats
@@ -54,3 +174,193 @@ pub fn image_buffer_to_mat(image: ImageBuffer<Rgb<u8>, Vec<u8>>) -> Result<core:
 
     Ok(mat)
 }
+
+// Converts a single channel image, such as a mask, into an 8-bit grayscale Mat
+pub fn gray_image_to_mat(image: &GrayImage) -> Result<core::Mat> {
+    let (width, height) = image.dimensions();
+
+    let mat = unsafe {
+        cv::core::Mat::new_rows_cols_with_data(
+            height as i32,
+            width as i32,
+            cv::core::CV_8UC1,
+            image.as_ptr() as *mut _,
+            cv::core::Mat_AUTO_STEP,
+        )?
+        .try_clone()?
+    };
+
+    Ok(mat)
+}
+
+// Converts a decoded image of any color type into the 8-bit RGB Mat the pipeline works with.
+// Transparent pixels are composited onto white since pages are drawn on white paper.
+pub fn dynamic_image_to_mat(image: &DynamicImage) -> Result<core::Mat> {
+    if !image.color().has_alpha() {
+        // The image crate already scales grayscale and 16-bit images correctly
+        return image_buffer_to_mat(image.to_rgb8());
+    }
+
+    let rgba_image = image.to_rgba8();
+    let mut rgb_image = ImageBuffer::new(rgba_image.width(), rgba_image.height());
+
+    for (x, y, pixel) in rgba_image.enumerate_pixels() {
+        let [red, green, blue, alpha] = pixel.0;
+        let alpha = alpha as u32;
+
+        let blend =
+            |channel: u8| ((channel as u32 * alpha + 255 * (255 - alpha) + 127) / 255) as u8;
+
+        rgb_image.put_pixel(x, y, Rgb([blend(red), blend(green), blend(blue)]));
+    }
+
+    image_buffer_to_mat(rgb_image)
+}
+
+// Converts a Mat with 8 or 16-bit depth and 1, 3 or 4 channels into an 8-bit RGB Mat
+fn to_rgb8_mat(image: &core::Mat) -> Result<core::Mat> {
+    let converted = match image.depth() {
+        core::CV_8U => core::Mat::copy(image)?,
+        core::CV_16U => {
+            let mut converted = core::Mat::default();
+            image.convert_to(&mut converted, core::CV_8U, 1.0 / 257.0, 0.0)?;
+            converted
+        }
+        depth => {
+            bail!("Unsupported image depth ({depth}); only 8-bit and 16-bit images are supported.")
+        }
+    };
+
+    let color_code = match image.channels() {
+        3 => return Ok(converted),
+        1 => imgproc::COLOR_GRAY2RGB,
+        4 => imgproc::COLOR_RGBA2RGB,
+        channels => bail!(
+            "Unsupported number of image channels ({channels}); expected 1, 3, or 4 channels."
+        ),
+    };
+
+    let mut rgb_image = core::Mat::default();
+    imgproc::cvt_color(&converted, &mut rgb_image, color_code, 0)?;
+
+    Ok(rgb_image)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::image_conversion::{
+        dynamic_image_to_mat, image_dimensions, load_image_within, mat_to_image_buffer,
+    };
+    use image::{DynamicImage, ImageBuffer, ImageOutputFormat, Luma, Rgba};
+    use opencv::{core, prelude::*};
+    use std::io::Cursor;
+
+    #[test]
+    fn test_pixel_limit() {
+        let mut png = Vec::new();
+        DynamicImage::ImageLuma8(ImageBuffer::from_pixel(300, 200, Luma([0u8])))
+            .write_to(&mut Cursor::new(&mut png), ImageOutputFormat::Png)
+            .unwrap();
+
+        assert_eq!(image_dimensions(&png).unwrap(), Some((300, 200)));
+
+        assert!(load_image_within(&png, 60_000).is_ok());
+        assert_eq!(
+            format!("{}", load_image_within(&png, 59_999).unwrap_err()),
+            "The image is 300x200 (60000 pixels), which is more than the limit of 59999 pixels. Raise the limit with --max-pixels or split the image."
+        );
+
+        assert!(image_dimensions(b"not an image").is_err());
+    }
+
+    #[test]
+    fn test_rgba_image_composited_on_white() {
+        let mut rgba = ImageBuffer::from_pixel(2, 1, Rgba([255u8, 0, 0, 255]));
+        rgba.put_pixel(1, 0, Rgba([0, 0, 0, 0]));
+
+        let mat = dynamic_image_to_mat(&DynamicImage::ImageRgba8(rgba)).unwrap();
+
+        assert_eq!(mat.typ(), core::CV_8UC3);
+        assert_eq!(
+            *mat.at_2d::<core::Vec3b>(0, 0).unwrap(),
+            core::Vec3b::from([255, 0, 0])
+        );
+        assert_eq!(
+            *mat.at_2d::<core::Vec3b>(0, 1).unwrap(),
+            core::Vec3b::from([255, 255, 255])
+        );
+    }
+
+    #[test]
+    fn test_grayscale_images() {
+        let gray = ImageBuffer::from_pixel(1, 1, Luma([100u8]));
+        let gray16 = ImageBuffer::from_pixel(1, 1, Luma([u16::MAX]));
+
+        let mat = dynamic_image_to_mat(&DynamicImage::ImageLuma8(gray)).unwrap();
+        let mat16 = dynamic_image_to_mat(&DynamicImage::ImageLuma16(gray16)).unwrap();
+
+        assert_eq!(
+            *mat.at_2d::<core::Vec3b>(0, 0).unwrap(),
+            core::Vec3b::from([100, 100, 100])
+        );
+        assert_eq!(
+            *mat16.at_2d::<core::Vec3b>(0, 0).unwrap(),
+            core::Vec3b::from([255, 255, 255])
+        );
+    }
+
+    #[test]
+    fn test_mat_conversion() {
+        let gray =
+            core::Mat::new_rows_cols_with_default(1, 1, core::CV_8UC1, core::Scalar::all(100.0))
+                .unwrap();
+        let rgba = core::Mat::new_rows_cols_with_default(
+            1,
+            1,
+            core::CV_8UC4,
+            core::Scalar::new(10.0, 20.0, 30.0, 0.0),
+        )
+        .unwrap();
+        let rgb16 =
+            core::Mat::new_rows_cols_with_default(1, 1, core::CV_16UC3, core::Scalar::all(65535.0))
+                .unwrap();
+
+        assert_eq!(
+            mat_to_image_buffer(&gray).unwrap().get_pixel(0, 0).0,
+            [100, 100, 100]
+        );
+        assert_eq!(
+            mat_to_image_buffer(&rgba).unwrap().get_pixel(0, 0).0,
+            [10, 20, 30]
+        );
+        assert_eq!(
+            mat_to_image_buffer(&rgb16).unwrap().get_pixel(0, 0).0,
+            [255, 255, 255]
+        );
+
+        let parent = core::Mat::new_rows_cols_with_default(
+            4,
+            4,
+            core::CV_8UC3,
+            core::Scalar::new(1.0, 2.0, 3.0, 0.0),
+        )
+        .unwrap();
+        let roi = core::Mat::roi(&parent, core::Rect2i::new(1, 1, 2, 3)).unwrap();
+
+        assert!(!roi.is_continuous());
+
+        let roi_buffer = mat_to_image_buffer(&roi).unwrap();
+
+        assert_eq!(roi_buffer.dimensions(), (2, 3));
+        assert!(roi_buffer.pixels().all(|pixel| pixel.0 == [1, 2, 3]));
+
+        let float_mat =
+            core::Mat::new_rows_cols_with_default(1, 1, core::CV_32FC3, core::Scalar::all(0.0))
+                .unwrap();
+
+        assert_eq!(
+            format!("{}", mat_to_image_buffer(&float_mat).unwrap_err()),
+            "Unsupported image depth (5); only 8-bit and 16-bit images are supported."
+        );
+    }
+}
@@ -0,0 +1,413 @@
+use crate::fonts::{self, Script};
+
+// Where lines of a language may be broken
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Default)]
+pub enum LineBreaking {
+    // At spaces, for languages that put spaces between words
+    #[default]
+    Words,
+    // Between any two characters, for Chinese, Japanese and Thai, which don't. Thai is properly
+    // broken between words, which takes a dictionary to find, so it is broken like Chinese instead.
+    Characters,
+}
+
+impl LineBreaking {
+    // Line breaking of the language translations are written in, from its ISO 639 code
+    pub fn of_language(lang: &str) -> LineBreaking {
+        match Script::of_language(lang) {
+            // Korean puts spaces between words
+            Some(Script::Cjk) if fonts::is_korean(lang) => LineBreaking::Words,
+            Some(Script::Cjk | Script::Thai) => LineBreaking::Characters,
+            _ => LineBreaking::Words,
+        }
+    }
+
+    pub fn segments(&self, text: &str) -> Vec<Segment> {
+        match self {
+            LineBreaking::Words => segments(text),
+            LineBreaking::Characters => character_segments(text),
+        }
+    }
+}
+
+// A piece of text that lines may be broken before, and whether a space separates it from the
+// previous one. Pieces keep the punctuation that can't be separated from them.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Segment {
+    pub text: String,
+    pub space_before: bool,
+}
+
+// Splits text into the pieces lines may be broken between: at spaces, and after dashes and
+// ellipses between words ("Wait—what", "So...you"). Lines never start with closing punctuation
+// set apart by spaces ("Wait …", "Really ?!") or end with an opening quote or bracket, which are
+// kept with the word next to them instead.
+pub fn segments(text: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    // Opening punctuation waiting for the word it belongs to
+    let mut opening = String::new();
+
+    for word in text.split_whitespace() {
+        if !word.chars().any(char::is_alphanumeric) {
+            if word.starts_with(is_opening) {
+                opening.push_str(word);
+                opening.push(' ');
+                continue;
+            }
+
+            if opening.is_empty() {
+                if let Some(previous) = segments.last_mut() {
+                    previous.text.push(' ');
+                    previous.text.push_str(word);
+                    continue;
+                }
+            }
+        }
+
+        for (i, piece) in split_after_breaks(word).into_iter().enumerate() {
+            let text = match i {
+                0 => format!("{}{piece}", std::mem::take(&mut opening)),
+                _ => piece.to_string(),
+            };
+
+            segments.push(Segment {
+                text,
+                space_before: i == 0,
+            });
+        }
+    }
+
+    // Opening punctuation at the very end has no word to go with
+    if !opening.is_empty() {
+        let opening = opening.trim_end().to_string();
+
+        match segments.last_mut() {
+            Some(previous) => {
+                previous.text.push(' ');
+                previous.text.push_str(&opening);
+            }
+            None => segments.push(Segment {
+                text: opening,
+                space_before: true,
+            }),
+        }
+    }
+
+    segments
+}
+
+// Splits text into single characters, each with the marks that combine with it. Runs of letters and
+// digits of other scripts ("OK", "100") are kept whole, lines don't start with closing punctuation,
+// small kana or the long vowel mark, and don't end with opening punctuation or Thai vowels that are
+// written before their consonant.
+fn character_segments(text: &str) -> Vec<Segment> {
+    let mut segments: Vec<Segment> = Vec::new();
+    let mut space_before = false;
+    let mut previous: Option<char> = None;
+
+    for c in text.chars() {
+        if c.is_whitespace() {
+            space_before = true;
+            continue;
+        }
+
+        let joins = match previous {
+            Some(previous) => {
+                is_opening(previous)
+                    || is_leading_vowel(previous)
+                    || is_closing(c)
+                    || is_mark(c)
+                    || (!space_before && is_word_character(previous) && is_word_character(c))
+            }
+            None => false,
+        };
+
+        match segments.last_mut() {
+            Some(segment) if joins => {
+                if space_before {
+                    segment.text.push(' ');
+                }
+
+                segment.text.push(c);
+            }
+            _ => segments.push(Segment {
+                text: c.to_string(),
+                space_before,
+            }),
+        }
+
+        space_before = false;
+        previous = Some(c);
+    }
+
+    segments
+}
+
+// Letters and digits of scripts that put spaces between words
+fn is_word_character(c: char) -> bool {
+    c.is_alphanumeric() && !is_spaceless(c)
+}
+
+// Characters of Chinese, Japanese and Thai
+fn is_spaceless(c: char) -> bool {
+    matches!(c as u32,
+        0x0E00..=0x0E7F // Thai
+        | 0x3000..=0x30FF // CJK punctuation, hiragana and katakana
+        | 0x3400..=0x4DBF // CJK ideographs, extension A
+        | 0x4E00..=0x9FFF // CJK ideographs
+        | 0xF900..=0xFAFF // CJK compatibility ideographs
+        | 0xFF00..=0xFFEF // Full and half width forms
+        | 0x20000..=0x2FFFF // CJK ideographs, extensions B and later
+    )
+}
+
+// Marks drawn onto the character before them
+fn is_mark(c: char) -> bool {
+    matches!(c as u32,
+        0x0300..=0x036F // Combining diacritics
+        | 0x0E31 | 0x0E34..=0x0E3A | 0x0E47..=0x0E4E // Thai vowel and tone marks
+        | 0x3099..=0x309A // Combining (semi-)voiced sound marks
+        | 0x200D // Zero width joiner
+        | 0xFE00..=0xFE0F // Variation selectors
+    )
+}
+
+// Thai vowels that are written before the consonant they follow in speech
+fn is_leading_vowel(c: char) -> bool {
+    matches!(c, 'เ' | 'แ' | 'โ' | 'ใ' | 'ไ')
+}
+
+// Characters a line may not start with
+fn is_closing(c: char) -> bool {
+    matches!(
+        c,
+        '.' | ','
+            | ';'
+            | ':'
+            | '!'
+            | '?'
+            | ')'
+            | ']'
+            | '}'
+            | '”'
+            | '’'
+            | '»'
+            | '、'
+            | '。'
+            | '，'
+            | '．'
+            | '：'
+            | '；'
+            | '！'
+            | '？'
+            | '）'
+            | '］'
+            | '｝'
+            | '」'
+            | '』'
+            | '】'
+            | '〉'
+            | '》'
+            | '〕'
+            | '…'
+            | '‥'
+            | '・'
+            | 'ー'
+            | '々'
+            | 'ゝ'
+            | 'ゞ'
+            | 'ヽ'
+            | 'ヾ'
+            | 'ぁ'
+            | 'ぃ'
+            | 'ぅ'
+            | 'ぇ'
+            | 'ぉ'
+            | 'っ'
+            | 'ゃ'
+            | 'ゅ'
+            | 'ょ'
+            | 'ゎ'
+            | 'ァ'
+            | 'ィ'
+            | 'ゥ'
+            | 'ェ'
+            | 'ォ'
+            | 'ッ'
+            | 'ャ'
+            | 'ュ'
+            | 'ョ'
+            | 'ヮ'
+            | 'ヵ'
+            | 'ヶ'
+    )
+}
+
+// Splits a word after the dashes and ellipses between letters within it, where a line may end
+fn split_after_breaks(word: &str) -> Vec<&str> {
+    let mut pieces = Vec::new();
+    let mut start = 0;
+    // Where the run of dashes, ellipses and dots being read started, if it follows a letter or digit
+    let mut run = None;
+    let mut after_alphanumeric = false;
+
+    for (i, c) in word.char_indices() {
+        if matches!(c, '—' | '…' | '.') {
+            if after_alphanumeric {
+                run = Some(i);
+            }
+
+            after_alphanumeric = false;
+            continue;
+        }
+
+        if let (Some(run_start), true) = (run, c.is_alphanumeric()) {
+            // Single dots are abbreviations ("U.S.") rather than ellipses
+            if is_break(&word[run_start..i]) {
+                pieces.push(&word[start..i]);
+                start = i;
+            }
+        }
+
+        run = None;
+        after_alphanumeric = c.is_alphanumeric();
+    }
+
+    pieces.push(&word[start..]);
+    pieces
+}
+
+fn is_break(run: &str) -> bool {
+    run.contains(['—', '…']) || run.contains("...")
+}
+
+fn is_opening(c: char) -> bool {
+    matches!(
+        c,
+        '(' | '['
+            | '{'
+            | '“'
+            | '‘'
+            | '„'
+            | '«'
+            | '‹'
+            | '¿'
+            | '¡'
+            | '「'
+            | '『'
+            | '（'
+            | '［'
+            | '｛'
+            | '【'
+            | '〈'
+            | '《'
+            | '〔'
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::linebreak::{segments, LineBreaking, Segment};
+
+    fn texts(text: &str) -> Vec<(String, bool)> {
+        segments(text)
+            .into_iter()
+            .map(|Segment { text, space_before }| (text, space_before))
+            .collect()
+    }
+
+    #[test]
+    fn test_segments() {
+        let spaced = |pieces: &[&str]| -> Vec<(String, bool)> {
+            pieces
+                .iter()
+                .map(|piece| (piece.to_string(), true))
+                .collect()
+        };
+
+        assert_eq!(
+            texts("  Let's go,  Ken! "),
+            spaced(&["Let's", "go,", "Ken!"])
+        );
+
+        // Closing punctuation stays on the line of the word before it
+        assert_eq!(texts("Wait … Really ?!"), spaced(&["Wait …", "Really ?!"]));
+
+        // Opening quotes and brackets go with the word after them
+        assert_eq!(
+            texts("He said « Run » ( quietly )"),
+            spaced(&["He", "said", "« Run »", "( quietly )"])
+        );
+        assert_eq!(texts("Look “"), spaced(&["Look “"]));
+
+        // Lines may break after dashes and ellipses within a word, without a space
+        assert_eq!(
+            texts("Wait—what? So...you came…back"),
+            vec![
+                ("Wait—".to_string(), true),
+                ("what?".to_string(), false),
+                ("So...".to_string(), true),
+                ("you".to_string(), false),
+                ("came…".to_string(), true),
+                ("back".to_string(), false),
+            ]
+        );
+
+        // Dashes at the edges of a word and single dots don't make breaks
+        assert_eq!(texts("—and U.S. well—"), spaced(&["—and", "U.S.", "well—"]));
+        assert_eq!(texts("…"), spaced(&["…"]));
+        assert!(texts(" ").is_empty());
+    }
+
+    #[test]
+    fn test_character_segments() {
+        let characters = |text: &str| -> Vec<String> {
+            LineBreaking::Characters
+                .segments(text)
+                .into_iter()
+                .map(|segment| segment.text)
+                .collect()
+        };
+
+        assert_eq!(characters("你好吗"), vec!["你", "好", "吗"]);
+
+        // Kinsoku: no closing punctuation, small kana or long vowel marks at the start of a line, and
+        // no opening brackets at the end of one
+        assert_eq!(
+            characters("「ちょっと待って！」ラーメン"),
+            vec!["「ちょっ", "と", "待っ", "て！」", "ラー", "メ", "ン"]
+        );
+
+        // Words of other scripts stay whole and spaces are kept
+        assert_eq!(
+            LineBreaking::Characters.segments("OK 100円"),
+            vec![
+                Segment {
+                    text: "OK".to_string(),
+                    space_before: false
+                },
+                Segment {
+                    text: "100".to_string(),
+                    space_before: true
+                },
+                Segment {
+                    text: "円".to_string(),
+                    space_before: false
+                },
+            ]
+        );
+
+        // Thai marks stay on their consonant and leading vowels before theirs
+        assert_eq!(characters("ไปที่"), vec!["ไป", "ที่"]);
+    }
+
+    #[test]
+    fn test_line_breaking_of_language() {
+        assert_eq!(LineBreaking::of_language("ja"), LineBreaking::Characters);
+        assert_eq!(LineBreaking::of_language("zh-TW"), LineBreaking::Characters);
+        assert_eq!(LineBreaking::of_language("th"), LineBreaking::Characters);
+        assert_eq!(LineBreaking::of_language("ko"), LineBreaking::Words);
+        assert_eq!(LineBreaking::of_language("en"), LineBreaking::Words);
+        assert_eq!(LineBreaking::of_language("xx"), LineBreaking::Words);
+    }
+}
@@ -0,0 +1,100 @@
+use anyhow::Result;
+use image::{GrayImage, Luma};
+use imageproc::drawing;
+use imageproc::rect::Rect;
+use serde::{Deserialize, Serialize};
+
+// Mask of a page that is white in the given areas and black everywhere else
+pub fn from_areas(width: u32, height: u32, areas: &[Rect]) -> GrayImage {
+    let mut mask = GrayImage::new(width, height);
+
+    for area in areas {
+        drawing::draw_filled_rect_mut(&mut mask, *area, Luma([255]));
+    }
+
+    mask
+}
+
+pub fn encode_png(mask: &GrayImage) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+
+    let mut encoder = png::Encoder::new(&mut buffer, mask.width(), mask.height());
+    encoder.set_color(png::ColorType::Grayscale);
+    encoder.set_depth(png::BitDepth::Eight);
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(mask.as_raw())?;
+    writer.finish()?;
+
+    Ok(buffer)
+}
+
+// Mask in the uncompressed run-length encoding of COCO, which most segmentation tools read: the
+// lengths of alternating runs of unmasked and masked pixels, going down each column from the left,
+// starting with unmasked pixels
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct RleMask {
+    // Height and width
+    pub size: [u32; 2],
+    pub counts: Vec<u32>,
+}
+
+impl RleMask {
+    // Encodes a mask whose masked pixels are the non-black ones
+    pub fn encode(mask: &GrayImage) -> RleMask {
+        let (width, height) = mask.dimensions();
+        let mut counts = Vec::new();
+        let mut masked = false;
+        let mut run = 0;
+
+        for x in 0..width {
+            for y in 0..height {
+                if (mask.get_pixel(x, y).0[0] > 0) != masked {
+                    counts.push(run);
+                    masked = !masked;
+                    run = 0;
+                }
+
+                run += 1;
+            }
+        }
+
+        counts.push(run);
+
+        RleMask {
+            size: [height, width],
+            counts,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::mask::{from_areas, RleMask};
+    use imageproc::rect::Rect;
+
+    #[test]
+    fn test_rle_mask() {
+        // Columns 1 and 2 are masked from the second row to the third of four
+        let mask = from_areas(3, 4, &[Rect::at(1, 1).of_size(5, 2)]);
+
+        assert_eq!(mask.get_pixel(1, 1).0, [255]);
+        assert_eq!(mask.get_pixel(0, 1).0, [0]);
+        assert_eq!(mask.get_pixel(2, 3).0, [0]);
+        assert_eq!(
+            RleMask::encode(&mask),
+            RleMask {
+                size: [4, 3],
+                counts: vec![5, 2, 2, 2, 1],
+            }
+        );
+
+        // Runs start with unmasked pixels, even when there are none
+        let full = from_areas(2, 2, &[Rect::at(0, 0).of_size(2, 2)]);
+        assert_eq!(RleMask::encode(&full).counts, vec![0, 4]);
+        assert_eq!(
+            serde_json::to_string(&RleMask::encode(&from_areas(1, 2, &[]))).unwrap(),
+            r#"{"size":[2,1],"counts":[2]}"#
+        );
+    }
+}
@@ -0,0 +1,312 @@
+use anyhow::Result;
+use image::{DynamicImage, ImageFormat};
+use std::path::Path;
+
+const INCHES_PER_METER: f64 = 39.3701;
+
+// Resolution, color profile and EXIF data of an input page. Pages lose all of this on the
+// round-trip through OpenCV, so it is read from the encoded input and written back onto the output.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ImageMetadata {
+    // Horizontal and vertical resolution in dots per inch
+    pub dpi: Option<(f64, f64)>,
+    pub icc_profile: Option<Vec<u8>>,
+    // Raw EXIF (TIFF) data without the JPEG "Exif" header
+    pub exif: Option<Vec<u8>>,
+}
+
+impl ImageMetadata {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<ImageMetadata> {
+        Ok(Self::read(&std::fs::read(path)?))
+    }
+
+    // Reads metadata from an encoded PNG, JPEG or WebP image. Metadata is best-effort, so
+    // unsupported formats and malformed chunks just result in missing fields.
+    pub fn read(image_bytes: &[u8]) -> ImageMetadata {
+        match image::guess_format(image_bytes) {
+            Ok(ImageFormat::Png) => read_png(image_bytes),
+            Ok(ImageFormat::Jpeg) => read_jpeg(image_bytes),
+            Ok(ImageFormat::WebP) => read_webp(image_bytes),
+            _ => ImageMetadata::default(),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self == &ImageMetadata::default()
+    }
+}
+
+// Encodes an image as an 8-bit RGB PNG carrying the given metadata
+pub fn encode_png(image: &DynamicImage, metadata: &ImageMetadata) -> Result<Vec<u8>> {
+    let image = image.to_rgb8();
+    let mut buffer = Vec::new();
+
+    let mut info = png::Info::with_size(image.width(), image.height());
+    info.color_type = png::ColorType::Rgb;
+    info.bit_depth = png::BitDepth::Eight;
+    info.pixel_dims = metadata.dpi.map(|(x_dpi, y_dpi)| png::PixelDimensions {
+        xppu: (x_dpi * INCHES_PER_METER).round() as u32,
+        yppu: (y_dpi * INCHES_PER_METER).round() as u32,
+        unit: png::Unit::Meter,
+    });
+    info.icc_profile = metadata.icc_profile.as_deref().map(Into::into);
+    info.exif_metadata = metadata.exif.as_deref().map(Into::into);
+
+    let mut writer = png::Encoder::with_info(&mut buffer, info)?.write_header()?;
+    writer.write_image_data(image.as_raw())?;
+    writer.finish()?;
+
+    Ok(buffer)
+}
+
+// Saves an output page. PNG outputs keep the metadata of the input page, other formats are
+// written by the image crate without it. Pages are written to a temporary file first, so a run that
+// is stopped never leaves a truncated page behind.
+pub fn save_image<P: AsRef<Path>>(
+    image: &DynamicImage,
+    path: P,
+    metadata: &ImageMetadata,
+) -> Result<()> {
+    let path = path.as_ref();
+    let format = ImageFormat::from_path(path)?;
+    let mut temporary_path = path.as_os_str().to_owned();
+    temporary_path.push(".partial");
+
+    if format == ImageFormat::Png {
+        std::fs::write(&temporary_path, encode_png(image, metadata)?)?;
+    } else {
+        image.save_with_format(&temporary_path, format)?;
+    }
+    std::fs::rename(&temporary_path, path)?;
+
+    Ok(())
+}
+
+fn read_png(image_bytes: &[u8]) -> ImageMetadata {
+    let reader = match png::Decoder::new(image_bytes).read_info() {
+        Ok(reader) => reader,
+        Err(_) => return ImageMetadata::default(),
+    };
+    let info = reader.info();
+
+    let dpi = info.pixel_dims.and_then(|dims| match dims.unit {
+        png::Unit::Meter => Some((
+            dims.xppu as f64 / INCHES_PER_METER,
+            dims.yppu as f64 / INCHES_PER_METER,
+        )),
+        // Without a unit the values only describe the pixel aspect ratio
+        png::Unit::Unspecified => None,
+    });
+
+    ImageMetadata {
+        dpi,
+        icc_profile: info.icc_profile.as_ref().map(|profile| profile.to_vec()),
+        exif: read_png_exif(image_bytes),
+    }
+}
+
+// The png decoder doesn't parse eXIf chunks, so they are looked up by walking the chunks directly
+fn read_png_exif(image_bytes: &[u8]) -> Option<Vec<u8>> {
+    let mut position = 8;
+
+    while position + 8 <= image_bytes.len() {
+        let length = u32::from_be_bytes([
+            image_bytes[position],
+            image_bytes[position + 1],
+            image_bytes[position + 2],
+            image_bytes[position + 3],
+        ]) as usize;
+        let chunk_type = &image_bytes[position + 4..position + 8];
+
+        let start = position + 8;
+        let end = start.checked_add(length)?;
+
+        if end > image_bytes.len() || chunk_type == b"IEND" {
+            return None;
+        }
+
+        if chunk_type == b"eXIf" {
+            return Some(image_bytes[start..end].to_vec());
+        }
+
+        // Skip the data and the CRC
+        position = end + 4;
+    }
+
+    None
+}
+
+// Walks the JPEG marker segments up to the start of the scan data
+fn read_jpeg(image_bytes: &[u8]) -> ImageMetadata {
+    let mut metadata = ImageMetadata::default();
+    // ICC profiles larger than a segment are split across several numbered APP2 segments
+    let mut icc_chunks: Vec<(u8, &[u8])> = Vec::new();
+
+    let mut position = 2;
+
+    while position + 4 <= image_bytes.len() && image_bytes[position] == 0xff {
+        let marker = image_bytes[position + 1];
+
+        // Start of scan, the metadata segments all come before it
+        if marker == 0xda {
+            break;
+        }
+
+        let length = u16::from_be_bytes([image_bytes[position + 2], image_bytes[position + 3]]);
+        let end = position + 2 + length as usize;
+
+        if length < 2 || end > image_bytes.len() {
+            break;
+        }
+
+        let segment = &image_bytes[position + 4..end];
+
+        match marker {
+            0xe0 if segment.len() >= 12 && segment.starts_with(b"JFIF\0") => {
+                let x_density = u16::from_be_bytes([segment[8], segment[9]]) as f64;
+                let y_density = u16::from_be_bytes([segment[10], segment[11]]) as f64;
+
+                metadata.dpi = match segment[7] {
+                    1 => Some((x_density, y_density)),
+                    2 => Some((x_density * 2.54, y_density * 2.54)),
+                    _ => None,
+                };
+            }
+            0xe1 if segment.starts_with(b"Exif\0\0") => {
+                metadata.exif = Some(segment[6..].to_vec());
+            }
+            0xe2 if segment.len() >= 14 && segment.starts_with(b"ICC_PROFILE\0") => {
+                icc_chunks.push((segment[12], &segment[14..]));
+            }
+            _ => {}
+        }
+
+        position = end;
+    }
+
+    if !icc_chunks.is_empty() {
+        icc_chunks.sort_by_key(|(sequence_number, _)| *sequence_number);
+        metadata.icc_profile = Some(
+            icc_chunks
+                .into_iter()
+                .flat_map(|(_, chunk)| chunk.to_vec())
+                .collect(),
+        );
+    }
+
+    metadata
+}
+
+// WebP stores ICC and EXIF data as RIFF chunks. It has no notion of resolution.
+fn read_webp(image_bytes: &[u8]) -> ImageMetadata {
+    let mut metadata = ImageMetadata::default();
+
+    let mut position = 12;
+
+    while position + 8 <= image_bytes.len() {
+        let fourcc = &image_bytes[position..position + 4];
+        let size = u32::from_le_bytes([
+            image_bytes[position + 4],
+            image_bytes[position + 5],
+            image_bytes[position + 6],
+            image_bytes[position + 7],
+        ]) as usize;
+
+        let start = position + 8;
+        let end = start + size;
+
+        if end > image_bytes.len() {
+            break;
+        }
+
+        match fourcc {
+            b"ICCP" => metadata.icc_profile = Some(image_bytes[start..end].to_vec()),
+            b"EXIF" => {
+                let exif = &image_bytes[start..end];
+                metadata.exif = Some(exif.strip_prefix(b"Exif\0\0").unwrap_or(exif).to_vec());
+            }
+            _ => {}
+        }
+
+        // Chunks are padded to an even size
+        position = end + (size & 1);
+    }
+
+    metadata
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::metadata::{encode_png, save_image, ImageMetadata};
+    use image::{DynamicImage, RgbImage};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_png_round_trip() {
+        let metadata = ImageMetadata {
+            dpi: Some((300.0, 300.0)),
+            icc_profile: Some(vec![1, 2, 3, 4]),
+            exif: Some(b"MM\0*".to_vec()),
+        };
+
+        let image = DynamicImage::ImageRgb8(RgbImage::new(2, 2));
+        let encoded = encode_png(&image, &metadata).unwrap();
+        let decoded = ImageMetadata::read(&encoded);
+
+        let (x_dpi, y_dpi) = decoded.dpi.unwrap();
+
+        assert!((x_dpi - 300.0).abs() < 0.1 && (y_dpi - 300.0).abs() < 0.1);
+        assert_eq!(decoded.icc_profile, metadata.icc_profile);
+        assert_eq!(decoded.exif, metadata.exif);
+        assert_eq!(image::load_from_memory(&encoded).unwrap(), image);
+    }
+
+    #[test]
+    fn test_save_image() {
+        let directory = TempDir::new().unwrap();
+        let image = DynamicImage::ImageRgb8(RgbImage::new(3, 2));
+
+        for file_name in ["001.png", "002.JPG"] {
+            let path = directory.path().join(file_name);
+            save_image(&image, &path, &ImageMetadata::default()).unwrap();
+
+            assert_eq!(image::image_dimensions(&path).unwrap(), (3, 2));
+        }
+
+        // Only the pages are left, without the files they were written to first
+        assert_eq!(std::fs::read_dir(directory.path()).unwrap().count(), 2);
+        assert!(save_image(
+            &image,
+            directory.path().join("003.txt"),
+            &ImageMetadata::default()
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn test_jpeg_segments() {
+        let mut jpeg = vec![0xff, 0xd8];
+
+        // JFIF header at 72x96 dots per centimeter
+        jpeg.extend([0xff, 0xe0, 0x00, 0x10]);
+        jpeg.extend(b"JFIF\0");
+        jpeg.extend([1, 2, 2, 0, 72, 0, 96, 0, 0]);
+
+        // ICC profile split across two segments, stored out of order
+        for (sequence_number, chunk) in [(2u8, [3u8, 4]), (1, [1, 2])] {
+            jpeg.extend([0xff, 0xe2, 0x00, 0x12]);
+            jpeg.extend(b"ICC_PROFILE\0");
+            jpeg.extend([sequence_number, 2]);
+            jpeg.extend(chunk);
+        }
+
+        jpeg.extend([0xff, 0xda, 0x00, 0x02]);
+
+        let metadata = ImageMetadata::read(&jpeg);
+
+        assert_eq!(metadata.dpi, Some((72.0 * 2.54, 96.0 * 2.54)));
+        assert_eq!(metadata.icc_profile, Some(vec![1, 2, 3, 4]));
+        assert_eq!(metadata.exif, None);
+        assert!(ImageMetadata::read(b"not an image").is_empty());
+    }
+}
@@ -1,2 +1,23 @@
+pub mod cancellation;
+pub mod deskew;
+#[cfg(feature = "pipeline")]
+pub mod enhance;
+#[cfg(feature = "pipeline")]
+pub mod fetch;
+pub mod floating;
+#[cfg(feature = "pipeline")]
+pub mod hashing;
+#[cfg(feature = "pipeline")]
 pub mod image_conversion;
+pub mod linebreak;
+#[cfg(feature = "pipeline")]
+pub mod mask;
+#[cfg(feature = "pipeline")]
+pub mod metadata;
+pub mod palette;
+pub mod polarity;
+pub mod quality;
+#[cfg(feature = "pipeline")]
+pub mod resolution;
+#[cfg(feature = "pipeline")]
 pub mod validation;
@@ -0,0 +1,151 @@
+use crate::utils::polarity;
+use image::{GrayImage, Rgb, RgbImage};
+
+// Smallest difference in brightness between a region's text and its background at which the color of
+// the text is kept. Faint or mostly anti-aliased strokes would otherwise give translations that can
+// hardly be read.
+const MIN_TEXT_CONTRAST: u8 = 96;
+
+// How far outlines are mixed from the background towards white or black, in percent
+const OUTLINE_MIX: u16 = 60;
+
+// Colors sampled from a region of a color page, so that translations match the bubble they are
+// set in instead of being pure black on white
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Palette {
+    pub background: Rgb<u8>,
+    pub text: Rgb<u8>,
+}
+
+impl Palette {
+    // Samples the median color of the strokes for the text and the median color of the pixels outside
+    // the mask for the background. Regions without enough of either, or whose text barely stands out,
+    // fall back to black or white text.
+    pub fn sample(region: &RgbImage, strokes: &GrayImage, mask: &GrayImage) -> Palette {
+        let text = median_color(region, strokes, true);
+        let background =
+            median_color(region, mask, false).unwrap_or_else(|| polarity::colors(false).0);
+        let (_, contrasting_text) = polarity::colors(brightness(background) < 128);
+
+        let text = match text {
+            Some(text)
+                if brightness(text).abs_diff(brightness(background)) >= MIN_TEXT_CONTRAST =>
+            {
+                text
+            }
+            _ => contrasting_text,
+        };
+
+        Palette { background, text }
+    }
+
+    // Background tinted towards white behind dark text and towards black behind light text, so the
+    // outline of floating text keeps the hue of the artwork around it
+    pub fn outline(&self) -> Rgb<u8> {
+        let target = match brightness(self.text) < 128 {
+            true => 255,
+            false => 0,
+        };
+
+        Rgb(self
+            .background
+            .0
+            .map(|channel| mix(channel, target, OUTLINE_MIX)))
+    }
+}
+
+// Median of each channel over the pixels that are white in the mask, or black if "masked" is false
+fn median_color(region: &RgbImage, mask: &GrayImage, masked: bool) -> Option<Rgb<u8>> {
+    let mut histograms = [[0usize; 256]; 3];
+    let mut count = 0usize;
+
+    for (pixel, mask_pixel) in region.pixels().zip(mask.pixels()) {
+        if (mask_pixel.0[0] != 0) != masked {
+            continue;
+        }
+
+        for (histogram, channel) in histograms.iter_mut().zip(pixel.0) {
+            histogram[channel as usize] += 1;
+        }
+        count += 1;
+    }
+
+    if count == 0 {
+        return None;
+    }
+
+    let half = count.div_ceil(2);
+
+    Some(Rgb(histograms.map(|histogram| {
+        let mut seen = 0;
+
+        histogram
+            .iter()
+            .position(|pixels| {
+                seen += pixels;
+                seen >= half
+            })
+            .unwrap_or(0) as u8
+    })))
+}
+
+// Brightness as "imageops::grayscale" weighs the channels
+fn brightness(Rgb([red, green, blue]): Rgb<u8>) -> u8 {
+    ((2126 * red as u32 + 7152 * green as u32 + 722 * blue as u32) / 10000) as u8
+}
+
+fn mix(channel: u8, target: u8, percent: u16) -> u8 {
+    ((channel as u16 * (100 - percent) + target as u16 * percent) / 100) as u8
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::palette::Palette;
+    use image::{GrayImage, Luma, Rgb, RgbImage};
+
+    #[test]
+    fn test_palette() {
+        // Dark red text in a pale yellow bubble
+        let strokes = GrayImage::from_fn(40, 20, |x, y| match (5..35).contains(&x) && y % 5 < 2 {
+            true => Luma([255]),
+            false => Luma([0]),
+        });
+        let region = RgbImage::from_fn(40, 20, |x, y| match strokes.get_pixel(x, y).0[0] {
+            0 => Rgb([250, 240, 200]),
+            _ => Rgb([120, 10, 20]),
+        });
+
+        let palette = Palette::sample(&region, &strokes, &strokes);
+        assert_eq!(palette.background, Rgb([250, 240, 200]));
+        assert_eq!(palette.text, Rgb([120, 10, 20]));
+        assert_eq!(palette.outline(), Rgb([253, 249, 233]));
+
+        // Text that barely stands out from the bubble is set in black instead
+        let faint = RgbImage::from_fn(40, 20, |x, y| match strokes.get_pixel(x, y).0[0] {
+            0 => Rgb([250, 240, 200]),
+            _ => Rgb([220, 210, 170]),
+        });
+        assert_eq!(
+            Palette::sample(&faint, &strokes, &strokes).text,
+            Rgb([0, 0, 0])
+        );
+
+        // Light text over a dark blue night sky is outlined in a darker blue
+        let night = RgbImage::from_fn(40, 20, |x, y| match strokes.get_pixel(x, y).0[0] {
+            0 => Rgb([20, 30, 90]),
+            _ => Rgb([255, 255, 255]),
+        });
+        let palette = Palette::sample(&night, &strokes, &strokes);
+        assert_eq!(palette.text, Rgb([255, 255, 255]));
+        assert_eq!(palette.outline(), Rgb([8, 12, 36]));
+
+        let empty = GrayImage::new(40, 20);
+        assert_eq!(
+            Palette::sample(&region, &empty, &empty),
+            Palette {
+                background: Rgb([250, 240, 200]),
+                text: Rgb([0, 0, 0]),
+            }
+        );
+    }
+}
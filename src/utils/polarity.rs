@@ -0,0 +1,61 @@
+use image::{GrayImage, Rgb};
+
+const WHITE: Rgb<u8> = Rgb([255, 255, 255]);
+const BLACK: Rgb<u8> = Rgb([0, 0, 0]);
+
+// Text makes up the smaller part of a region, so the median brightness is that of the background.
+// A dark background means light text, as in the black bubbles of flashbacks.
+pub fn is_inverted(region: &GrayImage) -> bool {
+    if region.is_empty() {
+        return false;
+    }
+
+    let mut histogram = [0usize; 256];
+
+    for pixel in region.pixels() {
+        histogram[pixel.0[0] as usize] += 1;
+    }
+
+    let half = (region.width() as usize * region.height() as usize).div_ceil(2);
+    let mut count = 0;
+
+    for (brightness, pixels) in histogram.iter().enumerate() {
+        count += pixels;
+
+        if count >= half {
+            return brightness < 128;
+        }
+    }
+
+    false
+}
+
+// Background and text colors to clean and typeset a region with
+pub fn colors(inverted: bool) -> (Rgb<u8>, Rgb<u8>) {
+    match inverted {
+        true => (BLACK, WHITE),
+        false => (WHITE, BLACK),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::polarity::is_inverted;
+    use image::{GrayImage, Luma};
+
+    #[test]
+    fn test_polarity() {
+        let text = |background: u8, text: u8| {
+            GrayImage::from_fn(40, 20, move |x, y| {
+                match (5..35).contains(&x) && y % 5 < 2 {
+                    true => Luma([text]),
+                    false => Luma([background]),
+                }
+            })
+        };
+
+        assert!(!is_inverted(&text(250, 10)));
+        assert!(is_inverted(&text(20, 240)));
+        assert!(!is_inverted(&GrayImage::new(0, 0)));
+    }
+}
@@ -0,0 +1,200 @@
+use crate::utils::floating;
+use image::{imageops, GrayImage};
+
+// Difference in brightness from the background from which a stroke counts as text rather than paper
+// grain or noise
+const INK_CONTRAST: u8 = 64;
+// Difference in brightness up to which a stroke was left as it was by the cleaning
+const RESIDUAL_DIFFERENCE: u8 = 32;
+// Difference in brightness from which the pixels on either side of the seam don't match
+const SEAM_DIFFERENCE: u8 = 64;
+
+// Rectangle on a page as (x, y, width, height)
+pub type Area = (u32, u32, u32, u32);
+
+// Scores from 0 to 100 how well the text in "text_area" of a page was removed by pasting "cleaned"
+// over it at "origin". Text that is left over inside the cleaned area and strokes of text that are
+// cut off at the seam around it both lower the score.
+pub fn clean_quality(
+    page: &GrayImage,
+    text_area: Area,
+    cleaned: &GrayImage,
+    origin: (u32, u32),
+) -> u8 {
+    let residual = residual_text(page, text_area, cleaned, origin);
+    let seam = seam_artifacts(page, cleaned, origin);
+
+    ((1.0 - residual) * (1.0 - seam) * 100.0).round() as u8
+}
+
+// Share of the strokes of the original text that are still on the page after cleaning
+fn residual_text(
+    page: &GrayImage,
+    (text_x, text_y, width, height): Area,
+    cleaned: &GrayImage,
+    (x, y): (u32, u32),
+) -> f64 {
+    let text = imageops::crop_imm(page, text_x, text_y, width, height).to_image();
+    let (strokes, _) = floating::text_strokes(&text);
+
+    let mut background: Vec<u8> = text
+        .pixels()
+        .zip(strokes.pixels())
+        .filter(|(_, stroke)| stroke.0[0] == 0)
+        .map(|(pixel, _)| pixel.0[0])
+        .collect();
+    background.sort_unstable();
+
+    let background = match background.get(background.len() / 2) {
+        Some(background) => *background,
+        None => return 0.0,
+    };
+
+    let mut num_strokes = 0;
+    let mut num_left = 0;
+
+    for (stroke_x, stroke_y, pixel) in text.enumerate_pixels() {
+        let original = pixel.0[0];
+
+        if strokes.get_pixel(stroke_x, stroke_y).0[0] == 0
+            || original.abs_diff(background) <= INK_CONTRAST
+        {
+            continue;
+        }
+
+        num_strokes += 1;
+
+        // Strokes outside of the cleaned area weren't cleaned at all
+        let cleaned_pixel = (text_x + stroke_x)
+            .checked_sub(x)
+            .zip((text_y + stroke_y).checked_sub(y))
+            .and_then(|(cleaned_x, cleaned_y)| cleaned.get_pixel_checked(cleaned_x, cleaned_y));
+
+        match cleaned_pixel {
+            Some(cleaned_pixel) if cleaned_pixel.0[0].abs_diff(original) > RESIDUAL_DIFFERENCE => {}
+            _ => num_left += 1,
+        }
+    }
+
+    match num_strokes {
+        0 => 0.0,
+        _ => num_left as f64 / num_strokes as f64,
+    }
+}
+
+// Share of the seam around the pasted area where the pixels on either side of it don't match. Long
+// runs of them are the edge of a bubble or panel that the area was expanded up to, so only runs of at
+// most half a side count, which is what strokes of text cut off by the seam look like.
+fn seam_artifacts(page: &GrayImage, cleaned: &GrayImage, (x, y): (u32, u32)) -> f64 {
+    let (width, height) = cleaned.dimensions();
+    let (x, y) = (x as i64, y as i64);
+
+    let sides = [
+        seam_side(page, cleaned, width, |i| (i, 0), |i| (x + i as i64, y - 1)),
+        seam_side(
+            page,
+            cleaned,
+            width,
+            |i| (i, height - 1),
+            |i| (x + i as i64, y + height as i64),
+        ),
+        seam_side(page, cleaned, height, |i| (0, i), |i| (x - 1, y + i as i64)),
+        seam_side(
+            page,
+            cleaned,
+            height,
+            |i| (width - 1, i),
+            |i| (x + width as i64, y + i as i64),
+        ),
+    ];
+
+    let num_positions: usize = sides.iter().map(Vec::len).sum();
+    let num_artifacts: usize = sides.iter().map(|side| short_runs(side)).sum();
+
+    match num_positions {
+        0 => 0.0,
+        _ => num_artifacts as f64 / num_positions as f64,
+    }
+}
+
+// Whether the pixels on either side of the seam differ, at each position along one side of the
+// pasted area. Sides that lie on the border of the page have no pixels outside of them.
+fn seam_side(
+    page: &GrayImage,
+    cleaned: &GrayImage,
+    length: u32,
+    inside: impl Fn(u32) -> (u32, u32),
+    outside: impl Fn(u32) -> (i64, i64),
+) -> Vec<bool> {
+    (0..length)
+        .filter_map(|i| {
+            let (outside_x, outside_y) = outside(i);
+
+            if outside_x < 0 || outside_y < 0 {
+                return None;
+            }
+
+            let outside_pixel = page.get_pixel_checked(outside_x as u32, outside_y as u32)?;
+            let (inside_x, inside_y) = inside(i);
+            let inside_pixel = cleaned.get_pixel_checked(inside_x, inside_y)?;
+
+            Some(outside_pixel.0[0].abs_diff(inside_pixel.0[0]) > SEAM_DIFFERENCE)
+        })
+        .collect()
+}
+
+// Number of mismatches in runs of at most half the length of the side
+fn short_runs(mismatches: &[bool]) -> usize {
+    let max_run = mismatches.len() / 2;
+
+    mismatches
+        .split(|mismatch| !mismatch)
+        .map(<[bool]>::len)
+        .filter(|run| *run <= max_run)
+        .sum()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::quality::clean_quality;
+    use image::{GrayImage, Luma};
+
+    // A bubble outlined from (10, 10) to (89, 89) with lines of text from (30, 30) to (69, 69)
+    fn page() -> GrayImage {
+        GrayImage::from_fn(100, 100, |x, y| {
+            let outline = (x == 10 || x == 89) && (10..90).contains(&y)
+                || (y == 10 || y == 89) && (10..90).contains(&x);
+            let text = (30..70).contains(&x) && (30..70).contains(&y) && y % 5 < 2;
+
+            match outline || text {
+                true => Luma([0]),
+                false => Luma([250]),
+            }
+        })
+    }
+
+    #[test]
+    fn test_clean_quality() {
+        let page = page();
+        let text_area = (28, 28, 44, 44);
+
+        // Cleaned up to the outline of the bubble
+        let cleaned = GrayImage::from_pixel(78, 78, Luma([255]));
+        assert_eq!(clean_quality(&page, text_area, &cleaned, (11, 11)), 100);
+
+        // Cleaned only on the left half of the text, which leaves the rest and cuts off its lines
+        let cleaned = GrayImage::from_pixel(30, 50, Luma([255]));
+        assert!(clean_quality(&page, text_area, &cleaned, (20, 25)) < 60);
+
+        // Text that is still there after cleaning
+        let smudged = GrayImage::from_fn(78, 78, |_, y| match (y + 11) % 5 < 2 {
+            true => Luma([20]),
+            false => Luma([255]),
+        });
+        assert!(clean_quality(&page, text_area, &smudged, (11, 11)) < 50);
+
+        // An empty region has no text to leave over
+        let blank = GrayImage::from_pixel(100, 100, Luma([250]));
+        assert_eq!(clean_quality(&blank, text_area, &cleaned, (20, 25)), 100);
+    }
+}
@@ -0,0 +1,99 @@
+use crate::utils::floating;
+use image::GrayImage;
+
+// Size in pixels of a glyph of body text (10 pt) scanned at 300 DPI, which tesseract is tuned for
+const GLYPH_SIZE_AT_300_DPI: f64 = 42.0;
+// Resolutions tesseract accepts for "user_defined_dpi"
+const MIN_DPI: u32 = 70;
+const MAX_DPI: u32 = 2400;
+// Runs of ink shorter than this are specks or thin strokes rather than lines of text
+const MIN_RUN: usize = 3;
+
+// Estimates the resolution a region would have been scanned at if its text were set at the size of
+// body text, from the size of its glyphs. Small bubbles have small glyphs, and tesseract otherwise
+// assumes its fallback resolution, which misreads vertical Japanese in particular. Returns None for
+// regions without text.
+pub fn estimate_dpi(region: &GrayImage) -> Option<u32> {
+    let glyph_size = glyph_size(region)?;
+    let dpi = (glyph_size * 300.0 / GLYPH_SIZE_AT_300_DPI).round() as u32;
+
+    Some(dpi.clamp(MIN_DPI, MAX_DPI))
+}
+
+// Median thickness of the lines of horizontal text or the columns of vertical text. Lines show up as
+// runs of rows with ink in them, columns as runs of columns. The other direction runs across all lines
+// at once, so the direction with the shorter longest run is the one across the lines.
+fn glyph_size(region: &GrayImage) -> Option<f64> {
+    let (strokes, _) = floating::text_strokes(region);
+    let (width, height) = strokes.dimensions();
+
+    let rows: Vec<bool> = (0..height)
+        .map(|y| (0..width).any(|x| strokes.get_pixel(x, y).0[0] != 0))
+        .collect();
+    let columns: Vec<bool> = (0..width)
+        .map(|x| (0..height).any(|y| strokes.get_pixel(x, y).0[0] != 0))
+        .collect();
+
+    let line_runs = ink_runs(&rows);
+    let column_runs = ink_runs(&columns);
+
+    let mut runs = match (line_runs.iter().max(), column_runs.iter().max()) {
+        (Some(longest_line), Some(longest_column)) if longest_column < longest_line => column_runs,
+        (Some(_), _) => line_runs,
+        (None, _) => column_runs,
+    };
+    runs.sort_unstable();
+
+    runs.get(runs.len() / 2).map(|run| *run as f64)
+}
+
+// Lengths of the runs of ink, leaving out specks
+fn ink_runs(profile: &[bool]) -> Vec<usize> {
+    profile
+        .split(|ink| !ink)
+        .map(<[bool]>::len)
+        .filter(|run| *run >= MIN_RUN)
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::utils::resolution::estimate_dpi;
+    use image::{GrayImage, Luma};
+
+    // Lines of text "size" pixels thick, set horizontally or in vertical columns
+    fn text(size: u32, vertical: bool) -> GrayImage {
+        GrayImage::from_fn(300, 300, |x, y| {
+            let (along, across) = match vertical {
+                true => (y, x),
+                false => (x, y),
+            };
+            let ink = (20..280).contains(&along)
+                && (20..280).contains(&across)
+                && across % (size * 2) < size
+                // Gaps between glyphs, which don't line up from one line to the next
+                && along % 7 != across / (size * 2) % 7;
+
+            match ink {
+                true => Luma([0]),
+                false => Luma([255]),
+            }
+        })
+    }
+
+    #[test]
+    fn test_estimate_dpi() {
+        assert_eq!(estimate_dpi(&text(42, false)), Some(300));
+        assert_eq!(estimate_dpi(&text(14, false)), Some(100));
+        assert_eq!(estimate_dpi(&text(14, true)), Some(100));
+        assert_eq!(estimate_dpi(&text(28, true)), Some(200));
+
+        // Tiny text still gets a resolution tesseract accepts
+        assert_eq!(estimate_dpi(&text(4, false)), Some(70));
+
+        assert_eq!(
+            estimate_dpi(&GrayImage::from_pixel(50, 50, Luma([255]))),
+            None
+        );
+    }
+}
@@ -1,6 +1,8 @@
 use anyhow::{bail, ensure, Result};
+use globwalk::GlobWalkerBuilder;
 use std::collections::HashSet;
 use std::env;
+use std::fs::File;
 use std::path::{Path, PathBuf};
 use tracing::error;
 
@@ -22,13 +24,13 @@ pub fn validate_model(model: &Path) -> Result<()> {
     }
 }
 
-// Validate that text files are JSONs
+// Validate that text files are JSONs, XLIFF files or tables
 pub fn validate_text(text: &Path) -> Result<()> {
     if let Some(extension) = text.extension() {
         match extension.to_str() {
-            Some("json") => Ok(()),
+            Some("json" | "xlf" | "xliff" | "csv" | "tsv") => Ok(()),
             Some(_) => {
-                bail!("Text file must be a JSON file.");
+                bail!("Text file must be a JSON, XLIFF, CSV or TSV file.");
             }
             None => {
                 let bad_path = text.display();
@@ -36,12 +38,12 @@ pub fn validate_text(text: &Path) -> Result<()> {
             }
         }
     } else {
-        bail!("Text file must be a JSON file.");
+        bail!("Text file must be a JSON, XLIFF, CSV or TSV file.");
     }
 }
 
 pub fn validate_replace_mode(input_stems: Vec<String>, text_paths: &[PathBuf]) -> Result<()> {
-    let output_hash = &text_paths
+    let output_hash = text_paths
         .iter()
         .filter_map(|path| match path.file_stem() {
             Some(stem) => stem.to_str(),
@@ -49,6 +51,15 @@ pub fn validate_replace_mode(input_stems: Vec<String>, text_paths: &[PathBuf]) -
         })
         .collect::<HashSet<&str>>();
 
+    validate_text_pages(input_stems, &output_hash)
+}
+
+// Same as "validate_replace_mode" for a single table holding the text of every page
+pub fn validate_table_pages(input_stems: Vec<String>, table_pages: &[&str]) -> Result<()> {
+    validate_text_pages(input_stems, &table_pages.iter().copied().collect())
+}
+
+fn validate_text_pages(input_stems: Vec<String>, output_hash: &HashSet<&str>) -> Result<()> {
     let mut validated = true;
 
     for input_stem in input_stems {
@@ -65,13 +76,62 @@ pub fn validate_replace_mode(input_stems: Vec<String>, text_paths: &[PathBuf]) -
     }
 }
 
+// Image formats accepted as input. AVIF and JPEG XL depend on the "avif" and "jxl" features.
+pub fn supported_image_formats() -> Vec<&'static str> {
+    #[allow(unused_mut)]
+    let mut formats = vec!["JPG", "PNG", "WebP"];
+
+    #[cfg(feature = "avif")]
+    formats.push("AVIF");
+
+    #[cfg(feature = "jxl")]
+    formats.push("JPEG XL");
+
+    formats
+}
+
+// Glob pattern matching every supported input image in a directory
+pub fn image_glob_pattern() -> String {
+    #[allow(unused_mut)]
+    let mut extensions = vec![
+        "jpg", "JPG", "jpeg", "JPEG", "png", "PNG", "webp", "WEBP", "tiff", "TIFF",
+    ];
+
+    #[cfg(feature = "avif")]
+    extensions.extend(["avif", "AVIF"]);
+
+    #[cfg(feature = "jxl")]
+    extensions.extend(["jxl", "JXL"]);
+
+    format!("*{{{}}}", extensions.join(","))
+}
+
+// Supported images directly within a directory, sorted by path
+pub fn images_in_directory(directory: &Path) -> Result<Vec<PathBuf>> {
+    let mut images: Vec<PathBuf> =
+        GlobWalkerBuilder::from_patterns(directory, &[image_glob_pattern()])
+            .max_depth(1)
+            .follow_links(false)
+            .build()?
+            .filter_map(|entry| Some(entry.ok()?.into_path()))
+            .collect();
+    images.sort();
+
+    Ok(images)
+}
+
 // Validate image is in one of allowed image formats
 pub fn validate_image(image: &Path) -> Result<()> {
     if let Some(extension) = image.extension() {
         match extension.to_str() {
             Some("jpg" | "jpeg" | "png" | "webp") => Ok(()),
+            #[cfg(feature = "avif")]
+            Some("avif") => Ok(()),
+            #[cfg(feature = "jxl")]
+            Some("jxl") => Ok(()),
             Some(_) => {
-                bail!("Image file must be in one of the specified formats: JPG, PNG, WebP.");
+                let formats = supported_image_formats().join(", ");
+                bail!("Image file must be in one of the specified formats: {formats}.");
             }
             None => {
                 let bad_path = image.display();
@@ -106,11 +166,60 @@ pub fn validate_data(data: &Option<PathBuf>) -> Result<PathBuf> {
     }
 }
 
+// Validate that the tessdata folder has a readable ".traineddata" file for each requested language.
+// Multiple languages are separated by "+", the same as for tesseract.
+pub fn validate_lang(data: &Path, lang: &str) -> Result<()> {
+    for language in lang.split('+') {
+        let traineddata = data.join(format!("{language}.traineddata"));
+
+        if language.is_empty() || File::open(&traineddata).is_err() {
+            let available = available_languages(data);
+
+            let available = if available.is_empty() {
+                "none".to_string()
+            } else {
+                available.join(", ")
+            };
+
+            bail!(
+                "No readable language data for \"{language}\" in {}. Available languages: {available}.",
+                data.display()
+            );
+        }
+    }
+
+    Ok(())
+}
+
+// Languages with a ".traineddata" file in the tessdata folder, sorted by name
+fn available_languages(data: &Path) -> Vec<String> {
+    let mut languages: Vec<String> = match std::fs::read_dir(data) {
+        Ok(entries) => entries
+            .filter_map(|entry| {
+                let path = entry.ok()?.path();
+
+                if path.extension()? != "traineddata" {
+                    return None;
+                }
+
+                Some(path.file_stem()?.to_str()?.to_string())
+            })
+            .collect(),
+        Err(_) => Vec::new(),
+    };
+
+    languages.sort();
+
+    languages
+}
+
 #[cfg(test)]
 mod tests {
     use std::path::Path;
 
-    use crate::utils::validation::{validate_data, validate_image, validate_model, validate_text};
+    use crate::utils::validation::{
+        validate_data, validate_image, validate_lang, validate_model, validate_text,
+    };
     use tempfile::TempDir;
 
     #[test]
@@ -142,6 +251,7 @@ mod tests {
         let test_dir_path = TempDir::new().unwrap();
 
         let good_result = validate_text(good_text_path);
+        let xliff_result = validate_text(Path::new("./text.xlf"));
         let bad_err = validate_text(bad_text_path).unwrap_err();
         let dir_err = validate_text(test_dir_path.path()).unwrap_err();
 
@@ -152,9 +262,17 @@ mod tests {
             }
         }
 
-        assert_eq!(format!("{bad_err}"), "Text file must be a JSON file.");
+        assert!(xliff_result.is_ok());
+
+        assert_eq!(
+            format!("{bad_err}"),
+            "Text file must be a JSON, XLIFF, CSV or TSV file."
+        );
 
-        assert_eq!(format!("{dir_err}"), "Text file must be a JSON file.");
+        assert_eq!(
+            format!("{dir_err}"),
+            "Text file must be a JSON, XLIFF, CSV or TSV file."
+        );
     }
 
     #[test]
@@ -239,4 +357,26 @@ mod tests {
             "Libtesseract data path must lead to a directory."
         );
     }
+
+    #[test]
+    fn test_lang_validation() {
+        let data_path = TempDir::new().unwrap();
+
+        std::fs::write(data_path.path().join("jpn.traineddata"), []).unwrap();
+        std::fs::write(data_path.path().join("eng.traineddata"), []).unwrap();
+        std::fs::write(data_path.path().join("notes.txt"), []).unwrap();
+
+        validate_lang(data_path.path(), "jpn").unwrap();
+        validate_lang(data_path.path(), "jpn+eng").unwrap();
+
+        let err = validate_lang(data_path.path(), "jpn+kor").unwrap_err();
+
+        assert_eq!(
+            format!("{err}"),
+            format!(
+                "No readable language data for \"kor\" in {}. Available languages: eng, jpn.",
+                data_path.path().display()
+            )
+        );
+    }
 }
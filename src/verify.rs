@@ -0,0 +1,420 @@
+use crate::utils::{image_conversion, validation};
+use anyhow::{bail, ensure, Result};
+use image::{imageops, GrayImage, Rgb, RgbImage};
+use std::path::{Path, PathBuf};
+
+// Side of the square windows SSIM is computed over
+const SSIM_WINDOW: u32 = 8;
+// Stabilizing constants of SSIM for 8-bit images, (0.01 * 255)^2 and (0.03 * 255)^2
+const SSIM_C1: f64 = 6.5025;
+const SSIM_C2: f64 = 58.5225;
+
+// When a rendered page still counts as matching its golden image
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Thresholds {
+    // Lowest mean structural similarity, from 0 to 1
+    pub min_ssim: f64,
+    // Highest fraction of pixels that may differ by more than "tolerance"
+    pub max_changed: f64,
+    // Largest difference per color channel that is not counted as a change, to allow for encoder noise
+    pub tolerance: u8,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub enum Verdict {
+    Match,
+    Regressed(String),
+    // Golden image without a rendered page
+    Missing,
+    // Rendered page without a golden image
+    New,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Comparison {
+    pub page: String,
+    pub ssim: Option<f64>,
+    pub changed: Option<f64>,
+    pub verdict: Verdict,
+}
+
+// Mean structural similarity of two images of the same size, over non-overlapping windows.
+// Images smaller than a window are compared as a single window.
+pub fn ssim(expected: &GrayImage, actual: &GrayImage) -> f64 {
+    let (width, height) = expected.dimensions();
+    let window_width = SSIM_WINDOW.min(width).max(1);
+    let window_height = SSIM_WINDOW.min(height).max(1);
+
+    let mut total = 0.0;
+    let mut num_windows = 0;
+
+    for y in (0..=height.saturating_sub(window_height)).step_by(window_height as usize) {
+        for x in (0..=width.saturating_sub(window_width)).step_by(window_width as usize) {
+            let pixels = (y..y + window_height)
+                .flat_map(|y| (x..x + window_width).map(move |x| (x, y)))
+                .map(|(x, y)| {
+                    (
+                        expected.get_pixel(x, y).0[0] as f64,
+                        actual.get_pixel(x, y).0[0] as f64,
+                    )
+                });
+
+            total += window_ssim(pixels);
+            num_windows += 1;
+        }
+    }
+
+    match num_windows {
+        0 => 1.0,
+        _ => total / num_windows as f64,
+    }
+}
+
+fn window_ssim(pixels: impl Iterator<Item = (f64, f64)> + Clone) -> f64 {
+    let count = pixels.clone().count() as f64;
+
+    let (sum_expected, sum_actual) = pixels
+        .clone()
+        .fold((0.0, 0.0), |(a, b), (expected, actual)| {
+            (a + expected, b + actual)
+        });
+    let (mean_expected, mean_actual) = (sum_expected / count, sum_actual / count);
+
+    let (variance_expected, variance_actual, covariance) = pixels.fold(
+        (0.0, 0.0, 0.0),
+        |(variance_expected, variance_actual, covariance), (expected, actual)| {
+            let (expected, actual) = (expected - mean_expected, actual - mean_actual);
+
+            (
+                variance_expected + expected * expected,
+                variance_actual + actual * actual,
+                covariance + expected * actual,
+            )
+        },
+    );
+    let (variance_expected, variance_actual, covariance) = (
+        variance_expected / count,
+        variance_actual / count,
+        covariance / count,
+    );
+
+    ((2.0 * mean_expected * mean_actual + SSIM_C1) * (2.0 * covariance + SSIM_C2))
+        / ((mean_expected.powi(2) + mean_actual.powi(2) + SSIM_C1)
+            * (variance_expected + variance_actual + SSIM_C2))
+}
+
+fn is_changed(expected: &Rgb<u8>, actual: &Rgb<u8>, tolerance: u8) -> bool {
+    expected
+        .0
+        .iter()
+        .zip(actual.0)
+        .any(|(expected, actual)| expected.abs_diff(actual) > tolerance)
+}
+
+// Fraction of pixels that differ by more than the tolerance in any channel
+pub fn changed_fraction(expected: &RgbImage, actual: &RgbImage, tolerance: u8) -> f64 {
+    let num_pixels = expected.width() as u64 * expected.height() as u64;
+
+    if num_pixels == 0 {
+        return 0.0;
+    }
+
+    let changed = expected
+        .pixels()
+        .zip(actual.pixels())
+        .filter(|(expected, actual)| is_changed(expected, actual, tolerance))
+        .count();
+
+    changed as f64 / num_pixels as f64
+}
+
+// Dimmed copy of the golden image with the changed pixels in red, for looking at what moved
+pub fn diff_image(expected: &RgbImage, actual: &RgbImage, tolerance: u8) -> RgbImage {
+    RgbImage::from_fn(expected.width(), expected.height(), |x, y| {
+        let (expected, actual) = (expected.get_pixel(x, y), actual.get_pixel(x, y));
+
+        match is_changed(expected, actual, tolerance) {
+            true => Rgb([255, 0, 0]),
+            false => Rgb(expected.0.map(|channel| 128 + channel / 2)),
+        }
+    })
+}
+
+pub fn compare(
+    page: &str,
+    expected: &RgbImage,
+    actual: &RgbImage,
+    thresholds: &Thresholds,
+) -> Comparison {
+    if expected.dimensions() != actual.dimensions() {
+        let (expected_width, expected_height) = expected.dimensions();
+        let (actual_width, actual_height) = actual.dimensions();
+
+        return Comparison {
+            page: page.to_string(),
+            ssim: None,
+            changed: None,
+            verdict: Verdict::Regressed(format!(
+                "size changed from {expected_width}x{expected_height} to {actual_width}x{actual_height}"
+            )),
+        };
+    }
+
+    let ssim = ssim(&imageops::grayscale(expected), &imageops::grayscale(actual));
+    let changed = changed_fraction(expected, actual, thresholds.tolerance);
+
+    let verdict = if ssim < thresholds.min_ssim {
+        Verdict::Regressed(format!("SSIM {ssim:.4} is below {}", thresholds.min_ssim))
+    } else if changed > thresholds.max_changed {
+        Verdict::Regressed(format!(
+            "{:.3}% of pixels changed, more than {}%",
+            changed * 100.0,
+            thresholds.max_changed * 100.0
+        ))
+    } else {
+        Verdict::Match
+    };
+
+    Comparison {
+        page: page.to_string(),
+        ssim: Some(ssim),
+        changed: Some(changed),
+        verdict,
+    }
+}
+
+// One line per page that didn't match, then the totals
+pub fn report(comparisons: &[Comparison]) -> String {
+    let mut report = String::new();
+    let (mut matched, mut regressed, mut missing, mut new) = (0, 0, 0, 0);
+
+    for comparison in comparisons {
+        match &comparison.verdict {
+            Verdict::Match => matched += 1,
+            Verdict::Regressed(reason) => {
+                regressed += 1;
+                report.push_str(&format!("REGRESSED {}: {reason}\n", comparison.page));
+            }
+            Verdict::Missing => {
+                missing += 1;
+                report.push_str(&format!("MISSING   {}\n", comparison.page));
+            }
+            Verdict::New => {
+                new += 1;
+                report.push_str(&format!("NEW       {}\n", comparison.page));
+            }
+        }
+    }
+
+    report.push_str(&format!(
+        "{matched} matched, {regressed} regressed, {missing} missing, {new} new\n"
+    ));
+
+    report
+}
+
+fn file_names(directory: &Path) -> Result<Vec<String>> {
+    ensure!(
+        directory.is_dir(),
+        "{} must be a directory of rendered pages.",
+        directory.display()
+    );
+
+    Ok(validation::images_in_directory(directory)?
+        .iter()
+        .filter_map(|path| Some(path.file_name()?.to_string_lossy().to_string()))
+        .collect())
+}
+
+#[derive(clap::Args, Debug)]
+pub struct VerifyArgs {
+    #[arg(long, help = "Directory of golden images")]
+    pub expected: PathBuf,
+    #[arg(
+        long,
+        help = "Directory of freshly rendered pages, matched to the golden images by file name"
+    )]
+    pub actual: PathBuf,
+    #[arg(
+        long,
+        default_value_t = 0.98,
+        help = "Lowest mean structural similarity (SSIM, 0 to 1) at which a page still matches"
+    )]
+    pub min_ssim: f64,
+    #[arg(
+        long,
+        default_value_t = 0.001,
+        help = "Highest fraction of changed pixels at which a page still matches"
+    )]
+    pub max_changed: f64,
+    #[arg(
+        long,
+        default_value_t = 8,
+        help = "Largest difference per color channel that is not counted as a changed pixel"
+    )]
+    pub tolerance: u8,
+    #[arg(
+        long,
+        help = "Write an image highlighting the changed pixels of every regressed page to this directory"
+    )]
+    pub diff: Option<PathBuf>,
+}
+
+impl VerifyArgs {
+    pub fn run(self) -> Result<()> {
+        let thresholds = Thresholds {
+            min_ssim: self.min_ssim,
+            max_changed: self.max_changed,
+            tolerance: self.tolerance,
+        };
+
+        let expected_pages = file_names(&self.expected)?;
+        let actual_pages = file_names(&self.actual)?;
+
+        if let Some(diff_directory) = &self.diff {
+            std::fs::create_dir_all(diff_directory)?;
+        }
+
+        let mut comparisons = Vec::new();
+
+        for page in expected_pages.iter() {
+            if !actual_pages.contains(page) {
+                comparisons.push(Comparison {
+                    page: page.clone(),
+                    ssim: None,
+                    changed: None,
+                    verdict: Verdict::Missing,
+                });
+                continue;
+            }
+
+            let expected = image_conversion::open_image(self.expected.join(page))?.to_rgb8();
+            let actual = image_conversion::open_image(self.actual.join(page))?.to_rgb8();
+
+            let comparison = compare(page, &expected, &actual, &thresholds);
+
+            // Pages whose size changed can't be overlaid, and have no changed fraction
+            if let (Some(diff_directory), Verdict::Regressed(_), Some(_)) =
+                (&self.diff, &comparison.verdict, comparison.changed)
+            {
+                diff_image(&expected, &actual, thresholds.tolerance)
+                    .save(diff_directory.join(page).with_extension("png"))?;
+            }
+
+            comparisons.push(comparison);
+        }
+
+        comparisons.extend(
+            actual_pages
+                .iter()
+                .filter(|page| !expected_pages.contains(page))
+                .map(|page| Comparison {
+                    page: page.clone(),
+                    ssim: None,
+                    changed: None,
+                    verdict: Verdict::New,
+                }),
+        );
+
+        print!("{}", report(&comparisons));
+
+        let num_failed = comparisons
+            .iter()
+            .filter(|comparison| {
+                matches!(comparison.verdict, Verdict::Regressed(_) | Verdict::Missing)
+            })
+            .count();
+
+        if num_failed > 0 {
+            bail!(
+                "{num_failed} of {} pages don't match their golden images.",
+                expected_pages.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::verify::{compare, report, ssim, Thresholds, Verdict};
+    use image::{imageops, GrayImage, Luma, Rgb, RgbImage};
+
+    const THRESHOLDS: Thresholds = Thresholds {
+        min_ssim: 0.98,
+        max_changed: 0.001,
+        tolerance: 8,
+    };
+
+    fn page() -> RgbImage {
+        RgbImage::from_fn(64, 64, |x, y| match (x / 8 + y / 8) % 2 {
+            0 => Rgb([255, 255, 255]),
+            _ => Rgb([20, 20, 20]),
+        })
+    }
+
+    #[test]
+    fn test_ssim() {
+        let gray = imageops::grayscale(&page());
+
+        assert!((ssim(&gray, &gray) - 1.0).abs() < 1e-9);
+
+        let inverted = GrayImage::from_fn(64, 64, |x, y| Luma([255 - gray.get_pixel(x, y).0[0]]));
+
+        assert!(ssim(&gray, &inverted) < 0.5);
+    }
+
+    #[test]
+    fn test_comparison() {
+        let expected = page();
+
+        // Slight noise everywhere stays within the tolerance
+        let mut noisy = expected.clone();
+        noisy
+            .pixels_mut()
+            .for_each(|pixel| pixel.0[0] = pixel.0[0].saturating_sub(3));
+
+        assert_eq!(
+            compare("001.png", &expected, &noisy, &THRESHOLDS).verdict,
+            Verdict::Match
+        );
+
+        // A changed block of text does not
+        let mut changed = expected.clone();
+        for x in 8..24 {
+            changed.put_pixel(x, 4, Rgb([0, 0, 0]));
+        }
+
+        let comparison = compare("001.png", &expected, &changed, &THRESHOLDS);
+
+        assert!(matches!(comparison.verdict, Verdict::Regressed(_)));
+        assert_eq!(comparison.changed, Some(16.0 / 4096.0));
+
+        let resized = RgbImage::new(32, 64);
+
+        assert_eq!(
+            compare("002.png", &expected, &resized, &THRESHOLDS).verdict,
+            Verdict::Regressed("size changed from 64x64 to 32x64".to_string())
+        );
+
+        assert_eq!(
+            report(&[
+                comparison,
+                compare("003.png", &expected, &expected, &THRESHOLDS)
+            ]),
+            "REGRESSED 001.png: SSIM 0.9777 is below 0.98\n\
+             1 matched, 1 regressed, 0 missing, 0 new\n"
+        );
+
+        let pixel_thresholds = Thresholds {
+            min_ssim: 0.0,
+            ..THRESHOLDS
+        };
+
+        assert_eq!(
+            compare("001.png", &expected, &changed, &pixel_thresholds).verdict,
+            Verdict::Regressed("0.391% of pixels changed, more than 0.1%".to_string())
+        );
+    }
+}
@@ -0,0 +1,217 @@
+use anyhow::{bail, ensure, Context, Result};
+use serde::{Deserialize, Serialize};
+use std::path::{Path, PathBuf};
+
+// Directory next to the project file that holds the copies of the languages with the vocabulary set
+pub const TESSDATA_DIRECTORY: &str = ".mangatra-tessdata";
+
+// Index of the config component in a traineddata file, whose settings are applied while the language loads
+const CONFIG_COMPONENT: usize = 0;
+// Largest number of components tesseract reads from a traineddata file
+const MAX_COMPONENTS: u32 = 1000;
+
+// Words and patterns that bias OCR towards a series' own vocabulary, such as the names of its
+// characters or its jargon in kana. Patterns use tesseract's syntax, e.g. "\d\d\d-\d\d\d\d".
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq, Eq)]
+pub struct Vocabulary {
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_words: Vec<String>,
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub user_patterns: Vec<String>,
+}
+
+impl Vocabulary {
+    pub fn is_empty(&self) -> bool {
+        self.user_words.is_empty() && self.user_patterns.is_empty()
+    }
+
+    // Tesseract only reads word and pattern files while it loads a language, and leptess can't pass
+    // settings to that. Instead, the traineddata of each language in "lang" is copied into "directory"
+    // with the files set in its config. Returns the directory to use as tesseract's data path.
+    pub fn prepare_tessdata(
+        &self,
+        data_path: &Path,
+        lang: &str,
+        directory: &Path,
+    ) -> Result<PathBuf> {
+        std::fs::create_dir_all(directory).with_context(|| {
+            format!(
+                "Could not create the directory for the vocabulary at {}.",
+                directory.display()
+            )
+        })?;
+        let directory = directory.canonicalize()?;
+
+        let mut config = String::new();
+
+        if !self.user_words.is_empty() {
+            let path = directory.join("user-words");
+            std::fs::write(&path, self.user_words.join("\n") + "\n")?;
+            config.push_str(&format!("user_words_file {}\n", path.display()));
+        }
+        if !self.user_patterns.is_empty() {
+            let path = directory.join("user-patterns");
+            std::fs::write(&path, self.user_patterns.join("\n") + "\n")?;
+            config.push_str(&format!("user_patterns_file {}\n", path.display()));
+        }
+
+        for language in lang.split('+') {
+            let file_name = format!("{language}.traineddata");
+            let traineddata = std::fs::read(data_path.join(&file_name))
+                .with_context(|| format!("Could not read {file_name} for the vocabulary."))?;
+
+            let path = directory.join(&file_name);
+            // Languages can sit in subdirectories, such as "script/Japanese"
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::write(&path, with_config(&traineddata, &config)?)
+                .with_context(|| format!("Could not write {}.", path.display()))?;
+        }
+
+        Ok(directory)
+    }
+}
+
+// Appends settings to the config component of a traineddata file, adding the component if it has none
+fn with_config(traineddata: &[u8], settings: &str) -> Result<Vec<u8>> {
+    let mut components = read_components(traineddata)?;
+
+    let config = components[CONFIG_COMPONENT].get_or_insert_with(Vec::new);
+    if !config.is_empty() && !config.ends_with(b"\n") {
+        config.push(b'\n');
+    }
+    config.extend_from_slice(settings.as_bytes());
+
+    Ok(write_components(&components))
+}
+
+// Splits a traineddata file into its components: the number of components, a table of their offsets
+// (-1 for missing ones), then the components one after another
+fn read_components(traineddata: &[u8]) -> Result<Vec<Option<Vec<u8>>>> {
+    let num_components = match traineddata.get(..4) {
+        Some(bytes) => u32::from_le_bytes(bytes.try_into()?),
+        None => bail!("The traineddata file is empty."),
+    };
+    ensure!(
+        (CONFIG_COMPONENT as u32) < num_components && num_components <= MAX_COMPONENTS,
+        "The traineddata file has an unsupported layout."
+    );
+
+    let offsets = (0..num_components as usize)
+        .map(|index| {
+            let start = 4 + index * 8;
+            let bytes = traineddata
+                .get(start..start + 8)
+                .context("The traineddata file is truncated.")?;
+
+            Ok(i64::from_le_bytes(bytes.try_into()?))
+        })
+        .collect::<Result<Vec<i64>>>()?;
+
+    offsets
+        .iter()
+        .enumerate()
+        .map(|(index, offset)| {
+            if *offset < 0 {
+                return Ok(None);
+            }
+
+            // A component ends where the next one that is there starts
+            let end = offsets[index + 1..]
+                .iter()
+                .find(|offset| **offset >= 0)
+                .map_or(traineddata.len(), |offset| *offset as usize);
+
+            match traineddata.get(*offset as usize..end) {
+                Some(component) => Ok(Some(component.to_vec())),
+                None => bail!("The traineddata file is truncated."),
+            }
+        })
+        .collect()
+}
+
+fn write_components(components: &[Option<Vec<u8>>]) -> Vec<u8> {
+    let mut offset = 4 + components.len() as i64 * 8;
+    let mut traineddata = (components.len() as u32).to_le_bytes().to_vec();
+
+    for component in components {
+        match component {
+            Some(component) => {
+                traineddata.extend_from_slice(&offset.to_le_bytes());
+                offset += component.len() as i64;
+            }
+            None => traineddata.extend_from_slice(&(-1i64).to_le_bytes()),
+        }
+    }
+
+    for component in components.iter().flatten() {
+        traineddata.extend_from_slice(component);
+    }
+
+    traineddata
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::vocabulary::{read_components, write_components, Vocabulary};
+    use tempfile::TempDir;
+
+    #[test]
+    fn test_vocabulary_tessdata() {
+        let traineddata = write_components(&[
+            Some(b"tessedit_ocr_engine_mode 1".to_vec()),
+            None,
+            Some(b"unicharset".to_vec()),
+        ]);
+
+        let data_path = TempDir::new().unwrap();
+        std::fs::write(data_path.path().join("jpn.traineddata"), &traineddata).unwrap();
+        std::fs::write(
+            data_path.path().join("eng.traineddata"),
+            write_components(&[None, Some(b"lstm".to_vec())]),
+        )
+        .unwrap();
+
+        let vocabulary = Vocabulary {
+            user_words: vec!["ナルト".to_string(), "チャクラ".to_string()],
+            user_patterns: Vec::new(),
+        };
+        let directory = data_path.path().join("vocabulary");
+        let tessdata = vocabulary
+            .prepare_tessdata(data_path.path(), "jpn+eng", &directory)
+            .unwrap();
+
+        let words_path = tessdata.join("user-words");
+        assert_eq!(
+            std::fs::read_to_string(&words_path).unwrap(),
+            "ナルト\nチャクラ\n"
+        );
+        assert!(!tessdata.join("user-patterns").exists());
+
+        let components =
+            read_components(&std::fs::read(tessdata.join("jpn.traineddata")).unwrap()).unwrap();
+        assert_eq!(
+            components[0].as_deref(),
+            Some(
+                format!(
+                    "tessedit_ocr_engine_mode 1\nuser_words_file {}\n",
+                    words_path.display()
+                )
+                .as_bytes()
+            )
+        );
+        assert_eq!(components[1], None);
+        assert_eq!(components[2].as_deref(), Some(b"unicharset".as_slice()));
+
+        // Languages without a config get one
+        let components =
+            read_components(&std::fs::read(tessdata.join("eng.traineddata")).unwrap()).unwrap();
+        assert!(components[0].is_some());
+        assert_eq!(components[1].as_deref(), Some(b"lstm".as_slice()));
+
+        assert!(read_components(&[]).is_err());
+        assert!(read_components(&traineddata[..20]).is_err());
+    }
+}
@@ -0,0 +1,886 @@
+use crate::audit::{AuditLog, AuditParameters, AuditRecord};
+use crate::config::{Config, ServiceArgs};
+use crate::extraction::{self, PageSize};
+use crate::fonts;
+use crate::handlers::{self, ImageSource, RenderedPage};
+#[cfg(feature = "history")]
+use crate::history::JobHistory;
+use crate::jobs::{
+    self, Defaults, Job, JobQueue, JobResult, Mask, MaskFormat, MaskRequest, Operation, Outcome,
+    QueueFull, RegionCrop, JOB_FORMAT_VERSION,
+};
+use crate::storage::{self, ObjectUri};
+use crate::tenants::Tenants;
+use crate::timings::{Stage, StageTimings};
+use crate::utils::cancellation::CancellationToken;
+use crate::utils::image_conversion;
+use crate::utils::mask::{self, RleMask};
+use anyhow::{anyhow, bail, Context, Result};
+use image::GenericImageView;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::mpsc::{self, RecvTimeoutError};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use tracing::{info, warn};
+
+// How long a worker waits for a job before checking for expired leases again
+const POLL_INTERVAL: Duration = Duration::from_secs(5);
+// How long a worker waits before trying again when the queue can't be reached
+const RETRY_INTERVAL: Duration = Duration::from_secs(5);
+
+// Name of the page of a job, used in region IDs: the file name of its URL or object without the
+// extension, or the job's ID for images sent along with the job
+pub fn page_name(job: &Job) -> String {
+    let location = match &job.image {
+        ImageSource::Bytes(_) => return job.id.clone(),
+        ImageSource::Url(url) => url.split(['?', '#']).next().unwrap_or(url),
+        ImageSource::Object(uri) => uri,
+    };
+
+    match location.rsplit('/').next() {
+        Some(file_name) if !file_name.is_empty() => extraction::page_name(file_name),
+        _ => job.id.clone(),
+    }
+}
+
+// Runs a job on its image, read with "ImageSource::read". Images made by the job are written to its
+// output rather than kept in the queue. The time each stage took is added to "timings".
+pub fn run_job(
+    config: &Config,
+    job: Job,
+    image_bytes: &[u8],
+    timings: &mut StageTimings,
+    cancellation_token: &CancellationToken,
+) -> Result<Outcome> {
+    let page_name = page_name(&job);
+
+    // A font that isn't there fails the job before the page is processed
+    let font = match &job.operation {
+        Operation::Replace {
+            font: Some(font), ..
+        } => match &config.font_store {
+            Some(font_store) => Some(font_store.load(font)?),
+            None => bail!("The job asks for the font {font}, but the worker has no --font-dir."),
+        },
+        _ => None,
+    };
+
+    let image = timings.time(Stage::Decode, || {
+        image_conversion::load_image_within(image_bytes, config.max_pixels)
+    })?;
+    let page = timings.time(Stage::Detect, || {
+        handlers::detect_page(config, &image, cancellation_token)
+    })?;
+
+    let rendered_page = match job.operation {
+        Operation::Extract { crops } => {
+            let extracted_text = timings.time(Stage::Ocr, || {
+                handlers::recognize_page(config, &page, cancellation_token)
+            })?;
+            let crops = match crops {
+                true => timings.time(Stage::Encode, || page.encoded_crops())?,
+                false => Vec::new(),
+            };
+            let size = PageSize {
+                width: image.width(),
+                height: image.height(),
+            };
+
+            let extraction = handlers::page_extraction(config, &page_name, extracted_text, size);
+
+            let crops = crops
+                .into_iter()
+                .enumerate()
+                .map(|(index, png)| RegionCrop {
+                    region: extraction::region_id(&page_name, index),
+                    png,
+                })
+                .collect();
+
+            return Ok(Outcome::Extracted { extraction, crops });
+        }
+        Operation::Clean { mask: mask_request } => {
+            // Under --skip-empty-regions, OCR tells which regions to leave alone
+            let extracted_text = match config.skip_empty_regions {
+                true => Some(timings.time(Stage::Ocr, || {
+                    handlers::recognize_page(config, &page, cancellation_token)
+                })?),
+                false => None,
+            };
+            let cleaned_page = timings.time(Stage::Layout, || {
+                handlers::clean_detected_page(
+                    config,
+                    page,
+                    extracted_text.as_ref(),
+                    cancellation_token,
+                )
+            })?;
+
+            if let Some(request) = mask_request {
+                return clean_with_mask(job.output, image_bytes, cleaned_page, request, timings);
+            }
+
+            cleaned_page
+        }
+        Operation::Replace { extraction, .. } => timings.time(Stage::Layout, || match &font {
+            Some(font) => handlers::typeset_detected_page_in_font(
+                config,
+                page,
+                &extraction,
+                font,
+                cancellation_token,
+            ),
+            None => handlers::typeset_detected_page(config, page, &extraction, cancellation_token),
+        })?,
+    };
+
+    let output = match job.output {
+        Some(output) => output,
+        None => bail!("The job has no output to write its page to."),
+    };
+    timings.time(Stage::Encode, || {
+        write_output(
+            &output,
+            handlers::encode_png(&rendered_page.image, image_bytes)?,
+        )
+    })?;
+
+    Ok(Outcome::Written { output, mask: None })
+}
+
+// Writes the cleaned page of a job that asked for the mask of the areas that were erased
+fn clean_with_mask(
+    output: Option<String>,
+    image_bytes: &[u8],
+    cleaned_page: RenderedPage,
+    request: MaskRequest,
+    timings: &mut StageTimings,
+) -> Result<Outcome> {
+    let (width, height) = cleaned_page.image.dimensions();
+    let erased = mask::from_areas(width, height, &cleaned_page.areas);
+
+    let mask = timings.time(Stage::Encode, || -> Result<Mask> {
+        match request.format {
+            MaskFormat::Rle => Ok(Mask::Rle(RleMask::encode(&erased))),
+            MaskFormat::Png => {
+                let mask_output = match (&output, request.only) {
+                    (Some(output), true) => output.clone(),
+                    (Some(output), false) => mask_output(output),
+                    (None, _) => bail!("The job has no output to write its mask to."),
+                };
+                write_output(&mask_output, mask::encode_png(&erased)?)?;
+
+                Ok(Mask::Png {
+                    output: mask_output,
+                })
+            }
+        }
+    })?;
+
+    if request.only {
+        return Ok(Outcome::Masked { mask });
+    }
+
+    let output = match output {
+        Some(output) => output,
+        None => bail!("The job has no output to write its page to."),
+    };
+    timings.time(Stage::Encode, || {
+        write_output(
+            &output,
+            handlers::encode_png(&cleaned_page.image, image_bytes)?,
+        )
+    })?;
+
+    Ok(Outcome::Written {
+        output,
+        mask: Some(mask),
+    })
+}
+
+// Where the PNG mask of a cleaned page written to "output" goes: next to it, as "<name>.mask.png"
+pub fn mask_output(output: &str) -> String {
+    let (directory, file_name) = match output.rfind('/') {
+        Some(i) => output.split_at(i + 1),
+        None => ("", output),
+    };
+    let name = match file_name.rfind('.') {
+        Some(i) if i > 0 => &file_name[..i],
+        _ => file_name,
+    };
+
+    format!("{directory}{name}.mask.png")
+}
+
+fn write_output(output: &str, data: Vec<u8>) -> Result<()> {
+    match ObjectUri::parse(output)? {
+        Some(uri) => storage::put_object(&uri, data),
+        None => {
+            if let Some(parent) = Path::new(output).parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+
+            std::fs::write(output, data).with_context(|| format!("Could not write {output}."))
+        }
+    }
+}
+
+#[derive(clap::Args, Debug)]
+pub struct WorkerArgs {
+    #[arg(
+        long,
+        help = "Queue to take jobs from, e.g. redis://queue.internal:6379"
+    )]
+    pub queue: String,
+    #[arg(
+        long,
+        default_value_t = jobs::DEFAULT_LEASE.as_secs(),
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Seconds a job goes on without word from its worker before it is handed to another worker, in case this one died. Workers renew the lease of their job while they process it"
+    )]
+    pub lease: u64,
+    #[arg(
+        long,
+        help = "Append a JSON line for every processed job to this file, with its ID, operation, image hash, options, duration and outcome"
+    )]
+    pub audit_log: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "JSON file of the tenants jobs may name, each with the options that differ from this worker's, e.g. its own model. Read again when the worker gets SIGHUP"
+    )]
+    pub tenants: Option<PathBuf>,
+    #[arg(
+        long,
+        help = "Keep the completed jobs and their results in this SQLite file, for fetching results the queue no longer has and looking into failures. Needs the \"history\" feature"
+    )]
+    pub history: Option<PathBuf>,
+    #[arg(
+        long,
+        default_value_t = 30,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Days jobs are kept in the --history"
+    )]
+    pub history_days: u64,
+    #[command(flatten)]
+    pub service: ServiceArgs,
+}
+
+// Options the worker was started with that the queue's defaults may change
+struct StartOptions {
+    defaults: Defaults,
+    // Whether a font was given with --font, which is kept when the target language changes
+    font: bool,
+}
+
+impl StartOptions {
+    fn of_config(config: &Config, font: bool) -> StartOptions {
+        StartOptions {
+            defaults: Defaults {
+                padding: Some(config.padding),
+                max_detections: Some(config.max_detections),
+                target_lang: Some(config.target_lang.clone()),
+            },
+            font,
+        }
+    }
+
+    // Sets the options of the config to the queue's defaults, or back to the ones the worker was
+    // started with for the defaults that aren't set
+    fn apply(&self, config: &mut Config, defaults: &Defaults) {
+        let defaults = defaults.or(&self.defaults);
+
+        if let Some(padding) = defaults.padding {
+            config.padding = padding;
+        }
+        if let Some(max_detections) = defaults.max_detections {
+            config.max_detections = max_detections;
+        }
+        if let Some(target_lang) = defaults.target_lang {
+            if target_lang != config.target_lang {
+                info!("Translations are now in {target_lang}");
+
+                if !self.font {
+                    config.font = fonts::default_font(&target_lang);
+                }
+                config.target_lang = target_lang;
+            }
+        }
+    }
+}
+
+// What the worker keeps of the jobs it processed
+struct Records {
+    audit_log: Option<AuditLog>,
+    #[cfg(feature = "history")]
+    history: Option<JobHistory>,
+}
+
+impl WorkerArgs {
+    fn load_tenants(&self) -> Result<HashMap<String, Config>> {
+        match &self.tenants {
+            Some(path) => Tenants::load(path)?.configs(&self.service),
+            None => Ok(HashMap::new()),
+        }
+    }
+
+    pub fn run(self) -> Result<()> {
+        let queue = jobs::open_shared(&self.queue, Duration::from_secs(self.lease))?;
+        let mut tenants = self.load_tenants()?;
+        let font = self.service.font.is_some();
+        let mut config = self.service.clone().config()?;
+        let start_options = StartOptions::of_config(&config, font);
+        #[cfg(not(feature = "history"))]
+        if self.history.is_some() {
+            bail!(
+                "--history can't be used since mangatra was built without the \"history\" feature."
+            );
+        }
+        let records = Records {
+            audit_log: match &self.audit_log {
+                Some(path) => Some(AuditLog::open(path)?),
+                None => None,
+            },
+            #[cfg(feature = "history")]
+            history: match &self.history {
+                Some(path) => Some(JobHistory::open(
+                    path,
+                    Duration::from_secs(self.history_days * 24 * 60 * 60),
+                )?),
+                None => None,
+            },
+        };
+
+        // Ctrl-C stops the job in progress, which goes back in the queue for another worker
+        let cancellation_token = CancellationToken::new();
+        let handler_token = cancellation_token.clone();
+        ctrlc::set_handler(move || handler_token.cancel())?;
+
+        // SIGHUP reloads the tenants file between jobs, so tenants can be added or changed without
+        // stopping the worker. The other options are the command line's and stay as they are.
+        let reload = Arc::new(AtomicBool::new(false));
+        #[cfg(unix)]
+        signal_hook::flag::register(signal_hook::consts::SIGHUP, Arc::clone(&reload))?;
+
+        // Renewed a few times per lease, so one slow call to the queue doesn't lose the job
+        let heartbeat = Duration::from_secs(self.lease) / 3;
+
+        info!("Waiting for jobs on {}", self.queue);
+
+        while !cancellation_token.is_cancelled() {
+            if reload.swap(false, Ordering::Relaxed) {
+                // A broken file shouldn't take down a worker that is serving the tenants it had
+                match self.load_tenants() {
+                    Ok(reloaded) => {
+                        info!("Reloaded {} tenants", reloaded.len());
+                        tenants = reloaded;
+                    }
+                    Err(e) => warn!(
+                        "Kept the tenants as they were, since they could not be reloaded: {e:#}"
+                    ),
+                }
+            }
+
+            match work(
+                queue.as_ref(),
+                &mut config,
+                &start_options,
+                &tenants,
+                &records,
+                heartbeat,
+                &cancellation_token,
+            ) {
+                Ok(()) => {}
+                Err(e) => {
+                    warn!("Could not reach the queue: {e:#}");
+                    thread::sleep(RETRY_INTERVAL);
+                }
+            }
+        }
+
+        info!("Stopped");
+
+        Ok(())
+    }
+}
+
+// Takes the next job, if one comes within the poll interval, and stores its outcome
+fn work(
+    queue: &dyn JobQueue,
+    config: &mut Config,
+    start_options: &StartOptions,
+    tenants: &HashMap<String, Config>,
+    records: &Records,
+    heartbeat: Duration,
+    cancellation_token: &CancellationToken,
+) -> Result<()> {
+    // Any worker picks up the jobs of workers that died, so no job waits for its worker to return
+    let requeued = queue.requeue_expired()?;
+    if requeued > 0 {
+        warn!("Requeued {requeued} jobs whose workers stopped responding");
+    }
+
+    let job = match queue.pop(POLL_INTERVAL)? {
+        Some(job) => job,
+        None => return Ok(()),
+    };
+    info!("Processing job {}", job.id);
+
+    // Read for every job, so changes reach running workers. Tenants keep the options of the
+    // tenants file.
+    start_options.apply(config, &queue.defaults()?);
+    let tenant_config = job.tenant.as_ref().and_then(|tenant| tenants.get(tenant));
+
+    // Jobs of tenants this worker doesn't serve are left to a worker that does. Waiting before the
+    // next job keeps workers from passing such a job back and forth while no worker serves it.
+    if let (Some(tenant), None) = (&job.tenant, tenant_config) {
+        info!(
+            "Passed on job {}, since this worker doesn't serve the tenant {tenant}",
+            job.id
+        );
+        queue.pass_on(&job)?;
+        thread::sleep(POLL_INTERVAL);
+
+        return Ok(());
+    }
+    let config = tenant_config.unwrap_or(&*config);
+
+    // Renewing the lease keeps long jobs from being handed to another worker while this one works
+    let result = with_heartbeat(queue, &job, heartbeat, || {
+        process(&job, config, records, cancellation_token)
+    });
+
+    match result {
+        Some(result) => queue.complete(&result),
+        None => queue.release(&job),
+    }
+}
+
+// Runs a job and records it, returning its result, or nothing if the worker is stopping
+fn process(
+    job: &Job,
+    config: &Config,
+    records: &Records,
+    cancellation_token: &CancellationToken,
+) -> Option<JobResult> {
+    let started = SystemTime::now();
+    let start = Instant::now();
+
+    // Checked before the image is read, since a newer version may read images another way
+    let image_bytes = match job.version {
+        version if version > JOB_FORMAT_VERSION => Err(anyhow!(
+            "The job is of version {version} of the job format, but this worker only reads up to version {JOB_FORMAT_VERSION}."
+        )),
+        _ => job.image.clone().read(config),
+    };
+
+    let mut timings = StageTimings::default();
+    let (image_bytes, outcome) = match image_bytes {
+        Ok(image_bytes) => {
+            let outcome = run_job(
+                config,
+                job.clone(),
+                &image_bytes,
+                &mut timings,
+                cancellation_token,
+            );
+            (Some(image_bytes), outcome)
+        }
+        Err(e) => (None, Err(e)),
+    };
+
+    let result = match cancellation_token.is_cancelled() {
+        true => None,
+        false => Some(JobResult {
+            id: job.id.clone(),
+            outcome: match outcome {
+                Ok(outcome) => outcome,
+                Err(e) => {
+                    warn!("Job {} failed: {e:#}", job.id);
+
+                    Outcome::Failed {
+                        error: format!("{e:#}"),
+                    }
+                }
+            },
+            timings: Some(timings).filter(|_| job.timings),
+        }),
+    };
+
+    if let Some(audit_log) = &records.audit_log {
+        let record = AuditRecord::for_job(
+            job,
+            image_bytes.as_deref(),
+            AuditParameters::from_config(config),
+            started,
+            start.elapsed(),
+            result.as_ref().map(|result| &result.outcome),
+        );
+
+        // A full disk shouldn't stop jobs from being processed
+        if let Err(e) = audit_log.record(&record) {
+            warn!("Could not write to the audit log: {e:#}");
+        }
+    }
+
+    // Jobs put back in the queue are recorded by the worker that completes them
+    #[cfg(feature = "history")]
+    if let (Some(history), Some(result)) = (&records.history, &result) {
+        if let Err(e) = history.record(job, result, started, start.elapsed()) {
+            warn!("Could not write to the job history: {e:#}");
+        }
+    }
+
+    result
+}
+
+// Renews the lease of a job every "interval" while "f" runs
+fn with_heartbeat<T>(
+    queue: &dyn JobQueue,
+    job: &Job,
+    interval: Duration,
+    f: impl FnOnce() -> T,
+) -> T {
+    let (done, stopped) = mpsc::channel::<()>();
+
+    thread::scope(|scope| {
+        scope.spawn(move || {
+            while let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(interval) {
+                match queue.renew(job) {
+                    Ok(true) => {}
+                    Ok(false) => {
+                        warn!(
+                            "Lost the lease of job {}, which may be processed by another worker",
+                            job.id
+                        );
+                        break;
+                    }
+                    // Tried again at the next beat, while there is time left on the lease
+                    Err(e) => warn!("Could not renew the lease of job {}: {e:#}", job.id),
+                }
+            }
+        });
+
+        let output = f();
+        drop(done);
+
+        output
+    })
+}
+
+// Reads and changes the defaults in the queue. There is no HTTP API for them, so whoever may write to
+// the queue may change them.
+#[derive(clap::Args, Debug)]
+pub struct DefaultsArgs {
+    #[arg(
+        long,
+        help = "Queue the workers take jobs from, e.g. redis://queue.internal:6379"
+    )]
+    pub queue: String,
+    #[command(subcommand)]
+    pub command: DefaultsCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum DefaultsCommand {
+    #[command(about = "Print the defaults set in the queue as JSON")]
+    Show,
+    #[command(
+        about = "Change the defaults workers process jobs with. Options that aren't given are left as they are"
+    )]
+    Set {
+        #[arg(long, help = "Size of padding for text regions")]
+        padding: Option<u16>,
+        #[arg(
+            long,
+            value_parser = clap::value_parser!(u32).range(1..),
+            help = "Most text regions processed on a page"
+        )]
+        max_detections: Option<u32>,
+        #[arg(
+            long,
+            help = "Language the translations are written in, e.g. es, ru or ja"
+        )]
+        target_lang: Option<String>,
+    },
+    #[command(
+        about = "Remove the defaults, so workers go back to the options they were started with"
+    )]
+    Reset,
+}
+
+impl DefaultsArgs {
+    pub fn run(self) -> Result<()> {
+        let queue = jobs::open_shared(&self.queue, jobs::DEFAULT_LEASE)?;
+
+        match self.command {
+            DefaultsCommand::Show => {}
+            DefaultsCommand::Set {
+                padding,
+                max_detections,
+                target_lang,
+            } => {
+                let changes = Defaults {
+                    padding,
+                    max_detections: max_detections.map(|max_detections| max_detections as usize),
+                    target_lang,
+                };
+                queue.set_defaults(&changes.or(&queue.defaults()?))?;
+            }
+            DefaultsCommand::Reset => queue.set_defaults(&Defaults::default())?,
+        }
+
+        println!("{}", serde_json::to_string_pretty(&queue.defaults()?)?);
+
+        Ok(())
+    }
+}
+
+// How often "submit --wait" checks whether the job's result is in
+const RESULT_POLL_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(clap::Args, Debug)]
+pub struct SubmitArgs {
+    #[arg(
+        long,
+        help = "Queue the workers take jobs from, e.g. redis://queue.internal:6379"
+    )]
+    pub queue: String,
+    #[arg(
+        long,
+        help = "Where the job writes the page it makes: a path on the workers, or an s3:// or gs:// URI"
+    )]
+    pub output: Option<String>,
+    #[arg(
+        long,
+        help = "Tenant of the workers' tenants file to process the job with"
+    )]
+    pub tenant: Option<String>,
+    #[arg(long, help = "Also return how long each stage of the job took")]
+    pub timings: bool,
+    #[arg(
+        long,
+        help = "Wait for the job's result and print it as JSON, instead of printing the job's ID"
+    )]
+    pub wait: bool,
+    #[arg(
+        long,
+        value_parser = clap::value_parser!(u64).range(1..),
+        help = "Hold the job back while this many jobs are already waiting, and try again when the queue says to"
+    )]
+    pub max_pending: Option<u64>,
+    #[command(subcommand)]
+    pub command: SubmitCommand,
+}
+
+#[derive(clap::Subcommand, Debug)]
+pub enum SubmitCommand {
+    #[command(about = "Extract the text of a page")]
+    Extract {
+        #[arg(help = IMAGE_HELP)]
+        image: String,
+        #[arg(long, help = "Also return the crop of every region as a PNG")]
+        crops: bool,
+    },
+    #[command(about = "Erase the text of a page")]
+    Clean {
+        #[arg(help = IMAGE_HELP)]
+        image: String,
+        #[arg(
+            long,
+            value_enum,
+            help = "Also return the mask of the areas that were erased"
+        )]
+        mask: Option<MaskFormat>,
+        #[arg(
+            long,
+            requires = "mask",
+            help = "Return only the mask, without the cleaned page"
+        )]
+        mask_only: bool,
+    },
+    #[command(about = "Replace the text of a page with its translations")]
+    Replace {
+        #[arg(help = IMAGE_HELP)]
+        image: String,
+        #[arg(
+            long,
+            help = "Text file of the page with its translations (JSON, XLIFF, CSV or TSV)"
+        )]
+        text: PathBuf,
+        #[arg(
+            long,
+            help = "Name of a font of the workers' --font-dir to typeset the page in"
+        )]
+        font: Option<String>,
+    },
+}
+
+const IMAGE_HELP: &str = "Image of the page: a local file, which is sent along with the job, or a URL or an s3:// or gs:// URI the workers read it from";
+
+// Where the workers get the image of a submitted job from
+fn image_source(image: &str) -> Result<ImageSource> {
+    if image.starts_with("http://") || image.starts_with("https://") {
+        return Ok(ImageSource::Url(image.to_string()));
+    }
+    if ObjectUri::parse(image)?.is_some() {
+        return Ok(ImageSource::Object(image.to_string()));
+    }
+
+    let image_bytes =
+        std::fs::read(image).with_context(|| format!("Could not read the image {image}."))?;
+
+    Ok(ImageSource::Bytes(image_bytes))
+}
+
+impl SubmitCommand {
+    fn job(self, output: Option<String>) -> Result<Job> {
+        let (operation, image) = match self {
+            SubmitCommand::Extract { image, crops } => (Operation::Extract { crops }, image),
+            SubmitCommand::Clean {
+                image,
+                mask,
+                mask_only,
+            } => {
+                let mask = mask.map(|format| MaskRequest {
+                    format,
+                    only: mask_only,
+                });
+
+                (Operation::Clean { mask }, image)
+            }
+            SubmitCommand::Replace { image, text, font } => {
+                let file_name = image.rsplit('/').next().unwrap_or(&image);
+                let extraction =
+                    extraction::read_text_file(&text, &extraction::page_name(file_name))
+                        .with_context(|| {
+                            format!("Could not read the text file {}.", text.display())
+                        })?;
+
+                (Operation::Replace { extraction, font }, image)
+            }
+        };
+
+        Ok(Job::new(operation, image_source(&image)?, output))
+    }
+}
+
+impl SubmitArgs {
+    pub fn run(self) -> Result<()> {
+        let queue = jobs::open_shared(&self.queue, jobs::DEFAULT_LEASE)?;
+
+        let mut job = self.command.job(self.output)?;
+        if let Some(tenant) = &self.tenant {
+            job = job.for_tenant(tenant);
+        }
+        if self.timings {
+            job = job.with_timings();
+        }
+
+        match self.max_pending {
+            Some(max_pending) => loop {
+                match jobs::push_within(queue.as_ref(), &job, max_pending as usize) {
+                    Ok(()) => break,
+                    Err(e) => match e.downcast_ref::<QueueFull>() {
+                        Some(full) => {
+                            warn!("{full}");
+                            thread::sleep(full.retry_after);
+                        }
+                        None => return Err(e),
+                    },
+                }
+            },
+            None => queue.push(&job)?,
+        }
+
+        if !self.wait {
+            println!("{}", job.id);
+            return Ok(());
+        }
+
+        let result = loop {
+            match queue.result(&job.id)? {
+                Some(result) => break result,
+                None => thread::sleep(RESULT_POLL_INTERVAL),
+            }
+        };
+        println!("{}", serde_json::to_string_pretty(&result)?);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::handlers::ImageSource;
+    use crate::jobs::{Job, Operation, JOB_FORMAT_VERSION};
+    use crate::worker::{image_source, mask_output, page_name};
+
+    #[test]
+    fn test_page_name() {
+        let job = |image| Job {
+            version: JOB_FORMAT_VERSION,
+            id: "1f".to_string(),
+            operation: Operation::Extract { crops: false },
+            image,
+            output: None,
+            tenant: None,
+            timings: false,
+        };
+
+        assert_eq!(
+            page_name(&job(ImageSource::Url(
+                "https://images.example.com/ch01/001.png?X-Amz-Signature=abc".to_string()
+            ))),
+            "001"
+        );
+        assert_eq!(
+            page_name(&job(ImageSource::Object(
+                "s3://raws/ch01/002.jpg".to_string()
+            ))),
+            "002"
+        );
+        assert_eq!(page_name(&job(ImageSource::Bytes(vec![1]))), "1f");
+        assert_eq!(
+            page_name(&job(ImageSource::Url(
+                "https://images.example.com/".to_string()
+            ))),
+            "1f"
+        );
+    }
+
+    #[test]
+    fn test_mask_output() {
+        assert_eq!(
+            mask_output("s3://releases/ch01/001.png"),
+            "s3://releases/ch01/001.mask.png"
+        );
+        assert_eq!(mask_output("out/002.jpg"), "out/002.mask.png");
+        assert_eq!(mask_output("003"), "003.mask.png");
+        assert_eq!(mask_output("out/.hidden"), "out/.hidden.mask.png");
+    }
+
+    #[test]
+    fn test_image_source() {
+        assert_eq!(
+            image_source("https://images.example.com/001.png").unwrap(),
+            ImageSource::Url("https://images.example.com/001.png".to_string())
+        );
+        assert_eq!(
+            image_source("gs://raws/ch01/002.png").unwrap(),
+            ImageSource::Object("gs://raws/ch01/002.png".to_string())
+        );
+        assert!(image_source("s3://").is_err());
+
+        // Local files are sent along with the job
+        let file = tempfile::NamedTempFile::new().unwrap();
+        std::fs::write(file.path(), [137, 80]).unwrap();
+        assert_eq!(
+            image_source(file.path().to_str().unwrap()).unwrap(),
+            ImageSource::Bytes(vec![137, 80])
+        );
+        assert!(image_source("missing/003.png").is_err());
+    }
+}
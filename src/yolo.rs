@@ -0,0 +1,386 @@
+use crate::text_box::TextBox;
+use anyhow::{bail, ensure, Result};
+use image::{imageops, RgbImage};
+use ndarray as nd;
+
+// Most text regions processed on a page by default. Even dense pages of dialogue have well under a
+// hundred, so pages with more are usually index pages or run with the wrong model.
+pub const DEFAULT_MAX_DETECTIONS: u32 = 200;
+
+// Lowest score, from 0 to 100, of the boxes the model's predictions are kept at
+pub const MIN_SCORE: u8 = 40;
+
+// Input resolution of text detection models exported with a dynamic input size
+pub const DEFAULT_INPUT_SIZE: i32 = 640;
+
+// Score of a class that a YOLOv5 prediction needs, besides its objectness
+const MIN_CLASS_SCORE: f32 = 0.25;
+
+// Score below which candidates are dropped by non-maximum suppression, and the overlap above which
+// the less confident of two boxes is suppressed
+pub const NMS_SCORE_THRESHOLD: f32 = 0.25;
+pub const NMS_THRESHOLD: f32 = 0.45;
+
+// Width and height of the images a detection model takes, which pages are padded to a square and
+// scaled to
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct InputSize {
+    pub width: i32,
+    pub height: i32,
+}
+
+impl Default for InputSize {
+    fn default() -> InputSize {
+        InputSize {
+            width: DEFAULT_INPUT_SIZE,
+            height: DEFAULT_INPUT_SIZE,
+        }
+    }
+}
+
+impl InputSize {
+    // Input size of a model whose input has the given dimensions, (batch, channels, height, width).
+    // Sizes the model leaves open are the default size.
+    pub fn from_dimensions(dimensions: &[Option<usize>]) -> Result<InputSize> {
+        let &[_, channels, height, width] = dimensions else {
+            bail!("The detection model's input has {} dimensions, but an input of shape [batch, 3, height, width] is expected.", dimensions.len());
+        };
+        ensure!(
+            channels.unwrap_or(3) == 3,
+            "The detection model's input has {} channels, but RGB pages have 3.",
+            channels.unwrap_or_default()
+        );
+
+        let size = |size: Option<usize>| -> Result<i32> {
+            match size {
+                Some(size) => Ok(size.try_into()?),
+                None => Ok(DEFAULT_INPUT_SIZE),
+            }
+        };
+
+        Ok(InputSize {
+            width: size(width)?,
+            height: size(height)?,
+        })
+    }
+}
+
+// Generation of YOLO a detection model was exported from, which decides how its predictions are laid out
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum YoloVersion {
+    // A row per prediction: the box, its objectness and the score of each class
+    V5,
+    // A column per prediction: the box and the score of each class, without objectness. YOLOv11
+    // exports the same layout.
+    V8,
+}
+
+// Layout of a detection model's output, told apart by its shape. YOLOv5 models output
+// (1, predictions, 5 + classes) and YOLOv8 models (1, 4 + classes, predictions), and there are always
+// far more predictions than classes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct OutputLayout {
+    pub version: YoloVersion,
+    // Size of the output after its batch dimension
+    pub shape: (usize, usize),
+}
+
+impl OutputLayout {
+    pub fn from_shape(shape: &[usize]) -> Result<OutputLayout> {
+        let &[1, rows, columns] = shape else {
+            bail!("The detection model's output has shape {shape:?}, but an output of shape [1, predictions, 5 + classes] (YOLOv5) or [1, 4 + classes, predictions] (YOLOv8 and YOLOv11) is expected.");
+        };
+        let version = match rows > columns {
+            true => YoloVersion::V5,
+            false => YoloVersion::V8,
+        };
+        let prediction_size = rows.min(columns);
+        let min_prediction_size = match version {
+            YoloVersion::V5 => 6,
+            YoloVersion::V8 => 5,
+        };
+        ensure!(
+            prediction_size >= min_prediction_size,
+            "The detection model's output has shape {shape:?}, which leaves no room for the scores of its classes."
+        );
+
+        Ok(OutputLayout {
+            version,
+            shape: (rows, columns),
+        })
+    }
+
+    // Layout of the predictions for each page of the output of a batch of "pages" pages, which the
+    // batch dimension has to match
+    pub fn from_batch_shape(shape: &[usize], pages: usize) -> Result<OutputLayout> {
+        match shape {
+            [batch, page_shape @ ..] if *batch == pages => {
+                OutputLayout::from_shape(&[&[1], page_shape].concat())
+            }
+            [_, _, _] => bail!("The detection model's output has shape {shape:?}, but predictions for a batch of {pages} pages were expected. Export the model with a dynamic batch size to run pages in batches."),
+            _ => OutputLayout::from_shape(shape),
+        }
+    }
+
+    // The output as a row per prediction, starting with the center, width and height of its box
+    pub fn predictions<'a>(&self, output: nd::ArrayView2<'a, f32>) -> nd::ArrayView2<'a, f32> {
+        match self.version {
+            YoloVersion::V5 => output,
+            YoloVersion::V8 => output.reversed_axes(),
+        }
+    }
+
+    // Confidence of a prediction from 0 to 1, if it is high enough for the prediction to be a
+    // candidate box
+    pub fn score(&self, prediction: nd::ArrayView1<f32>) -> Option<f32> {
+        let min_score = MIN_SCORE as f32 / 100.0;
+        let max_value =
+            |values: nd::ArrayView1<f32>| values.iter().copied().fold(f32::NEG_INFINITY, f32::max);
+
+        match self.version {
+            // The largest value of the row, box included, has to pass as a class score as well
+            YoloVersion::V5 => {
+                let objectness = prediction[4];
+
+                (objectness >= min_score && max_value(prediction) > MIN_CLASS_SCORE)
+                    .then_some(objectness)
+            }
+            YoloVersion::V8 => {
+                let score = max_value(prediction.slice(nd::s![4..]));
+
+                (score >= min_score).then_some(score)
+            }
+        }
+    }
+}
+
+// Candidate boxes of the model's predictions with their scores, in the coordinates of the padded,
+// square page of the given size that was scaled to the model's input
+pub fn candidates(
+    layout: OutputLayout,
+    output: nd::ArrayView2<f32>,
+    input_size: InputSize,
+    (image_width, image_height): (i32, i32),
+) -> Vec<(TextBox, f32)> {
+    let x_factor = image_width as f32 / input_size.width as f32;
+    let y_factor = image_height as f32 / input_size.height as f32;
+
+    layout
+        .predictions(output)
+        .rows()
+        .into_iter()
+        .filter_map(|row| {
+            let score = layout.score(row)?;
+            let (x, y, w, h) = (row[0], row[1], row[2], row[3]);
+
+            let text_box = TextBox {
+                x: ((x - 0.5 * w) * x_factor) as i32,
+                y: ((y - 0.5 * h) * y_factor) as i32,
+                width: (w * x_factor) as i32,
+                height: (h * y_factor) as i32,
+                score: None,
+            };
+
+            Some((text_box, score))
+        })
+        .collect()
+}
+
+// Greedy non-maximum suppression as OpenCV's "dnn::nms_boxes" does it, for detection without OpenCV:
+// the most confident candidates are kept first, along with every candidate that doesn't overlap a
+// kept one by more than "NMS_THRESHOLD"
+pub fn non_max_suppression(mut candidates: Vec<(TextBox, f32)>) -> Vec<(TextBox, f32)> {
+    candidates.retain(|(_, score)| *score >= NMS_SCORE_THRESHOLD);
+    candidates.sort_by(|(_, a), (_, b)| b.total_cmp(a));
+
+    let mut kept: Vec<(TextBox, f32)> = Vec::new();
+
+    for (candidate, score) in candidates {
+        if kept
+            .iter()
+            .all(|(kept_box, _)| kept_box.iou(&candidate) <= NMS_THRESHOLD as f64)
+        {
+            kept.push((candidate, score));
+        }
+    }
+
+    kept
+}
+
+// Score of a box from 0 to 100 as it is stored
+pub fn score_percent(score: f32) -> u8 {
+    (score * 100.0).round().clamp(0.0, 100.0) as u8
+}
+
+// A page as the model takes it, without OpenCV: padded to a square with black on its bottom and right,
+// scaled to the model's input and laid out as (1, 3, height, width) in RGB from 0 to 1. This matches
+// "dnn::blob_from_image", which also subtracts the mean of 1 that the OpenCV detector passes it.
+pub fn input_blob(page: &RgbImage, input_size: InputSize) -> nd::Array4<f32> {
+    let side = page.width().max(page.height());
+    let mut square = RgbImage::new(side, side);
+    imageops::replace(&mut square, page, 0, 0);
+
+    let (width, height) = (input_size.width as u32, input_size.height as u32);
+    let scaled = imageops::resize(&square, width, height, imageops::FilterType::Triangle);
+
+    nd::Array4::from_shape_fn(
+        (1, 3, height as usize, width as usize),
+        |(_, channel, y, x)| (scaled.get_pixel(x as u32, y as u32).0[channel] as f32 - 1.0) / 255.0,
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::text_box::TextBox;
+    use crate::yolo::{
+        candidates, input_blob, non_max_suppression, InputSize, OutputLayout, YoloVersion,
+    };
+    use image::{Rgb, RgbImage};
+    use ndarray::{array, Axis};
+
+    #[test]
+    fn test_output_layout() {
+        let yolo_v5 = OutputLayout::from_shape(&[1, 25200, 10]).unwrap();
+        assert_eq!(yolo_v5.version, YoloVersion::V5);
+        let yolo_v8 = OutputLayout::from_shape(&[1, 6, 8400]).unwrap();
+        assert_eq!(yolo_v8.version, YoloVersion::V8);
+        assert_eq!(yolo_v8.shape, (6, 8400));
+
+        assert!(OutputLayout::from_shape(&[25200, 10]).is_err());
+        assert!(OutputLayout::from_shape(&[2, 25200, 10]).is_err());
+        // A box and its objectness, but no classes
+        assert!(OutputLayout::from_shape(&[1, 25200, 5]).is_err());
+        assert!(OutputLayout::from_shape(&[1, 4, 8400]).is_err());
+
+        // Each page of a batch has the layout of a single page
+        assert_eq!(
+            OutputLayout::from_batch_shape(&[4, 6, 8400], 4).unwrap(),
+            yolo_v8
+        );
+        assert_eq!(
+            OutputLayout::from_batch_shape(&[1, 25200, 10], 1).unwrap(),
+            yolo_v5
+        );
+        // Models exported for a single page give one page of predictions however many went in
+        assert!(OutputLayout::from_batch_shape(&[1, 6, 8400], 4).is_err());
+        assert!(OutputLayout::from_batch_shape(&[25200, 10], 1).is_err());
+
+        // Two predictions of a YOLOv5 model with a single class, the second one unsure
+        let output = array![
+            [320.0, 240.0, 100.0, 50.0, 0.9, 0.8],
+            [100.0, 100.0, 20.0, 20.0, 0.3, 0.9]
+        ];
+        let predictions = yolo_v5.predictions(output.view());
+        assert_eq!(predictions.row(0)[0], 320.0);
+        assert_eq!(yolo_v5.score(predictions.row(0)), Some(0.9));
+        assert_eq!(yolo_v5.score(predictions.row(1)), None);
+
+        // The same predictions from a YOLOv8 model with two classes, a column each
+        let output = array![
+            [320.0, 100.0],
+            [240.0, 100.0],
+            [100.0, 20.0],
+            [50.0, 20.0],
+            [0.2, 0.1],
+            [0.7, 0.3]
+        ];
+        let predictions = yolo_v8.predictions(output.view());
+        assert_eq!(predictions.len_of(Axis(0)), 2);
+        assert_eq!(
+            predictions.row(0).to_vec()[..4],
+            [320.0, 240.0, 100.0, 50.0]
+        );
+        assert_eq!(yolo_v8.score(predictions.row(0)), Some(0.7));
+        assert_eq!(yolo_v8.score(predictions.row(1)), None);
+    }
+
+    #[test]
+    fn test_candidates() {
+        // A real output has far more predictions than that, which is how its layout is told apart
+        let layout = OutputLayout {
+            version: YoloVersion::V5,
+            shape: (3, 6),
+        };
+        // Two overlapping boxes of the same bubble and a box elsewhere, on a page twice the model's
+        // input
+        let output = array![
+            [320.0, 240.0, 100.0, 50.0, 0.9, 0.8],
+            [322.0, 242.0, 100.0, 50.0, 0.6, 0.8],
+            [100.0, 100.0, 20.0, 20.0, 0.5, 0.9]
+        ];
+
+        let found = candidates(layout, output.view(), InputSize::default(), (1280, 1280));
+        assert_eq!(found.len(), 3);
+        assert_eq!(
+            found[0].0,
+            TextBox {
+                x: 540,
+                y: 430,
+                width: 200,
+                height: 100,
+                score: None,
+            }
+        );
+
+        let kept = non_max_suppression(found);
+        assert_eq!(
+            kept.iter().map(|(_, score)| *score).collect::<Vec<f32>>(),
+            [0.9, 0.5]
+        );
+        assert_eq!(kept[1].0.x, 180);
+    }
+
+    #[test]
+    fn test_input_blob() {
+        // A wide page is padded with black below it
+        let page = RgbImage::from_pixel(1280, 640, Rgb([255, 128, 0]));
+        let blob = input_blob(&page, InputSize::default());
+
+        assert_eq!(blob.shape(), [1, 3, 640, 640]);
+        assert_eq!(blob[[0, 0, 100, 100]], 254.0 / 255.0);
+        assert_eq!(blob[[0, 1, 100, 100]], 127.0 / 255.0);
+        assert_eq!(blob[[0, 0, 600, 100]], -1.0 / 255.0);
+        assert_eq!(blob.index_axis(Axis(1), 2)[[0, 0, 0]], -1.0 / 255.0);
+
+        // Models with an input of their own take the page at that size
+        let input_size = InputSize {
+            width: 320,
+            height: 192,
+        };
+        let blob = input_blob(&page, input_size);
+        assert_eq!(blob.shape(), [1, 3, 192, 320]);
+        assert_eq!(blob[[0, 0, 180, 100]], -1.0 / 255.0);
+    }
+
+    #[test]
+    fn test_input_size() {
+        assert_eq!(
+            InputSize::from_dimensions(&[Some(1), Some(3), Some(736), Some(1280)]).unwrap(),
+            InputSize {
+                width: 1280,
+                height: 736,
+            }
+        );
+        // Dynamic sizes take the default
+        assert_eq!(
+            InputSize::from_dimensions(&[None, Some(3), None, None]).unwrap(),
+            InputSize::default()
+        );
+        assert!(InputSize::from_dimensions(&[Some(1), Some(1), Some(640), Some(640)]).is_err());
+        assert!(InputSize::from_dimensions(&[Some(1), Some(640), Some(640)]).is_err());
+
+        // Predictions of a model with a 1280 x 1280 input are in the page's own pixels on a page
+        // of that size
+        let layout = OutputLayout {
+            version: YoloVersion::V5,
+            shape: (1, 6),
+        };
+        let output = array![[320.0, 240.0, 100.0, 50.0, 0.9, 0.8]];
+        let input_size = InputSize {
+            width: 1280,
+            height: 1280,
+        };
+        let found = candidates(layout, output.view(), input_size, (1280, 1280));
+        assert_eq!(found[0].0.geometry(), (270, 215, 100, 50));
+    }
+}
@@ -0,0 +1,287 @@
+mod util;
+
+use util::{output, read_json, spawn_app};
+
+#[test]
+fn test_missing_input() {
+    let app = spawn_app();
+
+    let printed = output(app.fixture_command().args(["--input", "missing"]));
+
+    assert!(printed.contains("Input must be either a directory or supported image type."));
+}
+
+#[test]
+fn test_unsupported_image() {
+    let app = spawn_app();
+    std::fs::write(app.path("pages/002.txt"), "not a page").unwrap();
+
+    let printed = output(
+        app.fixture_command()
+            .arg("--input")
+            .arg(app.path("pages/002.txt")),
+    );
+
+    assert!(printed.contains("Image file must be in one of the specified formats"));
+}
+
+#[test]
+fn test_missing_language_data() {
+    let app = spawn_app();
+
+    let printed = output(
+        app.command()
+            .arg("--input")
+            .arg(app.page())
+            .arg("--model")
+            .arg(app.path("model.onnx"))
+            .arg("--data")
+            .arg(app.path("tessdata"))
+            .args(["--lang", "jpn"]),
+    );
+
+    assert!(printed.contains(r#"No readable language data for "jpn""#));
+    assert!(printed.contains("Available languages: eng."));
+}
+
+#[test]
+fn test_broken_model() {
+    let app = spawn_app();
+    std::fs::write(app.path("model.onnx"), "not a model").unwrap();
+
+    let printed = output(app.fixture_command().arg("--input").arg(app.page()));
+
+    assert!(printed.contains("Could not load the detection model at"));
+    assert!(!app.path("001.json").exists());
+}
+
+#[test]
+fn test_unsupported_text_file() {
+    let app = spawn_app();
+    std::fs::write(app.path("001.txt"), "").unwrap();
+
+    let printed = output(
+        app.fixture_command()
+            .arg("--input")
+            .arg(app.page())
+            .arg("--text")
+            .arg(app.path("001.txt")),
+    );
+
+    assert!(printed.contains("Text file must be a JSON, XLIFF, CSV or TSV file."));
+}
+
+#[test]
+fn test_project_commands() {
+    let app = spawn_app();
+
+    output(
+        app.command()
+            .args(["project", "init", "--title", "Volume 1"])
+            .arg(app.path("pages")),
+    );
+
+    assert!(app.path("pages/mangatra.project.json").is_file());
+
+    output(
+        app.command()
+            .args(["project", "set", "--status", "translated"])
+            .arg(app.path("pages"))
+            .arg("001"),
+    );
+
+    let printed = output(
+        app.command()
+            .args(["project", "status"])
+            .arg(app.path("pages")),
+    );
+
+    assert!(printed.starts_with("Volume 1 (1 pages)\n"));
+    assert!(printed.contains("translated"));
+
+    let printed = output(
+        app.command()
+            .args(["project", "set", "--status", "translated"])
+            .arg(app.path("pages"))
+            .arg("002"),
+    );
+
+    assert!(printed.contains(r#"002 is not a page of the project "Volume 1"."#));
+}
+
+#[test]
+fn test_diff_command() {
+    let app = spawn_app();
+
+    std::fs::write(
+        app.path("old.json"),
+        r#"{"page": "001", "regions": [{"id": "001-0", "text": "a", "translation": "A"}]}"#,
+    )
+    .unwrap();
+    std::fs::write(
+        app.path("new.json"),
+        r#"{"page": "001", "regions": [{"id": "001-0", "text": "a", "translation": "B"}]}"#,
+    )
+    .unwrap();
+
+    let printed = output(
+        app.command()
+            .arg("diff")
+            .arg(app.path("old.json"))
+            .arg(app.path("new.json")),
+    );
+
+    assert!(printed.contains("~ 001-0\n    translation: [-A-]{+B+}\n"));
+    assert!(printed.contains("1 changed, 0 added, 0 removed\n"));
+}
+
+// The tests below run the full pipeline. They need the detection model and language data from the
+// environment (see "tests/util/mod.rs"), so they only run with "cargo test -- --ignored", as in CI.
+
+#[test]
+#[ignore = "needs MANGATRA_TEST_MODEL and TESSDATA_PREFIX"]
+fn test_extract_and_replace() {
+    let app = spawn_app();
+
+    let mut command = app.pipeline_command();
+
+    output(
+        command
+            .arg("--input")
+            .arg(app.page())
+            .arg("--output")
+            .arg(app.path("output/001.json"))
+            .arg("--clean"),
+    );
+
+    let extraction = read_json(&app.path("output/001.json"));
+
+    assert_eq!(extraction["page"], "001");
+    assert!(extraction["schema_version"].is_u64());
+    assert!(!extraction["regions"].as_array().unwrap().is_empty());
+    assert!(app.path("output/001_cleaned.png").is_file());
+
+    let mut command = app.pipeline_command();
+
+    output(
+        command
+            .arg("--input")
+            .arg(app.page())
+            .arg("--text")
+            .arg(app.path("output/001.json"))
+            .arg("--output")
+            .arg(app.path("output/001_output.png")),
+    );
+
+    let rendered = image::open(app.path("output/001_output.png")).unwrap();
+    let original = image::open(app.page()).unwrap();
+
+    assert_eq!(
+        (rendered.width(), rendered.height()),
+        (original.width(), original.height())
+    );
+}
+
+#[test]
+#[ignore = "needs MANGATRA_TEST_MODEL and TESSDATA_PREFIX"]
+fn test_directory_summary() {
+    let app = spawn_app();
+    std::fs::copy(app.page(), app.path("pages/002.png")).unwrap();
+
+    let mut command = app.pipeline_command();
+
+    let printed = output(
+        command
+            .arg("--input")
+            .arg(app.path("pages"))
+            .arg("--output")
+            .arg(app.path("output"))
+            .arg("--summary")
+            .arg(app.path("summary.json"))
+            .arg("--skip-duplicates"),
+    );
+
+    assert!(printed.contains("Pages processed: 2 (2 succeeded, 0 failed)"));
+
+    let summary = read_json(&app.path("summary.json"));
+
+    assert_eq!(summary["totals"]["pages"], 2);
+    assert!(app.path("output/001.json").is_file());
+    assert!(app.path("output/002.json").is_file());
+}
+
+#[test]
+#[ignore = "needs MANGATRA_TEST_MODEL and TESSDATA_PREFIX"]
+fn test_deterministic_runs() {
+    let app = spawn_app();
+    std::fs::copy(app.page(), app.path("pages/002.png")).unwrap();
+
+    // Extracts the pages and typesets them again, returning every file the two runs wrote
+    let run = |directory: &str| {
+        std::fs::create_dir_all(app.path(&format!("{directory}/text"))).unwrap();
+        std::fs::create_dir_all(app.path(&format!("{directory}/pages"))).unwrap();
+
+        let mut command = app.pipeline_command();
+        output(
+            command
+                .arg("--input")
+                .arg(app.path("pages"))
+                .arg("--output")
+                .arg(app.path(&format!("{directory}/text")))
+                .arg("--summary")
+                .arg(app.path(&format!("{directory}/summary.json")))
+                .args([
+                    "--export-table",
+                    "csv",
+                    "--skip-duplicates",
+                    "--deterministic",
+                ]),
+        );
+
+        let mut command = app.pipeline_command();
+        output(
+            command
+                .arg("--input")
+                .arg(app.path("pages"))
+                .arg("--text")
+                .arg(app.path(&format!("{directory}/text")))
+                .arg("--output")
+                .arg(app.path(&format!("{directory}/pages")))
+                .arg("--deterministic"),
+        );
+
+        let mut files = Vec::new();
+        for subdirectory in ["", "text", "pages"] {
+            for entry in
+                std::fs::read_dir(app.path(&format!("{directory}/{subdirectory}"))).unwrap()
+            {
+                let path = entry.unwrap().path();
+                if path.is_file() {
+                    files.push((
+                        path.strip_prefix(app.path(directory))
+                            .unwrap()
+                            .to_path_buf(),
+                        std::fs::read(&path).unwrap(),
+                    ));
+                }
+            }
+        }
+        files.sort();
+        files
+    };
+
+    let first_run = run("first");
+    let second_run = run("second");
+
+    assert!(first_run
+        .iter()
+        .any(|(path, _)| path.ends_with("001_output.png")));
+    assert!(first_run.iter().any(|(path, _)| path.ends_with("002.json")));
+    assert_eq!(
+        first_run.iter().map(|(path, _)| path).collect::<Vec<_>>(),
+        second_run.iter().map(|(path, _)| path).collect::<Vec<_>>()
+    );
+    for ((path, first), (_, second)) in first_run.iter().zip(&second_run) {
+        assert!(first == second, "{} differs between runs", path.display());
+    }
+}
@@ -0,0 +1,122 @@
+use assert_cmd::Command;
+use std::path::{Path, PathBuf};
+use tempfile::TempDir;
+
+// Page shipped with the repository that the tests run on
+const FIXTURE_PAGE: &str = "assets/input.png";
+
+// Scratch directory for a single test, laid out the way mangatra is usually run:
+//
+//   pages/001.png    copy of the fixture page
+//   tessdata/        language data (real if TESSDATA_PREFIX is set, otherwise an empty eng.traineddata)
+//   output/          empty
+//
+// Each test gets its own directory, so tests can run in parallel.
+pub struct TestApp {
+    pub directory: TempDir,
+    // Real detection model and language data, if the environment provides them
+    pub pipeline: Option<Pipeline>,
+}
+
+pub struct Pipeline {
+    pub model: PathBuf,
+    pub tessdata: PathBuf,
+}
+
+pub fn spawn_app() -> TestApp {
+    let directory = TempDir::new().unwrap();
+
+    std::fs::create_dir(directory.path().join("pages")).unwrap();
+    std::fs::create_dir(directory.path().join("output")).unwrap();
+    std::fs::create_dir(directory.path().join("tessdata")).unwrap();
+
+    std::fs::copy(FIXTURE_PAGE, directory.path().join("pages").join("001.png")).unwrap();
+
+    // Configuration only checks that the language file can be opened
+    std::fs::write(
+        directory.path().join("tessdata").join("eng.traineddata"),
+        "",
+    )
+    .unwrap();
+
+    TestApp {
+        directory,
+        pipeline: pipeline(),
+    }
+}
+
+// Running the full pipeline needs the YOLOv5 text detection model and English language data,
+// which are too large to keep in the repository. Point MANGATRA_TEST_MODEL at the model
+// and TESSDATA_PREFIX at a tessdata folder, and run those tests with "cargo test -- --ignored".
+fn pipeline() -> Option<Pipeline> {
+    let model = PathBuf::from(std::env::var_os("MANGATRA_TEST_MODEL")?);
+    let tessdata = PathBuf::from(std::env::var_os("TESSDATA_PREFIX")?);
+
+    (model.is_file() && tessdata.join("eng.traineddata").is_file())
+        .then_some(Pipeline { model, tessdata })
+}
+
+impl TestApp {
+    pub fn path(&self, path: &str) -> PathBuf {
+        self.directory.path().join(path)
+    }
+
+    pub fn page(&self) -> PathBuf {
+        self.path("pages/001.png")
+    }
+
+    // The mangatra binary, run from the scratch directory so default output paths end up there
+    pub fn command(&self) -> Command {
+        let mut command = Command::cargo_bin("mangatra").unwrap();
+        command
+            .current_dir(self.directory.path())
+            .env_remove("TESSDATA_PREFIX");
+        command
+    }
+
+    // Command for processing pages with the real model and language data. Tests that need them are
+    // ignored by default, so running them without the environment is an error rather than a pass.
+    pub fn pipeline_command(&self) -> Command {
+        let pipeline = self.pipeline.as_ref().expect(
+            "MANGATRA_TEST_MODEL must point at the detection model and TESSDATA_PREFIX at a tessdata folder with eng.traineddata",
+        );
+
+        let mut command = self.command();
+        command
+            .arg("--model")
+            .arg(&pipeline.model)
+            .arg("--data")
+            .arg(&pipeline.tessdata)
+            .args(["--lang", "eng"]);
+
+        command
+    }
+
+    // Command for processing pages with placeholder model and language data, for tests that
+    // fail before either is used
+    pub fn fixture_command(&self) -> Command {
+        let mut command = self.command();
+        command
+            .arg("--model")
+            .arg(self.path("model.onnx"))
+            .arg("--data")
+            .arg(self.path("tessdata"))
+            .args(["--lang", "eng"]);
+        command
+    }
+}
+
+// Everything the command printed. Errors are logged rather than returned, so both streams are checked.
+pub fn output(command: &mut Command) -> String {
+    let output = command.output().unwrap();
+
+    format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+}
+
+pub fn read_json(path: &Path) -> serde_json::Value {
+    serde_json::from_str(&std::fs::read_to_string(path).unwrap()).unwrap()
+}